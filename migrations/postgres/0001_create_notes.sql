@@ -0,0 +1,25 @@
+CREATE TABLE notes (
+    -- Insertion order, so that pages don't overlap
+    seq BIGINT GENERATED ALWAYS AS IDENTITY,
+    id TEXT PRIMARY KEY,
+    title TEXT NOT NULL,
+    body TEXT NOT NULL,
+    url TEXT NOT NULL,
+    tags TEXT[] NOT NULL DEFAULT '{}',
+    created_at TIMESTAMPTZ NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL
+);
+
+-- Prefix search for title suggestions
+CREATE INDEX notes_title_lower ON notes (lower(title) text_pattern_ops);
+CREATE INDEX notes_tags ON notes USING GIN (tags);
+CREATE INDEX notes_seq ON notes (seq);
+
+CREATE TABLE announcements (
+    id TEXT PRIMARY KEY,
+    title TEXT NOT NULL,
+    body TEXT NOT NULL,
+    severity TEXT NOT NULL,
+    starts_at TIMESTAMPTZ NOT NULL,
+    ends_at TIMESTAMPTZ
+);
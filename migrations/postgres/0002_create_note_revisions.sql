@@ -0,0 +1,10 @@
+-- States of notes before their patches, numbered per note
+CREATE TABLE note_revisions (
+    note_id TEXT NOT NULL,
+    rev BIGINT NOT NULL,
+    title TEXT NOT NULL,
+    body TEXT NOT NULL,
+    tags TEXT[] NOT NULL DEFAULT '{}',
+    updated_at TIMESTAMPTZ NOT NULL,
+    PRIMARY KEY (note_id, rev)
+);
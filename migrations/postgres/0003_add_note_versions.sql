@@ -0,0 +1,2 @@
+-- Incremented by every update, for optimistic concurrency
+ALTER TABLE notes ADD COLUMN version BIGINT NOT NULL DEFAULT 0;
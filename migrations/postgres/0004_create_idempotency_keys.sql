@@ -0,0 +1,8 @@
+-- Notes created for idempotency keys of clients, until the key expires
+CREATE TABLE idempotency_keys (
+    key TEXT PRIMARY KEY,
+    note_id TEXT NOT NULL,
+    expires_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX idempotency_keys_expires_at ON idempotency_keys (expires_at);
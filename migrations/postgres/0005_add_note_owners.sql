@@ -0,0 +1,4 @@
+-- Subject of the user who created the note, NULL for notes created
+-- without authentication
+ALTER TABLE notes ADD COLUMN owner TEXT;
+CREATE INDEX notes_owner ON notes (owner);
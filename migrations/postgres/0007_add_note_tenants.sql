@@ -0,0 +1,3 @@
+-- Tenant the note was created in, NULL outside of multi-tenant deployments
+ALTER TABLE notes ADD COLUMN tenant TEXT;
+CREATE INDEX notes_tenant ON notes (tenant);
@@ -0,0 +1,12 @@
+CREATE TABLE orgs (
+    id TEXT PRIMARY KEY,
+    name TEXT NOT NULL,
+    -- Subjects of the members
+    members TEXT[] NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL
+);
+CREATE INDEX orgs_members ON orgs USING GIN (members);
+
+-- Organization whose members share the note
+ALTER TABLE notes ADD COLUMN org TEXT;
+CREATE INDEX notes_org ON notes (org);
@@ -0,0 +1,9 @@
+CREATE TABLE shares (
+    id TEXT PRIMARY KEY,
+    note_id TEXT NOT NULL,
+    tenant TEXT,
+    -- Hex encoded SHA-256 of the token
+    token_hash TEXT NOT NULL UNIQUE,
+    created_at TIMESTAMPTZ NOT NULL,
+    expires_at TIMESTAMPTZ
+);
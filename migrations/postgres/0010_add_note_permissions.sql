@@ -0,0 +1,5 @@
+-- Subjects of the users granted access to the note besides its owner
+ALTER TABLE notes ADD COLUMN read_access TEXT[] NOT NULL DEFAULT '{}';
+ALTER TABLE notes ADD COLUMN write_access TEXT[] NOT NULL DEFAULT '{}';
+CREATE INDEX notes_read_access ON notes USING GIN (read_access);
+CREATE INDEX notes_write_access ON notes USING GIN (write_access);
@@ -0,0 +1,2 @@
+-- API keys are listed per user
+CREATE INDEX api_keys_subject ON api_keys (subject);
@@ -0,0 +1,4 @@
+-- Trigrams of the title for fuzzy title suggestions, written by the
+-- server. NULL for notes stored before, until the server fills them in.
+ALTER TABLE notes ADD COLUMN title_trigrams TEXT[];
+CREATE INDEX notes_title_trigrams ON notes USING GIN (title_trigrams);
@@ -0,0 +1,79 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use nanoid::nanoid;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Serialize,
+    Deserialize,
+    ToSchema,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Info,
+    Warning,
+    Critical,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct Announcement {
+    pub id: String,
+    pub title: String,
+    pub body: String,
+    pub severity: Severity,
+    pub starts_at: DateTime<Utc>,
+    pub ends_at: Option<DateTime<Utc>>,
+}
+
+impl Announcement {
+    pub fn new(new_announcement: NewAnnouncement, now: DateTime<Utc>) -> Self {
+        Announcement {
+            id: nanoid!(),
+            title: new_announcement.title,
+            body: new_announcement.body,
+            severity: new_announcement.severity,
+            starts_at: new_announcement.starts_at.unwrap_or(now),
+            ends_at: new_announcement.ends_at,
+        }
+    }
+
+    /// An announcement is active from `starts_at` (inclusive) until
+    /// `ends_at` (exclusive), or indefinitely without an end.
+    pub fn is_active(&self, now: DateTime<Utc>) -> bool {
+        self.starts_at <= now && self.ends_at.is_none_or(|end| now < end)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct NewAnnouncement {
+    pub title: String,
+    pub body: String,
+    pub severity: Severity,
+    pub starts_at: Option<DateTime<Utc>>,
+    pub ends_at: Option<DateTime<Utc>>,
+}
+
+#[async_trait]
+pub trait AnnouncementDb: Send + Sync {
+    async fn create_announcement(
+        &self,
+        announcement: &Announcement,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>>;
+
+    async fn delete_announcement(
+        &self,
+        id: &str,
+    ) -> Result<bool, Box<dyn std::error::Error + Send + Sync>>;
+
+    async fn list_announcements(
+        &self,
+    ) -> Result<Vec<Announcement>, Box<dyn std::error::Error + Send + Sync>>;
+}
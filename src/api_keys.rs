@@ -0,0 +1,117 @@
+//! Static API keys for machine clients like CI pipelines, which can't go
+//! through a login flow. A key acts as the user who created it. Only a
+//! hash of each key is stored, the key itself is shown once on creation.
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use nanoid::nanoid;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use utoipa::ToSchema;
+
+use crate::notes::NoteDbError;
+
+pub const API_KEY_HEADER: &str = "x-api-key";
+// Marks keys of this service, so that secret scanners can find leaked ones.
+const KEY_PREFIX: &str = "nk_";
+const KEY_RANDOM_CHARS: usize = 40;
+
+/// A stored API key.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ApiKey {
+    pub id: String,
+    pub name: String,
+    /// Subject of the user the key acts as.
+    pub subject: String,
+    /// Hex encoded SHA-256 of the key.
+    pub key_hash: String,
+    pub created_at: DateTime<Utc>,
+}
+
+impl ApiKey {
+    /// Generates a key for `subject` and returns its record along with the
+    /// key itself.
+    pub fn generate(
+        name: &str,
+        subject: &str,
+        now: DateTime<Utc>,
+    ) -> (ApiKey, String) {
+        let key = format!("{}{}", KEY_PREFIX, nanoid!(KEY_RANDOM_CHARS));
+        let api_key = ApiKey {
+            id: nanoid!(),
+            name: name.to_string(),
+            subject: subject.to_string(),
+            key_hash: hash_key(&key),
+            created_at: now,
+        };
+        (api_key, key)
+    }
+}
+
+/// Keys are long random strings, so a fast unsalted hash is enough to
+/// keep them from leaking with the database.
+pub fn hash_key(key: &str) -> String {
+    hex::encode(Sha256::digest(key.as_bytes()))
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct NewApiKey {
+    /// What the key is for, e.g. the pipeline using it.
+    pub name: String,
+}
+
+/// An API key as listed to its owner, without the key.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct ApiKeyInfo {
+    pub id: String,
+    pub name: String,
+    pub subject: String,
+    pub created_at: DateTime<Utc>,
+}
+
+impl From<ApiKey> for ApiKeyInfo {
+    fn from(api_key: ApiKey) -> Self {
+        ApiKeyInfo {
+            id: api_key.id,
+            name: api_key.name,
+            subject: api_key.subject,
+            created_at: api_key.created_at,
+        }
+    }
+}
+
+/// A newly created API key. The key can't be retrieved again.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct CreatedApiKey {
+    #[serde(flatten)]
+    pub info: ApiKeyInfo,
+    /// Sent as `X-Api-Key` to authenticate.
+    pub key: String,
+}
+
+#[async_trait]
+pub trait ApiKeyDb: Send + Sync {
+    async fn create_api_key(&self, api_key: &ApiKey)
+        -> Result<(), NoteDbError>;
+
+    /// The key with the given hash, if it exists.
+    async fn find_api_key(
+        &self,
+        key_hash: &str,
+    ) -> Result<Option<ApiKey>, NoteDbError>;
+
+    async fn list_api_keys(&self) -> Result<Vec<ApiKey>, NoteDbError>;
+
+    /// The keys acting as `subject`. Backends with an index on subjects
+    /// should override this.
+    async fn list_api_keys_of(
+        &self,
+        subject: &str,
+    ) -> Result<Vec<ApiKey>, NoteDbError> {
+        let mut api_keys = self.list_api_keys().await?;
+        api_keys.retain(|api_key| api_key.subject == subject);
+        Ok(api_keys)
+    }
+
+    async fn delete_api_key(&self, id: &str) -> Result<bool, NoteDbError>;
+}
@@ -0,0 +1,316 @@
+//! Bearer token authentication with JWTs signed by an external identity
+//! provider, whose public keys are fetched from its JWKS endpoint. Machine
+//! clients may send an API key instead.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::{
+    extract::{FromRequestParts, Request, State},
+    http::{header, request::Parts, HeaderValue, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    Router,
+};
+use jsonwebtoken::{
+    jwk::{Jwk, JwkSet},
+    DecodingKey, Validation,
+};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use tokio::{sync::RwLock, time::Instant};
+
+use crate::api_keys::{self, ApiKeyDb, API_KEY_HEADER};
+use crate::orgs::OrgDb;
+use crate::problem::Problem;
+
+// Unknown key ids trigger a refetch of the key set, but not more often
+// than this, so that forged tokens can't hammer the identity provider.
+const JWKS_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+const JWKS_FETCH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Where the keys verifying tokens come from.
+#[derive(Debug, Clone)]
+pub enum KeySource {
+    /// A JWKS endpoint, fetched on first use and on unknown key ids.
+    Url(url::Url),
+    /// A fixed key set, e.g. for tests.
+    Static(JwkSet),
+}
+
+#[derive(Debug, Clone)]
+pub struct AuthConfig {
+    /// Required `iss` claim of tokens.
+    pub issuer: String,
+    /// Required `aud` claim of tokens, if any.
+    pub audience: Option<String>,
+    pub keys: KeySource,
+}
+
+/// The authenticated caller, added to the request extensions by the auth
+/// layer.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UserContext {
+    /// `sub` claim of the token.
+    pub subject: String,
+    /// All claims of the token.
+    pub claims: Map<String, Value>,
+    /// Ids of the organizations the user belongs to.
+    pub orgs: Vec<String>,
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for UserContext {
+    type Rejection = Response;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        _state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        parts
+            .extensions
+            .get::<UserContext>()
+            .cloned()
+            .ok_or_else(|| unauthorized("authentication required"))
+    }
+}
+
+/// Claim listing the roles of the user, e.g. `"roles": ["admin"]`.
+pub const ROLES_CLAIM: &str = "roles";
+/// Role required by the administrative endpoints.
+pub const ADMIN_ROLE: &str = "admin";
+
+impl UserContext {
+    pub fn is_admin(&self) -> bool {
+        self.claims
+            .get(ROLES_CLAIM)
+            .and_then(Value::as_array)
+            .is_some_and(|roles| roles.iter().any(|role| role == ADMIN_ROLE))
+    }
+}
+
+/// Rejects requests of users without the admin role with 403. Goes inside
+/// the auth layer, which adds the user.
+pub async fn require_admin(request: Request, next: Next) -> Response {
+    match request.extensions().get::<UserContext>() {
+        Some(user) if user.is_admin() => next.run(request).await,
+        Some(user) => {
+            tracing::debug!("reject {}: not an admin", user.subject);
+            Problem::new(StatusCode::FORBIDDEN, "admin role required")
+                .into_response()
+        }
+        None => unauthorized("authentication required"),
+    }
+}
+
+#[derive(Deserialize)]
+struct Claims {
+    sub: String,
+    #[serde(flatten)]
+    claims: Map<String, Value>,
+}
+
+struct CachedKeys {
+    set: JwkSet,
+    fetched_at: Option<Instant>,
+}
+
+/// Rejects requests without a valid bearer token or API key with 401.
+pub struct JwtAuth {
+    config: AuthConfig,
+    client: reqwest::Client,
+    keys: RwLock<CachedKeys>,
+    api_keys: Option<Arc<dyn ApiKeyDb + Send + Sync>>,
+    orgs: Option<Arc<dyn OrgDb + Send + Sync>>,
+}
+
+impl JwtAuth {
+    pub fn new(config: AuthConfig) -> JwtAuth {
+        let set = match &config.keys {
+            KeySource::Static(set) => set.clone(),
+            KeySource::Url(_) => JwkSet { keys: Vec::new() },
+        };
+        JwtAuth {
+            config,
+            client: reqwest::Client::new(),
+            keys: RwLock::new(CachedKeys {
+                set,
+                fetched_at: None,
+            }),
+            api_keys: None,
+            orgs: None,
+        }
+    }
+
+    /// Also accepts the API keys of `api_keys`, sent as `X-Api-Key`.
+    pub fn with_api_keys(
+        mut self,
+        api_keys: Arc<dyn ApiKeyDb + Send + Sync>,
+    ) -> JwtAuth {
+        self.api_keys = Some(api_keys);
+        self
+    }
+
+    /// Looks up the organizations of authenticated users in `orgs`.
+    pub fn with_orgs(mut self, orgs: Arc<dyn OrgDb + Send + Sync>) -> JwtAuth {
+        self.orgs = Some(orgs);
+        self
+    }
+
+    pub fn apply<S>(self, router: Router<S>) -> Router<S>
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        router
+            .layer(middleware::from_fn_with_state(Arc::new(self), authenticate))
+    }
+
+    async fn verify(&self, token: &str) -> Result<UserContext, String> {
+        let header = jsonwebtoken::decode_header(token)
+            .map_err(|err| format!("malformed token: {}", err))?;
+        let jwk = self.key(header.kid.as_deref()).await?;
+        let key = DecodingKey::from_jwk(&jwk)
+            .map_err(|err| format!("unusable signing key: {}", err))?;
+        let mut validation = Validation::new(header.alg);
+        validation.set_issuer(&[&self.config.issuer]);
+        match &self.config.audience {
+            Some(audience) => validation.set_audience(&[audience]),
+            None => validation.validate_aud = false,
+        }
+        let token = jsonwebtoken::decode::<Claims>(token, &key, &validation)
+            .map_err(|err| format!("invalid token: {}", err))?;
+        Ok(UserContext {
+            subject: token.claims.sub,
+            claims: token.claims.claims,
+            orgs: Vec::new(),
+        })
+    }
+
+    async fn verify_api_key(&self, key: &str) -> Result<UserContext, String> {
+        let Some(api_keys) = &self.api_keys else {
+            return Err("api keys are not accepted".to_string());
+        };
+        let api_key = api_keys
+            .find_api_key(&api_keys::hash_key(key))
+            .await
+            .map_err(|err| {
+                tracing::error!("unable to look up api key: {}", err);
+                "api keys unavailable".to_string()
+            })?
+            .ok_or_else(|| "invalid api key".to_string())?;
+        Ok(UserContext {
+            subject: api_key.subject,
+            claims: Map::new(),
+            orgs: Vec::new(),
+        })
+    }
+
+    async fn load_orgs(&self, user: &mut UserContext) -> Result<(), String> {
+        let Some(orgs) = &self.orgs else {
+            return Ok(());
+        };
+        let orgs = orgs.list_orgs(&user.subject).await.map_err(|err| {
+            tracing::error!("unable to look up organizations: {}", err);
+            "organizations unavailable".to_string()
+        })?;
+        user.orgs = orgs.into_iter().map(|org| org.id).collect();
+        Ok(())
+    }
+
+    /// The key with the given id, or the only key if the token names none.
+    async fn key(&self, kid: Option<&str>) -> Result<Jwk, String> {
+        let find = |set: &JwkSet| match kid {
+            Some(kid) => set.find(kid).cloned(),
+            None if set.keys.len() == 1 => set.keys.first().cloned(),
+            None => None,
+        };
+        let keys = self.keys.read().await;
+        if let Some(jwk) = find(&keys.set) {
+            return Ok(jwk);
+        }
+        let KeySource::Url(url) = &self.config.keys else {
+            return Err("unknown signing key".to_string());
+        };
+        if keys
+            .fetched_at
+            .is_some_and(|at| at.elapsed() < JWKS_REFRESH_INTERVAL)
+        {
+            return Err("unknown signing key".to_string());
+        }
+        drop(keys);
+
+        let mut keys = self.keys.write().await;
+        // Another request may have refreshed the keys in the meantime.
+        if keys
+            .fetched_at
+            .is_none_or(|at| at.elapsed() >= JWKS_REFRESH_INTERVAL)
+        {
+            keys.fetched_at = Some(Instant::now());
+            keys.set = self.fetch_keys(url).await.map_err(|err| {
+                tracing::error!("unable to fetch jwks: {}", err);
+                "signing keys unavailable".to_string()
+            })?;
+        }
+        find(&keys.set).ok_or_else(|| "unknown signing key".to_string())
+    }
+
+    async fn fetch_keys(
+        &self,
+        url: &url::Url,
+    ) -> Result<JwkSet, Box<dyn std::error::Error + Send + Sync>> {
+        tracing::info!("fetch jwks from {}", url);
+        let body = self
+            .client
+            .get(url.clone())
+            .timeout(JWKS_FETCH_TIMEOUT)
+            .send()
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await?;
+        Ok(serde_json::from_slice(&body)?)
+    }
+}
+
+async fn authenticate(
+    State(auth): State<Arc<JwtAuth>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let headers = request.headers();
+    let api_key = headers
+        .get(API_KEY_HEADER)
+        .map(|value| value.to_str().unwrap_or_default());
+    let token = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    let user = match (api_key, token) {
+        (Some(key), _) => auth.verify_api_key(key.trim()).await,
+        (None, Some(token)) => auth.verify(token.trim()).await,
+        (None, None) => return unauthorized("bearer token required"),
+    };
+    let user = match user {
+        Ok(mut user) => auth.load_orgs(&mut user).await.map(|_| user),
+        Err(err) => Err(err),
+    };
+    match user {
+        Ok(user) => {
+            tracing::debug!("authenticated {}", user.subject);
+            request.extensions_mut().insert(user);
+            next.run(request).await
+        }
+        Err(err) => {
+            tracing::debug!("reject request: {}", err);
+            unauthorized(err)
+        }
+    }
+}
+
+fn unauthorized(detail: impl Into<String>) -> Response {
+    let mut response =
+        Problem::new(StatusCode::UNAUTHORIZED, detail).into_response();
+    response
+        .headers_mut()
+        .insert(header::WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+    response
+}
@@ -0,0 +1,55 @@
+//! Caps the size of request bodies, so that a single oversized request
+//! can't exhaust memory. Requests over the limit are refused with 413 and a
+//! problem body naming the limit, before or while their body is read.
+
+use axum::{
+    extract::{DefaultBodyLimit, State},
+    http::{header, StatusCode},
+    middleware,
+    response::{IntoResponse, Response},
+    Router,
+};
+use tower_http::limit::RequestBodyLimitLayer;
+
+use crate::problem::Problem;
+
+/// Leaves room for a note body at its default limit of 1 MiB, escaped as
+/// JSON, and for imports of a few hundred notes.
+pub const DEFAULT_MAX_REQUEST_BYTES: usize = 2 * 1024 * 1024;
+
+/// Limits the request bodies of all routes of `router` to `max_bytes`,
+/// replacing the default limit of the body extractors.
+pub fn apply<S>(router: Router<S>, max_bytes: usize) -> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    router
+        .layer(DefaultBodyLimit::disable())
+        .layer(RequestBodyLimitLayer::new(max_bytes))
+        .layer(middleware::map_response_with_state(max_bytes, to_problem))
+}
+
+/// Turns the bare 413 of the limit, or of an extractor that hit it, into a
+/// problem. Problems of handlers, like notes too large for the database,
+/// pass unchanged.
+async fn to_problem(
+    State(max_bytes): State<usize>,
+    response: Response,
+) -> Response {
+    let is_problem = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .is_some_and(|value| value == "application/problem+json");
+    if response.status() != StatusCode::PAYLOAD_TOO_LARGE || is_problem {
+        return response;
+    }
+    Problem::new(
+        StatusCode::PAYLOAD_TOO_LARGE,
+        format!(
+            "the request body is larger than the {} bytes the server accepts",
+            max_bytes
+        ),
+    )
+    .with_extension("limit", max_bytes)
+    .into_response()
+}
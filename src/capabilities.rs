@@ -0,0 +1,98 @@
+//! What this deployment supports, so that generic clients can adapt at
+//! runtime instead of probing endpoints.
+
+use std::sync::Arc;
+
+use axum::{Extension, Json};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::AppConfig;
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum AuthMode {
+    /// Every request is accepted.
+    None,
+    /// Requests need a bearer JWT of the configured issuer.
+    Jwt,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct Capabilities {
+    pub api_version: String,
+    pub auth: AuthMode,
+    /// Whether machine clients may authenticate with `X-Api-Key`.
+    pub api_keys: bool,
+    /// Whether users can share notes through organizations.
+    pub orgs: bool,
+    /// Full-text search over note bodies. Listing filters by title, tag
+    /// and time regardless.
+    pub search: bool,
+    /// Files attached to notes.
+    pub attachments: bool,
+    pub max_title_chars: usize,
+    pub max_body_bytes: usize,
+    pub max_tags: usize,
+    pub max_tag_chars: usize,
+    /// Largest request body accepted, in bytes.
+    pub max_request_bytes: usize,
+    /// Formats accepted under `/import*`, `json` being `/notes/import`.
+    pub import_formats: Vec<String>,
+    pub export_formats: Vec<String>,
+    /// Content types accepted by `PATCH /notes/{id}`.
+    pub patch_formats: Vec<String>,
+    /// Whether PATCH and DELETE of notes need `If-Match`.
+    pub require_if_match: bool,
+    /// Whether requests name a tenant with `X-Tenant-Id` or a `/t/{tenant}`
+    /// path prefix.
+    pub multi_tenant: bool,
+}
+
+impl Capabilities {
+    pub fn of(app_config: &AppConfig) -> Capabilities {
+        let strings = |values: &[&str]| -> Vec<String> {
+            values.iter().map(|value| value.to_string()).collect()
+        };
+        let mut export_formats = strings(&["csv", "jex"]);
+        if cfg!(feature = "pdf") {
+            export_formats.push("pdf".to_string());
+        }
+        Capabilities {
+            api_version: app_config.api_version.as_str().to_string(),
+            auth: match app_config.auth {
+                Some(_) => AuthMode::Jwt,
+                None => AuthMode::None,
+            },
+            api_keys: app_config.auth.is_some(),
+            orgs: app_config.auth.is_some(),
+            search: false,
+            attachments: false,
+            max_title_chars: app_config.note_limits.max_title_chars,
+            max_body_bytes: app_config.note_limits.max_body_bytes,
+            max_tags: app_config.note_limits.max_tags,
+            max_tag_chars: app_config.note_limits.max_tag_chars,
+            max_request_bytes: app_config.max_request_bytes,
+            import_formats: strings(&["json", "csv", "notion", "html", "jex"]),
+            export_formats,
+            patch_formats: strings(&[
+                "application/json",
+                crate::note_patch::JSON_PATCH_CONTENT_TYPE,
+            ]),
+            require_if_match: app_config.require_if_match,
+            multi_tenant: app_config.multi_tenant,
+        }
+    }
+}
+
+/// Lists the optional features of this deployment. Answered without
+/// authentication, so that clients learn whether they need a token.
+#[utoipa::path(get, path = "/capabilities", tag = "health",
+    responses((status = 200, body = Capabilities)))]
+pub async fn get_capabilities(
+    Extension(capabilities): Extension<Arc<Capabilities>>,
+) -> Json<Capabilities> {
+    Json(capabilities.as_ref().clone())
+}
@@ -0,0 +1,127 @@
+//! Conditional requests on notes: entity tags derived from the content of
+//! a note, `If-None-Match` for cheap re-reads and `If-Match` against lost
+//! updates.
+
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+use axum::{
+    extract::FromRequestParts,
+    http::{header, request::Parts, HeaderMap, StatusCode},
+};
+
+use crate::notes::Note;
+
+/// Strong entity tag of the current content of a note.
+pub fn etag(note: &Note) -> String {
+    let mut hasher = DefaultHasher::new();
+    note.id.hash(&mut hasher);
+    note.title.hash(&mut hasher);
+    note.body.hash(&mut hasher);
+    note.tags.hash(&mut hasher);
+    note.updated_at.hash(&mut hasher);
+    note.version.hash(&mut hasher);
+    format!("\"{:016x}\"", hasher.finish())
+}
+
+/// The `If-Match` and `If-None-Match` headers of a request.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Preconditions {
+    if_match: Option<String>,
+    if_none_match: Option<String>,
+}
+
+impl Preconditions {
+    /// Whether a read can be answered with 304 because the client already
+    /// has the current content.
+    pub fn not_modified(&self, note: &Note) -> bool {
+        self.if_none_match
+            .as_deref()
+            .is_some_and(|tags| matches(tags, &etag(note), true))
+    }
+
+    /// Checks a write against the current content of the note. Without
+    /// `If-Match` the write goes through unless `required` is set.
+    pub fn check_write(
+        &self,
+        note: &Note,
+        required: bool,
+    ) -> Result<(), StatusCode> {
+        match &self.if_match {
+            Some(tags) if matches(tags, &etag(note), false) => Ok(()),
+            Some(_) => Err(StatusCode::PRECONDITION_FAILED),
+            None if required => Err(StatusCode::PRECONDITION_REQUIRED),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Whether the header value, a list of entity tags or `*`, contains
+/// `etag`. Weak tags only match in the weak comparison used for reads.
+fn matches(tags: &str, etag: &str, weak: bool) -> bool {
+    tags.split(',').map(str::trim).any(|tag| {
+        if tag == "*" {
+            return true;
+        }
+        match tag.strip_prefix("W/") {
+            Some(tag) => weak && tag == etag,
+            None => tag == etag,
+        }
+    })
+}
+
+fn header_value(
+    headers: &HeaderMap,
+    name: header::HeaderName,
+) -> Option<String> {
+    headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string)
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for Preconditions {
+    type Rejection = std::convert::Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        _state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        Ok(Preconditions {
+            if_match: header_value(&parts.headers, header::IF_MATCH),
+            if_none_match: header_value(&parts.headers, header::IF_NONE_MATCH),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_compares_entity_tags() {
+        // Setup
+        let note = Note::new("a", "b", "c");
+        let current = etag(&note);
+        let stale = Preconditions {
+            if_match: Some("\"0\"".to_string()),
+            if_none_match: Some(format!("\"0\", W/{}", current)),
+        };
+        let fresh = Preconditions {
+            if_match: Some(format!("\"0\", {}", current)),
+            if_none_match: None,
+        };
+
+        // Assert
+        assert!(stale.not_modified(&note));
+        assert_eq!(
+            stale.check_write(&note, false),
+            Err(StatusCode::PRECONDITION_FAILED)
+        );
+        assert!(!fresh.not_modified(&note));
+        assert_eq!(fresh.check_write(&note, true), Ok(()));
+        assert_eq!(
+            Preconditions::default().check_write(&note, true),
+            Err(StatusCode::PRECONDITION_REQUIRED)
+        );
+    }
+}
@@ -0,0 +1,93 @@
+//! Typed configuration values that are validated when parsed.
+
+use std::{fmt, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+/// Version prefix of all API routes: `v` followed by a number, e.g. `v1`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct ApiVersion(String);
+
+impl ApiVersion {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Default for ApiVersion {
+    fn default() -> Self {
+        ApiVersion("v1".to_string())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidApiVersion(String);
+
+impl fmt::Display for InvalidApiVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid api version {:?}: expected `v` followed by a number, \
+             e.g. v1",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for InvalidApiVersion {}
+
+impl FromStr for ApiVersion {
+    type Err = InvalidApiVersion;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.strip_prefix('v') {
+            Some(number)
+                if !number.is_empty()
+                    && number.chars().all(|c| c.is_ascii_digit()) =>
+            {
+                Ok(ApiVersion(value.to_string()))
+            }
+            _ => Err(InvalidApiVersion(value.to_string())),
+        }
+    }
+}
+
+impl TryFrom<String> for ApiVersion {
+    type Error = InvalidApiVersion;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<ApiVersion> for String {
+    fn from(version: ApiVersion) -> Self {
+        version.0
+    }
+}
+
+impl fmt::Display for ApiVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_parses_api_versions() {
+        // Execute
+        let valid = "v2".parse::<ApiVersion>();
+        let invalid: Vec<_> = ["", "v", "2", "v1/notes", "V1"]
+            .iter()
+            .map(|value| value.parse::<ApiVersion>())
+            .collect();
+
+        // Assert
+        assert_eq!(valid.unwrap().as_str(), "v2");
+        assert!(invalid.iter().all(Result::is_err));
+    }
+}
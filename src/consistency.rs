@@ -0,0 +1,99 @@
+//! Per-request read consistency. Clients send `X-Consistency: strong` to
+//! read their own writes past replicas and caches, or `eventual` to accept
+//! stale reads for lower latency. The level that applied is echoed back.
+
+use std::{fmt, str::FromStr};
+
+use axum::{
+    extract::{Request, State},
+    http::{HeaderValue, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    Router,
+};
+
+use crate::problem::Problem;
+
+pub const CONSISTENCY_HEADER: &str = "x-consistency";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Consistency {
+    /// Reads come from the primary and see every acknowledged write.
+    Strong,
+    /// Reads may be served by replicas and caches that lag behind.
+    Eventual,
+}
+
+impl Consistency {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Consistency::Strong => "strong",
+            Consistency::Eventual => "eventual",
+        }
+    }
+}
+
+impl fmt::Display for Consistency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Consistency {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "strong" => Ok(Consistency::Strong),
+            "eventual" => Ok(Consistency::Eventual),
+            _ => Err(format!(
+                "invalid consistency {:?}: expected strong or eventual",
+                s
+            )),
+        }
+    }
+}
+
+/// Reads the requested level into the request extensions, where
+/// `OpContext` picks it up, and echoes the level that applied. Without a
+/// header, `default` applies: eventual when reads may hit a replica or a
+/// cache.
+pub fn apply<S>(router: Router<S>, default: Consistency) -> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    router.layer(middleware::from_fn_with_state(default, negotiate))
+}
+
+async fn negotiate(
+    State(default): State<Consistency>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let requested = match request.headers().get(CONSISTENCY_HEADER) {
+        Some(value) => {
+            match value
+                .to_str()
+                .map_err(|err| err.to_string())
+                .and_then(|value| value.trim().to_ascii_lowercase().parse())
+            {
+                Ok(level) => Some(level),
+                Err(err) => {
+                    return Problem::new(StatusCode::BAD_REQUEST, err)
+                        .into_response()
+                }
+            }
+        }
+        None => None,
+    };
+    if let Some(level) = requested {
+        request.extensions_mut().insert(level);
+    }
+    let mut response = next.run(request).await;
+    let applied = requested.unwrap_or(default);
+    response.headers_mut().insert(
+        CONSISTENCY_HEADER,
+        HeaderValue::from_static(applied.as_str()),
+    );
+    response
+}
@@ -0,0 +1,190 @@
+//! Per-operation context handed to every `NoteDb` call, so that backends
+//! can correlate logs, respect deadlines and scope data without relying on
+//! thread-locals.
+
+use std::{future::Future, time::Duration};
+
+use axum::{extract::FromRequestParts, http::request::Parts};
+use tokio::time::Instant;
+
+use crate::auth::UserContext;
+use crate::consistency::Consistency;
+use crate::metrics::CANCELLED_DB_OPERATIONS;
+use crate::notes::{Note, NoteDbError};
+use crate::tenancy::Tenant;
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+#[derive(Debug, Clone, Default)]
+pub struct OpContext {
+    /// Correlation id of the request the operation serves.
+    pub request_id: Option<String>,
+    /// The authenticated caller, if any.
+    pub principal: Option<String>,
+    /// Organizations of the caller, whose notes are in scope as well.
+    pub orgs: Vec<String>,
+    /// The tenant the operation is scoped to. Only set when the deployment
+    /// serves several tenants.
+    pub tenant: Option<String>,
+    /// Point in time after which the result is of no use to the caller.
+    pub deadline: Option<Instant>,
+    /// Read consistency the caller asked for, `None` for the default of
+    /// the backend.
+    pub consistency: Option<Consistency>,
+}
+
+impl OpContext {
+    /// Context of work not triggered by a request, like background jobs
+    /// and command line tools.
+    pub fn background() -> OpContext {
+        OpContext::default()
+    }
+
+    /// Context of background work on the notes of `tenant`, like writes
+    /// queued by requests of that tenant.
+    pub fn for_tenant(tenant: Option<String>) -> OpContext {
+        OpContext {
+            tenant,
+            ..OpContext::background()
+        }
+    }
+
+    /// The user whose notes the operation is scoped to. Without one, like
+    /// for background jobs or without authentication, all notes are in
+    /// scope.
+    pub fn owner(&self) -> Option<&str> {
+        self.principal.as_deref()
+    }
+
+    /// Whether `note` is in the scope of the operation, which includes
+    /// notes shared with the owner for reading.
+    pub fn can_access(&self, note: &Note) -> bool {
+        self.owner().is_none_or(|owner| {
+            self.can_manage(note) || note.permissions.can_read(owner)
+        }) && self.in_tenant(note)
+    }
+
+    /// Whether the operation may change the content of `note`.
+    pub fn can_edit(&self, note: &Note) -> bool {
+        self.owner().is_none_or(|owner| {
+            self.can_manage(note) || note.permissions.can_write(owner)
+        }) && self.in_tenant(note)
+    }
+
+    /// Whether the operation may delete, share or grant access to `note`,
+    /// which only its owner and the members of its organization can.
+    pub fn can_manage(&self, note: &Note) -> bool {
+        self.owner().is_none_or(|owner| {
+            note.owner.as_deref() == Some(owner)
+                || note.org.as_ref().is_some_and(|org| self.orgs.contains(org))
+        }) && self.in_tenant(note)
+    }
+
+    fn in_tenant(&self, note: &Note) -> bool {
+        self.tenant
+            .as_deref()
+            .is_none_or(|tenant| note.tenant.as_deref() == Some(tenant))
+    }
+
+    /// Time left until the deadline, zero once it has passed.
+    pub fn remaining(&self) -> Option<Duration> {
+        self.deadline
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()))
+    }
+
+    /// Runs a database operation until the deadline passes, then drops it
+    /// and fails with `NoteDbError::DeadlineExceeded`. Operations that are
+    /// already past their deadline are not started at all.
+    pub async fn within_deadline<T, F>(
+        &self,
+        operation: F,
+    ) -> Result<T, NoteDbError>
+    where
+        F: Future<Output = Result<T, NoteDbError>>,
+    {
+        let result = match self.deadline {
+            Some(deadline) if deadline <= Instant::now() => {
+                Err(NoteDbError::DeadlineExceeded)
+            }
+            Some(deadline) => tokio::time::timeout_at(deadline, operation)
+                .await
+                .unwrap_or(Err(NoteDbError::DeadlineExceeded)),
+            None => operation.await,
+        };
+        if let Err(NoteDbError::DeadlineExceeded) = result {
+            CANCELLED_DB_OPERATIONS.increment();
+        }
+        result
+    }
+}
+
+/// Deadline of a request, set by the route timeout middleware.
+#[derive(Debug, Clone, Copy)]
+pub struct Deadline(pub Instant);
+
+impl<S: Send + Sync> FromRequestParts<S> for OpContext {
+    type Rejection = std::convert::Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        _state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        Ok(OpContext {
+            request_id: parts
+                .headers
+                .get(REQUEST_ID_HEADER)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string),
+            principal: parts
+                .extensions
+                .get::<UserContext>()
+                .map(|user| user.subject.clone()),
+            orgs: parts
+                .extensions
+                .get::<UserContext>()
+                .map(|user| user.orgs.clone())
+                .unwrap_or_default(),
+            deadline: parts
+                .extensions
+                .get::<Deadline>()
+                .map(|deadline| deadline.0),
+            consistency: parts.extensions.get::<Consistency>().copied(),
+            tenant: parts
+                .extensions
+                .get::<Tenant>()
+                .map(|tenant| tenant.0.clone()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn it_gives_up_on_operations_past_their_deadline() {
+        // Setup
+        let ctx = OpContext {
+            deadline: Some(Instant::now() + Duration::from_millis(10)),
+            ..OpContext::background()
+        };
+        let cancelled = CANCELLED_DB_OPERATIONS.get();
+
+        // Execute
+        let fast = ctx.within_deadline(async { Ok(1) }).await;
+        let slow = ctx
+            .within_deadline(async {
+                tokio::time::sleep(Duration::from_secs(5)).await;
+                Ok(())
+            })
+            .await;
+        let late = ctx.within_deadline(async { Ok(2) }).await;
+
+        // Assert
+        assert!(matches!(slow, Err(NoteDbError::DeadlineExceeded)));
+        assert!(matches!(fast, Ok(1)));
+        assert!(matches!(late, Err(NoteDbError::DeadlineExceeded)));
+        assert!(CANCELLED_DB_OPERATIONS.get() >= cancelled + 2);
+        assert_eq!(ctx.remaining(), Some(Duration::ZERO));
+    }
+}
@@ -0,0 +1,110 @@
+//! CSV export and import of notes, for round trips through spreadsheets.
+
+use serde::Deserialize;
+use utoipa::IntoParams;
+
+use crate::notes::{NewNote, Note};
+
+const HEADER: [&str; 6] =
+    ["id", "title", "body", "tags", "created_at", "updated_at"];
+/// Tags share one cell, separated by this.
+const TAG_SEPARATOR: char = ';';
+
+pub fn write_notes(notes: &[Note]) -> Result<Vec<u8>, csv::Error> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.write_record(HEADER)?;
+    for note in notes {
+        writer.write_record([
+            note.id.as_str(),
+            &note.title,
+            &note.body,
+            &note.tags.join(&TAG_SEPARATOR.to_string()),
+            &note.created_at.to_rfc3339(),
+            &note.updated_at.to_rfc3339(),
+        ])?;
+    }
+    writer.into_inner().map_err(|err| err.into_error().into())
+}
+
+/// Names of the CSV columns holding the note fields. Columns not mapped
+/// here, like `id` and the timestamps, are ignored on import.
+#[derive(Debug, Clone, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ColumnMapping {
+    #[serde(default = "default_title_column")]
+    pub title: String,
+    #[serde(default = "default_body_column")]
+    pub body: String,
+    #[serde(default = "default_tags_column")]
+    pub tags: String,
+}
+
+fn default_title_column() -> String {
+    "title".to_string()
+}
+
+fn default_body_column() -> String {
+    "body".to_string()
+}
+
+fn default_tags_column() -> String {
+    "tags".to_string()
+}
+
+impl Default for ColumnMapping {
+    fn default() -> Self {
+        ColumnMapping {
+            title: default_title_column(),
+            body: default_body_column(),
+            tags: default_tags_column(),
+        }
+    }
+}
+
+/// Reads new notes from CSV with a header row. Fails as a whole if the
+/// title or body column is missing, otherwise each row is parsed on its
+/// own.
+pub fn read_notes(
+    data: &[u8],
+    mapping: &ColumnMapping,
+) -> Result<Vec<Result<NewNote, String>>, String> {
+    let mut reader = csv::Reader::from_reader(data);
+    let headers = reader.headers().map_err(|err| err.to_string())?.clone();
+    let column = |name: &str| headers.iter().position(|header| header == name);
+    let Some(title) = column(&mapping.title) else {
+        return Err(format!("missing title column {:?}", mapping.title));
+    };
+    let Some(body) = column(&mapping.body) else {
+        return Err(format!("missing body column {:?}", mapping.body));
+    };
+    let tags = column(&mapping.tags);
+
+    let rows = reader
+        .records()
+        .map(|record| {
+            let record = record.map_err(|err| err.to_string())?;
+            let field = |index: usize| {
+                record
+                    .get(index)
+                    .map(str::to_string)
+                    .ok_or_else(|| format!("missing field {}", index))
+            };
+            let tags = match tags.and_then(|index| record.get(index)) {
+                Some(tags) => tags
+                    .split(TAG_SEPARATOR)
+                    .map(str::trim)
+                    .filter(|tag| !tag.is_empty())
+                    .map(str::to_string)
+                    .collect(),
+                None => Vec::new(),
+            };
+            Ok(NewNote {
+                title: field(title)?,
+                body: field(body)?,
+                tags,
+                org: None,
+            })
+        })
+        .collect();
+    Ok(rows)
+}
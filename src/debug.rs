@@ -0,0 +1,136 @@
+//! Endpoints for diagnosing performance in production: CPU profiles in the
+//! pprof format and metrics of the tokio runtime. Only built with the
+//! `pprof` feature.
+
+use std::time::Duration;
+
+use axum::{
+    extract::Query,
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use pprof::protos::Message;
+use serde::{Deserialize, Serialize};
+
+use crate::problem::Problem;
+
+const DEFAULT_PROFILE_SECS: u64 = 30;
+const MAX_PROFILE_SECS: u64 = 300;
+// Samples per second, as used by Go's pprof.
+const PROFILE_FREQUENCY: i32 = 100;
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+pub struct ProfileQuery {
+    /// How long to sample for, 30 seconds by default.
+    pub seconds: Option<u64>,
+}
+
+/// Samples the CPU usage of the whole process for the requested duration
+/// and returns it as a protobuf encoded profile, which `go tool pprof`
+/// reads. Only one profile can be captured at a time.
+pub async fn get_pprof(
+    Query(query): Query<ProfileQuery>,
+) -> Result<Response, Response> {
+    let seconds = query.seconds.unwrap_or(DEFAULT_PROFILE_SECS);
+    if seconds == 0 || seconds > MAX_PROFILE_SECS {
+        return Err(Problem::new(
+            StatusCode::BAD_REQUEST,
+            format!("seconds must be between 1 and {}", MAX_PROFILE_SECS),
+        )
+        .into_response());
+    }
+    let guard = pprof::ProfilerGuardBuilder::default()
+        .frequency(PROFILE_FREQUENCY)
+        .blocklist(&["libc", "libgcc", "pthread", "vdso"])
+        .build()
+        .map_err(|err| {
+            tracing::warn!("unable to start profiler: {}", err);
+            Problem::new(StatusCode::CONFLICT, "a profile is already running")
+                .into_response()
+        })?;
+    tracing::info!("capture cpu profile for {}s", seconds);
+    tokio::time::sleep(Duration::from_secs(seconds)).await;
+    let profile = guard
+        .report()
+        .build()
+        .and_then(|report| report.pprof())
+        .map_err(|err| {
+            tracing::error!("unable to build cpu profile: {}", err);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        })?;
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/octet-stream"),
+            (
+                header::CONTENT_DISPOSITION,
+                "attachment; filename=\"profile.pb\"",
+            ),
+        ],
+        profile.encode_to_vec(),
+    )
+        .into_response())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct RuntimeMetrics {
+    pub workers: usize,
+    pub alive_tasks: usize,
+    /// Tasks waiting in the queue shared by all workers.
+    pub global_queue_depth: usize,
+    /// Threads of the blocking pool, busy or idle. Tokio only reports the
+    /// blocking pool when built with `--cfg tokio_unstable`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub blocking_threads: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub idle_blocking_threads: Option<usize>,
+    /// Blocking tasks waiting for a thread.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub blocking_queue_depth: Option<usize>,
+}
+
+impl RuntimeMetrics {
+    /// Metrics of the runtime the caller runs on.
+    pub fn current() -> RuntimeMetrics {
+        let metrics = tokio::runtime::Handle::current().metrics();
+        #[cfg(tokio_unstable)]
+        let blocking = (
+            Some(metrics.num_blocking_threads()),
+            Some(metrics.num_idle_blocking_threads()),
+            Some(metrics.blocking_queue_depth()),
+        );
+        #[cfg(not(tokio_unstable))]
+        let blocking = (None, None, None);
+        RuntimeMetrics {
+            workers: metrics.num_workers(),
+            alive_tasks: metrics.num_alive_tasks(),
+            global_queue_depth: metrics.global_queue_depth(),
+            blocking_threads: blocking.0,
+            idle_blocking_threads: blocking.1,
+            blocking_queue_depth: blocking.2,
+        }
+    }
+}
+
+pub async fn get_runtime_metrics() -> Json<RuntimeMetrics> {
+    Json(RuntimeMetrics::current())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn it_reports_runtime_metrics() {
+        // Setup
+        let task = tokio::spawn(std::future::pending::<()>());
+
+        // Execute
+        let Json(metrics) = get_runtime_metrics().await;
+
+        // Assert
+        assert_eq!(metrics.workers, 2);
+        assert!(metrics.alive_tasks >= 1);
+        task.abort();
+    }
+}
@@ -0,0 +1,113 @@
+use std::{
+    collections::HashMap,
+    hash::{DefaultHasher, Hash, Hasher},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    body::Body,
+    extract::{Request, State},
+    http::{header, Method, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    Router,
+};
+use http_body_util::BodyExt;
+
+use crate::api_keys::API_KEY_HEADER;
+use crate::auth::UserContext;
+use crate::tenancy::Tenant;
+
+/// Rejects byte-identical POSTs from the same caller that arrive within a
+/// short window, which protects against double-submitting UIs. Unlike
+/// idempotency keys, this needs no cooperation from the client. Goes inside
+/// the auth layer, so that the caller is the authenticated principal.
+pub struct DedupeWindow {
+    window: Duration,
+    seen: Mutex<HashMap<u64, Instant>>,
+}
+
+impl DedupeWindow {
+    pub fn new(window: Duration) -> Self {
+        DedupeWindow {
+            window,
+            seen: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn apply<S>(self, router: Router<S>) -> Router<S>
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        router.layer(middleware::from_fn_with_state(Arc::new(self), dedupe))
+    }
+
+    /// Records the fingerprint and returns false if it was already seen
+    /// within the window.
+    fn check_and_record(&self, fingerprint: u64, now: Instant) -> bool {
+        let mut seen = self.seen.lock().unwrap();
+        seen.retain(|_, at| now.duration_since(*at) < self.window);
+        if seen.contains_key(&fingerprint) {
+            return false;
+        }
+        seen.insert(fingerprint, now);
+        true
+    }
+
+    fn forget(&self, fingerprint: u64) {
+        self.seen.lock().unwrap().remove(&fingerprint);
+    }
+}
+
+async fn dedupe(
+    State(window): State<Arc<DedupeWindow>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if request.method() != Method::POST {
+        return next.run(request).await;
+    }
+
+    let (parts, body) = request.into_parts();
+    let Ok(body) = body.collect().await else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+    let body = body.to_bytes();
+
+    // Without authentication the caller is identified by whatever
+    // credentials it sends, if any.
+    let mut hasher = DefaultHasher::new();
+    match parts.extensions.get::<UserContext>() {
+        Some(user) => user.subject.hash(&mut hasher),
+        None => {
+            for name in [header::AUTHORIZATION.as_str(), API_KEY_HEADER] {
+                parts
+                    .headers
+                    .get(name)
+                    .map(|value| value.as_bytes())
+                    .hash(&mut hasher);
+            }
+        }
+    }
+    parts
+        .extensions
+        .get::<Tenant>()
+        .map(|tenant| tenant.0.as_str())
+        .hash(&mut hasher);
+    parts.uri.path().hash(&mut hasher);
+    body.hash(&mut hasher);
+    let fingerprint = hasher.finish();
+
+    if !window.check_and_record(fingerprint, Instant::now()) {
+        tracing::warn!("reject duplicate submission to {}", parts.uri.path());
+        return StatusCode::CONFLICT.into_response();
+    }
+
+    let response = next.run(Request::from_parts(parts, Body::from(body))).await;
+    // Only successful submissions block a retry.
+    if !response.status().is_success() {
+        window.forget(fingerprint);
+    }
+    response
+}
@@ -0,0 +1,96 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{FromRequest, Request},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::de::DeserializeOwned;
+use serde_json::json;
+
+use crate::notes::NoteDb;
+use crate::validation::Validate;
+use crate::AppState;
+
+/// JSON body extractor for request DTOs. Unknown fields are ignored unless
+/// `AppState::strict_json` is set, in which case the request is rejected
+/// with 400 and the offending field names.
+pub struct RequestJson<T>(pub T);
+
+impl<T, D> FromRequest<Arc<AppState<D>>> for RequestJson<T>
+where
+    T: DeserializeOwned,
+    D: NoteDb + ?Sized,
+{
+    type Rejection = Response;
+
+    async fn from_request(
+        req: Request,
+        state: &Arc<AppState<D>>,
+    ) -> Result<Self, Self::Rejection> {
+        let Json(value) = Json::<serde_json::Value>::from_request(req, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+
+        let mut unknown_fields = Vec::new();
+        let parsed = serde_ignored::deserialize(value, |path| {
+            unknown_fields.push(path.to_string())
+        });
+        let parsed = match parsed {
+            Ok(parsed) => parsed,
+            Err(err) => {
+                tracing::debug!("unable to deserialize request body: {}", err);
+                return Err((
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    Json(json!({ "error": err.to_string() })),
+                )
+                    .into_response());
+            }
+        };
+
+        if !unknown_fields.is_empty() {
+            if state.strict_json {
+                tracing::warn!("unknown request fields {:?}", unknown_fields);
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    Json(json!({
+                        "error": "unknown fields",
+                        "fields": unknown_fields,
+                    })),
+                )
+                    .into_response());
+            }
+            tracing::debug!(
+                "ignore unknown request fields {:?}",
+                unknown_fields
+            );
+        }
+        Ok(RequestJson(parsed))
+    }
+}
+
+/// `RequestJson` that is additionally checked against
+/// `AppState::note_limits`, rejecting invalid input with 422.
+pub struct ValidJson<T>(pub T);
+
+impl<T, D> FromRequest<Arc<AppState<D>>> for ValidJson<T>
+where
+    T: DeserializeOwned + Validate,
+    D: NoteDb + ?Sized,
+{
+    type Rejection = Response;
+
+    async fn from_request(
+        req: Request,
+        state: &Arc<AppState<D>>,
+    ) -> Result<Self, Self::Rejection> {
+        let RequestJson(value) =
+            RequestJson::<T>::from_request(req, state).await?;
+        if let Err(errors) = value.validate(&state.note_limits) {
+            tracing::debug!("invalid request body: {}", errors);
+            return Err(errors.into_response());
+        }
+        Ok(ValidJson(value))
+    }
+}
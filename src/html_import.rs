@@ -0,0 +1,323 @@
+//! Import of HTML note exports, like the ones of Apple Notes, as a zip of
+//! HTML files.
+//!
+//! Scripts, styles and script links are dropped before the HTML is
+//! converted to Markdown. Notes have no attachments, so inline images are
+//! removed and reported as unmapped, like every file that is no HTML page.
+//! A `created` meta tag sets the creation time of the note.
+
+use std::io::Cursor;
+
+use chrono::{DateTime, Utc};
+
+use crate::import::{ArchiveLimits, ArchiveReader};
+use crate::notes::{NewNote, Note};
+
+const CREATED_META_NAMES: [&str; 3] = ["created", "creation-date", "date"];
+const REMOVED_ELEMENTS: [&str; 2] = ["script", "style"];
+const UNSAFE_SCHEMES: [&str; 3] = ["javascript:", "vbscript:", "data:"];
+
+struct Page {
+    path: String,
+    title: String,
+    html: String,
+    created_at: Option<DateTime<Utc>>,
+}
+
+pub struct HtmlExport {
+    pages: Vec<Page>,
+    unmapped: Vec<String>,
+}
+
+impl HtmlExport {
+    pub fn read(
+        data: &[u8],
+        limits: ArchiveLimits,
+    ) -> Result<HtmlExport, zip::result::ZipError> {
+        let mut archive = zip::ZipArchive::new(Cursor::new(data))?;
+        let mut reader = ArchiveReader::new(limits);
+        let mut export = HtmlExport {
+            pages: Vec::new(),
+            unmapped: Vec::new(),
+        };
+        for index in 0..archive.len() {
+            let mut file = archive.by_index(index)?;
+            if file.is_dir() {
+                continue;
+            }
+            let path = file.name().to_string();
+            if !path.ends_with(".html") && !path.ends_with(".htm") {
+                export.unmapped.push(path);
+                continue;
+            }
+            match reader.read_text(&mut file)? {
+                Ok(html) => export.pages.push(Page::new(path, html)),
+                Err(reason) => {
+                    export.unmapped.push(format!("{} ({})", path, reason))
+                }
+            }
+        }
+        Ok(export)
+    }
+
+    pub fn into_notes(self, notes_path: &str) -> (Vec<Note>, Vec<String>) {
+        let HtmlExport {
+            pages,
+            mut unmapped,
+        } = self;
+        let notes = pages
+            .into_iter()
+            .map(|page| {
+                let body = page.markdown(&mut unmapped);
+                let mut note = NewNote {
+                    title: page.title,
+                    body,
+                    tags: Vec::new(),
+                    org: None,
+                }
+                .into_note(notes_path);
+                if let Some(created_at) = page.created_at {
+                    note.created_at = created_at;
+                }
+                note
+            })
+            .collect();
+        (notes, unmapped)
+    }
+}
+
+impl Page {
+    fn new(path: String, html: String) -> Page {
+        let file = path.rsplit('/').next().unwrap_or(&path);
+        let stem = file.rsplit_once('.').map_or(file, |(stem, _)| stem);
+        let title = element_text(&html, "title")
+            .filter(|title| !title.is_empty())
+            .unwrap_or_else(|| stem.to_string());
+        let created_at = CREATED_META_NAMES
+            .iter()
+            .filter_map(|name| meta_content(&html, name))
+            .find_map(|content| parse_time(&content));
+        Page {
+            path,
+            title,
+            html,
+            created_at,
+        }
+    }
+
+    fn markdown(&self, unmapped: &mut Vec<String>) -> String {
+        let mut body = element_inner(&self.html, "body")
+            .unwrap_or(&self.html)
+            .to_string();
+        for element in REMOVED_ELEMENTS {
+            body = remove_elements(&body, element);
+        }
+        let markdown = html2md::parse_html(&body);
+        let markdown = remove_images(&markdown, &self.path, unmapped);
+        neutralize_links(&markdown).trim().to_string()
+    }
+}
+
+fn parse_time(value: &str) -> Option<DateTime<Utc>> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Some(time.to_utc());
+    }
+    DateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S %z")
+        .ok()
+        .map(|time| time.to_utc())
+}
+
+/// Finds `needle` case-insensitively. Lowercasing ASCII keeps byte
+/// offsets, so the result indexes into `haystack`.
+fn find_ignore_case(
+    haystack: &str,
+    needle: &str,
+    from: usize,
+) -> Option<usize> {
+    haystack
+        .get(from..)?
+        .to_ascii_lowercase()
+        .find(needle)
+        .map(|index| from + index)
+}
+
+/// The content between the opening and closing tag of the first element.
+fn element_inner<'a>(html: &'a str, name: &str) -> Option<&'a str> {
+    let open = find_ignore_case(html, &format!("<{}", name), 0)?;
+    let start = open + html[open..].find('>')? + 1;
+    let end = find_ignore_case(html, &format!("</{}", name), start)?;
+    Some(&html[start..end])
+}
+
+fn element_text(html: &str, name: &str) -> Option<String> {
+    Some(element_inner(html, name)?.trim().to_string())
+}
+
+fn remove_elements(html: &str, name: &str) -> String {
+    let mut out = String::with_capacity(html.len());
+    let mut rest = html;
+    while let Some(open) = find_ignore_case(rest, &format!("<{}", name), 0) {
+        out.push_str(&rest[..open]);
+        let close = format!("</{}>", name);
+        rest = match find_ignore_case(rest, &close, open) {
+            Some(end) => &rest[end + close.len()..],
+            None => "",
+        };
+    }
+    out.push_str(rest);
+    out
+}
+
+/// The `content` of `<meta name="{name}" content="...">`.
+fn meta_content(html: &str, name: &str) -> Option<String> {
+    let mut from = 0;
+    while let Some(start) = find_ignore_case(html, "<meta", from) {
+        let end = start + html[start..].find('>')?;
+        let tag = &html[start..end];
+        from = end;
+        if attribute(tag, "name")?.eq_ignore_ascii_case(name) {
+            return attribute(tag, "content");
+        }
+    }
+    None
+}
+
+fn attribute(tag: &str, name: &str) -> Option<String> {
+    let start = find_ignore_case(tag, &format!("{}=", name), 0)? + name.len();
+    let value = &tag[start + 1..];
+    let quote = value.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+    let value = &value[1..];
+    Some(value[..value.find(quote)?].to_string())
+}
+
+fn remove_images(
+    markdown: &str,
+    path: &str,
+    unmapped: &mut Vec<String>,
+) -> String {
+    let mut out = String::with_capacity(markdown.len());
+    let mut rest = markdown;
+    while let Some(start) = rest.find("![") {
+        let image = &rest[start..];
+        let Some(end) = image
+            .find("](")
+            .and_then(|mid| Some(mid + 2 + target_end(&image[mid + 2..])? + 1))
+        else {
+            break;
+        };
+        out.push_str(&rest[..start]);
+        unmapped.push(format!("{}: inline image", path));
+        rest = &image[end..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Index of the parenthesis closing a link target, which may contain
+/// balanced parentheses itself.
+fn target_end(target: &str) -> Option<usize> {
+    let mut depth = 0;
+    for (index, c) in target.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' if depth == 0 => return Some(index),
+            ')' => depth -= 1,
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Points links with script or data URLs nowhere.
+fn neutralize_links(markdown: &str) -> String {
+    let mut out = String::with_capacity(markdown.len());
+    let mut rest = markdown;
+    while let Some(start) = rest.find("](") {
+        out.push_str(&rest[..start + 2]);
+        rest = &rest[start + 2..];
+        let target = rest.trim_start().to_ascii_lowercase();
+        if UNSAFE_SCHEMES
+            .iter()
+            .any(|scheme| target.starts_with(scheme))
+        {
+            out.push('#');
+            rest = target_end(rest).map_or("", |end| &rest[end..]);
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    #[test]
+    fn it_sanitizes_and_converts_html_pages() {
+        // Setup
+        let html = r#"<html><head>
+            <meta name="created" content="2021-01-02T03:04:05Z">
+            <title>Groceries</title><style>p { color: red }</style>
+            </head><body><p>Buy <b>milk</b></p><script>alert(1)</script>
+            <p><a href="javascript:alert(1)">click</a></p>
+            <img src="data:image/png;base64,AAAA"></body></html>"#;
+        let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        let options = zip::write::SimpleFileOptions::default();
+        zip.start_file("Groceries.html", options).unwrap();
+        zip.write_all(html.as_bytes()).unwrap();
+        zip.start_file("photo.jpg", options).unwrap();
+        let data = zip.finish().unwrap().into_inner();
+
+        // Execute
+        let (notes, unmapped) =
+            HtmlExport::read(&data, ArchiveLimits::default())
+                .unwrap()
+                .into_notes("/notes");
+
+        // Assert
+        assert_eq!(notes.len(), 1);
+        assert_eq!(notes[0].title, "Groceries");
+        assert_eq!(notes[0].body, "Buy **milk**\n\n[click](#)");
+        assert_eq!(
+            notes[0].created_at,
+            "2021-01-02T03:04:05Z".parse::<DateTime<Utc>>().unwrap()
+        );
+        assert_eq!(unmapped, vec!["photo.jpg", "Groceries.html: inline image"]);
+    }
+
+    #[test]
+    fn it_limits_the_decompressed_size() {
+        // Setup
+        let limits = ArchiveLimits {
+            max_entry_bytes: 100,
+            max_total_bytes: 150,
+        };
+        let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        let options = zip::write::SimpleFileOptions::default();
+        zip.start_file("Large.html", options).unwrap();
+        zip.write_all("a".repeat(101).as_bytes()).unwrap();
+        zip.start_file("Small.html", options).unwrap();
+        zip.write_all(b"<title>Small</title>").unwrap();
+        let data = zip.finish().unwrap().into_inner();
+        let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        for name in ["a.html", "b.html"] {
+            zip.start_file(name, options).unwrap();
+            zip.write_all("b".repeat(100).as_bytes()).unwrap();
+        }
+        let too_much = zip.finish().unwrap().into_inner();
+
+        // Execute
+        let (notes, unmapped) = HtmlExport::read(&data, limits)
+            .unwrap()
+            .into_notes("/notes");
+        let refused = HtmlExport::read(&too_much, limits);
+
+        // Assert
+        assert_eq!(notes.len(), 1);
+        assert_eq!(notes[0].title, "Small");
+        assert_eq!(unmapped, vec!["Large.html (too large)"]);
+        assert!(refused.is_err());
+    }
+}
@@ -0,0 +1,100 @@
+//! Idempotency keys for creating notes. Clients that retry a create after
+//! a timeout send the same `Idempotency-Key` and get the note of the first
+//! attempt back instead of a duplicate.
+
+use std::time::Duration;
+
+use async_trait::async_trait;
+use axum::{
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::notes::NoteDbError;
+use crate::problem::Problem;
+
+pub const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+/// Set on responses that replay the note created by an earlier request.
+pub const REPLAYED_HEADER: &str = "idempotent-replayed";
+/// How long a key is remembered unless configured otherwise.
+pub const DEFAULT_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+const MAX_KEY_LEN: usize = 255;
+
+/// The note created for an idempotency key.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IdempotencyRecord {
+    pub key: String,
+    pub note_id: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl IdempotencyRecord {
+    pub fn new(
+        key: &str,
+        note_id: &str,
+        now: DateTime<Utc>,
+        ttl: Duration,
+    ) -> IdempotencyRecord {
+        IdempotencyRecord {
+            key: key.to_string(),
+            note_id: note_id.to_string(),
+            expires_at: chrono::Duration::from_std(ttl)
+                .ok()
+                .and_then(|ttl| now.checked_add_signed(ttl))
+                .unwrap_or(DateTime::<Utc>::MAX_UTC),
+        }
+    }
+}
+
+#[async_trait]
+pub trait IdempotencyDb: Send + Sync {
+    /// Remembers the note created for the key, replacing an earlier record
+    /// of the key.
+    async fn record_key(
+        &self,
+        record: &IdempotencyRecord,
+    ) -> Result<(), NoteDbError>;
+
+    /// The id of the note created for the key, unless the record expired
+    /// at `now`.
+    async fn find_key(
+        &self,
+        key: &str,
+        now: DateTime<Utc>,
+    ) -> Result<Option<String>, NoteDbError>;
+}
+
+/// An `Idempotency-Key` header the service does not accept.
+#[derive(Debug)]
+pub struct InvalidKey;
+
+impl IntoResponse for InvalidKey {
+    fn into_response(self) -> Response {
+        Problem::new(
+            StatusCode::BAD_REQUEST,
+            format!(
+                "{} must be 1 to {} visible ASCII characters",
+                IDEMPOTENCY_KEY_HEADER, MAX_KEY_LEN
+            ),
+        )
+        .into_response()
+    }
+}
+
+/// The idempotency key of a request, if it sent one. Keys are opaque to
+/// the service but must be visible ASCII of at most 255 characters.
+pub fn idempotency_key(
+    headers: &HeaderMap,
+) -> Result<Option<String>, InvalidKey> {
+    let Some(value) = headers.get(IDEMPOTENCY_KEY_HEADER) else {
+        return Ok(None);
+    };
+    match value.to_str() {
+        Ok(key) if !key.is_empty() && key.len() <= MAX_KEY_LEN => {
+            Ok(Some(key.to_string()))
+        }
+        _ => Err(InvalidKey),
+    }
+}
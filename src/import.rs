@@ -0,0 +1,505 @@
+//! Bulk import of notes, answered with a report on every item.
+
+use std::io::Read;
+
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+use zip::{read::ZipFile, result::ZipError};
+
+use crate::context::OpContext;
+use crate::notes::{Note, NoteDb, NoteDbError, PatchNote};
+use crate::validation::{NoteLimits, Validate};
+
+/// What to do when an item can't be imported.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum OnError {
+    /// Import nothing unless every item is valid.
+    #[default]
+    Abort,
+    /// Import every item that can be imported.
+    Continue,
+}
+
+/// What to do with an item whose title a note in the database already
+/// has, compared case-insensitively.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum OnConflict {
+    /// Keep the existing note and leave the item out.
+    Skip,
+    /// Replace title, body and tags of the existing note.
+    Overwrite,
+    /// Import the item as another note with the same title.
+    #[default]
+    Duplicate,
+    /// Append the body of the item to the existing note and add its tags.
+    /// Items that would make the note exceed the note limits fail.
+    Merge,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ImportQuery {
+    #[serde(default)]
+    pub on_error: OnError,
+    #[serde(default)]
+    pub on_conflict: OnConflict,
+}
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum ItemStatus {
+    Created,
+    Failed,
+    /// Valid, but not imported because another item failed.
+    Skipped,
+    /// Not imported because a note with the same title exists.
+    Ignored,
+    /// Replaced the note with the same title.
+    Overwritten,
+    /// Merged into the note with the same title.
+    Merged,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct ItemReport {
+    /// Position of the item in the request.
+    pub index: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    pub status: ItemStatus,
+    /// Existing note with the same title as the item.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub conflict: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct ImportReport {
+    pub created: usize,
+    /// Existing notes overwritten or merged into.
+    pub updated: usize,
+    pub failed: usize,
+    pub items: Vec<ItemReport>,
+}
+
+impl ImportReport {
+    fn new(items: Vec<ItemReport>) -> ImportReport {
+        let count =
+            |status| items.iter().filter(|i| i.status == status).count();
+        ImportReport {
+            created: count(ItemStatus::Created),
+            updated: count(ItemStatus::Overwritten) + count(ItemStatus::Merged),
+            failed: count(ItemStatus::Failed),
+            items,
+        }
+    }
+}
+
+/// Report of importing an archive of another note tool.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct ArchiveImportReport {
+    #[serde(flatten)]
+    pub import: ImportReport,
+    /// Content of the archive that was not imported.
+    pub unmapped: Vec<String>,
+}
+
+/// Limits on the decompressed size of uploaded archives. A small upload
+/// can inflate to gigabytes, which the request size limit doesn't cover.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ArchiveLimits {
+    /// Larger entries are left out and reported as unmapped.
+    pub max_entry_bytes: u64,
+    /// Archives inflating to more are refused as a whole.
+    pub max_total_bytes: u64,
+}
+
+impl Default for ArchiveLimits {
+    fn default() -> Self {
+        ArchiveLimits {
+            max_entry_bytes: 8 * 1024 * 1024,
+            max_total_bytes: 64 * 1024 * 1024,
+        }
+    }
+}
+
+/// Reads entries of a zip archive as text within `ArchiveLimits`, keeping
+/// count of what was decompressed so far.
+pub struct ArchiveReader {
+    limits: ArchiveLimits,
+    total: u64,
+}
+
+impl ArchiveReader {
+    pub fn new(limits: ArchiveLimits) -> ArchiveReader {
+        ArchiveReader { limits, total: 0 }
+    }
+
+    /// Reads `file` as text, or returns why it can't be imported. Fails if
+    /// the archive can't be read or inflates past the total limit.
+    pub fn read_text(
+        &mut self,
+        file: &mut ZipFile<'_>,
+    ) -> Result<Result<String, &'static str>, ZipError> {
+        const TOO_LARGE: &str = "archive is too large when decompressed";
+        let max_entry = self.limits.max_entry_bytes;
+        // The size in the archive may be forged, reading is capped as well.
+        if file.size() > max_entry {
+            return Ok(Err("too large"));
+        }
+        if self.total + file.size() > self.limits.max_total_bytes {
+            return Err(ZipError::UnsupportedArchive(TOO_LARGE));
+        }
+        let mut data = Vec::new();
+        file.by_ref().take(max_entry + 1).read_to_end(&mut data)?;
+        self.total += data.len() as u64;
+        if self.total > self.limits.max_total_bytes {
+            return Err(ZipError::UnsupportedArchive(TOO_LARGE));
+        }
+        if data.len() as u64 > max_entry {
+            return Ok(Err("too large"));
+        }
+        Ok(String::from_utf8(data).map_err(|_| "not UTF-8"))
+    }
+}
+
+/// How an item ends up in the database after checking it for conflicts.
+enum Write {
+    Create(Note),
+    Update {
+        existing: Note,
+        patch: PatchNote,
+        status: ItemStatus,
+    },
+    Ignore(String),
+    /// The update of the existing note would break the note limits, e.g.
+    /// a merge growing the body past the largest allowed.
+    Invalid {
+        existing: String,
+        error: String,
+    },
+}
+
+impl Write {
+    fn conflict(&self) -> Option<String> {
+        match self {
+            Write::Create(_) => None,
+            Write::Update { existing, .. } => Some(existing.id.clone()),
+            Write::Ignore(id) => Some(id.clone()),
+            Write::Invalid { existing, .. } => Some(existing.clone()),
+        }
+    }
+}
+
+/// Looks for a note with the title of `note`. Items are only checked
+/// against the database, not against each other, and not at all when
+/// duplicates are allowed.
+async fn resolve<D: NoteDb + ?Sized>(
+    ctx: &OpContext,
+    db: &D,
+    note: Note,
+    on_conflict: OnConflict,
+    limits: &NoteLimits,
+) -> Result<Write, NoteDbError> {
+    if on_conflict == OnConflict::Duplicate {
+        return Ok(Write::Create(note));
+    }
+    let Some(existing) = db.find_by_title(ctx, &note.title).await?.pop() else {
+        return Ok(Write::Create(note));
+    };
+    if on_conflict == OnConflict::Skip {
+        return Ok(Write::Ignore(existing.id));
+    }
+    let Some(existing) = db.get_note(ctx, &existing.id).await? else {
+        return Ok(Write::Create(note));
+    };
+    // Notes shared with the caller read-only are left alone.
+    if !ctx.can_edit(&existing) {
+        return Ok(Write::Ignore(existing.id));
+    }
+    let (patch, status) = if on_conflict == OnConflict::Merge {
+        let mut tags = existing.tags.clone();
+        for tag in note.tags {
+            if !tags.contains(&tag) {
+                tags.push(tag);
+            }
+        }
+        let body = format!("{}\n\n{}", existing.body, note.body);
+        (
+            PatchNote {
+                title: None,
+                body: Some(body),
+                tags: Some(tags),
+                updated_at: note.updated_at,
+                version: Some(existing.version),
+                permissions: None,
+            },
+            ItemStatus::Merged,
+        )
+    } else {
+        (
+            PatchNote {
+                title: Some(note.title),
+                body: Some(note.body),
+                tags: Some(note.tags),
+                updated_at: note.updated_at,
+                version: Some(existing.version),
+                permissions: None,
+            },
+            ItemStatus::Overwritten,
+        )
+    };
+    if let Err(err) = patch.validate(limits) {
+        return Ok(Write::Invalid {
+            existing: existing.id,
+            error: err.to_string(),
+        });
+    }
+    Ok(Write::Update {
+        existing,
+        patch,
+        status,
+    })
+}
+
+/// Updates an existing note, keeping its previous state as a revision
+/// where the backend has history.
+async fn update<D: NoteDb + ?Sized>(
+    ctx: &OpContext,
+    db: &D,
+    existing: &Note,
+    patch: &PatchNote,
+) -> Result<(), NoteDbError> {
+    match db.record_revision(ctx, existing).await {
+        Ok(_) | Err(NoteDbError::Unsupported(_)) => {}
+        Err(err) => return Err(err),
+    }
+    db.update_note(ctx, &existing.id, patch).await
+}
+
+/// Imports the parsed items, where an `Err` holds the reason an item could
+/// not be parsed.
+///
+/// With `OnError::Abort` all new notes are written in one `create_notes`
+/// call after every item was parsed and checked for conflicts. Backends
+/// that don't write in bulk atomically may keep the notes written before a
+/// failure. Overwrites and merges follow one by one once the new notes are
+/// written.
+pub async fn import_notes<D: NoteDb + ?Sized>(
+    ctx: &OpContext,
+    db: &D,
+    items: Vec<Result<Note, String>>,
+    query: ImportQuery,
+    limits: &NoteLimits,
+) -> ImportReport {
+    let report = |index, id, status, conflict, error| ItemReport {
+        index,
+        id,
+        status,
+        conflict,
+        error,
+    };
+    // Imported notes belong to the importing user and tenant, and only to
+    // organizations the user is a member of.
+    let items: Vec<Result<Note, String>> = items
+        .into_iter()
+        .map(|item| {
+            item.and_then(|note| match &note.org {
+                Some(org) if !ctx.orgs.contains(org) => {
+                    Err(format!("not a member of organization {}", org))
+                }
+                _ => Ok(Note {
+                    owner: ctx.principal.clone(),
+                    tenant: ctx.tenant.clone(),
+                    ..note
+                }),
+            })
+        })
+        .collect();
+    match query.on_error {
+        OnError::Abort if items.iter().any(Result::is_err) => {
+            let items = items
+                .into_iter()
+                .enumerate()
+                .map(|(index, item)| match item {
+                    Ok(note) => report(
+                        index,
+                        Some(note.id),
+                        ItemStatus::Skipped,
+                        None,
+                        None,
+                    ),
+                    Err(err) => {
+                        report(index, None, ItemStatus::Failed, None, Some(err))
+                    }
+                })
+                .collect();
+            ImportReport::new(items)
+        }
+        OnError::Abort => {
+            let count = items.len();
+            let mut writes = Vec::with_capacity(count);
+            for note in items.into_iter().flatten() {
+                match resolve(ctx, db, note, query.on_conflict, limits).await {
+                    Ok(write) => writes.push(write),
+                    Err(err) => {
+                        tracing::error!(
+                            "unable to check for conflicts: {}",
+                            err
+                        );
+                        let items = (0..count)
+                            .map(|index| {
+                                report(
+                                    index,
+                                    None,
+                                    ItemStatus::Failed,
+                                    None,
+                                    Some(err.to_string()),
+                                )
+                            })
+                            .collect();
+                        return ImportReport::new(items);
+                    }
+                }
+            }
+            let notes: Vec<Note> = writes
+                .iter()
+                .filter_map(|write| match write {
+                    Write::Create(note) => Some(note.clone()),
+                    _ => None,
+                })
+                .collect();
+            let created = db.create_notes(ctx, &notes).await.map_err(|err| {
+                tracing::error!("unable to import notes: {}", err);
+                err.to_string()
+            });
+            let mut reports = Vec::with_capacity(writes.len());
+            for (index, write) in writes.into_iter().enumerate() {
+                let conflict = write.conflict();
+                let (id, status, error) = match (write, &created) {
+                    (Write::Create(note), Ok(())) => {
+                        (Some(note.id), ItemStatus::Created, None)
+                    }
+                    (Write::Create(note), Err(err)) => {
+                        (Some(note.id), ItemStatus::Failed, Some(err.clone()))
+                    }
+                    (Write::Update { existing, .. }, Err(_)) => {
+                        (Some(existing.id), ItemStatus::Skipped, None)
+                    }
+                    (
+                        Write::Update {
+                            existing,
+                            patch,
+                            status,
+                        },
+                        Ok(()),
+                    ) => match update(ctx, db, &existing, &patch).await {
+                        Ok(()) => (Some(existing.id), status, None),
+                        Err(err) => {
+                            tracing::error!("unable to import note: {}", err);
+                            (
+                                Some(existing.id),
+                                ItemStatus::Failed,
+                                Some(err.to_string()),
+                            )
+                        }
+                    },
+                    (Write::Ignore(_), _) => (None, ItemStatus::Ignored, None),
+                    (Write::Invalid { error, .. }, _) => {
+                        (None, ItemStatus::Failed, Some(error))
+                    }
+                };
+                reports.push(report(index, id, status, conflict, error));
+            }
+            ImportReport::new(reports)
+        }
+        OnError::Continue => {
+            let mut reports = Vec::with_capacity(items.len());
+            for (index, item) in items.into_iter().enumerate() {
+                let note = match item {
+                    Ok(note) => note,
+                    Err(err) => {
+                        reports.push(report(
+                            index,
+                            None,
+                            ItemStatus::Failed,
+                            None,
+                            Some(err),
+                        ));
+                        continue;
+                    }
+                };
+                let id = note.id.clone();
+                let write =
+                    match resolve(ctx, db, note, query.on_conflict, limits)
+                        .await
+                    {
+                        Ok(write) => write,
+                        Err(err) => {
+                            tracing::error!(
+                                "unable to check for conflicts: {}",
+                                err
+                            );
+                            reports.push(report(
+                                index,
+                                Some(id),
+                                ItemStatus::Failed,
+                                None,
+                                Some(err.to_string()),
+                            ));
+                            continue;
+                        }
+                    };
+                let conflict = write.conflict();
+                let (id, status, result) = match write {
+                    Write::Create(note) => (
+                        Some(note.id.clone()),
+                        ItemStatus::Created,
+                        db.create_note(ctx, &note).await,
+                    ),
+                    Write::Update {
+                        existing,
+                        patch,
+                        status,
+                    } => (
+                        Some(existing.id.clone()),
+                        status,
+                        update(ctx, db, &existing, &patch).await,
+                    ),
+                    Write::Ignore(_) => (None, ItemStatus::Ignored, Ok(())),
+                    Write::Invalid { error, .. } => {
+                        reports.push(report(
+                            index,
+                            None,
+                            ItemStatus::Failed,
+                            conflict,
+                            Some(error),
+                        ));
+                        continue;
+                    }
+                };
+                match result {
+                    Ok(()) => {
+                        reports.push(report(index, id, status, conflict, None))
+                    }
+                    Err(err) => {
+                        tracing::error!("unable to import note: {}", err);
+                        reports.push(report(
+                            index,
+                            id,
+                            ItemStatus::Failed,
+                            conflict,
+                            Some(err.to_string()),
+                        ))
+                    }
+                }
+            }
+            ImportReport::new(reports)
+        }
+    }
+}
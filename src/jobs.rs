@@ -0,0 +1,92 @@
+//! Spans for work running outside of requests, like replicating writes to
+//! a mirror or flushing batched creates. Every run gets a root span of its
+//! own, so that its logs don't end up in whatever span was current when the
+//! work was queued, and names the request it was caused by, if any.
+
+use std::future::Future;
+
+use nanoid::nanoid;
+use tokio::time::Instant;
+use tracing::{field, Instrument};
+
+pub struct Job {
+    kind: &'static str,
+    attempt: u32,
+    request_id: Option<String>,
+}
+
+impl Job {
+    /// A first attempt at a job of the given type, e.g. `mirror_write`.
+    pub fn new(kind: &'static str) -> Job {
+        Job {
+            kind,
+            attempt: 1,
+            request_id: None,
+        }
+    }
+
+    /// Numbers the attempt of a retried job, starting at 1.
+    pub fn attempt(mut self, attempt: u32) -> Job {
+        self.attempt = attempt;
+        self
+    }
+
+    /// Links the job to the request it was queued by.
+    pub fn caused_by(mut self, request_id: Option<&str>) -> Job {
+        self.request_id = request_id.map(str::to_string);
+        self
+    }
+
+    /// Runs `work` within the span of the job and logs its duration.
+    pub async fn run<F: Future>(self, work: F) -> F::Output {
+        let span = tracing::info_span!(
+            parent: None,
+            "job",
+            job.id = %nanoid!(),
+            "job.type" = self.kind,
+            job.attempt = self.attempt,
+            request_id = self.request_id,
+            duration_ms = field::Empty,
+        );
+        let started = Instant::now();
+        let output = work.instrument(span.clone()).await;
+        let elapsed = started.elapsed();
+        span.record("duration_ms", elapsed.as_millis() as u64);
+        span.in_scope(|| tracing::debug!("finished job after {:?}", elapsed));
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tracing_subscriber::registry::{LookupSpan, Registry};
+
+    #[tokio::test]
+    async fn it_runs_jobs_in_root_spans() {
+        // Setup
+        let _subscriber =
+            tracing::subscriber::set_default(tracing_subscriber::registry());
+        let request = tracing::info_span!("request");
+        let _entered = request.enter();
+
+        // Execute
+        let (name, parent) = Job::new("test")
+            .caused_by(Some("r1"))
+            .run(async {
+                let span = tracing::Span::current();
+                let parent = span.with_subscriber(|(id, subscriber)| {
+                    let registry = subscriber.downcast_ref::<Registry>()?;
+                    let span = registry.span(id)?;
+                    Some(span.parent().map(|parent| parent.name()))
+                });
+                (span.metadata().map(|meta| meta.name()), parent)
+            })
+            .await;
+
+        // Assert
+        assert_eq!(name, Some("job"));
+        assert_eq!(parent, Some(Some(None)));
+    }
+}
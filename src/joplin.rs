@@ -0,0 +1,364 @@
+//! Import and export of Joplin's JEX archives, a tar of Joplin items.
+//!
+//! Each item is a Markdown file holding the title, the body and a block of
+//! `key: value` properties, whose `type_` tells notes, folders and tags
+//! apart. Notes have no notebooks, so on import the folder of a Joplin note
+//! is kept as a tag, and on export all notes go into one folder.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    io::Read,
+};
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use nanoid::nanoid;
+
+use crate::notes::{NewNote, Note};
+
+const TYPE_NOTE: &str = "1";
+const TYPE_FOLDER: &str = "2";
+const TYPE_TAG: &str = "5";
+const TYPE_NOTE_TAG: &str = "6";
+
+const EXPORT_FOLDER: &str = "Notes";
+const HEX: [char; 16] = [
+    '0', '1', '2', '3', '4', '5', '6', '7', '8', '9', 'a', 'b', 'c', 'd', 'e',
+    'f',
+];
+// Folders nest by parent id, this stops cycles in broken archives.
+const MAX_FOLDER_DEPTH: usize = 32;
+
+struct Item {
+    title: String,
+    body: String,
+    props: HashMap<String, String>,
+}
+
+impl Item {
+    fn parse(text: &str) -> Item {
+        let lines: Vec<&str> = text.lines().collect();
+        let mut split = lines.len();
+        let mut props = HashMap::new();
+        while split > 0 {
+            let Some((key, value)) = lines[split - 1].split_once(": ") else {
+                break;
+            };
+            if key.is_empty()
+                || !key.chars().all(|c| c.is_ascii_lowercase() || c == '_')
+            {
+                break;
+            }
+            props.insert(key.to_string(), value.to_string());
+            split -= 1;
+        }
+        // Items without a title, like note tags, are just properties.
+        let content = &lines[..split];
+        let title = content.first().copied().unwrap_or_default();
+        let body = content.get(2..).unwrap_or_default().join("\n");
+        Item {
+            title: title.to_string(),
+            body: body.trim_end().to_string(),
+            props,
+        }
+    }
+
+    fn prop(&self, key: &str) -> Option<&str> {
+        self.props.get(key).map(String::as_str)
+    }
+
+    fn time(&self, key: &str) -> Option<DateTime<Utc>> {
+        let value = self.prop(key)?;
+        Some(DateTime::parse_from_rfc3339(value).ok()?.to_utc())
+    }
+}
+
+fn serialize_item(
+    title: Option<&str>,
+    body: Option<&str>,
+    props: &[(&str, &str)],
+) -> String {
+    let mut text = String::new();
+    if let Some(title) = title {
+        text.push_str(title);
+        text.push_str("\n\n");
+    }
+    if let Some(body) = body {
+        text.push_str(body);
+        text.push_str("\n\n");
+    }
+    let props: Vec<String> = props
+        .iter()
+        .map(|(key, value)| format!("{}: {}", key, value))
+        .collect();
+    text.push_str(&props.join("\n"));
+    text
+}
+
+fn joplin_id() -> String {
+    nanoid!(32, &HEX)
+}
+
+fn joplin_time(time: &DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+pub struct JoplinExport {
+    items: Vec<Item>,
+    unmapped: Vec<String>,
+}
+
+impl JoplinExport {
+    pub fn read(data: &[u8]) -> Result<JoplinExport, std::io::Error> {
+        let mut archive = tar::Archive::new(data);
+        let mut export = JoplinExport {
+            items: Vec::new(),
+            unmapped: Vec::new(),
+        };
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            if !entry.header().entry_type().is_file() {
+                continue;
+            }
+            let path = entry.path()?.to_string_lossy().into_owned();
+            if !path.ends_with(".md") {
+                export.unmapped.push(path);
+                continue;
+            }
+            let mut text = String::new();
+            if entry.read_to_string(&mut text).is_err() {
+                export.unmapped.push(format!("{} (not UTF-8)", path));
+                continue;
+            }
+            export.items.push(Item::parse(&text));
+        }
+        Ok(export)
+    }
+
+    /// Creates the notes, keeping their timestamps, tags and folder.
+    pub fn into_notes(self, notes_path: &str) -> (Vec<Note>, Vec<String>) {
+        let JoplinExport {
+            items,
+            mut unmapped,
+        } = self;
+        let by_type = |kind: &'static str| {
+            items
+                .iter()
+                .filter(move |item| item.prop("type_") == Some(kind))
+        };
+        let folders: HashMap<&str, &Item> = by_type(TYPE_FOLDER)
+            .filter_map(|folder| Some((folder.prop("id")?, folder)))
+            .collect();
+        let tags: HashMap<&str, &str> = by_type(TYPE_TAG)
+            .filter_map(|tag| Some((tag.prop("id")?, tag.title.as_str())))
+            .collect();
+        let mut note_tags: HashMap<&str, Vec<String>> = HashMap::new();
+        for link in by_type(TYPE_NOTE_TAG) {
+            let (Some(note_id), Some(tag)) = (
+                link.prop("note_id"),
+                link.prop("tag_id").and_then(|id| tags.get(id)),
+            ) else {
+                continue;
+            };
+            note_tags.entry(note_id).or_default().push(tag.to_string());
+        }
+
+        let mut notes = Vec::new();
+        for item in &items {
+            match item.prop("type_") {
+                Some(TYPE_NOTE) => {}
+                Some(TYPE_FOLDER | TYPE_TAG | TYPE_NOTE_TAG) => continue,
+                kind => {
+                    unmapped.push(format!(
+                        "item {} of type {}",
+                        item.prop("id").unwrap_or_default(),
+                        kind.unwrap_or("unknown")
+                    ));
+                    continue;
+                }
+            }
+            let id = item.prop("id").unwrap_or_default();
+            let mut tags = note_tags.remove(id).unwrap_or_default();
+            if let Some(folder) = folder_path(item.prop("parent_id"), &folders)
+            {
+                tags.insert(0, folder);
+            }
+            let mut note = NewNote {
+                title: item.title.clone(),
+                body: item.body.clone(),
+                tags,
+                org: None,
+            }
+            .into_note(notes_path);
+            if let Some(created_at) = item.time("created_time") {
+                note.created_at = created_at;
+            }
+            if let Some(updated_at) = item.time("updated_time") {
+                note.updated_at = updated_at;
+            }
+            notes.push(note);
+        }
+        (notes, unmapped)
+    }
+}
+
+fn folder_path<'a>(
+    mut parent_id: Option<&'a str>,
+    folders: &HashMap<&str, &'a Item>,
+) -> Option<String> {
+    let mut path = Vec::new();
+    while let Some(folder) = parent_id.and_then(|id| folders.get(id)) {
+        if path.len() == MAX_FOLDER_DEPTH {
+            break;
+        }
+        path.push(folder.title.as_str());
+        parent_id = folder.prop("parent_id").filter(|id| !id.is_empty());
+    }
+    path.reverse();
+    (!path.is_empty()).then(|| path.join("/"))
+}
+
+/// Writes the notes as JEX archive.
+pub fn write_export(notes: &[Note]) -> Result<Vec<u8>, std::io::Error> {
+    let now = joplin_time(&Utc::now());
+    let mut items = Vec::new();
+
+    let folder_id = joplin_id();
+    items.push((
+        folder_id.clone(),
+        serialize_item(
+            Some(EXPORT_FOLDER),
+            None,
+            &[
+                ("id", &folder_id),
+                ("created_time", &now),
+                ("updated_time", &now),
+                ("parent_id", ""),
+                ("type_", TYPE_FOLDER),
+            ],
+        ),
+    ));
+
+    let tag_names: BTreeSet<&str> = notes
+        .iter()
+        .flat_map(|note| note.tags.iter().map(String::as_str))
+        .collect();
+    let mut tag_ids = HashMap::new();
+    for name in tag_names {
+        let tag_id = joplin_id();
+        items.push((
+            tag_id.clone(),
+            serialize_item(
+                Some(name),
+                None,
+                &[
+                    ("id", &tag_id),
+                    ("created_time", &now),
+                    ("updated_time", &now),
+                    ("type_", TYPE_TAG),
+                ],
+            ),
+        ));
+        tag_ids.insert(name, tag_id);
+    }
+
+    for note in notes {
+        let note_id = joplin_id();
+        let created_time = joplin_time(&note.created_at);
+        let updated_time = joplin_time(&note.updated_at);
+        items.push((
+            note_id.clone(),
+            serialize_item(
+                Some(&note.title),
+                Some(&note.body),
+                &[
+                    ("id", &note_id),
+                    ("parent_id", &folder_id),
+                    ("created_time", &created_time),
+                    ("updated_time", &updated_time),
+                    ("user_created_time", &created_time),
+                    ("user_updated_time", &updated_time),
+                    ("markup_language", "1"),
+                    ("type_", TYPE_NOTE),
+                ],
+            ),
+        ));
+        for tag in &note.tags {
+            let link_id = joplin_id();
+            items.push((
+                link_id.clone(),
+                serialize_item(
+                    None,
+                    None,
+                    &[
+                        ("id", &link_id),
+                        ("note_id", &note_id),
+                        ("tag_id", &tag_ids[tag.as_str()]),
+                        ("created_time", &now),
+                        ("updated_time", &now),
+                        ("type_", TYPE_NOTE_TAG),
+                    ],
+                ),
+            ));
+        }
+    }
+
+    let mut archive = tar::Builder::new(Vec::new());
+    let mtime = Utc::now().timestamp().max(0) as u64;
+    for (id, text) in items {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(text.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(mtime);
+        archive.append_data(
+            &mut header,
+            format!("{}.md", id),
+            text.as_bytes(),
+        )?;
+    }
+    archive.into_inner()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_round_trips_notes_through_jex() {
+        // Setup
+        let mut note = Note::new("Title", "line1\n\nline2", "");
+        note.tags = vec!["work".to_string()];
+        note.created_at = "2024-01-02T03:04:05.006Z".parse().unwrap();
+
+        // Execute
+        let data = write_export(std::slice::from_ref(&note)).unwrap();
+        let (notes, unmapped) =
+            JoplinExport::read(&data).unwrap().into_notes("/notes");
+
+        // Assert
+        assert!(unmapped.is_empty());
+        assert_eq!(notes.len(), 1);
+        assert_eq!(notes[0].title, note.title);
+        assert_eq!(notes[0].body, note.body);
+        assert_eq!(notes[0].tags, vec![EXPORT_FOLDER, "work"]);
+        assert_eq!(notes[0].created_at, note.created_at);
+    }
+
+    #[test]
+    fn it_parses_joplin_items() {
+        // Setup
+        let text = "Groceries\n\n- milk\n\nid: abc\nparent_id: def\n\
+                    created_time: 2020-05-01T10:00:00.000Z\ntype_: 1";
+
+        // Execute
+        let item = Item::parse(text);
+
+        // Assert
+        assert_eq!(item.title, "Groceries");
+        assert_eq!(item.body, "- milk");
+        assert_eq!(item.prop("parent_id"), Some("def"));
+        assert_eq!(
+            item.time("created_time"),
+            Some("2020-05-01T10:00:00Z".parse().unwrap())
+        );
+    }
+}
@@ -0,0 +1,57 @@
+//! Streams a JSON array into the response body one chunk at a time, so large
+//! lists are never serialized into a single buffer.
+
+use axum::{
+    body::{Body, Bytes},
+    http::header,
+    response::{IntoResponse, Response},
+};
+use serde::Serialize;
+
+/// Chunks are sent once they reach this many bytes.
+const CHUNK_SIZE: usize = 16 * 1024;
+
+/// Like `Json<Vec<T>>`, but items are serialized lazily and each item is
+/// dropped once written.
+pub struct JsonArray<T>(pub Vec<T>);
+
+impl<T: Serialize + Send + 'static> IntoResponse for JsonArray<T> {
+    fn into_response(self) -> Response {
+        let mut items = self.0.into_iter();
+        let mut started = false;
+        let mut finished = false;
+        let chunks = futures::stream::poll_fn(move |_| {
+            if finished {
+                return std::task::Poll::Ready(None);
+            }
+            let mut chunk = Vec::with_capacity(CHUNK_SIZE);
+            if !started {
+                chunk.push(b'[');
+            }
+            while chunk.len() < CHUNK_SIZE {
+                let Some(item) = items.next() else {
+                    chunk.push(b']');
+                    finished = true;
+                    break;
+                };
+                if started {
+                    chunk.push(b',');
+                }
+                started = true;
+                if let Err(err) = serde_json::to_writer(&mut chunk, &item) {
+                    // The status is already sent, so all we can do is
+                    // abort the body.
+                    tracing::error!("unable to serialize list item: {}", err);
+                    finished = true;
+                    return std::task::Poll::Ready(Some(Err(err)));
+                }
+            }
+            std::task::Poll::Ready(Some(Ok(Bytes::from(chunk))))
+        });
+        (
+            [(header::CONTENT_TYPE, "application/json")],
+            Body::from_stream(chunks),
+        )
+            .into_response()
+    }
+}
@@ -1,257 +1,2483 @@
 use std::sync::Arc;
 use tokio::sync::Mutex;
 
-use tracing_subscriber::{self, layer::SubscriberExt, util::SubscriberInitExt};
-
 use axum::{
-    extract::{Path, State},
-    http::StatusCode,
-    routing::{get, post},
+    body::Bytes,
+    extract::{Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{AppendHeaders, IntoResponse, Response},
+    routing::{delete, get, post, put, MethodRouter},
     Json, Router,
 };
 
-use nanoid::nanoid;
+use serde::Deserialize;
 use tower_http::trace::TraceLayer;
 
+pub mod announcements;
+pub mod api_keys;
+pub mod auth;
+pub mod body_limit;
+pub mod capabilities;
+pub mod conditional;
+pub mod config;
+pub mod consistency;
+pub mod context;
+pub mod csv_notes;
+#[cfg(feature = "pprof")]
+pub mod debug;
+pub mod dedupe;
+pub mod extract;
+pub mod html_import;
+pub mod idempotency;
+pub mod import;
+pub mod jobs;
+pub mod joplin;
+pub mod json_stream;
+#[cfg(feature = "loadgen")]
+pub mod loadgen;
+pub mod logging;
+pub mod metrics;
+pub mod note_patch;
 pub mod notes;
+pub mod notion;
+pub mod openapi;
+pub mod orgs;
+#[cfg(feature = "pdf")]
+pub mod pdf;
 pub mod persistency;
+pub mod problem;
+pub mod request_id;
+pub mod security;
+pub mod shares;
+#[cfg(feature = "loadgen")]
+pub mod soak;
+pub mod startup;
+pub mod tenancy;
+#[cfg(feature = "test-support")]
+pub mod testing;
+pub mod timeouts;
+pub mod titles;
+pub mod validation;
 
+use announcements::*;
 use notes::*;
 
-use crate::persistency::{create_mongo_client, NoteMongoDb};
+use crate::api_keys::{ApiKey, ApiKeyDb, ApiKeyInfo, CreatedApiKey, NewApiKey};
+use crate::auth::{AuthConfig, JwtAuth};
+use crate::capabilities::Capabilities;
+use crate::conditional::{etag, Preconditions};
+use crate::config::ApiVersion;
+use crate::consistency::Consistency;
+use crate::context::OpContext;
+use crate::csv_notes::ColumnMapping;
+use crate::dedupe::DedupeWindow;
+use crate::extract::{RequestJson, ValidJson};
+use crate::html_import::HtmlExport;
+use crate::idempotency::{IdempotencyDb, IdempotencyRecord};
+use crate::import::{
+    ArchiveImportReport, ArchiveLimits, ImportQuery, ImportReport, OnError,
+};
+use crate::joplin::JoplinExport;
+use crate::json_stream::JsonArray;
+use crate::logging::LogFormat;
+use crate::note_patch::NotePatch;
+use crate::notion::NotionExport;
+use crate::orgs::{NewOrganization, OrgDb, Organization};
+use crate::persistency::{
+    batching::{BatchConfig, BatchingNoteDb},
+    create_mongo_client,
+    dual_write::{
+        DualWriteControl, DualWriteNoteDb, DualWriteStatus, ReadMode,
+    },
+    hedged::HedgedNoteDb,
+    mirror::{self, CatchUpReport, MirroredNoteDb},
+    negative_cache::NegativeCacheNoteDb,
+    shadow_read::ShadowReadNoteDb,
+    traced::TracedNoteDb,
+    NoteMongoDb,
+};
+use crate::problem::Problem;
+use crate::security::SecurityHeaders;
+use crate::shares::{CreatedShare, Share, ShareDb, ShareQuery};
+use crate::startup::{StartupError, StartupErrorKind};
+use crate::timeouts::{RouteClass, RouteTimeouts};
+use crate::titles::{Duplicates, TitlePolicy};
+use crate::validation::{NoteLimits, Validate, ValidationErrors};
 
 const APP_NAME: &str = "notes";
+const TOTAL_COUNT_HEADER: &str = "x-total-count";
+// Page size applied to a listing, after defaulting and clamping.
+const PAGE_LIMIT_HEADER: &str = "x-page-limit";
+const DEFAULT_PAGE_SIZE: u64 = 100;
+const MAX_PAGE_SIZE: u64 = 1000;
+const SUGGEST_DEFAULT_LIMIT: usize = 10;
+const SUGGEST_MAX_LIMIT: usize = 50;
+// Startup fails if the database doesn't answer within this time.
+const STARTUP_PING_TIMEOUT: std::time::Duration =
+    std::time::Duration::from_secs(10);
+const DRAIN_LOG_INTERVAL: std::time::Duration =
+    std::time::Duration::from_secs(1);
+const HEALTHCHECK_TIMEOUT: std::time::Duration =
+    std::time::Duration::from_secs(5);
 
 pub struct AppConfig {
-    pub host_port: String,
-    pub api_version: String,
+    pub host_port: std::net::SocketAddr,
+    pub api_version: ApiVersion,
+    /// Base URL clients reach the service at, used for note URLs. Defaults
+    /// to `host_port`.
+    pub public_url: Option<url::Url>,
+    /// Database selected by scheme: `mongodb://` by default, `memory://`,
+    /// and `s3://`, `couchdb://` and `postgres://` with the respective
+    /// feature.
     pub db_uri: String,
+    /// Secondary database that receives a copy of every write.
+    pub mirror_db_uri: Option<String>,
+    /// Percentage of reads that are compared against the mirror database.
+    /// Replication lag shows up as mismatches, so expect a small baseline.
+    pub shadow_read_percent: Option<u64>,
+    /// Send a get that the primary hasn't answered within this delay to
+    /// the mirror database as well. The mirror may lag behind.
+    pub hedge_delay: Option<std::time::Duration>,
+    /// Remember notes found missing for this long, unless created in the
+    /// meantime through this instance.
+    pub negative_cache_ttl: Option<std::time::Duration>,
+    /// Database being migrated to. Writes go to both databases and reads
+    /// can be switched over at runtime.
+    pub migration_db_uri: Option<String>,
+    pub security_headers: SecurityHeaders,
+    /// Reject request bodies containing fields the API does not know.
+    pub strict_json: bool,
+    /// Reject identical POSTs from the same caller within this window.
+    pub dedupe_window: Option<std::time::Duration>,
+    /// Buffers created notes and writes them in batches.
+    pub ingest_batching: Option<BatchConfig>,
+    pub route_timeouts: RouteTimeouts,
+    pub note_limits: NoteLimits,
+    /// Largest request body accepted, in bytes. Larger requests are
+    /// answered with 413.
+    pub max_request_bytes: usize,
+    /// What happens to notes whose title another note already has.
+    pub title_policy: TitlePolicy,
+    /// Refuse PATCH and DELETE of notes without `If-Match` with 428.
+    pub require_if_match: bool,
+    /// How long an `Idempotency-Key` of a create is remembered.
+    pub idempotency_ttl: std::time::Duration,
+    /// Notes listed when a listing asks for no limit.
+    pub default_page_size: u64,
+    /// Largest limit of a listing, larger ones are lowered to it.
+    pub max_page_size: u64,
+    /// Decompressed size of archives imported from other note tools.
+    pub archive_limits: ArchiveLimits,
+    /// Require a bearer JWT on every route but health and API docs.
+    pub auth: Option<AuthConfig>,
+    /// Require a tenant on every route but health and API docs and keep
+    /// the notes of tenants apart.
+    pub multi_tenant: bool,
+    /// Keep the notes of each tenant in a MongoDB database of their own.
+    /// Other backends store all tenants together.
+    pub database_per_tenant: bool,
+    /// Serve task and lock instrumentation to tokio-console. Only takes
+    /// effect when built with the `console` feature.
+    pub tokio_console: bool,
+    /// Write the log as text or as JSON lines.
+    pub log_format: LogFormat,
+    /// Export traces to this OTLP/HTTP endpoint. Only takes effect when
+    /// built with the `otel` feature.
+    pub otlp_endpoint: Option<String>,
+    /// Install the subscriber of `logging::init` on start. Applications
+    /// embedding the server that install a subscriber of their own turn
+    /// this off.
+    pub init_tracing: bool,
+}
+
+impl Default for AppConfig {
+    fn default() -> Self {
+        AppConfig {
+            host_port: std::net::SocketAddr::from(([0, 0, 0, 0], 3000)),
+            api_version: ApiVersion::default(),
+            public_url: None,
+            db_uri: "mongodb://localhost:27017".to_string(),
+            mirror_db_uri: None,
+            shadow_read_percent: None,
+            hedge_delay: None,
+            negative_cache_ttl: None,
+            migration_db_uri: None,
+            security_headers: SecurityHeaders::default(),
+            strict_json: false,
+            dedupe_window: None,
+            ingest_batching: None,
+            route_timeouts: RouteTimeouts::default(),
+            note_limits: NoteLimits::default(),
+            max_request_bytes: body_limit::DEFAULT_MAX_REQUEST_BYTES,
+            title_policy: TitlePolicy::default(),
+            require_if_match: false,
+            idempotency_ttl: idempotency::DEFAULT_TTL,
+            default_page_size: DEFAULT_PAGE_SIZE,
+            max_page_size: MAX_PAGE_SIZE,
+            archive_limits: ArchiveLimits::default(),
+            auth: None,
+            multi_tenant: false,
+            database_per_tenant: false,
+            tokio_console: false,
+            log_format: LogFormat::default(),
+            otlp_endpoint: None,
+            init_tracing: true,
+        }
+    }
 }
 
-pub struct AppState {
-    pub notes: Arc<Mutex<dyn NoteDb + Send + Sync>>,
+/// State shared by the handlers, generic over the note backend.
+pub struct AppState<D: NoteDb + ?Sized> {
+    pub notes: Arc<Mutex<D>>,
+    pub announcements: Arc<dyn AnnouncementDb + Send + Sync>,
+    /// Notes created for idempotency keys of clients.
+    pub idempotency: Arc<dyn IdempotencyDb + Send + Sync>,
+    /// API keys of machine clients.
+    pub api_keys: Arc<dyn ApiKeyDb + Send + Sync>,
+    pub orgs: Arc<dyn OrgDb + Send + Sync>,
+    /// Links sharing single notes without authentication.
+    pub shares: Arc<dyn ShareDb + Send + Sync>,
+    /// Read mode switch when dual-writing to a migration database.
+    pub migration: Option<Arc<DualWriteControl>>,
+    /// Batching writer used for creating notes, if enabled.
+    pub ingest: Option<Arc<dyn NoteDb + Send + Sync>>,
     pub notes_path: String,
+    pub strict_json: bool,
+    pub note_limits: NoteLimits,
+    pub title_policy: TitlePolicy,
+    pub require_if_match: bool,
+    pub idempotency_ttl: std::time::Duration,
+    pub default_page_size: u64,
+    pub max_page_size: u64,
+    pub archive_limits: ArchiveLimits,
 }
 
-pub async fn create_app(
-    app_config: AppConfig,
-) -> Result<(), Box<dyn std::error::Error>> {
-    // Setup tracing
-    tracing_subscriber::registry()
-        .with(tracing_subscriber::EnvFilter::from(format!(
-            "RUST_LOG={},{}=debug,tower_http=debug,axum::rejection=trace",
-            std::env::var("RUST_LOG").unwrap_or("info".to_string()),
-            env!("CARGO_CRATE_NAME")
-        )))
-        .with(tracing_subscriber::fmt::layer())
-        .init();
+/// `AppState` with the note backend picked at runtime.
+pub type DynAppState = AppState<dyn NoteDb + Send + Sync>;
+
+pub async fn create_app(app_config: AppConfig) -> Result<(), StartupError> {
+    // Setup tracing, unless the embedding application has its own
+    let telemetry = match app_config.init_tracing {
+        true => Some(logging::init(&app_config)?),
+        false => None,
+    };
 
     // Setup server address
-    let notes_path =
-        format!("{}/{}/notes", app_config.host_port, app_config.api_version);
+    let notes_path = match &app_config.public_url {
+        Some(url) => format!(
+            "{}/{}/notes",
+            url.as_str().trim_end_matches('/'),
+            app_config.api_version
+        ),
+        None => {
+            format!("{}/{}/notes", app_config.host_port, app_config.api_version)
+        }
+    };
 
     // Setup notes DB
-    let client = create_mongo_client(&app_config.db_uri).await;
-    let Ok(client) = client else {
-        tracing::error!("unable to get database client");
-        return Err(client.unwrap_err().into());
-    };
-    let db = NoteMongoDb::get_notes_db(client);
-    let note_db = NoteMongoDb::new(db);
+    let databases = create_databases(&app_config).await?;
 
-    let state = Arc::new(AppState {
-        notes: Arc::new(Mutex::new(note_db)),
+    let state: Arc<DynAppState> = Arc::new(AppState {
+        notes: databases.notes,
+        announcements: databases.announcements,
+        idempotency: databases.idempotency,
+        api_keys: databases.api_keys,
+        orgs: databases.orgs,
+        shares: databases.shares,
+        migration: databases.migration,
+        ingest: databases.ingest,
         notes_path,
+        strict_json: app_config.strict_json,
+        note_limits: app_config.note_limits,
+        title_policy: app_config.title_policy,
+        require_if_match: app_config.require_if_match,
+        idempotency_ttl: app_config.idempotency_ttl,
+        default_page_size: app_config.default_page_size,
+        max_page_size: app_config.max_page_size,
+        archive_limits: app_config.archive_limits,
     });
 
-    let app = create_axum_app(state, &app_config.api_version);
+    let app = create_axum_app(state, &app_config);
 
     // Setup TCP listener
     let span = tracing::info_span!(
         "Start app",
         app = APP_NAME,
-        api_version = app_config.api_version
+        api_version = app_config.api_version.as_str()
     );
     let _enter = span.enter();
     tracing::debug!("Setup listener on {}", app_config.host_port);
-    let listener = match tokio::net::TcpListener::bind(&app_config.host_port)
+    let listener = match tokio::net::TcpListener::bind(app_config.host_port)
         .await
     {
         Ok(listener) => listener,
         Err(err) => {
             tracing::error!("unable to setup lister {}", app_config.host_port);
-            return Err(err.into());
+            return Err(StartupError::new(
+                StartupErrorKind::Bind,
+                format!(
+                    "unable to listen on {}: {}",
+                    app_config.host_port, err
+                ),
+            ));
         }
     };
 
     // Setup listening
     tracing::info!("Serve on {}", app_config.host_port);
-    let serve = axum::serve(listener, app).await;
+    let serve = axum::serve(listener, app)
+        .with_graceful_shutdown(shutdown_signal())
+        .await;
+    tracing::info!("shut down with {:?}", metrics::gauges());
+    if let Some(telemetry) = telemetry {
+        telemetry.shutdown().await;
+    }
     if let Err(err) = serve {
         tracing::error!(
             "unable to serve app for listener at {}",
             app_config.host_port
         );
-        return Err(err.into());
+        return Err(StartupError::new(StartupErrorKind::Serve, err));
     }
     Ok(())
 }
 
-fn create_axum_app(state: Arc<AppState>, api_version: &str) -> Router {
-    Router::new()
-        .route(&format!("/{}/health", api_version), get(get_health))
+/// Resolves on Ctrl+C or SIGTERM, then logs the requests left to drain
+/// until they are done.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(err) = tokio::signal::ctrl_c().await {
+            tracing::error!("unable to listen for ctrl-c: {}", err);
+            std::future::pending::<()>().await;
+        }
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(err) => {
+                tracing::error!("unable to listen for SIGTERM: {}", err);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+
+    let started = std::time::Instant::now();
+    tracing::info!("shutting down, draining {:?}", metrics::gauges());
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(DRAIN_LOG_INTERVAL);
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            let gauges = metrics::gauges();
+            if gauges.in_flight_requests == 0 {
+                break;
+            }
+            tracing::info!(
+                "still draining after {:?}: {:?}",
+                started.elapsed(),
+                gauges
+            );
+        }
+    });
+}
+
+struct Databases {
+    notes: Arc<Mutex<dyn NoteDb + Send + Sync>>,
+    announcements: Arc<dyn AnnouncementDb + Send + Sync>,
+    idempotency: Arc<dyn IdempotencyDb + Send + Sync>,
+    api_keys: Arc<dyn ApiKeyDb + Send + Sync>,
+    orgs: Arc<dyn OrgDb + Send + Sync>,
+    shares: Arc<dyn ShareDb + Send + Sync>,
+    migration: Option<Arc<DualWriteControl>>,
+    ingest: Option<Arc<dyn NoteDb + Send + Sync>>,
+}
+
+type Database = (
+    Arc<dyn NoteDb + Send + Sync>,
+    Arc<dyn AnnouncementDb + Send + Sync>,
+    Arc<dyn IdempotencyDb + Send + Sync>,
+    Arc<dyn ApiKeyDb + Send + Sync>,
+    Arc<dyn OrgDb + Send + Sync>,
+    Arc<dyn ShareDb + Send + Sync>,
+);
+
+async fn create_databases(
+    app_config: &AppConfig,
+) -> Result<Databases, StartupError> {
+    let per_tenant = app_config.database_per_tenant;
+    let (
+        mut note_db,
+        announcement_db,
+        idempotency_db,
+        api_key_db,
+        org_db,
+        share_db,
+    ) = open_database(&app_config.db_uri, per_tenant).await?;
+    let mut migration = None;
+    if let Some(migration_db_uri) = &app_config.migration_db_uri {
+        let (new_db, _, _, _, _, _) =
+            open_database(migration_db_uri, per_tenant).await?;
+        let control = Arc::new(DualWriteControl::new(ReadMode::Old));
+        note_db =
+            Arc::new(DualWriteNoteDb::new(note_db, new_db, control.clone()));
+        migration = Some(control);
+    }
+    if let Some(mirror_db_uri) = &app_config.mirror_db_uri {
+        let (mirror_db, _, _, _, _, _) =
+            open_database(mirror_db_uri, per_tenant).await?;
+        note_db = Arc::new(MirroredNoteDb::new(note_db, mirror_db.clone()));
+        if let Some(percent) = app_config.shadow_read_percent {
+            note_db = Arc::new(ShadowReadNoteDb::new(
+                note_db,
+                mirror_db.clone(),
+                percent,
+            ));
+        }
+        if let Some(delay) = app_config.hedge_delay {
+            note_db = Arc::new(HedgedNoteDb::new(note_db, mirror_db, delay));
+        }
+    }
+    if let Some(ttl) = app_config.negative_cache_ttl {
+        note_db = Arc::new(NegativeCacheNoteDb::new(note_db, ttl));
+    }
+    // Outermost, so that spans measure the calls as the handlers see them.
+    note_db = Arc::new(TracedNoteDb::new(note_db));
+    let ingest = app_config.ingest_batching.clone().map(|config| {
+        Arc::new(BatchingNoteDb::new(note_db.clone(), config))
+            as Arc<dyn NoteDb + Send + Sync>
+    });
+    Ok(Databases {
+        notes: Arc::new(Mutex::new(note_db)),
+        announcements: announcement_db,
+        idempotency: idempotency_db,
+        api_keys: api_key_db,
+        orgs: org_db,
+        shares: share_db,
+        migration,
+        ingest,
+    })
+}
+
+/// Copies all notes from the database to its mirror and removes notes the
+/// mirror has in excess. With `tenant`, only the notes of that tenant, which
+/// is how databases per tenant are caught up.
+pub async fn catch_up_mirror(
+    db_uri: &str,
+    mirror_db_uri: &str,
+    database_per_tenant: bool,
+    tenant: Option<String>,
+) -> Result<CatchUpReport, Box<dyn std::error::Error>> {
+    let (note_db, _, _, _, _, _) =
+        open_database(db_uri, database_per_tenant).await?;
+    let (mirror_db, _, _, _, _, _) =
+        open_database(mirror_db_uri, database_per_tenant).await?;
+    let report = mirror::catch_up(
+        &OpContext::for_tenant(tenant),
+        note_db.as_ref(),
+        mirror_db.as_ref(),
+    )
+    .await?;
+    Ok(report)
+}
+
+/// Probes a health endpoint of a running instance. Used by the
+/// `healthcheck` subcommand so container images don't need curl.
+pub async fn healthcheck(url: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let client = reqwest::Client::builder()
+        .timeout(HEALTHCHECK_TIMEOUT)
+        .build()?;
+    let status = client.get(url).send().await?.status();
+    if !status.is_success() {
+        return Err(format!("health check answered {}", status).into());
+    }
+    Ok(())
+}
+
+/// Picks the persistence backend from the scheme of the database URI.
+async fn open_database(
+    db_uri: &str,
+    database_per_tenant: bool,
+) -> Result<Database, StartupError> {
+    if db_uri.starts_with("memory://") {
+        tracing::warn!("notes are kept in memory and lost on restart");
+        let note_db = Arc::new(persistency::memory::NoteMemoryDb::new());
+        return Ok((
+            note_db.clone(),
+            note_db.clone(),
+            note_db.clone(),
+            note_db.clone(),
+            note_db.clone(),
+            note_db,
+        ));
+    }
+
+    #[cfg(feature = "s3")]
+    if db_uri.starts_with("s3://") {
+        let note_db = match persistency::s3::NoteS3Db::from_uri(db_uri) {
+            Ok(note_db) => note_db,
+            Err(err) => {
+                tracing::error!("unable to setup object store");
+                return Err(StartupError::config(err));
+            }
+        };
+        if let Err(err) = note_db.ping().await {
+            tracing::error!("object store is unreachable: {}", err);
+            return Err(StartupError::new(
+                StartupErrorKind::Database,
+                format!("object store is unreachable: {}", err),
+            ));
+        }
+        return Ok((
+            Arc::new(note_db.clone()),
+            Arc::new(note_db.clone()),
+            Arc::new(note_db.clone()),
+            Arc::new(note_db.clone()),
+            Arc::new(note_db.clone()),
+            Arc::new(note_db),
+        ));
+    }
+
+    #[cfg(feature = "couchdb")]
+    if db_uri.starts_with("couchdb://") || db_uri.starts_with("couchdbs://") {
+        let note_db = match persistency::couchdb::NoteCouchDb::from_uri(db_uri)
+        {
+            Ok(note_db) => note_db,
+            Err(err) => {
+                tracing::error!("invalid couchdb uri");
+                return Err(StartupError::config(err));
+            }
+        };
+        if let Err(err) = note_db.ensure_database().await {
+            tracing::error!("unable to setup couchdb database");
+            return Err(StartupError::new(StartupErrorKind::Database, err));
+        }
+        return Ok((
+            Arc::new(note_db.clone()),
+            Arc::new(note_db.clone()),
+            Arc::new(note_db.clone()),
+            Arc::new(note_db.clone()),
+            Arc::new(note_db.clone()),
+            Arc::new(note_db),
+        ));
+    }
+
+    #[cfg(feature = "postgres")]
+    if db_uri.starts_with("postgres://") || db_uri.starts_with("postgresql://")
+    {
+        let note_db =
+            match persistency::postgres::NotePostgresDb::from_uri(db_uri) {
+                Ok(note_db) => note_db,
+                Err(err) => {
+                    tracing::error!("invalid postgres uri");
+                    return Err(StartupError::config(err));
+                }
+            };
+        let ping =
+            match tokio::time::timeout(STARTUP_PING_TIMEOUT, note_db.ping())
+                .await
+            {
+                Ok(ping) => ping.map_err(|err| err.to_string()),
+                Err(_) => {
+                    Err(format!("no answer within {:?}", STARTUP_PING_TIMEOUT))
+                }
+            };
+        if let Err(err) = ping {
+            tracing::error!("database is unreachable: {}", err);
+            return Err(StartupError::new(
+                StartupErrorKind::Database,
+                format!("database is unreachable: {}", err),
+            ));
+        }
+        if let Err(err) = note_db.migrate().await {
+            tracing::error!("unable to migrate database schema");
+            return Err(StartupError::new(
+                StartupErrorKind::DatabaseSetup,
+                err,
+            ));
+        }
+        return Ok((
+            Arc::new(note_db.clone()),
+            Arc::new(note_db.clone()),
+            Arc::new(note_db.clone()),
+            Arc::new(note_db.clone()),
+            Arc::new(note_db.clone()),
+            Arc::new(note_db),
+        ));
+    }
+
+    let client = match create_mongo_client(db_uri).await {
+        Ok(client) => client,
+        Err(err) => {
+            tracing::error!("unable to get database client");
+            return Err(StartupError::config(format!(
+                "invalid database uri: {}",
+                err
+            )));
+        }
+    };
+    let db = NoteMongoDb::get_notes_db(client);
+    let mut note_db = NoteMongoDb::new(db.clone());
+    if database_per_tenant {
+        note_db = note_db.with_database_per_tenant();
+    }
+    let announcement_db = NoteMongoDb::new(db.clone());
+    let idempotency_db = NoteMongoDb::new(db.clone());
+    let api_key_db = NoteMongoDb::new(db.clone());
+    let org_db = NoteMongoDb::new(db.clone());
+    let share_db = NoteMongoDb::new(db);
+    let ping = match tokio::time::timeout(STARTUP_PING_TIMEOUT, note_db.ping())
+        .await
+    {
+        Ok(ping) => ping.map_err(|err| err.to_string()),
+        Err(_) => Err(format!("no answer within {:?}", STARTUP_PING_TIMEOUT)),
+    };
+    if let Err(err) = ping {
+        tracing::error!("database is unreachable: {}", err);
+        return Err(StartupError::new(
+            StartupErrorKind::Database,
+            format!("database is unreachable: {}", err),
+        ));
+    }
+    if let Err(err) = note_db.create_indexes().await {
+        tracing::error!("unable to create database indexes");
+        return Err(StartupError::new(StartupErrorKind::DatabaseSetup, err));
+    }
+    Ok((
+        Arc::new(note_db),
+        Arc::new(announcement_db),
+        Arc::new(idempotency_db),
+        Arc::new(api_key_db),
+        Arc::new(org_db),
+        Arc::new(share_db),
+    ))
+}
+
+/// Builds the router serving the API from `state`. Embedders that know
+/// their backend statically can pass a concrete `D` to avoid dynamic
+/// dispatch on every database call.
+pub fn create_axum_app<D: NoteDb + ?Sized + 'static>(
+    state: Arc<AppState<D>>,
+    app_config: &AppConfig,
+) -> Router {
+    let api_version = &app_config.api_version;
+    let timeouts = &app_config.route_timeouts;
+    let crud = |route| timeouts.on(RouteClass::Crud, route);
+    let bulk = |route| timeouts.on(RouteClass::Bulk, route);
+    // Without authentication there are no roles, and everyone is let in.
+    let admin = |route: MethodRouter<Arc<AppState<D>>>| match &app_config.auth {
+        Some(_) => route.layer(axum::middleware::from_fn(auth::require_admin)),
+        None => route,
+    };
+    let api = Router::new()
         .route(
             &format!("/{}/notes", api_version),
-            post(post_note).get(list_notes),
+            crud(post(post_note::<D>)).merge(bulk(get(list_notes::<D>))),
+        )
+        .route(
+            &format!("/{}/notes/import", api_version),
+            bulk(post(import_notes::<D>)),
+        )
+        .route(
+            &format!("/{}/import/notion", api_version),
+            bulk(post(import_notion::<D>)),
+        )
+        .route(
+            &format!("/{}/import/html", api_version),
+            bulk(post(import_html::<D>)),
+        )
+        .route(
+            &format!("/{}/import/jex", api_version),
+            bulk(post(import_jex::<D>)),
+        )
+        .route(
+            &format!("/{}/export.jex", api_version),
+            bulk(get(export_jex::<D>)),
+        )
+        .route(
+            &format!("/{}/export.csv", api_version),
+            bulk(get(export_csv::<D>)),
+        )
+        .route(
+            &format!("/{}/import.csv", api_version),
+            bulk(post(import_csv::<D>)),
+        )
+        .route(
+            &format!("/{}/notes/suggest", api_version),
+            bulk(get(suggest_notes::<D>)),
+        )
+        .route(
+            &format!("/{}/notes/lookup", api_version),
+            bulk(post(lookup_notes::<D>)),
         )
         .route(
             &format!("/{}/notes/{{id}}", api_version),
-            get(get_note).delete(delete_note).patch(patch_note),
+            crud(
+                get(get_note::<D>)
+                    .delete(delete_note::<D>)
+                    .patch(patch_note::<D>),
+            ),
+        )
+        .route(
+            &format!("/{}/notes/{{id}}/share", api_version),
+            crud(post(post_note_share::<D>)),
+        )
+        .route(
+            &format!("/{}/notes/{{id}}/share/{{share_id}}", api_version),
+            crud(delete(delete_note_share::<D>)),
+        )
+        .route(
+            &format!("/{}/notes/{{id}}/permissions", api_version),
+            crud(post(post_note_permission::<D>)),
+        )
+        .route(
+            &format!("/{}/notes/{{id}}/permissions/{{subject}}", api_version),
+            crud(delete(delete_note_permission::<D>)),
+        )
+        .route(
+            &format!("/{}/notes/{{id}}/versions", api_version),
+            crud(get(list_note_versions::<D>)),
+        )
+        .route(
+            &format!("/{}/notes/{{id}}/versions/{{rev}}", api_version),
+            crud(get(get_note_version::<D>)),
+        )
+        .route(
+            &format!("/{}/notes/{{id}}/versions/{{rev}}/restore", api_version),
+            crud(post(restore_note_version::<D>)),
+        )
+        .route(
+            &format!("/{}/announcements", api_version),
+            admin(crud(post(post_announcement::<D>)))
+                .merge(crud(get(list_announcements::<D>))),
+        )
+        .route(
+            &format!("/{}/announcements/{{id}}", api_version),
+            admin(crud(delete(delete_announcement::<D>))),
+        )
+        .route(
+            &format!("/{}/admin/migration", api_version),
+            admin(crud(get(get_migration::<D>).put(put_migration::<D>))),
+        )
+        .route(
+            &format!("/{}/admin/api-keys", api_version),
+            admin(crud(post(post_api_key::<D>).get(list_api_keys::<D>))),
+        )
+        .route(
+            &format!("/{}/admin/api-keys/{{id}}", api_version),
+            admin(crud(delete(delete_api_key::<D>))),
+        )
+        .route(
+            &format!("/{}/orgs", api_version),
+            crud(post(post_org::<D>).get(list_orgs::<D>)),
+        )
+        .route(
+            &format!("/{}/orgs/{{id}}", api_version),
+            crud(get(get_org::<D>)),
+        )
+        .route(
+            &format!("/{}/orgs/{{id}}/members/{{subject}}", api_version),
+            crud(put(put_org_member::<D>).delete(delete_org_member::<D>)),
+        )
+        .route(
+            &format!("/{}/admin/metrics", api_version),
+            admin(get(get_metrics)),
+        );
+    #[cfg(feature = "pdf")]
+    let api = api.route(
+        &format!("/{}/notes/{{id}}/pdf", api_version),
+        crud(get(get_note_pdf::<D>)),
+    );
+    // Profiles take as long as asked for, so no route timeout applies.
+    #[cfg(feature = "pprof")]
+    let api = api
+        .route(
+            &format!("/{}/admin/debug/pprof", api_version),
+            admin(get(debug::get_pprof)),
+        )
+        .route(
+            &format!("/{}/admin/debug/runtime", api_version),
+            admin(get(debug::get_runtime_metrics)),
+        );
+    // Inside auth, so that submissions are told apart by their principal.
+    let api = match app_config.dedupe_window {
+        Some(window) => DedupeWindow::new(window).apply(api),
+        None => api,
+    };
+    let api = match &app_config.auth {
+        Some(auth) => JwtAuth::new(auth.clone())
+            .with_api_keys(state.api_keys.clone())
+            .with_orgs(state.orgs.clone())
+            .apply(api),
+        None => api,
+    };
+    let api = match app_config.multi_tenant {
+        true => tenancy::require(api),
+        false => api,
+    };
+    let capabilities = Arc::new(Capabilities::of(app_config));
+    let api = api
+        .route(&format!("/{}/health", api_version), get(get_health))
+        .route(
+            &format!("/{}/shared/{{token}}", api_version),
+            crud(get(get_shared_note::<D>)),
+        )
+        .route(
+            &format!("/{}/capabilities", api_version),
+            get(capabilities::get_capabilities)
+                .layer(axum::Extension(capabilities)),
         )
-        .with_state(state)
-        .layer(TraceLayer::new_for_http())
+        .merge(openapi::routes(api_version.as_str()))
+        .with_state(state);
+    // Hedged reads may be answered by the mirror, and cached misses hide
+    // notes created since.
+    let consistency =
+        match (app_config.hedge_delay, app_config.negative_cache_ttl) {
+            (None, None) => Consistency::Strong,
+            _ => Consistency::Eventual,
+        };
+    let api = consistency::apply(api, consistency);
+    let app = app_config.security_headers.apply(api);
+    let app = body_limit::apply(app, app_config.max_request_bytes)
+        .layer(axum::middleware::from_fn(metrics::track_in_flight))
+        .layer(
+            TraceLayer::new_for_http().make_span_with(request_id::make_span),
+        );
+    let app = match app_config.multi_tenant {
+        true => tenancy::resolve(app),
+        false => app,
+    };
+    request_id::apply(app)
 }
 
 // Handlers
+#[utoipa::path(get, path = "/health", tag = "health",
+    responses((status = 200, description = "Service is up")))]
 pub async fn get_health() -> StatusCode {
     StatusCode::OK
 }
 
-pub async fn post_note(
-    State(state): State<Arc<AppState>>,
-    Json(new_note): Json<NewNote>,
-) -> Result<(StatusCode, Json<Note>), StatusCode> {
+pub async fn get_metrics() -> Json<metrics::Gauges> {
+    Json(metrics::gauges())
+}
+
+#[utoipa::path(post, path = "/notes", tag = "notes",
+    request_body = NewNote,
+    params(("idempotency-key" = Option<String>, Header,
+        description = "Replays the note created with the same key")),
+    responses(
+        (status = 201, body = Note, headers(
+            ("etag" = String),
+            ("x-duplicate-title-of" = String,
+                description = "Notes with the same title, if any"),
+            ("idempotent-replayed" = bool,
+                description = "Set if the note was created earlier"))),
+        (status = 400, description = "Invalid idempotency key"),
+        (status = 403, description = "Not a member of the organization"),
+        (status = 409, description = "Title already used"),
+        (status = 410, description = "Note of the idempotency key deleted"),
+        (status = 413, description = "Too large for the database"),
+        (status = 422, body = ValidationErrors),
+    ))]
+pub async fn post_note<D: NoteDb + ?Sized>(
+    State(state): State<Arc<AppState<D>>>,
+    ctx: OpContext,
+    headers: HeaderMap,
+    ValidJson(new_note): ValidJson<NewNote>,
+) -> Result<Response, Response> {
+    if let Some(org) = &new_note.org {
+        if !ctx.orgs.contains(org) {
+            return Err(Problem::new(
+                StatusCode::FORBIDDEN,
+                format!("not a member of organization {}", org),
+            )
+            .into_response());
+        }
+    }
+    // Keys are scoped to the user and tenant, so that a key guessed from
+    // another user doesn't replay their note.
+    let key = idempotency::idempotency_key(&headers)
+        .map_err(IntoResponse::into_response)?
+        .map(|key| {
+            let scope = |part: Option<&str>| {
+                part.map(|part| format!("{}:", part)).unwrap_or_default()
+            };
+            let tenant = scope(ctx.tenant.as_deref());
+            format!("{}{}{}", tenant, scope(ctx.owner()), key)
+        });
     let notes = state.notes.lock().await;
-    let id = nanoid!();
+    if let Some(key) = &key {
+        if let Some(note) = replay(&state, &ctx, &*notes, key).await? {
+            return Ok(created(
+                note,
+                vec![(idempotency::REPLAYED_HEADER, "true".to_string())],
+            ));
+        }
+    }
+    let duplicates = state
+        .title_policy
+        .check(&ctx, &*notes, &new_note.title, None)
+        .await
+        .map_err(|err| {
+            tracing::error!("unable to check for duplicate titles: {}", err);
+            note_db_response(&err)
+        })?;
+    if duplicates.rejected {
+        return Err(duplicates.into_response());
+    }
     let note = Note {
-        id: id.clone(),
-        title: new_note.title,
-        body: new_note.body,
-        url: format!("{}/{}", state.notes_path, id.clone()),
+        owner: ctx.principal.clone(),
+        tenant: ctx.tenant.clone(),
+        ..new_note.into_note(&state.notes_path)
     };
     tracing::debug!("create new note {:?}", note);
-    let Ok(_) = notes.create_note(&note).await else {
-        return Err(StatusCode::INTERNAL_SERVER_ERROR);
-    };
-    let Ok(note) = notes.get_note(&id).await else {
-        return Err(StatusCode::INTERNAL_SERVER_ERROR);
-    };
+    if let Some(ingest) = &state.ingest {
+        drop(notes);
+        ingest.create_note(&ctx, &note).await.map_err(|err| {
+            tracing::error!("unable to create note: {}", err);
+            note_db_response(&err)
+        })?;
+        remember_key(&state, key, &note).await;
+        return Ok(created(note, duplicates.header().into_iter().collect()));
+    }
+    notes.create_note(&ctx, &note).await.map_err(|err| {
+        tracing::error!("unable to create note: {}", err);
+        note_db_response(&err)
+    })?;
+    let note = notes.get_note(&ctx, &note.id).await.map_err(|err| {
+        tracing::error!("unable to get note after create: {}", err);
+        note_db_response(&err)
+    })?;
     let Some(note) = note else {
-        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        return Err(StatusCode::INTERNAL_SERVER_ERROR.into_response());
     };
-    Ok((StatusCode::CREATED, Json(note.clone())))
+    remember_key(&state, key, &note).await;
+    Ok(created(note, duplicates.header().into_iter().collect()))
 }
 
-pub async fn list_notes(
-    State(state): State<Arc<AppState>>,
-) -> Result<Json<Vec<Note>>, StatusCode> {
-    let notes = state.notes.lock().await;
-    tracing::debug!("list notes");
-    let Ok(notes) = notes.list_notes().await else {
-        tracing::error!("unable to get notes");
-        return Err(StatusCode::INTERNAL_SERVER_ERROR);
-    };
-    Ok(Json(notes))
+fn created(note: Note, headers: Vec<(&'static str, String)>) -> Response {
+    (
+        StatusCode::CREATED,
+        [(header::ETAG, etag(&note))],
+        AppendHeaders(headers),
+        Json(note),
+    )
+        .into_response()
 }
 
-pub async fn get_note(
-    State(state): State<Arc<AppState>>,
-    Path(id): Path<String>,
-) -> Result<Json<Note>, StatusCode> {
-    let notes = state.notes.lock().await;
-    let note = notes.get_note(&id).await;
-    let Ok(note) = note else {
-        tracing::error!("unable to get note");
-        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+/// The note an earlier create with the same idempotency key made. Answers
+/// 410 if that note has been deleted since, creating it again would
+/// resurrect it.
+async fn replay<D: NoteDb + ?Sized>(
+    state: &AppState<D>,
+    ctx: &OpContext,
+    notes: &D,
+    key: &str,
+) -> Result<Option<Note>, Response> {
+    let note_id = state
+        .idempotency
+        .find_key(key, chrono::Utc::now())
+        .await
+        .map_err(|err| {
+            tracing::error!("unable to look up idempotency key: {}", err);
+            note_db_response(&err)
+        })?;
+    let Some(note_id) = note_id else {
+        return Ok(None);
     };
-    let Some(note) = note else {
-        tracing::warn!("note not found {}", id);
-        return Err(StatusCode::NOT_FOUND);
+    let note = notes.get_note(ctx, &note_id).await.map_err(|err| {
+        tracing::error!("unable to get note of idempotency key: {}", err);
+        note_db_response(&err)
+    })?;
+    match note {
+        Some(note) => Ok(Some(note)),
+        None => Err(Problem::new(
+            StatusCode::GONE,
+            format!("note {} created with this key was deleted", note_id),
+        )
+        .into_response()),
+    }
+}
+
+/// Records the note created for an idempotency key. The note exists by
+/// now, so failing the request would only provoke the retry this guards
+/// against; a failure is logged instead.
+async fn remember_key<D: NoteDb + ?Sized>(
+    state: &AppState<D>,
+    key: Option<String>,
+    note: &Note,
+) {
+    let Some(key) = key else {
+        return;
     };
-    tracing::debug!("get note {}", id);
-    Ok(Json(note.clone()))
+    let record = IdempotencyRecord::new(
+        &key,
+        &note.id,
+        chrono::Utc::now(),
+        state.idempotency_ttl,
+    );
+    if let Err(err) = state.idempotency.record_key(&record).await {
+        tracing::error!("unable to record idempotency key: {}", err);
+    }
 }
 
-pub async fn delete_note(
-    State(state): State<Arc<AppState>>,
-    Path(id): Path<String>,
-) -> StatusCode {
+/// Imports a JSON array of new notes. Responds 422 if the import was
+/// aborted because of invalid items, the report tells which.
+#[utoipa::path(post, path = "/notes/import", tag = "import",
+    params(ImportQuery),
+    request_body = Vec<NewNote>,
+    responses(
+        (status = 200, body = ImportReport),
+        (status = 422, body = ImportReport,
+            description = "Aborted because of invalid items"),
+    ))]
+pub async fn import_notes<D: NoteDb + ?Sized>(
+    State(state): State<Arc<AppState<D>>>,
+    ctx: OpContext,
+    Query(query): Query<ImportQuery>,
+    RequestJson(items): RequestJson<Vec<serde_json::Value>>,
+) -> (StatusCode, Json<ImportReport>) {
+    let items = items
+        .into_iter()
+        .map(|item| {
+            let new_note = serde_json::from_value::<NewNote>(item)
+                .map_err(|err| err.to_string())?;
+            new_note
+                .validate(&state.note_limits)
+                .map_err(|err| err.to_string())?;
+            Ok(new_note.into_note(&state.notes_path))
+        })
+        .collect();
     let notes = state.notes.lock().await;
-    tracing::info!("delete note {}", id);
-    let Ok(res) = notes.delete_note(&id).await else {
-        tracing::error!("unable to delete note {}", id);
-        return StatusCode::INTERNAL_SERVER_ERROR;
-    };
+    let report =
+        import::import_notes(&ctx, &*notes, items, query, &state.note_limits)
+            .await;
+    tracing::info!(
+        "imported {} notes, {} failed",
+        report.created,
+        report.failed
+    );
+    (import_status(query.on_error, &report), Json(report))
+}
 
-    if !res {
-        tracing::info!("unable to delete note {} (not found)", id);
-        return StatusCode::NOT_FOUND;
+fn import_status(on_error: OnError, report: &ImportReport) -> StatusCode {
+    match on_error {
+        OnError::Abort if report.failed > 0 => StatusCode::UNPROCESSABLE_ENTITY,
+        _ => StatusCode::OK,
     }
+}
 
-    StatusCode::NO_CONTENT
+/// Exports all notes as CSV.
+#[utoipa::path(get, path = "/export.csv", tag = "export",
+    responses((status = 200, content_type = "text/csv", body = String)))]
+pub async fn export_csv<D: NoteDb + ?Sized>(
+    State(state): State<Arc<AppState<D>>>,
+    ctx: OpContext,
+) -> Result<impl IntoResponse, StatusCode> {
+    let notes = state.notes.lock().await;
+    let page = notes
+        .list_notes(&ctx, &NoteFilter::default(), &Page::default())
+        .await
+        .map_err(|err| {
+            tracing::error!("unable to get notes for export: {}", err);
+            note_db_status(&err)
+        })?;
+    let csv = csv_notes::write_notes(&page.notes).map_err(|err| {
+        tracing::error!("unable to write notes as csv: {}", err);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    Ok((
+        [
+            (header::CONTENT_TYPE, "text/csv; charset=utf-8"),
+            (
+                header::CONTENT_DISPOSITION,
+                "attachment; filename=\"notes.csv\"",
+            ),
+        ],
+        csv,
+    ))
 }
 
-pub async fn patch_note(
-    State(state): State<Arc<AppState>>,
-    Path(id): Path<String>,
-    Json(patch): Json<PatchNote>,
-) -> Result<(StatusCode, Json<Note>), StatusCode> {
+/// Imports notes from CSV with a header row. The query names the columns
+/// holding title, body and tags, e.g. `?title=Name&body=Content`.
+#[utoipa::path(post, path = "/import.csv", tag = "import",
+    params(ColumnMapping, ImportQuery),
+    request_body(content = String, content_type = "text/csv"),
+    responses(
+        (status = 200, body = ImportReport),
+        (status = 400, description = "Unreadable CSV"),
+        (status = 422, body = ImportReport,
+            description = "Aborted because of invalid rows"),
+    ))]
+pub async fn import_csv<D: NoteDb + ?Sized>(
+    State(state): State<Arc<AppState<D>>>,
+    ctx: OpContext,
+    Query(mapping): Query<ColumnMapping>,
+    Query(query): Query<ImportQuery>,
+    body: Bytes,
+) -> Response {
+    let rows = match csv_notes::read_notes(&body, &mapping) {
+        Ok(rows) => rows,
+        Err(err) => {
+            tracing::debug!("unable to read csv import: {}", err);
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(serde_json::json!({ "error": err })),
+            )
+                .into_response();
+        }
+    };
+    let items = rows
+        .into_iter()
+        .map(|row| {
+            let new_note = row?;
+            new_note
+                .validate(&state.note_limits)
+                .map_err(|err| err.to_string())?;
+            Ok(new_note.into_note(&state.notes_path))
+        })
+        .collect();
     let notes = state.notes.lock().await;
+    let report =
+        import::import_notes(&ctx, &*notes, items, query, &state.note_limits)
+            .await;
+    tracing::info!(
+        "imported {} notes from csv, {} failed",
+        report.created,
+        report.failed
+    );
+    (import_status(query.on_error, &report), Json(report)).into_response()
+}
 
-    tracing::info!("patch note {}", id);
-    tracing::debug!("patch note: apply patch {:?}", patch);
+/// Imports a Notion "Markdown & CSV" export zip.
+#[utoipa::path(post, path = "/import/notion", tag = "import",
+    params(ImportQuery),
+    request_body(content = Vec<u8>, content_type = "application/zip"),
+    responses(
+        (status = 200, body = ArchiveImportReport),
+        (status = 400, description = "Unreadable archive"),
+        (status = 422, body = ArchiveImportReport),
+    ))]
+pub async fn import_notion<D: NoteDb + ?Sized>(
+    State(state): State<Arc<AppState<D>>>,
+    ctx: OpContext,
+    Query(query): Query<ImportQuery>,
+    body: Bytes,
+) -> Response {
+    let export = match NotionExport::read(&body, state.archive_limits) {
+        Ok(export) => export,
+        Err(err) => return archive_error("notion export", &err),
+    };
+    let (notes, unmapped) = export.into_notes(&state.notes_path);
+    import_archive(&ctx, &state, notes, unmapped, query, "notion").await
+}
 
-    let res = notes.update_note(&id, &patch).await;
+/// Imports a Joplin JEX archive.
+#[utoipa::path(post, path = "/import/jex", tag = "import",
+    params(ImportQuery),
+    request_body(content = Vec<u8>, content_type = "application/x-tar"),
+    responses(
+        (status = 200, body = ArchiveImportReport),
+        (status = 400, description = "Unreadable archive"),
+        (status = 422, body = ArchiveImportReport),
+    ))]
+pub async fn import_jex<D: NoteDb + ?Sized>(
+    State(state): State<Arc<AppState<D>>>,
+    ctx: OpContext,
+    Query(query): Query<ImportQuery>,
+    body: Bytes,
+) -> Response {
+    let export = match JoplinExport::read(&body) {
+        Ok(export) => export,
+        Err(err) => return archive_error("jex archive", &err),
+    };
+    let (notes, unmapped) = export.into_notes(&state.notes_path);
+    import_archive(&ctx, &state, notes, unmapped, query, "joplin").await
+}
 
-    let Ok(()) = res else {
-        tracing::error!("unable to update note");
-        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+/// Imports a zip of HTML pages, like an Apple Notes export.
+#[utoipa::path(post, path = "/import/html", tag = "import",
+    params(ImportQuery),
+    request_body(content = Vec<u8>, content_type = "application/zip"),
+    responses(
+        (status = 200, body = ArchiveImportReport),
+        (status = 400, description = "Unreadable archive"),
+        (status = 422, body = ArchiveImportReport),
+    ))]
+pub async fn import_html<D: NoteDb + ?Sized>(
+    State(state): State<Arc<AppState<D>>>,
+    ctx: OpContext,
+    Query(query): Query<ImportQuery>,
+    body: Bytes,
+) -> Response {
+    let export = match HtmlExport::read(&body, state.archive_limits) {
+        Ok(export) => export,
+        Err(err) => return archive_error("html export", &err),
     };
+    let (notes, unmapped) = export.into_notes(&state.notes_path);
+    import_archive(&ctx, &state, notes, unmapped, query, "html").await
+}
 
-    let Ok(note) = notes.get_note(&id).await else {
-        tracing::error!("unable to get note after update");
-        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+fn archive_error(kind: &str, err: &dyn std::error::Error) -> Response {
+    tracing::debug!("unable to read {}: {}", kind, err);
+    (
+        StatusCode::BAD_REQUEST,
+        Json(serde_json::json!({ "error": err.to_string() })),
+    )
+        .into_response()
+}
+
+async fn import_archive<D: NoteDb + ?Sized>(
+    ctx: &OpContext,
+    state: &AppState<D>,
+    notes: Vec<Note>,
+    unmapped: Vec<String>,
+    query: ImportQuery,
+    source: &str,
+) -> Response {
+    let items = notes
+        .into_iter()
+        .map(|note| {
+            note.validate(&state.note_limits)
+                .map_err(|err| err.to_string())?;
+            Ok(note)
+        })
+        .collect();
+    let notes = state.notes.lock().await;
+    let report =
+        import::import_notes(ctx, &*notes, items, query, &state.note_limits)
+            .await;
+    tracing::info!(
+        "imported {} notes from {}, {} failed, {} unmapped",
+        report.created,
+        source,
+        report.failed,
+        unmapped.len()
+    );
+    let status = import_status(query.on_error, &report);
+    let report = ArchiveImportReport {
+        import: report,
+        unmapped,
     };
+    (status, Json(report)).into_response()
+}
 
-    let Some(note) = note else {
-        tracing::error!("unable to get note after update");
-        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+/// Exports all notes as Joplin JEX archive.
+#[utoipa::path(get, path = "/export.jex", tag = "export",
+    responses((status = 200, content_type = "application/x-tar",
+        body = Vec<u8>)))]
+pub async fn export_jex<D: NoteDb + ?Sized>(
+    State(state): State<Arc<AppState<D>>>,
+    ctx: OpContext,
+) -> Result<impl IntoResponse, StatusCode> {
+    let notes = state.notes.lock().await;
+    let page = notes
+        .list_notes(&ctx, &NoteFilter::default(), &Page::default())
+        .await
+        .map_err(|err| {
+            tracing::error!("unable to get notes for export: {}", err);
+            note_db_status(&err)
+        })?;
+    let jex = joplin::write_export(&page.notes).map_err(|err| {
+        tracing::error!("unable to write notes as jex: {}", err);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/x-tar"),
+            (
+                header::CONTENT_DISPOSITION,
+                "attachment; filename=\"notes.jex\"",
+            ),
+        ],
+        jex,
+    ))
+}
+
+/// Lists notes, paged with `limit` and `offset`. Without a limit the
+/// default page size applies, limits above the maximum page size are
+/// lowered to it. The number of matching notes is returned in the
+/// `X-Total-Count` header, the limit applied in `X-Page-Limit`.
+#[utoipa::path(get, path = "/notes", tag = "notes",
+    params(NoteFilter, Page),
+    responses((status = 200, body = Vec<Note>, headers(
+        ("x-total-count" = u64, description = "Number of matching notes"),
+        ("x-page-limit" = u64, description = "Limit applied to the page"),
+    ))))]
+pub async fn list_notes<D: NoteDb + ?Sized>(
+    State(state): State<Arc<AppState<D>>>,
+    ctx: OpContext,
+    Query(filter): Query<NoteFilter>,
+    Query(page): Query<Page>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let limit = page
+        .limit
+        .unwrap_or(state.default_page_size)
+        .min(state.max_page_size);
+    let page = Page {
+        limit: Some(limit),
+        ..page
     };
+    let notes = state.notes.lock().await;
+    tracing::debug!("list notes {:?} {:?}", filter, page);
+    let page = notes
+        .list_notes(&ctx, &filter, &page)
+        .await
+        .map_err(|err| {
+            tracing::error!("unable to get notes: {}", err);
+            note_db_status(&err)
+        })?;
+    Ok((
+        [(TOTAL_COUNT_HEADER, page.total), (PAGE_LIMIT_HEADER, limit)],
+        JsonArray(page.notes),
+    ))
+}
 
-    Ok((StatusCode::OK, Json(note.clone())))
+#[derive(Debug, Deserialize, utoipa::IntoParams)]
+pub struct SuggestQuery {
+    pub q: String,
+    pub limit: Option<usize>,
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+/// Suggests notes whose title starts with `q`, compared case-insensitively,
+/// for typeahead, followed by notes whose title shares most trigrams with
+/// `q`, so that misspelled queries still find notes.
+#[utoipa::path(get, path = "/notes/suggest", tag = "notes",
+    params(SuggestQuery),
+    responses((status = 200, body = Vec<NoteSuggestion>)))]
+pub async fn suggest_notes<D: NoteDb + ?Sized>(
+    State(state): State<Arc<AppState<D>>>,
+    ctx: OpContext,
+    Query(query): Query<SuggestQuery>,
+) -> Result<Json<Vec<NoteSuggestion>>, StatusCode> {
+    let limit = query
+        .limit
+        .unwrap_or(SUGGEST_DEFAULT_LIMIT)
+        .min(SUGGEST_MAX_LIMIT);
+    if query.q.is_empty() || limit == 0 {
+        return Ok(Json(Vec::new()));
+    }
+    let notes = state.notes.lock().await;
+    tracing::debug!("suggest notes for {}", query.q);
+    let suggestions = notes
+        .suggest_titles(&ctx, &query.q, limit)
+        .await
+        .map_err(|err| {
+            tracing::error!("unable to suggest notes: {}", err);
+            note_db_status(&err)
+        })?;
+    Ok(Json(suggestions))
+}
 
-    use async_trait::async_trait;
-    use axum::{body::Body, http::Request, response::Response};
-    use http_body_util::BodyExt;
-    use std::sync::{
-        self,
-        atomic::{AtomicBool, Ordering},
-        Arc,
+/// Fetches several notes in one round trip, e.g. for clients syncing a
+/// known set of notes.
+#[utoipa::path(post, path = "/notes/lookup", tag = "notes",
+    request_body = NoteLookup,
+    responses(
+        (status = 200, body = NoteLookupResult),
+        (status = 422, body = ValidationErrors),
+    ))]
+pub async fn lookup_notes<D: NoteDb + ?Sized>(
+    State(state): State<Arc<AppState<D>>>,
+    ctx: OpContext,
+    ValidJson(lookup): ValidJson<NoteLookup>,
+) -> Result<Json<NoteLookupResult>, StatusCode> {
+    let mut ids = lookup.ids;
+    let mut seen = std::collections::HashSet::new();
+    ids.retain(|id| seen.insert(id.clone()));
+    let notes = state.notes.lock().await;
+    tracing::debug!("look up {} notes", ids.len());
+    let found = notes.get_notes(&ctx, &ids).await.map_err(|err| {
+        tracing::error!("unable to look up notes: {}", err);
+        note_db_status(&err)
+    })?;
+    let mut found: std::collections::HashMap<String, Note> = found
+        .into_iter()
+        .map(|note| (note.id.clone(), note))
+        .collect();
+    let mut result = NoteLookupResult {
+        notes: Vec::new(),
+        missing: Vec::new(),
     };
-    use tower::ServiceExt;
-
-    impl NewNote {
-        fn new(title: &str, body: &str) -> NewNote {
-            NewNote {
-                title: title.to_string(),
-                body: body.to_string(),
-            }
+    for id in ids {
+        match found.remove(&id) {
+            Some(note) => result.notes.push(note),
+            None => result.missing.push(id),
         }
     }
+    Ok(Json(result))
+}
 
-    struct NoteVecDb {
-        vec: sync::Mutex<Vec<Note>>,
-        fail_create: AtomicBool,
-        fail_get: AtomicBool,
-        none_get: AtomicBool,
-        fail_update: AtomicBool,
+#[utoipa::path(get, path = "/notes/{id}", tag = "notes",
+    params(("id" = String, Path)),
+    responses(
+        (status = 200, body = Note, headers(("etag" = String))),
+        (status = 304, description = "Matches If-None-Match"),
+        (status = 404),
+    ))]
+pub async fn get_note<D: NoteDb + ?Sized>(
+    State(state): State<Arc<AppState<D>>>,
+    ctx: OpContext,
+    preconditions: Preconditions,
+    Path(id): Path<String>,
+) -> Result<Response, StatusCode> {
+    let notes = state.notes.lock().await;
+    let note = notes.get_note(&ctx, &id).await.map_err(|err| {
+        tracing::error!("unable to get note: {}", err);
+        note_db_status(&err)
+    })?;
+    let Some(note) = note else {
+        tracing::warn!("note not found {}", id);
+        return Err(StatusCode::NOT_FOUND);
+    };
+    tracing::debug!("get note {}", id);
+    let etag = [(header::ETAG, etag(&note))];
+    if preconditions.not_modified(&note) {
+        return Ok((StatusCode::NOT_MODIFIED, etag).into_response());
+    }
+    Ok((etag, Json(note)).into_response())
+}
+
+/// Creates a link serving the note to anyone holding it, without
+/// authentication. The token is only part of this response.
+#[utoipa::path(post, path = "/notes/{id}/share", tag = "notes",
+    params(("id" = String, Path), ShareQuery),
+    responses(
+        (status = 201, body = CreatedShare),
+        (status = 400, description = "Expiry out of range"),
+        (status = 403, description = "Not the owner of the note"),
+        (status = 404),
+    ))]
+pub async fn post_note_share<D: NoteDb + ?Sized>(
+    State(state): State<Arc<AppState<D>>>,
+    ctx: OpContext,
+    Path(id): Path<String>,
+    Query(query): Query<ShareQuery>,
+) -> Result<(StatusCode, Json<CreatedShare>), StatusCode> {
+    let notes = state.notes.lock().await;
+    let note = notes.get_note(&ctx, &id).await.map_err(|err| {
+        tracing::error!("unable to get note: {}", err);
+        note_db_status(&err)
+    })?;
+    drop(notes);
+    let Some(note) = note else {
+        return Err(StatusCode::NOT_FOUND);
+    };
+    if !ctx.can_manage(&note) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let now = chrono::Utc::now();
+    let expires_at = match query.expires_in_secs {
+        Some(secs) => Some(
+            i64::try_from(secs)
+                .ok()
+                .and_then(chrono::Duration::try_seconds)
+                .and_then(|expires_in| now.checked_add_signed(expires_in))
+                .ok_or(StatusCode::BAD_REQUEST)?,
+        ),
+        None => None,
+    };
+    let (share, token) =
+        Share::generate(&note.id, note.tenant, now, expires_at);
+    tracing::info!("share note {} as {}", id, share.id);
+    state.shares.create_share(&share).await.map_err(|err| {
+        tracing::error!("unable to create share: {}", err);
+        note_db_status(&err)
+    })?;
+    Ok((
+        StatusCode::CREATED,
+        Json(CreatedShare {
+            url: shared_url(&state.notes_path, &token),
+            id: share.id,
+            note_id: share.note_id,
+            expires_at: share.expires_at,
+            token,
+        }),
+    ))
+}
+
+/// The URL of a shared note, next to the notes under `notes_path`.
+fn shared_url(notes_path: &str, token: &str) -> String {
+    let api_root = notes_path.strip_suffix("/notes").unwrap_or(notes_path);
+    format!("{}/shared/{}", api_root, token)
+}
+
+/// Revokes a link sharing the note.
+#[utoipa::path(delete, path = "/notes/{id}/share/{share_id}", tag = "notes",
+    params(("id" = String, Path), ("share_id" = String, Path)),
+    responses(
+        (status = 204),
+        (status = 403, description = "Not the owner of the note"),
+        (status = 404),
+    ))]
+pub async fn delete_note_share<D: NoteDb + ?Sized>(
+    State(state): State<Arc<AppState<D>>>,
+    ctx: OpContext,
+    Path((id, share_id)): Path<(String, String)>,
+) -> Result<StatusCode, StatusCode> {
+    let notes = state.notes.lock().await;
+    let note = notes.get_note(&ctx, &id).await.map_err(|err| {
+        tracing::error!("unable to get note: {}", err);
+        note_db_status(&err)
+    })?;
+    drop(notes);
+    let Some(note) = note else {
+        return Err(StatusCode::NOT_FOUND);
+    };
+    if !ctx.can_manage(&note) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    tracing::info!("revoke share {} of note {}", share_id, id);
+    let deleted =
+        state
+            .shares
+            .delete_share(&id, &share_id)
+            .await
+            .map_err(|err| {
+                tracing::error!("unable to delete share {}: {}", share_id, err);
+                note_db_status(&err)
+            })?;
+    match deleted {
+        true => Ok(StatusCode::NO_CONTENT),
+        false => Err(StatusCode::NOT_FOUND),
+    }
+}
+
+/// Grants a user read or write access to the note, replacing any access
+/// they had. Readers can't change the note, and neither readers nor
+/// writers can delete or share it.
+#[utoipa::path(post, path = "/notes/{id}/permissions", tag = "notes",
+    params(("id" = String, Path)),
+    request_body = NewPermission,
+    responses(
+        (status = 200, body = Permissions),
+        (status = 403, description = "Not the owner of the note"),
+        (status = 404),
+        (status = 409, description = "The note changed concurrently"),
+    ))]
+pub async fn post_note_permission<D: NoteDb + ?Sized>(
+    State(state): State<Arc<AppState<D>>>,
+    ctx: OpContext,
+    Path(id): Path<String>,
+    RequestJson(permission): RequestJson<NewPermission>,
+) -> Result<Json<Permissions>, Response> {
+    let notes = state.notes.lock().await;
+    let note = managed_note(&*notes, &ctx, &id).await?;
+    let mut permissions = note.permissions.clone();
+    tracing::info!(
+        "grant {:?} access on note {} to {}",
+        permission.access,
+        id,
+        permission.subject
+    );
+    permissions.grant(&permission.subject, permission.access);
+    update_permissions(&*notes, &ctx, &note, &permissions).await?;
+    Ok(Json(permissions))
+}
+
+/// Revokes all access of a user to the note.
+#[utoipa::path(delete, path = "/notes/{id}/permissions/{subject}",
+    tag = "notes",
+    params(("id" = String, Path), ("subject" = String, Path)),
+    responses(
+        (status = 204),
+        (status = 403, description = "Not the owner of the note"),
+        (status = 404, description = "No such note or no access to revoke"),
+    ))]
+pub async fn delete_note_permission<D: NoteDb + ?Sized>(
+    State(state): State<Arc<AppState<D>>>,
+    ctx: OpContext,
+    Path((id, subject)): Path<(String, String)>,
+) -> Result<StatusCode, Response> {
+    let notes = state.notes.lock().await;
+    let note = managed_note(&*notes, &ctx, &id).await?;
+    let mut permissions = note.permissions.clone();
+    if !permissions.revoke(&subject) {
+        return Err(StatusCode::NOT_FOUND.into_response());
+    }
+    tracing::info!("revoke access on note {} from {}", id, subject);
+    update_permissions(&*notes, &ctx, &note, &permissions).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// The note, if the caller may grant access to it.
+async fn managed_note<D: NoteDb + ?Sized>(
+    notes: &D,
+    ctx: &OpContext,
+    id: &str,
+) -> Result<Note, Response> {
+    let note = notes.get_note(ctx, id).await.map_err(|err| {
+        tracing::error!("unable to get note: {}", err);
+        note_db_response(&err)
+    })?;
+    let Some(note) = note else {
+        return Err(StatusCode::NOT_FOUND.into_response());
+    };
+    if !ctx.can_manage(&note) {
+        return Err(StatusCode::FORBIDDEN.into_response());
+    }
+    Ok(note)
+}
+
+/// Replaces the permissions of `note`, failing with a conflict if it has
+/// changed since it was read.
+async fn update_permissions<D: NoteDb + ?Sized>(
+    notes: &D,
+    ctx: &OpContext,
+    note: &Note,
+    permissions: &Permissions,
+) -> Result<(), Response> {
+    let patch = PatchNote {
+        title: None,
+        body: None,
+        tags: None,
+        updated_at: notes::now(),
+        version: Some(note.version),
+        permissions: Some(permissions.clone()),
+    };
+    notes
+        .update_note(ctx, &note.id, &patch)
+        .await
+        .map_err(|err| {
+            tracing::error!(
+                "unable to update permissions of {}: {}",
+                note.id,
+                err
+            );
+            note_db_response(&err)
+        })
+}
+
+/// Serves a shared note read-only. Answered without authentication, the
+/// token is the credential.
+#[utoipa::path(get, path = "/shared/{token}", tag = "notes",
+    params(("token" = String, Path)),
+    responses(
+        (status = 200, body = Note),
+        (status = 404, description = "Unknown, revoked or expired link"),
+    ))]
+pub async fn get_shared_note<D: NoteDb + ?Sized>(
+    State(state): State<Arc<AppState<D>>>,
+    ctx: OpContext,
+    Path(token): Path<String>,
+) -> Result<Response, StatusCode> {
+    let share = state
+        .shares
+        .find_share(&api_keys::hash_key(&token))
+        .await
+        .map_err(|err| {
+            tracing::error!("unable to look up share: {}", err);
+            note_db_status(&err)
+        })?;
+    let Some(share) = share.filter(|s| !s.is_expired(chrono::Utc::now()))
+    else {
+        return Err(StatusCode::NOT_FOUND);
+    };
+    // The share grants access to its note only, whoever the caller is.
+    let ctx = OpContext {
+        principal: None,
+        orgs: Vec::new(),
+        tenant: share.tenant,
+        ..ctx
+    };
+    let notes = state.notes.lock().await;
+    let note = notes.get_note(&ctx, &share.note_id).await.map_err(|err| {
+        tracing::error!("unable to get shared note: {}", err);
+        note_db_status(&err)
+    })?;
+    let Some(mut note) = note else {
+        return Err(StatusCode::NOT_FOUND);
+    };
+    // Who else has access is none of the link holder's business.
+    note.permissions = Permissions::default();
+    Ok(([(header::CACHE_CONTROL, "private, no-store")], Json(note))
+        .into_response())
+}
+
+/// Lists the states a note had before each of its patches, oldest first.
+#[utoipa::path(get, path = "/notes/{id}/versions", tag = "notes",
+    params(("id" = String, Path)),
+    responses(
+        (status = 200, body = Vec<NoteRevision>),
+        (status = 404),
+        (status = 501, description = "The backend keeps no history"),
+    ))]
+pub async fn list_note_versions<D: NoteDb + ?Sized>(
+    State(state): State<Arc<AppState<D>>>,
+    ctx: OpContext,
+    Path(id): Path<String>,
+) -> Result<Json<Vec<NoteRevision>>, StatusCode> {
+    let notes = state.notes.lock().await;
+    let note = notes.get_note(&ctx, &id).await.map_err(|err| {
+        tracing::error!("unable to get note: {}", err);
+        note_db_status(&err)
+    })?;
+    if note.is_none() {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let revisions = notes.list_revisions(&ctx, &id).await.map_err(|err| {
+        tracing::error!("unable to list revisions: {}", err);
+        note_db_status(&err)
+    })?;
+    Ok(Json(revisions))
+}
+
+#[utoipa::path(get, path = "/notes/{id}/versions/{rev}", tag = "notes",
+    params(("id" = String, Path), ("rev" = u64, Path)),
+    responses(
+        (status = 200, body = NoteRevision),
+        (status = 404),
+        (status = 501, description = "The backend keeps no history"),
+    ))]
+pub async fn get_note_version<D: NoteDb + ?Sized>(
+    State(state): State<Arc<AppState<D>>>,
+    ctx: OpContext,
+    Path((id, rev)): Path<(String, u64)>,
+) -> Result<Json<NoteRevision>, StatusCode> {
+    let notes = state.notes.lock().await;
+    // Revisions belong to their note, which may not be the caller's.
+    let note = notes.get_note(&ctx, &id).await.map_err(|err| {
+        tracing::error!("unable to get note: {}", err);
+        note_db_status(&err)
+    })?;
+    if note.is_none() {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let revision = notes.get_revision(&ctx, &id, rev).await.map_err(|err| {
+        tracing::error!("unable to get revision: {}", err);
+        note_db_status(&err)
+    })?;
+    match revision {
+        Some(revision) => Ok(Json(revision)),
+        None => Err(StatusCode::NOT_FOUND),
+    }
+}
+
+/// Makes an old revision the current content of the note. The content
+/// replaced by the rollback is kept as a new revision, and the restored
+/// title checked against the duplicate title policy, like for a patch.
+#[utoipa::path(post, path = "/notes/{id}/versions/{rev}/restore",
+    tag = "notes",
+    params(("id" = String, Path), ("rev" = u64, Path)),
+    responses(
+        (status = 200, body = Note, headers(
+            ("etag" = String),
+            ("x-duplicate-title-of" = String,
+                description = "Notes with the same title, if any"))),
+        (status = 403, description = "Shared with the caller read-only"),
+        (status = 404),
+        (status = 409, description = "Another note has the restored title"),
+        (status = 501, description = "The backend keeps no history"),
+    ))]
+pub async fn restore_note_version<D: NoteDb + ?Sized>(
+    State(state): State<Arc<AppState<D>>>,
+    ctx: OpContext,
+    preconditions: Preconditions,
+    Path((id, rev)): Path<(String, u64)>,
+) -> Result<impl IntoResponse, Response> {
+    let notes = state.notes.lock().await;
+    let revision = notes.get_revision(&ctx, &id, rev).await.map_err(|err| {
+        tracing::error!("unable to get revision: {}", err);
+        note_db_response(&err)
+    })?;
+    let Some(revision) = revision else {
+        return Err(StatusCode::NOT_FOUND.into_response());
+    };
+    tracing::info!("restore note {} to revision {}", id, rev);
+    let duplicates = state
+        .title_policy
+        .check(&ctx, &*notes, &revision.title, Some(&id))
+        .await
+        .map_err(|err| {
+            tracing::error!("unable to check for duplicate titles: {}", err);
+            note_db_response(&err)
+        })?;
+    if duplicates.rejected {
+        return Err(duplicates.into_response());
+    }
+    let patch = PatchNote {
+        title: Some(revision.title),
+        body: Some(revision.body),
+        tags: Some(revision.tags),
+        updated_at: notes::now(),
+        version: None,
+        permissions: None,
+    };
+    let note = update_with_revision(
+        &*notes,
+        &ctx,
+        &id,
+        &patch,
+        &preconditions,
+        state.require_if_match,
+    )
+    .await?;
+    Ok((
+        [(header::ETAG, etag(&note))],
+        AppendHeaders(duplicates.header()),
+        Json(note),
+    ))
+}
+
+#[cfg(feature = "pdf")]
+#[utoipa::path(get, path = "/notes/{id}/pdf", tag = "export",
+    params(("id" = String, Path)),
+    responses(
+        (status = 200, content_type = "application/pdf", body = Vec<u8>),
+        (status = 404),
+    ))]
+pub async fn get_note_pdf<D: NoteDb + ?Sized>(
+    State(state): State<Arc<AppState<D>>>,
+    ctx: OpContext,
+    Path(id): Path<String>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let notes = state.notes.lock().await;
+    let note = notes.get_note(&ctx, &id).await.map_err(|err| {
+        tracing::error!("unable to get note: {}", err);
+        note_db_status(&err)
+    })?;
+    let Some(note) = note else {
+        tracing::warn!("note not found {}", id);
+        return Err(StatusCode::NOT_FOUND);
+    };
+    let pdf = pdf::render_note(&note).map_err(|err| {
+        tracing::error!("unable to render note as pdf: {}", err);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/pdf".to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("inline; filename=\"{}.pdf\"", note.id),
+            ),
+        ],
+        pdf,
+    ))
+}
+
+#[utoipa::path(delete, path = "/notes/{id}", tag = "notes",
+    params(("id" = String, Path)),
+    responses(
+        (status = 204),
+        (status = 403, description = "Not the owner of the note"),
+        (status = 404),
+        (status = 412, description = "If-Match doesn't match"),
+        (status = 428, description = "If-Match is required"),
+    ))]
+pub async fn delete_note<D: NoteDb + ?Sized>(
+    State(state): State<Arc<AppState<D>>>,
+    ctx: OpContext,
+    preconditions: Preconditions,
+    Path(id): Path<String>,
+) -> StatusCode {
+    let notes = state.notes.lock().await;
+    tracing::info!("delete note {}", id);
+    // Unconditional deletes need no read, unless collaborators must be
+    // kept from deleting notes shared with them.
+    if state.require_if_match
+        || preconditions != Preconditions::default()
+        || ctx.owner().is_some()
+    {
+        let current = match notes.get_note(&ctx, &id).await {
+            Ok(Some(current)) => current,
+            Ok(None) => return StatusCode::NOT_FOUND,
+            Err(err) => {
+                tracing::error!("unable to get note {}: {}", id, err);
+                return note_db_status(&err);
+            }
+        };
+        if !ctx.can_manage(&current) {
+            return StatusCode::FORBIDDEN;
+        }
+        if let Err(status) =
+            preconditions.check_write(&current, state.require_if_match)
+        {
+            return status;
+        }
+    }
+    let res = match notes.delete_note(&ctx, &id).await {
+        Ok(res) => res,
+        Err(err) => {
+            tracing::error!("unable to delete note {}: {}", id, err);
+            return note_db_status(&err);
+        }
+    };
+
+    if !res {
+        tracing::info!("unable to delete note {} (not found)", id);
+        return StatusCode::NOT_FOUND;
+    }
+
+    StatusCode::NO_CONTENT
+}
+
+/// Patches a note with a `PatchNote`, or with a JSON Patch against the
+/// note document if sent as `application/json-patch+json`.
+#[utoipa::path(patch, path = "/notes/{id}", tag = "notes",
+    params(("id" = String, Path)),
+    request_body(content(
+        (PatchNote = "application/json"),
+        (Vec<Object> = "application/json-patch+json"),
+    )),
+    responses(
+        (status = 200, body = Note, headers(
+            ("etag" = String),
+            ("x-duplicate-title-of" = String,
+                description = "Notes with the same title, if any"))),
+        (status = 403, description = "Shared with the caller read-only"),
+        (status = 404),
+        (status = 409,
+            description = "Duplicate title, stale version or failed test"),
+        (status = 412, description = "If-Match doesn't match"),
+        (status = 413, description = "Too large for the database"),
+        (status = 422, body = ValidationErrors),
+        (status = 428, description = "If-Match is required"),
+    ))]
+pub async fn patch_note<D: NoteDb + ?Sized>(
+    State(state): State<Arc<AppState<D>>>,
+    ctx: OpContext,
+    preconditions: Preconditions,
+    Path(id): Path<String>,
+    patch: NotePatch,
+) -> Result<impl IntoResponse, Response> {
+    let notes = state.notes.lock().await;
+    let patch = match patch {
+        NotePatch::Merge(patch) => patch,
+        NotePatch::Json(ops) => {
+            let note = notes.get_note(&ctx, &id).await.map_err(|err| {
+                tracing::error!("unable to get note to patch: {}", err);
+                note_db_response(&err)
+            })?;
+            let Some(note) = note else {
+                return Err(StatusCode::NOT_FOUND.into_response());
+            };
+            let patch = note_patch::to_patch_note(&ops, &note)
+                .map_err(IntoResponse::into_response)?;
+            patch
+                .validate(&state.note_limits)
+                .map_err(IntoResponse::into_response)?;
+            patch
+        }
+    };
+
+    tracing::info!("patch note {}", id);
+    tracing::debug!("patch note: apply patch {:?}", patch);
+
+    let duplicates = match &patch.title {
+        Some(title) => state
+            .title_policy
+            .check(&ctx, &*notes, title, Some(&id))
+            .await
+            .map_err(|err| {
+                tracing::error!(
+                    "unable to check for duplicate titles: {}",
+                    err
+                );
+                note_db_response(&err)
+            })?,
+        None => Duplicates::default(),
+    };
+    if duplicates.rejected {
+        return Err(duplicates.into_response());
+    }
+
+    let note = update_with_revision(
+        &*notes,
+        &ctx,
+        &id,
+        &patch,
+        &preconditions,
+        state.require_if_match,
+    )
+    .await?;
+
+    Ok((
+        StatusCode::OK,
+        [(header::ETAG, etag(&note))],
+        AppendHeaders(duplicates.header()),
+        Json(note),
+    ))
+}
+
+/// Checks the preconditions of the request against the current state of
+/// the note and records that state as a revision, then applies the patch
+/// and returns the updated note.
+async fn update_with_revision<D: NoteDb + ?Sized>(
+    notes: &D,
+    ctx: &OpContext,
+    id: &str,
+    patch: &PatchNote,
+    preconditions: &Preconditions,
+    require_if_match: bool,
+) -> Result<Note, Response> {
+    let previous = notes.get_note(ctx, id).await.map_err(|err| {
+        tracing::error!("unable to get note before update: {}", err);
+        note_db_response(&err)
+    })?;
+    let Some(previous) = previous else {
+        return Err(StatusCode::NOT_FOUND.into_response());
+    };
+    if !ctx.can_edit(&previous) {
+        return Err(StatusCode::FORBIDDEN.into_response());
+    }
+    preconditions
+        .check_write(&previous, require_if_match)
+        .map_err(IntoResponse::into_response)?;
+    patch
+        .check_version(&previous)
+        .map_err(|err| note_db_response(&err))?;
+    // Refuses the update if another writer got in since the read above.
+    let patch = PatchNote {
+        version: Some(previous.version),
+        ..patch.clone()
+    };
+    match notes.record_revision(ctx, &previous).await {
+        Ok(rev) => tracing::debug!("recorded revision {} of {}", rev, id),
+        // Backends without history still take patches.
+        Err(NoteDbError::Unsupported(_)) => {}
+        Err(err) => {
+            tracing::error!("unable to record revision: {}", err);
+            return Err(note_db_response(&err));
+        }
+    }
+
+    notes.update_note(ctx, id, &patch).await.map_err(|err| {
+        tracing::error!("unable to update note: {}", err);
+        note_db_response(&err)
+    })?;
+
+    let note = notes.get_note(ctx, id).await.map_err(|err| {
+        tracing::error!("unable to get note after update: {}", err);
+        note_db_response(&err)
+    })?;
+    note.ok_or_else(|| {
+        tracing::error!("unable to get note after update");
+        StatusCode::INTERNAL_SERVER_ERROR.into_response()
+    })
+}
+
+/// Maps a `NoteDb` error to the status returned to clients.
+fn note_db_status(err: &NoteDbError) -> StatusCode {
+    match err {
+        NoteDbError::NotFound => StatusCode::NOT_FOUND,
+        NoteDbError::Conflict(_) => StatusCode::CONFLICT,
+        NoteDbError::Connection(_) => StatusCode::SERVICE_UNAVAILABLE,
+        NoteDbError::DeadlineExceeded => StatusCode::GATEWAY_TIMEOUT,
+        NoteDbError::TooLarge { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+        NoteDbError::Unsupported(_) => StatusCode::NOT_IMPLEMENTED,
+        NoteDbError::Serialization(_) | NoteDbError::Other(_) => {
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+/// Like `note_db_status`, with a problem body for errors the client can
+/// act on.
+fn note_db_response(err: &NoteDbError) -> Response {
+    match err {
+        NoteDbError::TooLarge { limit } => Problem::new(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!(
+                "the note is larger than the {} bytes the database can \
+                 store, keep large content in an attachment or blob store \
+                 and link to it from the body",
+                limit
+            ),
+        )
+        .with_extension("limit", *limit)
+        .into_response(),
+        NoteDbError::Conflict(reason) => {
+            Problem::new(StatusCode::CONFLICT, reason.clone()).into_response()
+        }
+        _ => note_db_status(err).into_response(),
+    }
+}
+
+#[utoipa::path(post, path = "/announcements", tag = "announcements",
+    request_body = NewAnnouncement,
+    responses(
+        (status = 201, body = Announcement),
+        (status = 400),
+        (status = 403, description = "Not an admin"),
+    ))]
+pub async fn post_announcement<D: NoteDb + ?Sized>(
+    State(state): State<Arc<AppState<D>>>,
+    RequestJson(new_announcement): RequestJson<NewAnnouncement>,
+) -> Result<(StatusCode, Json<Announcement>), StatusCode> {
+    let announcement = Announcement::new(new_announcement, chrono::Utc::now());
+    if announcement
+        .ends_at
+        .is_some_and(|end| end <= announcement.starts_at)
+    {
+        tracing::warn!("announcement ends before it starts");
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    tracing::info!("create announcement {}", announcement.id);
+    let Ok(_) = state.announcements.create_announcement(&announcement).await
+    else {
+        tracing::error!("unable to create announcement");
+        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+    };
+    Ok((StatusCode::CREATED, Json(announcement)))
+}
+
+#[utoipa::path(get, path = "/announcements", tag = "announcements",
+    responses((status = 200, body = Vec<Announcement>)))]
+pub async fn list_announcements<D: NoteDb + ?Sized>(
+    State(state): State<Arc<AppState<D>>>,
+) -> Result<Json<Vec<Announcement>>, StatusCode> {
+    let Ok(announcements) = state.announcements.list_announcements().await
+    else {
+        tracing::error!("unable to get announcements");
+        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+    };
+    let now = chrono::Utc::now();
+    let mut active: Vec<Announcement> = announcements
+        .into_iter()
+        .filter(|a| a.is_active(now))
+        .collect();
+    active.sort_by(|a, b| {
+        b.severity
+            .cmp(&a.severity)
+            .then_with(|| b.starts_at.cmp(&a.starts_at))
+    });
+    Ok(Json(active))
+}
+
+#[utoipa::path(delete, path = "/announcements/{id}",
+    tag = "announcements",
+    params(("id" = String, Path)),
+    responses(
+        (status = 204),
+        (status = 403, description = "Not an admin"),
+        (status = 404),
+    ))]
+pub async fn delete_announcement<D: NoteDb + ?Sized>(
+    State(state): State<Arc<AppState<D>>>,
+    Path(id): Path<String>,
+) -> StatusCode {
+    tracing::info!("delete announcement {}", id);
+    let Ok(res) = state.announcements.delete_announcement(&id).await else {
+        tracing::error!("unable to delete announcement {}", id);
+        return StatusCode::INTERNAL_SERVER_ERROR;
+    };
+    if !res {
+        return StatusCode::NOT_FOUND;
+    }
+    StatusCode::NO_CONTENT
+}
+
+/// Creates an API key acting as the caller. The key is only part of this
+/// response, store it right away. Keys carry no roles, so they can't
+/// create or revoke keys themselves.
+#[utoipa::path(post, path = "/admin/api-keys", tag = "api-keys",
+    request_body = NewApiKey,
+    responses(
+        (status = 201, body = CreatedApiKey),
+        (status = 400, description = "Authentication is disabled"),
+        (status = 403, description = "Not an admin"),
+    ))]
+pub async fn post_api_key<D: NoteDb + ?Sized>(
+    State(state): State<Arc<AppState<D>>>,
+    ctx: OpContext,
+    RequestJson(new_api_key): RequestJson<NewApiKey>,
+) -> Result<(StatusCode, Json<CreatedApiKey>), Response> {
+    let Some(subject) = ctx.owner() else {
+        return Err(Problem::new(
+            StatusCode::BAD_REQUEST,
+            "api keys need authentication to be enabled",
+        )
+        .into_response());
+    };
+    let (api_key, key) =
+        ApiKey::generate(&new_api_key.name, subject, chrono::Utc::now());
+    tracing::info!("create api key {} for {}", api_key.id, subject);
+    state
+        .api_keys
+        .create_api_key(&api_key)
+        .await
+        .map_err(|err| {
+            tracing::error!("unable to create api key: {}", err);
+            note_db_response(&err)
+        })?;
+    Ok((
+        StatusCode::CREATED,
+        Json(CreatedApiKey {
+            info: api_key.into(),
+            key,
+        }),
+    ))
+}
+
+/// Lists the API keys of the caller.
+#[utoipa::path(get, path = "/admin/api-keys", tag = "api-keys",
+    responses(
+        (status = 200, body = Vec<ApiKeyInfo>),
+        (status = 403, description = "Not an admin"),
+    ))]
+pub async fn list_api_keys<D: NoteDb + ?Sized>(
+    State(state): State<Arc<AppState<D>>>,
+    ctx: OpContext,
+) -> Result<Json<Vec<ApiKeyInfo>>, StatusCode> {
+    let Some(subject) = ctx.owner() else {
+        return Ok(Json(Vec::new()));
+    };
+    let api_keys =
+        state
+            .api_keys
+            .list_api_keys_of(subject)
+            .await
+            .map_err(|err| {
+                tracing::error!("unable to list api keys: {}", err);
+                note_db_status(&err)
+            })?;
+    Ok(Json(api_keys.into_iter().map(ApiKeyInfo::from).collect()))
+}
+
+/// Revokes an API key of the caller.
+#[utoipa::path(delete, path = "/admin/api-keys/{id}", tag = "api-keys",
+    params(("id" = String, Path)),
+    responses(
+        (status = 204),
+        (status = 403, description = "Not an admin"),
+        (status = 404),
+    ))]
+pub async fn delete_api_key<D: NoteDb + ?Sized>(
+    State(state): State<Arc<AppState<D>>>,
+    ctx: OpContext,
+    Path(id): Path<String>,
+) -> Result<StatusCode, StatusCode> {
+    let Some(subject) = ctx.owner() else {
+        return Err(StatusCode::NOT_FOUND);
+    };
+    let api_keys =
+        state
+            .api_keys
+            .list_api_keys_of(subject)
+            .await
+            .map_err(|err| {
+                tracing::error!("unable to list api keys: {}", err);
+                note_db_status(&err)
+            })?;
+    if !api_keys.iter().any(|api_key| api_key.id == id) {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    tracing::info!("revoke api key {}", id);
+    let deleted = state.api_keys.delete_api_key(&id).await.map_err(|err| {
+        tracing::error!("unable to delete api key {}: {}", id, err);
+        note_db_status(&err)
+    })?;
+    match deleted {
+        true => Ok(StatusCode::NO_CONTENT),
+        false => Err(StatusCode::NOT_FOUND),
+    }
+}
+
+/// Creates an organization with the caller as its only member.
+#[utoipa::path(post, path = "/orgs", tag = "orgs",
+    request_body = NewOrganization,
+    responses(
+        (status = 201, body = Organization),
+        (status = 400, description = "Authentication is disabled"),
+    ))]
+pub async fn post_org<D: NoteDb + ?Sized>(
+    State(state): State<Arc<AppState<D>>>,
+    ctx: OpContext,
+    RequestJson(new_org): RequestJson<NewOrganization>,
+) -> Result<(StatusCode, Json<Organization>), Response> {
+    let Some(subject) = ctx.owner() else {
+        return Err(Problem::new(
+            StatusCode::BAD_REQUEST,
+            "organizations need authentication to be enabled",
+        )
+        .into_response());
+    };
+    let org = Organization::new(&new_org.name, subject, chrono::Utc::now());
+    tracing::info!("create organization {} for {}", org.id, subject);
+    state.orgs.create_org(&org).await.map_err(|err| {
+        tracing::error!("unable to create organization: {}", err);
+        note_db_response(&err)
+    })?;
+    Ok((StatusCode::CREATED, Json(org)))
+}
+
+/// Lists the organizations of the caller.
+#[utoipa::path(get, path = "/orgs", tag = "orgs",
+    responses((status = 200, body = Vec<Organization>)))]
+pub async fn list_orgs<D: NoteDb + ?Sized>(
+    State(state): State<Arc<AppState<D>>>,
+    ctx: OpContext,
+) -> Result<Json<Vec<Organization>>, StatusCode> {
+    let Some(subject) = ctx.owner() else {
+        return Ok(Json(Vec::new()));
+    };
+    let orgs = state.orgs.list_orgs(subject).await.map_err(|err| {
+        tracing::error!("unable to list organizations: {}", err);
+        note_db_status(&err)
+    })?;
+    Ok(Json(orgs))
+}
+
+#[utoipa::path(get, path = "/orgs/{id}", tag = "orgs",
+    params(("id" = String, Path)),
+    responses((status = 200, body = Organization), (status = 404)))]
+pub async fn get_org<D: NoteDb + ?Sized>(
+    State(state): State<Arc<AppState<D>>>,
+    ctx: OpContext,
+    Path(id): Path<String>,
+) -> Result<Json<Organization>, StatusCode> {
+    Ok(Json(member_org(&state, &ctx, &id).await?))
+}
+
+/// Adds a user to an organization of the caller.
+#[utoipa::path(put, path = "/orgs/{id}/members/{subject}", tag = "orgs",
+    params(("id" = String, Path), ("subject" = String, Path)),
+    responses((status = 204), (status = 404)))]
+pub async fn put_org_member<D: NoteDb + ?Sized>(
+    State(state): State<Arc<AppState<D>>>,
+    ctx: OpContext,
+    Path((id, subject)): Path<(String, String)>,
+) -> Result<StatusCode, StatusCode> {
+    member_org(&state, &ctx, &id).await?;
+    tracing::info!("add {} to organization {}", subject, id);
+    let added = state.orgs.add_member(&id, &subject).await.map_err(|err| {
+        tracing::error!("unable to add member to {}: {}", id, err);
+        note_db_status(&err)
+    })?;
+    match added {
+        true => Ok(StatusCode::NO_CONTENT),
+        false => Err(StatusCode::NOT_FOUND),
+    }
+}
+
+/// Removes a user from an organization of the caller. The last member
+/// can't leave, so that the notes of the organization stay reachable.
+#[utoipa::path(delete, path = "/orgs/{id}/members/{subject}", tag = "orgs",
+    params(("id" = String, Path), ("subject" = String, Path)),
+    responses(
+        (status = 204),
+        (status = 404),
+        (status = 409, description = "Last member of the organization"),
+    ))]
+pub async fn delete_org_member<D: NoteDb + ?Sized>(
+    State(state): State<Arc<AppState<D>>>,
+    ctx: OpContext,
+    Path((id, subject)): Path<(String, String)>,
+) -> Result<StatusCode, StatusCode> {
+    let org = member_org(&state, &ctx, &id).await?;
+    if !org.has_member(&subject) {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    if org.members.len() == 1 {
+        return Err(StatusCode::CONFLICT);
+    }
+    tracing::info!("remove {} from organization {}", subject, id);
+    let removed =
+        state
+            .orgs
+            .remove_member(&id, &subject)
+            .await
+            .map_err(|err| {
+                tracing::error!("unable to remove member from {}: {}", id, err);
+                note_db_status(&err)
+            })?;
+    match removed {
+        true => Ok(StatusCode::NO_CONTENT),
+        false => Err(StatusCode::NOT_FOUND),
+    }
+}
+
+/// The organization with the given id, answering 404 unless the caller is
+/// a member.
+async fn member_org<D: NoteDb + ?Sized>(
+    state: &AppState<D>,
+    ctx: &OpContext,
+    id: &str,
+) -> Result<Organization, StatusCode> {
+    let org = state.orgs.get_org(id).await.map_err(|err| {
+        tracing::error!("unable to get organization {}: {}", id, err);
+        note_db_status(&err)
+    })?;
+    org.filter(|org| ctx.owner().is_some_and(|owner| org.has_member(owner)))
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+pub async fn get_migration<D: NoteDb + ?Sized>(
+    State(state): State<Arc<AppState<D>>>,
+) -> Result<Json<DualWriteStatus>, StatusCode> {
+    let Some(migration) = &state.migration else {
+        return Err(StatusCode::NOT_FOUND);
+    };
+    Ok(Json(migration.status()))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MigrationUpdate {
+    pub read_mode: ReadMode,
+}
+
+pub async fn put_migration<D: NoteDb + ?Sized>(
+    State(state): State<Arc<AppState<D>>>,
+    RequestJson(update): RequestJson<MigrationUpdate>,
+) -> Result<Json<DualWriteStatus>, StatusCode> {
+    let Some(migration) = &state.migration else {
+        return Err(StatusCode::NOT_FOUND);
+    };
+    migration.set_read_mode(update.read_mode);
+    Ok(Json(migration.status()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::persistency::batching::Acknowledge;
+    use crate::persistency::memory::NoteMemoryDb;
+    use async_trait::async_trait;
+    use axum::{body::Body, http::Request, response::Response};
+    use http_body_util::BodyExt;
+    use nanoid::nanoid;
+    use std::sync::{
+        self,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    };
+    use tower::ServiceExt;
+
+    impl NewNote {
+        fn new(title: &str, body: &str) -> NewNote {
+            NewNote {
+                title: title.to_string(),
+                body: body.to_string(),
+                tags: Vec::new(),
+                org: None,
+            }
+        }
+    }
+
+    struct NoteVecDb {
+        vec: sync::Mutex<Vec<Note>>,
+        fail_create: AtomicBool,
+        fail_get: AtomicBool,
+        none_get: AtomicBool,
+        fail_update: AtomicBool,
         fail_delete: AtomicBool,
         fail_list: AtomicBool,
+        get_delay_ms: AtomicU64,
+        create_batches: AtomicU64,
+        /// Tenant of every create, in call order.
+        create_tenants: sync::Mutex<Vec<Option<String>>>,
+        revisions: sync::Mutex<Vec<NoteRevision>>,
     }
 
     impl NoteVecDb {
@@ -264,6 +2490,10 @@ mod tests {
                 fail_delete: AtomicBool::new(false),
                 fail_list: AtomicBool::new(false),
                 fail_update: AtomicBool::new(false),
+                get_delay_ms: AtomicU64::new(0),
+                create_batches: AtomicU64::new(0),
+                create_tenants: sync::Mutex::new(Vec::new()),
+                revisions: sync::Mutex::new(Vec::new()),
             }
         }
 
@@ -288,26 +2518,51 @@ mod tests {
         pub fn set_fail_list(&self, value: bool) {
             self.fail_list.store(value, sync::atomic::Ordering::SeqCst);
         }
+        pub fn set_get_delay_ms(&self, value: u64) {
+            self.get_delay_ms
+                .store(value, sync::atomic::Ordering::SeqCst);
+        }
     }
 
     #[async_trait]
     impl NoteDb for NoteVecDb {
         async fn create_note(
             &self,
+            ctx: &OpContext,
             note: &Note,
-        ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        ) -> Result<(), NoteDbError> {
             if self.fail_create.load(Ordering::SeqCst) {
                 return Err("simulated create error".into());
             }
+            self.create_tenants.lock().unwrap().push(ctx.tenant.clone());
             self.vec.lock().unwrap().push(note.clone());
             Ok(())
         }
 
+        async fn create_notes(
+            &self,
+            ctx: &OpContext,
+            notes: &[Note],
+        ) -> Result<(), NoteDbError> {
+            if self.fail_create.load(Ordering::SeqCst) {
+                return Err("simulated create error".into());
+            }
+            self.create_batches.fetch_add(1, Ordering::SeqCst);
+            self.create_tenants.lock().unwrap().push(ctx.tenant.clone());
+            self.vec.lock().unwrap().extend_from_slice(notes);
+            Ok(())
+        }
+
         async fn get_note(
             &self,
+            ctx: &OpContext,
             id: &str,
-        ) -> Result<Option<Note>, Box<dyn std::error::Error + Send + Sync>>
-        {
+        ) -> Result<Option<Note>, NoteDbError> {
+            let delay = self.get_delay_ms.load(Ordering::SeqCst);
+            if delay > 0 {
+                tokio::time::sleep(std::time::Duration::from_millis(delay))
+                    .await;
+            }
             if self.fail_get.load(Ordering::SeqCst) {
                 return Err("simulated get error".into());
             }
@@ -315,7 +2570,9 @@ mod tests {
                 return Ok(None);
             }
             let vec = self.vec.lock().unwrap();
-            let Some(note) = vec.iter().find(|n| n.id == id) else {
+            let Some(note) =
+                vec.iter().find(|n| n.id == id && ctx.can_access(n))
+            else {
                 return Ok(None);
             };
             return Ok(Some(note.clone()));
@@ -323,48 +2580,137 @@ mod tests {
 
         async fn update_note(
             &self,
+            ctx: &OpContext,
             id: &str,
             note: &PatchNote,
-        ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        ) -> Result<(), NoteDbError> {
             if self.fail_update.load(Ordering::SeqCst) {
                 return Err("simulated get error".into());
             }
             let mut vec = self.vec.lock().unwrap();
-            let Some(get_note) = vec.iter_mut().find(|n| n.id == id) else {
-                return Ok(());
+            let Some(get_note) =
+                vec.iter_mut().find(|n| n.id == id && ctx.can_access(n))
+            else {
+                return Err(NoteDbError::NotFound);
             };
-            if let Some(title) = &note.title {
-                get_note.title = title.to_string();
-            }
-
-            if let Some(body) = &note.body {
-                get_note.body = body.to_string();
-            }
+            note.apply_to(get_note)?;
             Ok(())
         }
 
         async fn delete_note(
             &self,
+            ctx: &OpContext,
             id: &str,
-        ) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+        ) -> Result<bool, NoteDbError> {
             if self.fail_delete.load(Ordering::SeqCst) {
                 return Err("simulated get error".into());
             }
             let mut vec = self.vec.lock().unwrap();
-            let Some(_) = vec.iter().find(|n| n.id == id) else {
+            let Some(_) = vec.iter().find(|n| n.id == id && ctx.can_access(n))
+            else {
                 return Ok(false);
             };
-            vec.retain(|n| n.id != id);
+            vec.retain(|n| n.id != id || !ctx.can_access(n));
             Ok(true)
         }
 
         async fn list_notes(
             &self,
-        ) -> Result<Vec<Note>, Box<dyn std::error::Error + Send + Sync>>
-        {
+            ctx: &OpContext,
+            filter: &NoteFilter,
+            page: &Page,
+        ) -> Result<NotePage, NoteDbError> {
             if self.fail_list.load(Ordering::SeqCst) {
                 return Err("simulated get error".into());
             }
+            let vec = self.vec.lock().unwrap();
+            let notes: Vec<Note> = vec
+                .iter()
+                .filter(|n| ctx.can_access(n) && filter.matches(n))
+                .cloned()
+                .collect();
+            Ok(NotePage {
+                total: notes.len() as u64,
+                notes: page.slice(notes),
+            })
+        }
+
+        async fn suggest_titles(
+            &self,
+            ctx: &OpContext,
+            prefix: &str,
+            limit: usize,
+        ) -> Result<Vec<NoteSuggestion>, NoteDbError> {
+            if self.fail_list.load(Ordering::SeqCst) {
+                return Err("simulated suggest error".into());
+            }
+            let vec = self.vec.lock().unwrap();
+            let candidates =
+                vec.iter().filter(|n| ctx.can_access(n)).map(|n| {
+                    NoteSuggestion {
+                        id: n.id.clone(),
+                        title: n.title.clone(),
+                    }
+                });
+            Ok(notes::rank_suggestions(prefix, candidates, limit))
+        }
+
+        async fn record_revision(
+            &self,
+            _ctx: &OpContext,
+            note: &Note,
+        ) -> Result<u64, NoteDbError> {
+            let mut revisions = self.revisions.lock().unwrap();
+            let rev = revisions.iter().filter(|r| r.note_id == note.id).count()
+                as u64
+                + 1;
+            revisions.push(NoteRevision::new(note, rev));
+            Ok(rev)
+        }
+
+        async fn list_revisions(
+            &self,
+            _ctx: &OpContext,
+            note_id: &str,
+        ) -> Result<Vec<NoteRevision>, NoteDbError> {
+            let revisions = self.revisions.lock().unwrap();
+            Ok(revisions
+                .iter()
+                .filter(|revision| revision.note_id == note_id)
+                .cloned()
+                .collect())
+        }
+    }
+
+    #[derive(Default)]
+    struct AnnouncementVecDb {
+        vec: sync::Mutex<Vec<Announcement>>,
+    }
+
+    #[async_trait]
+    impl AnnouncementDb for AnnouncementVecDb {
+        async fn create_announcement(
+            &self,
+            announcement: &Announcement,
+        ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+            self.vec.lock().unwrap().push(announcement.clone());
+            Ok(())
+        }
+
+        async fn delete_announcement(
+            &self,
+            id: &str,
+        ) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+            let mut vec = self.vec.lock().unwrap();
+            let len = vec.len();
+            vec.retain(|a| a.id != id);
+            Ok(vec.len() != len)
+        }
+
+        async fn list_announcements(
+            &self,
+        ) -> Result<Vec<Announcement>, Box<dyn std::error::Error + Send + Sync>>
+        {
             Ok(self.vec.lock().unwrap().clone())
         }
     }
@@ -377,6 +2723,8 @@ mod tests {
         let new_note = NewNote {
             title: "a".to_string(),
             body: "b".to_string(),
+            tags: Vec::new(),
+            org: None,
         };
 
         // Execute
@@ -394,6 +2742,8 @@ mod tests {
         let new_note = NewNote {
             title: "a".to_string(),
             body: "b".to_string(),
+            tags: Vec::new(),
+            org: None,
         };
 
         // Execute
@@ -411,6 +2761,8 @@ mod tests {
         let new_note = NewNote {
             title: "a".to_string(),
             body: "b".to_string(),
+            tags: Vec::new(),
+            org: None,
         };
 
         // Execute
@@ -428,6 +2780,8 @@ mod tests {
         let new_note = NewNote {
             title: "a".to_string(),
             body: "b".to_string(),
+            tags: Vec::new(),
+            org: None,
         };
         let resp = post_test_note(app.clone(), new_note).await;
         let note = deserialize_note(resp.into_body()).await;
@@ -439,6 +2793,10 @@ mod tests {
             PatchNote {
                 title: None,
                 body: None,
+                tags: None,
+                updated_at: chrono::Utc::now(),
+                version: None,
+                permissions: None,
             },
         )
         .await;
@@ -447,6 +2805,104 @@ mod tests {
         assert_eq!(resp.status(), StatusCode::INTERNAL_SERVER_ERROR);
     }
 
+    #[tokio::test]
+    async fn it_fails_to_update_a_note_not_found() {
+        // Setup
+        let (app, _) = create_test_app();
+
+        // Execute
+        let resp = patch_test_note(
+            app,
+            &nanoid!(),
+            PatchNote {
+                title: Some("a".to_string()),
+                body: None,
+                tags: None,
+                updated_at: chrono::Utc::now(),
+                version: None,
+                permissions: None,
+            },
+        )
+        .await;
+
+        // Assert
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn it_maps_note_db_errors_to_status_codes() {
+        assert_eq!(
+            note_db_status(&NoteDbError::Conflict("a".to_string())),
+            StatusCode::CONFLICT
+        );
+        assert_eq!(
+            note_db_status(&NoteDbError::Connection("down".into())),
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+        assert_eq!(
+            note_db_status(&NoteDbError::DeadlineExceeded),
+            StatusCode::GATEWAY_TIMEOUT
+        );
+        assert_eq!(
+            note_db_status(&NoteDbError::Other("a".into())),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+    }
+
+    #[tokio::test]
+    async fn it_answers_too_large_notes_with_the_limit() {
+        // Execute
+        let resp = note_db_response(&NoteDbError::TooLarge { limit: 1024 });
+
+        // Assert
+        assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        let body = resp.into_body().collect().await.unwrap().to_bytes();
+        let problem: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(problem["limit"], 1024);
+        assert!(problem["detail"].as_str().unwrap().contains("attachment"));
+    }
+
+    #[tokio::test]
+    async fn it_refuses_oversized_request_bodies() {
+        // Setup
+        let (app, _) = create_test_app_with_config(AppConfig {
+            max_request_bytes: 64,
+            ..AppConfig::default()
+        });
+        let note =
+            serde_json::to_vec(&NewNote::new("a", &"b".repeat(64))).unwrap();
+        let send = |body: Body| {
+            app.clone().oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/v1/notes")
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(body)
+                    .unwrap(),
+            )
+        };
+
+        // Execute
+        let sized = send(Body::from(note.clone())).await.unwrap();
+        let chunks = [Ok::<_, std::io::Error>(note)];
+        let streamed = send(Body::from_stream(futures::stream::iter(chunks)))
+            .await
+            .unwrap();
+
+        // Assert
+        for resp in [sized, streamed] {
+            assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+            assert_eq!(
+                resp.headers()[header::CONTENT_TYPE],
+                "application/problem+json"
+            );
+            let body = resp.into_body().collect().await.unwrap().to_bytes();
+            let problem: serde_json::Value =
+                serde_json::from_slice(&body).unwrap();
+            assert_eq!(problem["limit"], 64);
+        }
+    }
+
     #[tokio::test]
     async fn it_fails_to_delete_a_note() {
         // Setup
@@ -455,107 +2911,2143 @@ mod tests {
         let new_note = NewNote {
             title: "a".to_string(),
             body: "b".to_string(),
+            tags: Vec::new(),
+            org: None,
         };
         let resp = post_test_note(app.clone(), new_note).await;
         let note = deserialize_note(resp.into_body()).await;
 
         // Execute
-        let resp = delete_test_note(app, &note.id).await;
+        let resp = delete_test_note(app, &note.id).await;
+
+        // Assert
+        assert_eq!(resp.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[tokio::test]
+    async fn it_fails_to_delete_a_note_not_found() {
+        // Setup
+        let (app, _) = create_test_app();
+
+        // Execute
+        let resp = delete_test_note(app, &nanoid!()).await;
+
+        // Assert
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn it_fails_to_list_notes() {
+        // Setup
+        let (app, state) = create_test_app();
+        state.lock().await.set_fail_list(true);
+
+        // Execute
+        let resp = list_test_notes(app).await;
+
+        // Assert
+        assert_eq!(resp.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[tokio::test]
+    async fn it_streams_large_note_lists() {
+        // Setup
+        let (app, state) = create_test_app();
+        let notes: Vec<Note> = (0..500)
+            .map(|i| Note::new(&i.to_string(), &"b".repeat(100), ""))
+            .collect();
+        *state.lock().await.vec.lock().unwrap() = notes.clone();
+
+        // Execute
+        let resp = app
+            .oneshot(
+                Request::builder()
+                    .method("GET")
+                    .uri("/v1/notes?limit=500")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers()["content-type"], "application/json");
+        let body = resp.into_body().collect().await.unwrap().to_bytes();
+        let listed: Vec<Note> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(listed, notes);
+    }
+
+    #[tokio::test]
+    async fn it_counts_in_flight_requests() {
+        // Setup
+        let (app, _) = create_test_app();
+
+        // Execute
+        let resp = app
+            .oneshot(
+                Request::builder()
+                    .uri("/v1/admin/metrics")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = resp.into_body().collect().await.unwrap().to_bytes();
+        let gauges: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        // Other tests may run concurrently, but this request counts.
+        assert!(gauges["in_flight_requests"].as_u64().unwrap() >= 1);
+    }
+
+    #[tokio::test]
+    async fn it_times_out_slow_requests_with_problem() {
+        // Setup
+        let (app, state) = create_test_app_with_config(AppConfig {
+            route_timeouts: RouteTimeouts {
+                crud: Some(std::time::Duration::from_millis(10)),
+                ..RouteTimeouts::default()
+            },
+            ..AppConfig::default()
+        });
+        state.lock().await.set_get_delay_ms(1000);
+
+        // Execute
+        let resp = app
+            .oneshot(
+                Request::builder()
+                    .uri(format!("/v1/notes/{}", nanoid!()))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(resp.status(), StatusCode::GATEWAY_TIMEOUT);
+        assert_eq!(resp.headers()["content-type"], "application/problem+json");
+        let body = resp.into_body().collect().await.unwrap().to_bytes();
+        let problem: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(problem["status"], 504);
+    }
+
+    #[tokio::test]
+    async fn it_aborts_imports_with_invalid_items() {
+        // Setup
+        let (app, state) = create_test_app();
+
+        // Execute
+        let resp = import_test_notes(
+            app,
+            "abort",
+            r#"[{"title": "a", "body": "b"}, {"title": "c"}]"#,
+        )
+        .await;
+
+        // Assert
+        assert_eq!(resp.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let report = deserialize_report(resp.into_body()).await;
+        assert_eq!(report.created, 0);
+        assert_eq!(report.failed, 1);
+        assert_eq!(report.items[0].status, import::ItemStatus::Skipped);
+        assert_eq!(report.items[1].status, import::ItemStatus::Failed);
+        assert!(report.items[1].error.is_some());
+        assert!(state.lock().await.vec.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn it_continues_imports_past_invalid_items() {
+        // Setup
+        let (app, state) = create_test_app();
+
+        // Execute
+        let resp = import_test_notes(
+            app,
+            "continue",
+            r#"[{"title": "a", "body": "b"}, {"title": "c"}]"#,
+        )
+        .await;
+
+        // Assert
+        assert_eq!(resp.status(), StatusCode::OK);
+        let report = deserialize_report(resp.into_body()).await;
+        assert_eq!(report.created, 1);
+        assert_eq!(report.failed, 1);
+        let notes = state.lock().await.vec.lock().unwrap().clone();
+        assert_eq!(Some(&notes[0].id), report.items[0].id.as_ref());
+    }
+
+    #[tokio::test]
+    async fn it_resolves_title_conflicts_on_import() {
+        // Setup
+        let (app, state) = create_test_app();
+        let mut existing = Note::new("Groceries", "milk", "");
+        existing.tags = vec!["home".to_string()];
+        state
+            .lock()
+            .await
+            .vec
+            .lock()
+            .unwrap()
+            .push(existing.clone());
+        let items = r#"[
+            {"title": "groceries", "body": "eggs", "tags": ["home", "week"]},
+            {"title": "Travel", "body": "tickets"}
+        ]"#;
+
+        // Execute
+        let merged =
+            import_test_notes(app.clone(), "abort&on_conflict=merge", items)
+                .await;
+        let skipped =
+            import_test_notes(app, "abort&on_conflict=skip", items).await;
+
+        // Assert
+        let report = deserialize_report(merged.into_body()).await;
+        assert_eq!((report.created, report.updated), (1, 1));
+        assert_eq!(report.items[0].status, import::ItemStatus::Merged);
+        assert_eq!(report.items[0].id.as_ref(), Some(&existing.id));
+        let report = deserialize_report(skipped.into_body()).await;
+        assert_eq!(report.items[0].status, import::ItemStatus::Ignored);
+        assert_eq!(report.items[0].conflict.as_ref(), Some(&existing.id));
+        assert_eq!(report.items[1].status, import::ItemStatus::Ignored);
+        let notes = state.lock().await.vec.lock().unwrap().clone();
+        assert_eq!(notes.len(), 2);
+        assert_eq!(notes[0].body, "milk\n\neggs");
+        assert_eq!(notes[0].tags, vec!["home", "week"]);
+    }
+
+    #[tokio::test]
+    async fn it_fails_merges_exceeding_the_note_limits() {
+        // Setup
+        let (app, state) = create_test_app_with_config(AppConfig {
+            note_limits: NoteLimits {
+                max_body_bytes: 8,
+                ..NoteLimits::default()
+            },
+            ..AppConfig::default()
+        });
+        let existing = Note::new("Groceries", "milk", "");
+        state
+            .lock()
+            .await
+            .vec
+            .lock()
+            .unwrap()
+            .push(existing.clone());
+        let items = r#"[{"title": "groceries", "body": "eggs"}]"#;
+
+        // Execute
+        let resp =
+            import_test_notes(app, "continue&on_conflict=merge", items).await;
+
+        // Assert
+        let report = deserialize_report(resp.into_body()).await;
+        assert_eq!((report.updated, report.failed), (0, 1));
+        assert_eq!(report.items[0].status, import::ItemStatus::Failed);
+        assert_eq!(report.items[0].conflict.as_ref(), Some(&existing.id));
+        let notes = state.lock().await.vec.lock().unwrap().clone();
+        assert_eq!(notes[0].body, "milk");
+    }
+
+    #[tokio::test]
+    async fn it_round_trips_notes_through_csv() {
+        // Setup
+        let (app, state) = create_test_app();
+        let mut note = Note::new("a, \"quoted\"", "line1\nline2", "");
+        note.tags = vec!["work".to_string(), "todo".to_string()];
+        state.lock().await.vec.lock().unwrap().push(note.clone());
+
+        // Execute
+        let resp = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/v1/export.csv")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let csv = resp.into_body().collect().await.unwrap().to_bytes();
+        let resp = app
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/v1/import.csv")
+                    .body(Body::from(csv))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(resp.status(), StatusCode::OK);
+        let report = deserialize_report(resp.into_body()).await;
+        assert_eq!(report.created, 1);
+        let notes = state.lock().await.vec.lock().unwrap().clone();
+        assert_eq!(notes[1].title, note.title);
+        assert_eq!(notes[1].body, note.body);
+        assert_eq!(notes[1].tags, note.tags);
+    }
+
+    #[tokio::test]
+    async fn it_imports_csv_with_column_mapping() {
+        // Setup
+        let (app, state) = create_test_app();
+        let csv = "Name,Content\nshopping,milk\n";
+
+        // Execute
+        let resp = app
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/v1/import.csv?title=Name&body=Content")
+                    .body(Body::from(csv))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(resp.status(), StatusCode::OK);
+        let notes = state.lock().await.vec.lock().unwrap().clone();
+        assert_eq!(notes[0].title, "shopping");
+        assert_eq!(notes[0].body, "milk");
+    }
+
+    #[tokio::test]
+    async fn it_rejects_csv_without_mapped_columns() {
+        // Setup
+        let (app, _) = create_test_app();
+
+        // Execute
+        let resp = app
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/v1/import.csv")
+                    .body(Body::from("Name,Content\na,b\n"))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn it_creates_a_note() {
+        // Setup
+        let (app, _) = create_test_app();
+        let new_note = NewNote {
+            title: "a".to_string(),
+            body: "b".to_string(),
+            tags: Vec::new(),
+            org: None,
+        };
+
+        // Execute
+        let resp = post_test_note(app, new_note).await;
+
+        // Assert
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        let note_json = deserialize_note(resp.into_body()).await;
+        assert_eq!(note_json.title, "a");
+        assert_eq!(note_json.body, "b");
+        assert_eq!(note_json.created_at, note_json.updated_at);
+    }
+
+    #[tokio::test]
+    async fn it_serializes_timestamps_as_rfc3339() {
+        // Setup
+        let (app, _) = create_test_app();
+
+        // Execute
+        let resp = post_test_note(app, NewNote::new("a", "b")).await;
+
+        // Assert
+        let body = resp.into_body().collect().await.unwrap().to_bytes();
+        let note: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        for field in ["created_at", "updated_at"] {
+            let value = note[field].as_str().unwrap();
+            assert!(chrono::DateTime::parse_from_rfc3339(value).is_ok());
+        }
+    }
+
+    #[test]
+    fn it_stores_timestamps_as_bson_dates() {
+        // Setup
+        let note = Note::new("a", "b", "/v1/notes");
+        let mut legacy = mongodb::bson::to_document(&note).unwrap();
+        legacy.insert("created_at", note.created_at.to_rfc3339());
+
+        // Execute
+        let stored = mongodb::bson::to_raw_document_buf(&note).unwrap();
+        let read: Note = mongodb::bson::from_slice(stored.as_bytes()).unwrap();
+        let read_legacy: Note = mongodb::bson::from_document(legacy).unwrap();
+
+        // Assert
+        let stored = stored.to_document().unwrap();
+        assert!(matches!(
+            stored.get("created_at"),
+            Some(mongodb::bson::Bson::DateTime(_))
+        ));
+        // Notes are timestamped at the millisecond precision of BSON dates.
+        assert_eq!(read.created_at, note.created_at);
+        assert_eq!(read_legacy.created_at, note.created_at);
+    }
+
+    #[tokio::test]
+    async fn it_gets_a_note() {
+        // Setup
+        let (app, _) = create_test_app();
+        let new_note = NewNote {
+            title: "a".to_string(),
+            body: "b".to_string(),
+            tags: Vec::new(),
+            org: None,
+        };
+        let resp = post_test_note(app.clone(), new_note).await;
+        let note_json = deserialize_note(resp.into_body()).await;
+
+        let resp = app
+            .oneshot(
+                Request::builder()
+                    .method("GET")
+                    .uri(format!("/v1/notes/{}", note_json.id))
+                    .header("Content-Type", "application/json")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(resp.status(), StatusCode::OK);
+        let note_json = deserialize_note(resp.into_body()).await;
+        assert_eq!(note_json.title, "a");
+        assert_eq!(note_json.body, "b");
+    }
+
+    #[tokio::test]
+    async fn it_lists_notes() {
+        // Setup
+        let (app, _) = create_test_app();
+        let note = NewNote::new("note0", "body0");
+        let _ = post_test_note(app.clone(), note).await;
+        let note = NewNote::new("note01", "body1");
+        let _ = post_test_note(app.clone(), note).await;
+
+        // Execute
+        let resp = app
+            .oneshot(
+                Request::builder()
+                    .method("GET")
+                    .uri("/v1/notes")
+                    .header("Content-Type", "application/json")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(resp.status(), StatusCode::OK);
+        let notes: Vec<Note> = deserialize_notes(resp.into_body()).await;
+        assert_eq!(notes.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn it_lists_a_page_of_notes() {
+        // Setup
+        let (app, _) = create_test_app();
+        for i in 0..5 {
+            let note = NewNote::new(&format!("note{}", i), "body");
+            let _ = post_test_note(app.clone(), note).await;
+        }
+
+        // Execute
+        let resp = app
+            .oneshot(
+                Request::builder()
+                    .method("GET")
+                    .uri("/v1/notes?limit=2&offset=3")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers()["x-total-count"], "5");
+        let notes = deserialize_notes(resp.into_body()).await;
+        let titles: Vec<&str> =
+            notes.iter().map(|n| n.title.as_str()).collect();
+        assert_eq!(titles, vec!["note3", "note4"]);
+    }
+
+    #[tokio::test]
+    async fn it_clamps_page_sizes() {
+        // Setup
+        let (app, _) = create_test_app_with_config(AppConfig {
+            default_page_size: 2,
+            max_page_size: 3,
+            ..AppConfig::default()
+        });
+        for i in 0..5 {
+            let note = NewNote::new(&format!("note{}", i), "body");
+            let _ = post_test_note(app.clone(), note).await;
+        }
+        let list = |uri: &'static str| {
+            app.clone().oneshot(
+                Request::builder()
+                    .method("GET")
+                    .uri(uri)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+        };
+
+        // Execute
+        let default = list("/v1/notes").await.unwrap();
+        let clamped = list("/v1/notes?limit=10").await.unwrap();
+        let empty = list("/v1/notes?limit=0").await.unwrap();
+
+        // Assert
+        assert_eq!(default.headers()[PAGE_LIMIT_HEADER], "2");
+        assert_eq!(deserialize_notes(default.into_body()).await.len(), 2);
+        assert_eq!(clamped.headers()[PAGE_LIMIT_HEADER], "3");
+        assert_eq!(clamped.headers()[TOTAL_COUNT_HEADER], "5");
+        assert_eq!(deserialize_notes(clamped.into_body()).await.len(), 3);
+        assert_eq!(empty.headers()[PAGE_LIMIT_HEADER], "0");
+        assert_eq!(deserialize_notes(empty.into_body()).await.len(), 0);
+    }
+
+    #[tokio::test]
+    async fn it_lists_notes_matching_filter() {
+        // Setup
+        let (app, _) = create_test_app();
+        for title in ["Weekly Report", "report draft", "Shopping"] {
+            let _ = post_test_note(app.clone(), NewNote::new(title, "b")).await;
+        }
+
+        // Execute
+        let resp = app
+            .oneshot(
+                Request::builder()
+                    .method("GET")
+                    .uri("/v1/notes?title_contains=REPORT")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(resp.status(), StatusCode::OK);
+        let notes = deserialize_notes(resp.into_body()).await;
+        assert_eq!(notes.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn it_lists_notes_by_time() {
+        // Setup
+        let (app, _) = create_test_app();
+        let mut created = Vec::new();
+        for title in ["first", "second"] {
+            let resp = post_test_note(app.clone(), NewNote::new(title, "b"));
+            created.push(deserialize_note(resp.await.into_body()).await);
+            tokio::time::sleep(std::time::Duration::from_millis(2)).await;
+        }
+        let time = |time: chrono::DateTime<chrono::Utc>| {
+            time.to_rfc3339_opts(chrono::SecondsFormat::Millis, true)
+        };
+        let list = |query: String| {
+            app.clone().oneshot(
+                Request::builder()
+                    .method("GET")
+                    .uri(format!("/v1/notes?{}", query))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+        };
+
+        // Execute
+        let after =
+            list(format!("created_after={}", time(created[0].created_at)))
+                .await
+                .unwrap();
+        let before = list(format!(
+            "updated_before={}&title_contains=F",
+            time(created[1].updated_at)
+        ))
+        .await
+        .unwrap();
+
+        // Assert
+        assert_eq!(after.status(), StatusCode::OK);
+        let notes = deserialize_notes(after.into_body()).await;
+        assert_eq!(notes, vec![created[1].clone()]);
+        assert_eq!(before.status(), StatusCode::OK);
+        let notes = deserialize_notes(before.into_body()).await;
+        assert_eq!(notes, vec![created[0].clone()]);
+    }
+
+    #[tokio::test]
+    async fn it_lists_notes_with_tag() {
+        // Setup
+        let (app, _) = create_test_app();
+        for (title, tags) in [("a", vec!["work"]), ("b", vec!["home"])] {
+            let note = NewNote {
+                tags: tags.into_iter().map(String::from).collect(),
+                ..NewNote::new(title, "b")
+            };
+            let _ = post_test_note(app.clone(), note).await;
+        }
+
+        // Execute
+        let resp = app
+            .oneshot(
+                Request::builder()
+                    .method("GET")
+                    .uri("/v1/notes?tag=work")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(resp.status(), StatusCode::OK);
+        let notes = deserialize_notes(resp.into_body()).await;
+        assert_eq!(notes.len(), 1);
+        assert_eq!(notes[0].title, "a");
+        assert_eq!(notes[0].tags, vec!["work"]);
+    }
+
+    #[tokio::test]
+    async fn it_deletes_a_note() {
+        // Setup
+        let (app, _) = create_test_app();
+        let note = NewNote::new("note0", "body0");
+        let note0 = post_test_note(app.clone(), note).await;
+        let note0 = deserialize_note(note0.into_body()).await;
+        let note = NewNote::new("note01", "body1");
+        let note = post_test_note(app.clone(), note).await;
+        let note = deserialize_note(note.into_body()).await;
+
+        // Execute
+        let resp = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("DELETE")
+                    .uri(format!("/v1/notes/{}", note.id))
+                    .header("Content-Type", "application/json")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        let resp = app
+            .oneshot(
+                Request::builder()
+                    .method("GET")
+                    .uri("/v1/notes")
+                    .header("Content-Type", "application/json")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let notes = deserialize_notes(resp.into_body()).await;
+        assert_eq!(notes.len(), 1);
+        assert_eq!(notes[0].id, note0.id);
+    }
+
+    #[tokio::test]
+    async fn it_patches_a_note() {
+        // Setup
+        let (app, _) = create_test_app();
+        let note = NewNote::new("note0", "body0");
+        let note = post_test_note(app.clone(), note).await;
+        let note = deserialize_note(note.into_body()).await;
+        // Timestamps have millisecond precision.
+        tokio::time::sleep(std::time::Duration::from_millis(2)).await;
+
+        // Execute
+        let resp = patch_test_note(
+            app.clone(),
+            &note.id,
+            PatchNote {
+                title: Some("newtitle".to_string()),
+                body: Some("newbody".to_string()),
+                tags: None,
+                updated_at: chrono::Utc::now(),
+                version: None,
+                permissions: None,
+            },
+        )
+        .await;
+
+        // assert
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp = app
+            .oneshot(
+                Request::builder()
+                    .method("GET")
+                    .uri(format!("/v1/notes/{}", note.id))
+                    .header("Content-Type", "application/json")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let patched_noted = deserialize_note(resp.into_body()).await;
+        assert_eq!(patched_noted.id, note.id);
+        assert_eq!(patched_noted.title, "newtitle");
+        assert_eq!(patched_noted.body, "newbody");
+        assert_eq!(patched_noted.created_at, note.created_at);
+        assert!(patched_noted.updated_at > note.updated_at);
+    }
+
+    #[tokio::test]
+    async fn it_suggests_note_titles() {
+        // Setup
+        let (app, _) = create_test_app();
+        for title in ["Groceries", "grocery list", "Gym plan", "Travel"] {
+            let _ = post_test_note(app.clone(), NewNote::new(title, "b")).await;
+        }
+
+        // Execute
+        let resp = app
+            .oneshot(
+                Request::builder()
+                    .method("GET")
+                    .uri("/v1/notes/suggest?q=groc&limit=5")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(resp.status(), StatusCode::OK);
+        let bytes = resp.into_body().collect().await.unwrap().to_bytes();
+        let suggestions: Vec<NoteSuggestion> =
+            serde_json::from_slice(&bytes).unwrap();
+        let titles: Vec<&str> =
+            suggestions.iter().map(|s| s.title.as_str()).collect();
+        assert_eq!(titles, vec!["Groceries", "grocery list"]);
+    }
+
+    #[tokio::test]
+    async fn it_suggests_similar_note_titles() {
+        // Setup
+        let (app, _) = create_test_app();
+        for title in ["Groceries", "grocery list", "Gym plan", "Travel"] {
+            let _ = post_test_note(app.clone(), NewNote::new(title, "b")).await;
+        }
+
+        // Execute
+        let resp = app
+            .oneshot(
+                Request::builder()
+                    .method("GET")
+                    .uri("/v1/notes/suggest?q=grocreies")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(resp.status(), StatusCode::OK);
+        let bytes = resp.into_body().collect().await.unwrap().to_bytes();
+        let suggestions: Vec<NoteSuggestion> =
+            serde_json::from_slice(&bytes).unwrap();
+        let titles: Vec<&str> =
+            suggestions.iter().map(|s| s.title.as_str()).collect();
+        assert_eq!(titles, vec!["Groceries"]);
+    }
+
+    #[tokio::test]
+    async fn it_looks_up_notes_by_id() {
+        // Setup
+        let (app, _) = create_test_app();
+        let mut created = Vec::new();
+        for title in ["a", "b"] {
+            let resp = post_test_note(app.clone(), NewNote::new(title, "b"));
+            created.push(deserialize_note(resp.await.into_body()).await);
+        }
+        let ids = [&created[1].id, "gone", &created[0].id, &created[1].id];
+
+        // Execute
+        let resp = app
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/v1/notes/lookup")
+                    .header("Content-Type", "application/json")
+                    .body(Body::from(
+                        serde_json::json!({ "ids": ids }).to_string(),
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(resp.status(), StatusCode::OK);
+        let bytes = resp.into_body().collect().await.unwrap().to_bytes();
+        let result: NoteLookupResult = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(result.notes, vec![created[1].clone(), created[0].clone()]);
+        assert_eq!(result.missing, vec!["gone".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn it_lists_only_active_announcements() {
+        // Setup
+        let (app, _) = create_test_app();
+        let now = chrono::Utc::now();
+        let announcements = [
+            (Severity::Info, None),
+            (Severity::Critical, Some(now - chrono::Duration::hours(1))),
+            (Severity::Warning, Some(now + chrono::Duration::hours(1))),
+        ];
+        for (severity, ends_at) in announcements {
+            let announcement = NewAnnouncement {
+                title: "maintenance".to_string(),
+                body: "db upgrade".to_string(),
+                severity,
+                starts_at: Some(now - chrono::Duration::hours(2)),
+                ends_at,
+            };
+            let resp = app
+                .clone()
+                .oneshot(
+                    Request::builder()
+                        .method("POST")
+                        .uri("/v1/announcements")
+                        .header("Content-Type", "application/json")
+                        .body(Body::from(
+                            serde_json::to_string(&announcement).unwrap(),
+                        ))
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(resp.status(), StatusCode::CREATED);
+        }
+
+        // Execute
+        let resp = app
+            .oneshot(
+                Request::builder()
+                    .method("GET")
+                    .uri("/v1/announcements")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(resp.status(), StatusCode::OK);
+        let bytes = resp.into_body().collect().await.unwrap().to_bytes();
+        let active: Vec<Announcement> = serde_json::from_slice(&bytes).unwrap();
+        let severities: Vec<Severity> =
+            active.iter().map(|a| a.severity).collect();
+        assert_eq!(severities, vec![Severity::Warning, Severity::Info]);
+    }
+
+    #[tokio::test]
+    async fn it_sets_security_headers() {
+        // Setup
+        let (app, _) = create_test_app();
+
+        // Execute
+        let resp = list_test_notes(app).await;
+
+        // Assert
+        let headers = resp.headers();
+        assert_eq!(headers["x-content-type-options"], "nosniff");
+        assert_eq!(headers["referrer-policy"], "no-referrer");
+        assert!(headers.contains_key("content-security-policy"));
+        assert!(!headers.contains_key("strict-transport-security"));
+    }
+
+    #[tokio::test]
+    async fn it_tags_responses_with_request_ids() {
+        // Setup
+        let (app, _) = create_test_app();
+        let get = |uri: &str, id: Option<&str>| {
+            let request = Request::builder().uri(uri);
+            let request = match id {
+                Some(id) => request.header("x-request-id", id),
+                None => request,
+            };
+            request.body(Body::empty()).unwrap()
+        };
+
+        // Execute
+        let missing = app
+            .clone()
+            .oneshot(get("/v1/notes/unknown", None))
+            .await
+            .unwrap();
+        let propagated = app
+            .clone()
+            .oneshot(get("/v1/notes", Some("client-42")))
+            .await
+            .unwrap();
+        let replaced = app
+            .oneshot(get("/v1/notes", Some("not an id")))
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(missing.status(), StatusCode::NOT_FOUND);
+        assert_eq!(
+            missing.headers()["content-type"],
+            "application/problem+json"
+        );
+        let id = missing.headers()["x-request-id"].to_str().unwrap();
+        assert!(!id.is_empty());
+        let id = id.to_string();
+        let body = missing.into_body().collect().await.unwrap().to_bytes();
+        let problem: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(problem["status"], 404);
+        assert_eq!(problem["request_id"], id);
+        assert_eq!(propagated.headers()["x-request-id"], "client-42");
+        assert_ne!(replaced.headers()["x-request-id"], "not an id");
+    }
+
+    #[tokio::test]
+    async fn it_lists_capabilities_without_authentication() {
+        // Setup
+        let (app, _) = create_test_app_with_config(AppConfig {
+            auth: Some(AuthConfig {
+                issuer: "https://idp.test".to_string(),
+                audience: None,
+                keys: auth::KeySource::Static(jsonwebtoken::jwk::JwkSet {
+                    keys: Vec::new(),
+                }),
+            }),
+            require_if_match: true,
+            ..AppConfig::default()
+        });
+
+        // Execute
+        let resp = app
+            .oneshot(
+                Request::builder()
+                    .uri("/v1/capabilities")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(resp.status(), StatusCode::OK);
+        let bytes = resp.into_body().collect().await.unwrap().to_bytes();
+        let capabilities: Capabilities =
+            serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(capabilities.auth, capabilities::AuthMode::Jwt);
+        assert!(capabilities.api_keys);
+        assert!(capabilities.require_if_match);
+        assert!(capabilities.import_formats.contains(&"csv".to_string()));
+    }
+
+    #[tokio::test]
+    async fn it_serves_the_openapi_document() {
+        // Setup
+        let (app, _) = create_test_app();
+
+        // Execute
+        let resp = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/v1/openapi.json")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let docs = app
+            .oneshot(
+                Request::builder()
+                    .uri("/docs/")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(resp.status(), StatusCode::OK);
+        let bytes = resp.into_body().collect().await.unwrap().to_bytes();
+        let doc: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(doc["servers"][0]["url"], "/v1");
+        assert!(doc["paths"]["/notes/{id}"]["patch"].is_object());
+        assert!(doc["components"]["schemas"]["Note"].is_object());
+        assert_eq!(docs.status(), StatusCode::OK);
+        assert!(docs.headers()["content-security-policy"]
+            .to_str()
+            .unwrap()
+            .contains("default-src 'self'"));
+    }
+
+    #[tokio::test]
+    async fn it_ignores_unknown_fields_by_default() {
+        // Setup
+        let (app, _) = create_test_app();
+
+        // Execute
+        let resp = post_raw_test_note(
+            app,
+            r#"{"title": "a", "body": "b", "titel": "c"}"#,
+        )
+        .await;
+
+        // Assert
+        assert_eq!(resp.status(), StatusCode::CREATED);
+    }
+
+    #[tokio::test]
+    async fn it_rejects_unknown_fields_in_strict_mode() {
+        // Setup
+        let (app, _) = create_test_app_with_config(AppConfig {
+            strict_json: true,
+            ..AppConfig::default()
+        });
+
+        // Execute
+        let resp = post_raw_test_note(
+            app,
+            r#"{"title": "a", "body": "b", "titel": "c"}"#,
+        )
+        .await;
+
+        // Assert
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let bytes = resp.into_body().collect().await.unwrap().to_bytes();
+        let error: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(error["fields"], serde_json::json!(["titel"]));
+    }
+
+    #[tokio::test]
+    async fn it_rejects_invalid_new_notes() {
+        // Setup
+        let (app, notes) = create_test_app_with_config(AppConfig {
+            note_limits: NoteLimits {
+                max_title_chars: 200,
+                max_body_bytes: 4,
+                max_tags: 1,
+                max_tag_chars: 3,
+            },
+            ..AppConfig::default()
+        });
+
+        // Execute
+        let resp = post_raw_test_note(
+            app,
+            r#"{"title": " ", "body": "too long", "tags": ["a", "long"]}"#,
+        )
+        .await;
+
+        // Assert
+        assert_eq!(resp.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let bytes = resp.into_body().collect().await.unwrap().to_bytes();
+        let error: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(
+            error["errors"],
+            serde_json::json!([
+                { "field": "title", "message": "must not be empty" },
+                { "field": "body", "message": "must be at most 4 bytes" },
+                { "field": "tags", "message": "must be at most 1 tags" },
+                {
+                    "field": "tags",
+                    "message": "must each be at most 3 characters"
+                },
+            ])
+        );
+        assert!(notes.lock().await.vec.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn it_applies_the_duplicate_title_policy() {
+        // Setup
+        let (reject, _) = create_test_app_with_config(AppConfig {
+            title_policy: TitlePolicy::Reject,
+            ..AppConfig::default()
+        });
+        let (warn, _) = create_test_app_with_config(AppConfig {
+            title_policy: TitlePolicy::Warn,
+            ..AppConfig::default()
+        });
+        let resp = post_test_note(reject.clone(), NewNote::new("Todo", "a"));
+        let rejected_note = deserialize_note(resp.await.into_body()).await;
+        let resp = post_test_note(warn.clone(), NewNote::new("Todo", "a"));
+        let warned_note = deserialize_note(resp.await.into_body()).await;
+
+        // Execute
+        let rejected =
+            post_test_note(reject.clone(), NewNote::new("todo", "b")).await;
+        let warned = post_test_note(warn, NewNote::new("todo", "b")).await;
+        let renamed = patch_test_note(
+            reject,
+            &rejected_note.id,
+            PatchNote {
+                title: Some("TODO".to_string()),
+                body: None,
+                tags: None,
+                updated_at: chrono::Utc::now(),
+                version: None,
+                permissions: None,
+            },
+        )
+        .await;
+
+        // Assert
+        assert_eq!(rejected.status(), StatusCode::CONFLICT);
+        let bytes = rejected.into_body().collect().await.unwrap().to_bytes();
+        let problem: serde_json::Value =
+            serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(
+            problem["duplicates"],
+            serde_json::json!([rejected_note.id])
+        );
+        assert_eq!(warned.status(), StatusCode::CREATED);
+        assert_eq!(
+            warned.headers()[titles::DUPLICATE_TITLE_HEADER],
+            warned_note.id.as_str()
+        );
+        // A note doesn't clash with itself
+        assert_eq!(renamed.status(), StatusCode::OK);
+        assert!(renamed
+            .headers()
+            .get(titles::DUPLICATE_TITLE_HEADER)
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn it_keeps_the_versions_of_patched_notes() {
+        // Setup
+        let (app, _) = create_test_app();
+        let resp = post_test_note(app.clone(), NewNote::new("a", "b")).await;
+        let note = deserialize_note(resp.into_body()).await;
+        for title in ["c", "d"] {
+            let patch = PatchNote {
+                title: Some(title.to_string()),
+                body: None,
+                tags: None,
+                updated_at: chrono::Utc::now(),
+                version: None,
+                permissions: None,
+            };
+            patch_test_note(app.clone(), &note.id, patch).await;
+        }
+
+        // Execute
+        let get = |uri: String| {
+            app.clone().oneshot(
+                Request::builder().uri(uri).body(Body::empty()).unwrap(),
+            )
+        };
+        let listed = get(format!("/v1/notes/{}/versions", note.id))
+            .await
+            .unwrap();
+        let second = get(format!("/v1/notes/{}/versions/2", note.id))
+            .await
+            .unwrap();
+        let missing = get(format!("/v1/notes/{}/versions/3", note.id))
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(listed.status(), StatusCode::OK);
+        let bytes = listed.into_body().collect().await.unwrap().to_bytes();
+        let revisions: Vec<NoteRevision> =
+            serde_json::from_slice(&bytes).unwrap();
+        let titles: Vec<&str> =
+            revisions.iter().map(|r| r.title.as_str()).collect();
+        assert_eq!(titles, vec!["a", "c"]);
+        assert_eq!(second.status(), StatusCode::OK);
+        let bytes = second.into_body().collect().await.unwrap().to_bytes();
+        let revision: NoteRevision = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!((revision.rev, revision.title.as_str()), (2, "c"));
+        assert_eq!(missing.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn it_restores_old_versions() {
+        // Setup
+        let (app, notes) = create_test_app();
+        let resp = post_test_note(app.clone(), NewNote::new("a", "b")).await;
+        let note = deserialize_note(resp.into_body()).await;
+        let patch = PatchNote {
+            title: Some("c".to_string()),
+            body: Some("d".to_string()),
+            tags: None,
+            updated_at: chrono::Utc::now(),
+            version: None,
+            permissions: None,
+        };
+        patch_test_note(app.clone(), &note.id, patch).await;
+
+        // Execute
+        let resp = app
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri(format!("/v1/notes/{}/versions/1/restore", note.id))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(resp.status(), StatusCode::OK);
+        let restored = deserialize_note(resp.into_body()).await;
+        assert_eq!(
+            (restored.title.as_str(), restored.body.as_str()),
+            ("a", "b")
+        );
+        let notes = notes.lock().await;
+        let revisions = notes.revisions.lock().unwrap();
+        let titles: Vec<&str> =
+            revisions.iter().map(|r| r.title.as_str()).collect();
+        assert_eq!(titles, vec!["a", "c"]);
+    }
+
+    #[tokio::test]
+    async fn it_applies_the_duplicate_title_policy_to_restores() {
+        // Setup
+        let (app, notes) = create_test_app_with_config(AppConfig {
+            title_policy: TitlePolicy::Reject,
+            ..AppConfig::default()
+        });
+        let resp = post_test_note(app.clone(), NewNote::new("Todo", "a"));
+        let note = deserialize_note(resp.await.into_body()).await;
+        let patch = PatchNote {
+            title: Some("Done".to_string()),
+            body: None,
+            tags: None,
+            updated_at: chrono::Utc::now(),
+            version: None,
+            permissions: None,
+        };
+        patch_test_note(app.clone(), &note.id, patch).await;
+        let resp = post_test_note(app.clone(), NewNote::new("todo", "b"));
+        let other = deserialize_note(resp.await.into_body()).await;
+
+        // Execute
+        let resp = app
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri(format!("/v1/notes/{}/versions/1/restore", note.id))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(resp.status(), StatusCode::CONFLICT);
+        let bytes = resp.into_body().collect().await.unwrap().to_bytes();
+        let problem: serde_json::Value =
+            serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(problem["duplicates"], serde_json::json!([other.id]));
+        let notes = notes.lock().await;
+        let current = notes
+            .get_note(&OpContext::background(), &note.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(current.title, "Done");
+    }
+
+    #[tokio::test]
+    async fn it_replays_creates_with_the_same_idempotency_key() {
+        // Setup
+        let (app, notes) = create_test_app();
+        let post = |key: &'static str, title: &str| {
+            app.clone().oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/v1/notes")
+                    .header("Content-Type", "application/json")
+                    .header(idempotency::IDEMPOTENCY_KEY_HEADER, key)
+                    .body(Body::from(
+                        serde_json::to_string(&NewNote::new(title, "b"))
+                            .unwrap(),
+                    ))
+                    .unwrap(),
+            )
+        };
+        let first = post("retry-1", "a").await.unwrap();
+        let created = deserialize_note(first.into_body()).await;
+
+        // Execute
+        let replayed = post("retry-1", "changed").await.unwrap();
+        let other = post("retry-2", "a").await.unwrap();
+        notes
+            .lock()
+            .await
+            .vec
+            .lock()
+            .unwrap()
+            .retain(|n| n.id != created.id);
+        let deleted = post("retry-1", "a").await.unwrap();
+
+        // Assert
+        assert_eq!(replayed.status(), StatusCode::CREATED);
+        assert_eq!(replayed.headers()[idempotency::REPLAYED_HEADER], "true");
+        assert_eq!(deserialize_note(replayed.into_body()).await, created);
+        assert_eq!(other.status(), StatusCode::CREATED);
+        assert!(!other.headers().contains_key(idempotency::REPLAYED_HEADER));
+        assert_eq!(deleted.status(), StatusCode::GONE);
+        assert_eq!(notes.lock().await.vec.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn it_requires_a_valid_bearer_token() {
+        // Setup
+        let (app, _) = create_test_app_with_config(AppConfig {
+            auth: Some(test_auth_config()),
+            ..AppConfig::default()
+        });
+        let get = |uri: &str, token: Option<String>| {
+            let mut request = Request::builder().uri(uri);
+            if let Some(token) = token {
+                request = request.header(header::AUTHORIZATION, token);
+            }
+            app.clone().oneshot(request.body(Body::empty()).unwrap())
+        };
+
+        // Execute
+        let anonymous = get("/v1/notes", None).await.unwrap();
+        let foreign =
+            get("/v1/notes", Some(bearer("alice", "https://other.test")))
+                .await
+                .unwrap();
+        let valid = get("/v1/notes", Some(bearer("alice", TEST_ISSUER)))
+            .await
+            .unwrap();
+        let health = get("/v1/health", None).await.unwrap();
+
+        // Assert
+        assert_eq!(anonymous.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(anonymous.headers()[header::WWW_AUTHENTICATE], "Bearer");
+        assert_eq!(foreign.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(valid.status(), StatusCode::OK);
+        assert_eq!(health.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn it_restricts_admin_endpoints_to_admins() {
+        // Setup
+        let (app, _) = create_test_app_with_config(AppConfig {
+            auth: Some(test_auth_config()),
+            ..AppConfig::default()
+        });
+        let send = |method: &str, uri: &str, roles: &[&str], body: &str| {
+            app.clone().oneshot(
+                Request::builder()
+                    .method(method)
+                    .uri(uri)
+                    .header(
+                        header::AUTHORIZATION,
+                        bearer_with_roles("alice", TEST_ISSUER, roles),
+                    )
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(body.to_string()))
+                    .unwrap(),
+            )
+        };
+        let announcement =
+            r#"{"title":"Maintenance","body":"Saturday","severity":"info"}"#;
+        let migration = r#"{"read_mode":"new"}"#;
+        let admin_requests = [
+            ("PUT", "/v1/admin/migration", migration),
+            ("GET", "/v1/admin/migration", ""),
+            ("GET", "/v1/admin/metrics", ""),
+            ("POST", "/v1/announcements", announcement),
+            ("DELETE", "/v1/announcements/unknown", ""),
+            ("POST", "/v1/admin/api-keys", r#"{"name":"ci"}"#),
+            ("GET", "/v1/admin/api-keys", ""),
+            ("DELETE", "/v1/admin/api-keys/unknown", ""),
+        ];
+
+        // Execute
+        let mut user_statuses = Vec::new();
+        for (method, uri, body) in admin_requests {
+            let resp = send(method, uri, &["editor"], body).await.unwrap();
+            user_statuses.push(resp.status());
+        }
+        let listed = send("GET", "/v1/announcements", &[], "").await.unwrap();
+        let announced =
+            send("POST", "/v1/announcements", &["admin"], announcement)
+                .await
+                .unwrap();
+        let metrics = send("GET", "/v1/admin/metrics", &["admin"], "")
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(user_statuses, vec![StatusCode::FORBIDDEN; 8]);
+        assert_eq!(listed.status(), StatusCode::OK);
+        assert_eq!(announced.status(), StatusCode::CREATED);
+        assert_eq!(metrics.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn it_authenticates_machine_clients_with_api_keys() {
+        // Setup
+        let (app, _) = create_test_app_with_config(AppConfig {
+            auth: Some(test_auth_config()),
+            ..AppConfig::default()
+        });
+        let send = |method: &str, uri: &str, auth: (&str, String), body| {
+            app.clone().oneshot(
+                Request::builder()
+                    .method(method)
+                    .uri(uri)
+                    .header(auth.0, auth.1)
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(body)
+                    .unwrap(),
+            )
+        };
+        let admin = |subject| {
+            let token = bearer_with_roles(subject, TEST_ISSUER, &["admin"]);
+            (header::AUTHORIZATION.as_str(), token)
+        };
+        let alice = || admin("alice");
+        let bob = || admin("bob");
+        let resp = send(
+            "POST",
+            "/v1/admin/api-keys",
+            alice(),
+            Body::from(r#"{"name":"ci"}"#),
+        )
+        .await
+        .unwrap();
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        let bytes = resp.into_body().collect().await.unwrap().to_bytes();
+        let created: CreatedApiKey = serde_json::from_slice(&bytes).unwrap();
+        let api_key = || (api_keys::API_KEY_HEADER, created.key.clone());
+        let new_note = serde_json::to_string(&NewNote::new("a", "b")).unwrap();
+
+        // Execute
+        let posted = send("POST", "/v1/notes", api_key(), Body::from(new_note))
+            .await
+            .unwrap();
+        let minted_by_key = send(
+            "POST",
+            "/v1/admin/api-keys",
+            api_key(),
+            Body::from(r#"{"name":"copy"}"#),
+        )
+        .await
+        .unwrap();
+        let listed = send("GET", "/v1/admin/api-keys", alice(), Body::empty())
+            .await
+            .unwrap();
+        let listed_by_bob =
+            send("GET", "/v1/admin/api-keys", bob(), Body::empty())
+                .await
+                .unwrap();
+        let revoked_by_bob = send(
+            "DELETE",
+            &format!("/v1/admin/api-keys/{}", created.info.id),
+            bob(),
+            Body::empty(),
+        )
+        .await
+        .unwrap();
+        let revoked = send(
+            "DELETE",
+            &format!("/v1/admin/api-keys/{}", created.info.id),
+            alice(),
+            Body::empty(),
+        )
+        .await
+        .unwrap();
+        let after_revoke = send("GET", "/v1/notes", api_key(), Body::empty())
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(created.info.subject, "alice");
+        assert!(created.key.starts_with("nk_"));
+        assert_eq!(posted.status(), StatusCode::CREATED);
+        let note = deserialize_note(posted.into_body()).await;
+        assert_eq!(note.owner.as_deref(), Some("alice"));
+        assert_eq!(minted_by_key.status(), StatusCode::FORBIDDEN);
+        let bytes = listed.into_body().collect().await.unwrap().to_bytes();
+        let listed: Vec<ApiKeyInfo> = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(listed, vec![created.info.clone()]);
+        assert!(!String::from_utf8_lossy(&bytes).contains(&created.key));
+        let bytes = listed_by_bob.into_body().collect().await.unwrap();
+        let listed_by_bob: Vec<ApiKeyInfo> =
+            serde_json::from_slice(&bytes.to_bytes()).unwrap();
+        assert_eq!(listed_by_bob, Vec::new());
+        assert_eq!(revoked_by_bob.status(), StatusCode::NOT_FOUND);
+        assert_eq!(revoked.status(), StatusCode::NO_CONTENT);
+        assert_eq!(after_revoke.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn it_scopes_notes_to_their_owner() {
+        // Setup
+        let (app, _) = create_test_app_with_config(AppConfig {
+            auth: Some(test_auth_config()),
+            ..AppConfig::default()
+        });
+        let send = |method: &str, uri: &str, subject: &str, body: Body| {
+            app.clone().oneshot(
+                Request::builder()
+                    .method(method)
+                    .uri(uri)
+                    .header(header::AUTHORIZATION, bearer(subject, TEST_ISSUER))
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(body)
+                    .unwrap(),
+            )
+        };
+        let new_note = serde_json::to_string(&NewNote::new("a", "b")).unwrap();
+        let resp = send("POST", "/v1/notes", "alice", Body::from(new_note))
+            .await
+            .unwrap();
+        let note = deserialize_note(resp.into_body()).await;
+        let uri = format!("/v1/notes/{}", note.id);
+
+        // Execute
+        let own = send("GET", &uri, "alice", Body::empty()).await.unwrap();
+        let foreign = send("GET", &uri, "bob", Body::empty()).await.unwrap();
+        let listed = send("GET", "/v1/notes", "bob", Body::empty())
+            .await
+            .unwrap();
+        let deleted = send("DELETE", &uri, "bob", Body::empty()).await.unwrap();
+
+        // Assert
+        assert_eq!(note.owner.as_deref(), Some("alice"));
+        assert_eq!(own.status(), StatusCode::OK);
+        assert_eq!(foreign.status(), StatusCode::NOT_FOUND);
+        assert_eq!(listed.headers()[TOTAL_COUNT_HEADER], "0");
+        assert_eq!(deleted.status(), StatusCode::NOT_FOUND);
+        let own = send("GET", &uri, "alice", Body::empty()).await.unwrap();
+        assert_eq!(own.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn it_serves_shared_notes_until_revoked() {
+        // Setup
+        let (app, _) = create_test_app_with_config(AppConfig {
+            auth: Some(test_auth_config()),
+            ..AppConfig::default()
+        });
+        let send = |method: &str, uri: &str, subject: Option<&str>| {
+            let mut request = Request::builder().method(method).uri(uri);
+            if let Some(subject) = subject {
+                request = request.header(
+                    header::AUTHORIZATION,
+                    bearer(subject, TEST_ISSUER),
+                );
+            }
+            let body = match method {
+                "POST" if uri == "/v1/notes" => {
+                    serde_json::to_string(&NewNote::new("a", "b"))
+                        .unwrap()
+                        .into()
+                }
+                _ => Body::empty(),
+            };
+            app.clone().oneshot(
+                request
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(body)
+                    .unwrap(),
+            )
+        };
+        let resp = send("POST", "/v1/notes", Some("alice")).await.unwrap();
+        let note = deserialize_note(resp.into_body()).await;
+        let share_uri = format!("/v1/notes/{}/share", note.id);
+        let resp = send("POST", &share_uri, Some("alice")).await.unwrap();
+        let share_bytes = resp.into_body().collect().await.unwrap().to_bytes();
+        let share: CreatedShare = serde_json::from_slice(&share_bytes).unwrap();
+        let shared_uri = share.url.clone();
+
+        // Execute
+        let shared = send("GET", &shared_uri, None).await.unwrap();
+        let foreign = send("POST", &share_uri, Some("bob")).await.unwrap();
+        let expiring = format!("{}?expires_in_secs=0", share_uri);
+        let resp = send("POST", &expiring, Some("alice")).await.unwrap();
+        let bytes = resp.into_body().collect().await.unwrap().to_bytes();
+        let expired: CreatedShare = serde_json::from_slice(&bytes).unwrap();
+        let expired = send("GET", &expired.url, None).await.unwrap();
+        let overflowing = format!("{}?expires_in_secs={}", share_uri, u64::MAX);
+        let overflowing =
+            send("POST", &overflowing, Some("alice")).await.unwrap();
+        let revoke_uri = format!("{}/{}", share_uri, share.id);
+        let revoked = send("DELETE", &revoke_uri, Some("alice")).await.unwrap();
+        let after_revoke = send("GET", &shared_uri, None).await.unwrap();
+
+        // Assert
+        assert_eq!(share.url, format!("/v1/shared/{}", share.token));
+        assert_eq!(shared.status(), StatusCode::OK);
+        assert_eq!(deserialize_note(shared.into_body()).await, note);
+        assert_eq!(foreign.status(), StatusCode::NOT_FOUND);
+        assert_eq!(expired.status(), StatusCode::NOT_FOUND);
+        assert_eq!(overflowing.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(revoked.status(), StatusCode::NO_CONTENT);
+        assert_eq!(after_revoke.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn it_shares_notes_within_organizations() {
+        // Setup
+        let (app, _) = create_test_app_with_config(AppConfig {
+            auth: Some(test_auth_config()),
+            ..AppConfig::default()
+        });
+        let send = |method: &str, uri: &str, subject: &str, body: String| {
+            app.clone().oneshot(
+                Request::builder()
+                    .method(method)
+                    .uri(uri)
+                    .header(header::AUTHORIZATION, bearer(subject, TEST_ISSUER))
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(body))
+                    .unwrap(),
+            )
+        };
+        let resp = send("POST", "/v1/orgs", "alice", r#"{"name":"a"}"#.into())
+            .await
+            .unwrap();
+        let org_bytes = resp.into_body().collect().await.unwrap().to_bytes();
+        let org: Organization = serde_json::from_slice(&org_bytes).unwrap();
+        let members = format!("/v1/orgs/{}/members", org.id);
+        send("PUT", &format!("{}/bob", members), "alice", String::new())
+            .await
+            .unwrap();
+        let new_note = serde_json::to_string(&NewNote {
+            org: Some(org.id.clone()),
+            ..NewNote::new("a", "b")
+        })
+        .unwrap();
+        let resp = send("POST", "/v1/notes", "alice", new_note.clone())
+            .await
+            .unwrap();
+        let note = deserialize_note(resp.into_body()).await;
+        let uri = format!("/v1/notes/{}", note.id);
+
+        // Execute
+        let member = send("GET", &uri, "bob", String::new()).await.unwrap();
+        let outsider = send("GET", &uri, "carol", String::new()).await.unwrap();
+        let foreign_org =
+            send("POST", "/v1/notes", "carol", new_note).await.unwrap();
+        send(
+            "DELETE",
+            &format!("{}/bob", members),
+            "alice",
+            String::new(),
+        )
+        .await
+        .unwrap();
+        let removed = send("GET", &uri, "bob", String::new()).await.unwrap();
+        let last_member =
+            send("DELETE", &format!("{}/alice", members), "alice", "".into())
+                .await
+                .unwrap();
+
+        // Assert
+        assert_eq!(note.owner.as_deref(), Some("alice"));
+        assert_eq!(member.status(), StatusCode::OK);
+        assert_eq!(outsider.status(), StatusCode::NOT_FOUND);
+        assert_eq!(foreign_org.status(), StatusCode::FORBIDDEN);
+        assert_eq!(removed.status(), StatusCode::NOT_FOUND);
+        assert_eq!(last_member.status(), StatusCode::CONFLICT);
+    }
+
+    #[tokio::test]
+    async fn it_enforces_note_permissions() {
+        // Setup
+        let (app, _) = create_test_app_with_config(AppConfig {
+            auth: Some(test_auth_config()),
+            ..AppConfig::default()
+        });
+        let send = |method: &str, uri: &str, subject: &str, body: &str| {
+            app.clone().oneshot(
+                Request::builder()
+                    .method(method)
+                    .uri(uri)
+                    .header(header::AUTHORIZATION, bearer(subject, TEST_ISSUER))
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(body.to_string()))
+                    .unwrap(),
+            )
+        };
+        let new_note = serde_json::to_string(&NewNote::new("a", "b")).unwrap();
+        let resp = send("POST", "/v1/notes", "alice", &new_note).await.unwrap();
+        let note = deserialize_note(resp.into_body()).await;
+        let uri = format!("/v1/notes/{}", note.id);
+        let permissions = format!("{}/permissions", uri);
+        let patch = r#"{"body":"c"}"#;
+
+        // Execute
+        let grant = send(
+            "POST",
+            &permissions,
+            "alice",
+            r#"{"subject":"bob","access":"read"}"#,
+        )
+        .await
+        .unwrap();
+        let by_reader = send(
+            "POST",
+            &permissions,
+            "bob",
+            r#"{"subject":"bob","access":"write"}"#,
+        )
+        .await
+        .unwrap();
+        let read = send("GET", &uri, "bob", "").await.unwrap();
+        let read_only_patch = send("PATCH", &uri, "bob", patch).await.unwrap();
+        send(
+            "POST",
+            &permissions,
+            "alice",
+            r#"{"subject":"bob","access":"write"}"#,
+        )
+        .await
+        .unwrap();
+        let write_patch = send("PATCH", &uri, "bob", patch).await.unwrap();
+        let delete = send("DELETE", &uri, "bob", "").await.unwrap();
+        let revoke =
+            send("DELETE", &format!("{}/bob", permissions), "alice", "")
+                .await
+                .unwrap();
+        let revoked = send("GET", &uri, "bob", "").await.unwrap();
+
+        // Assert
+        assert_eq!(grant.status(), StatusCode::OK);
+        let granted = grant.into_body().collect().await.unwrap();
+        let granted: Permissions =
+            serde_json::from_slice(&granted.to_bytes()).unwrap();
+        assert_eq!(granted.read, vec!["bob".to_string()]);
+        assert_eq!(by_reader.status(), StatusCode::FORBIDDEN);
+        assert_eq!(read.status(), StatusCode::OK);
+        assert_eq!(read_only_patch.status(), StatusCode::FORBIDDEN);
+        assert_eq!(write_patch.status(), StatusCode::OK);
+        assert_eq!(delete.status(), StatusCode::FORBIDDEN);
+        assert_eq!(revoke.status(), StatusCode::NO_CONTENT);
+        assert_eq!(revoked.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn it_keeps_tenants_apart() {
+        // Setup
+        let (app, _) = create_test_app_with_config(AppConfig {
+            multi_tenant: true,
+            ..AppConfig::default()
+        });
+        let send = |method: &str, uri: &str, tenant: Option<&str>| {
+            let mut request = Request::builder().method(method).uri(uri);
+            if let Some(tenant) = tenant {
+                request = request.header(tenancy::TENANT_HEADER, tenant);
+            }
+            let body = match method {
+                "POST" => serde_json::to_string(&NewNote::new("a", "b"))
+                    .unwrap()
+                    .into(),
+                _ => Body::empty(),
+            };
+            app.clone().oneshot(
+                request
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(body)
+                    .unwrap(),
+            )
+        };
+        let resp = send("POST", "/t/acme/v1/notes", None).await.unwrap();
+        let note = deserialize_note(resp.into_body()).await;
+
+        // Execute
+        let own = send("GET", &format!("/v1/notes/{}", note.id), Some("acme"))
+            .await
+            .unwrap();
+        let foreign =
+            send("GET", &format!("/t/other/v1/notes/{}", note.id), None)
+                .await
+                .unwrap();
+        let listed = send("GET", "/v1/notes", Some("other")).await.unwrap();
+        let missing = send("GET", "/v1/notes", None).await.unwrap();
+        let ambiguous = send("GET", "/t/acme/v1/notes", Some("other"))
+            .await
+            .unwrap();
+        let health = send("GET", "/v1/health", None).await.unwrap();
+
+        // Assert
+        assert_eq!(note.tenant.as_deref(), Some("acme"));
+        assert_eq!(own.status(), StatusCode::OK);
+        assert_eq!(foreign.status(), StatusCode::NOT_FOUND);
+        assert_eq!(listed.headers()[TOTAL_COUNT_HEADER], "0");
+        assert_eq!(missing.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(ambiguous.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(health.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn it_handles_conditional_requests() {
+        // Setup
+        let (app, _) = create_test_app_with_config(AppConfig {
+            require_if_match: true,
+            ..AppConfig::default()
+        });
+        let resp = post_test_note(app.clone(), NewNote::new("a", "b")).await;
+        let created_etag = resp.headers()[header::ETAG].clone();
+        let note = deserialize_note(resp.into_body()).await;
+        let uri = format!("/v1/notes/{}", note.id);
+        let request = |method: &str, name: header::HeaderName, etag| {
+            app.clone().oneshot(
+                Request::builder()
+                    .method(method)
+                    .uri(&uri)
+                    .header(name, etag)
+                    .header("Content-Type", "application/json")
+                    .body(Body::from(r#"{"title": "c"}"#))
+                    .unwrap(),
+            )
+        };
+
+        // Execute
+        let cached = request("GET", header::IF_NONE_MATCH, &created_etag)
+            .await
+            .unwrap();
+        let patched = request("PATCH", header::IF_MATCH, &created_etag)
+            .await
+            .unwrap();
+        let stale = request("DELETE", header::IF_MATCH, &created_etag)
+            .await
+            .unwrap();
+        let unconditional = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("DELETE")
+                    .uri(&uri)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(cached.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(patched.status(), StatusCode::OK);
+        assert_ne!(patched.headers()[header::ETAG], created_etag);
+        assert_eq!(stale.status(), StatusCode::PRECONDITION_FAILED);
+        assert_eq!(unconditional.status(), StatusCode::PRECONDITION_REQUIRED);
+    }
+
+    #[tokio::test]
+    async fn it_rejects_patches_of_stale_versions() {
+        // Setup
+        let (app, notes) = create_test_app();
+        let resp = post_test_note(app.clone(), NewNote::new("a", "b")).await;
+        let note = deserialize_note(resp.into_body()).await;
+        let patch = |title: &str| PatchNote {
+            title: Some(title.to_string()),
+            body: None,
+            tags: None,
+            updated_at: chrono::Utc::now(),
+            version: Some(note.version),
+            permissions: None,
+        };
+
+        // Execute
+        let first = patch_test_note(app.clone(), &note.id, patch("c")).await;
+        let second = patch_test_note(app, &note.id, patch("d")).await;
+
+        // Assert
+        assert_eq!(first.status(), StatusCode::OK);
+        let patched = deserialize_note(first.into_body()).await;
+        assert_eq!(patched.version, note.version + 1);
+        assert_eq!(second.status(), StatusCode::CONFLICT);
+        let stored = notes.lock().await.vec.lock().unwrap()[0].clone();
+        assert_eq!(stored.title, "c");
+    }
+
+    #[tokio::test]
+    async fn it_rejects_invalid_patches() {
+        // Setup
+        let (app, _) = create_test_app_with_config(AppConfig {
+            note_limits: NoteLimits {
+                max_title_chars: 3,
+                max_body_bytes: 1024,
+                ..NoteLimits::default()
+            },
+            ..AppConfig::default()
+        });
+        let resp = post_test_note(app.clone(), NewNote::new("a", "b")).await;
+        let note = deserialize_note(resp.into_body()).await;
+
+        // Execute
+        let resp = patch_test_note(
+            app,
+            &note.id,
+            PatchNote {
+                title: Some("abcd".to_string()),
+                body: None,
+                tags: None,
+                updated_at: chrono::Utc::now(),
+                version: None,
+                permissions: None,
+            },
+        )
+        .await;
+
+        // Assert
+        assert_eq!(resp.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[tokio::test]
+    async fn it_rejects_duplicate_submissions_within_window() {
+        // Setup
+        let (app, _) = create_test_app_with_config(AppConfig {
+            dedupe_window: Some(std::time::Duration::from_secs(60)),
+            ..AppConfig::default()
+        });
+        let body = r#"{"title": "a", "body": "b"}"#;
+
+        // Execute
+        let first = post_raw_test_note(app.clone(), body).await;
+        let second = post_raw_test_note(app.clone(), body).await;
+        let other =
+            post_raw_test_note(app, r#"{"title": "a", "body": "c"}"#).await;
 
         // Assert
-        assert_eq!(resp.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(first.status(), StatusCode::CREATED);
+        assert_eq!(second.status(), StatusCode::CONFLICT);
+        assert_eq!(other.status(), StatusCode::CREATED);
     }
 
     #[tokio::test]
-    async fn it_fails_to_delete_a_note_not_found() {
+    async fn it_tells_duplicate_submissions_apart_by_principal_and_tenant() {
         // Setup
-        let (app, _) = create_test_app();
+        let (app, _) = create_test_app_with_config(AppConfig {
+            dedupe_window: Some(std::time::Duration::from_secs(60)),
+            auth: Some(test_auth_config()),
+            multi_tenant: true,
+            ..AppConfig::default()
+        });
+        let send = |uri: &str, credential: (&str, String), body: &str| {
+            app.clone().oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri(uri)
+                    .header(credential.0, credential.1)
+                    .header(tenancy::TENANT_HEADER, "billing")
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(body.to_string()))
+                    .unwrap(),
+            )
+        };
+        let bearer = |subject| {
+            let token = bearer_with_roles(subject, TEST_ISSUER, &["admin"]);
+            (header::AUTHORIZATION.as_str(), token)
+        };
+        let mut api_keys = Vec::new();
+        for subject in ["alice", "bob"] {
+            let name = r#"{"name":"ci"}"#;
+            let resp = send("/v1/admin/api-keys", bearer(subject), name)
+                .await
+                .unwrap();
+            let bytes = resp.into_body().collect().await.unwrap().to_bytes();
+            let created: CreatedApiKey =
+                serde_json::from_slice(&bytes).unwrap();
+            api_keys.push((api_keys::API_KEY_HEADER, created.key));
+        }
+        let body = r#"{"title": "a", "body": "b"}"#;
 
         // Execute
-        let resp = delete_test_note(app, &nanoid!()).await;
+        let alice = send("/v1/notes", api_keys[0].clone(), body).await.unwrap();
+        let bob = send("/v1/notes", api_keys[1].clone(), body).await.unwrap();
+        let other_tenant = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/t/sales/v1/notes")
+                    .header(api_keys[0].0, api_keys[0].1.clone())
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(body))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let again = send("/v1/notes", bearer("alice"), body).await.unwrap();
 
         // Assert
-        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        assert_eq!(alice.status(), StatusCode::CREATED);
+        assert_eq!(bob.status(), StatusCode::CREATED);
+        assert_eq!(other_tenant.status(), StatusCode::CREATED);
+        assert_eq!(again.status(), StatusCode::CONFLICT);
     }
 
     #[tokio::test]
-    async fn it_fails_to_list_notes() {
+    async fn it_mirrors_writes_to_secondary() {
         // Setup
-        let (app, state) = create_test_app();
-        state.lock().await.set_fail_list(true);
+        let ctx = OpContext::background();
+        let primary =
+            Arc::new(NoteVecDb::new(sync::Mutex::new(Vec::<Note>::new())));
+        let secondary =
+            Arc::new(NoteVecDb::new(sync::Mutex::new(Vec::<Note>::new())));
+        let mirror = MirroredNoteDb::new(primary.clone(), secondary.clone());
+        let kept = Note::new("kept", "body", "url");
+        let removed = Note::new("removed", "body", "url");
+        let replicated = metrics::MIRROR_REPLICATED_WRITES.get();
 
         // Execute
-        let resp = list_test_notes(app).await;
+        mirror.create_note(&ctx, &kept).await.unwrap();
+        mirror.create_note(&ctx, &removed).await.unwrap();
+        mirror
+            .update_note(
+                &ctx,
+                &kept.id,
+                &PatchNote {
+                    title: Some("newtitle".to_string()),
+                    body: None,
+                    tags: None,
+                    updated_at: chrono::Utc::now(),
+                    version: None,
+                    permissions: None,
+                },
+            )
+            .await
+            .unwrap();
+        mirror.delete_note(&ctx, &removed.id).await.unwrap();
+        while mirror.stats().pending > 0 {
+            tokio::task::yield_now().await;
+        }
 
         // Assert
-        assert_eq!(resp.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let stats = mirror.stats();
+        assert_eq!(stats.replicated, 4);
+        assert_eq!(stats.failed, 0);
+        // Other tests replicate writes too.
+        assert!(metrics::gauges().mirror_replicated_writes >= replicated + 4);
+        let notes = secondary
+            .list_notes(&ctx, &NoteFilter::default(), &Page::default())
+            .await
+            .unwrap()
+            .notes;
+        assert_eq!(notes.len(), 1);
+        assert_eq!(notes[0].title, "newtitle");
+
+        secondary.create_note(&ctx, &removed).await.unwrap();
+        let report = mirror.catch_up(&ctx).await.unwrap();
+        assert_eq!(report.copied, 1);
+        assert_eq!(report.removed, 1);
     }
 
     #[tokio::test]
-    async fn it_creates_a_note() {
+    async fn it_mirrors_writes_to_the_tenant_of_each_note() {
         // Setup
-        let (app, _) = create_test_app();
-        let new_note = NewNote {
-            title: "a".to_string(),
-            body: "b".to_string(),
+        let primary =
+            Arc::new(NoteVecDb::new(sync::Mutex::new(Vec::<Note>::new())));
+        let secondary =
+            Arc::new(NoteVecDb::new(sync::Mutex::new(Vec::<Note>::new())));
+        let mirror = MirroredNoteDb::new(primary.clone(), secondary.clone());
+        let a = OpContext::for_tenant(Some("a".to_string()));
+        let b = OpContext::for_tenant(Some("b".to_string()));
+        let note = |ctx: &OpContext| Note {
+            tenant: ctx.tenant.clone(),
+            ..Note::new("title", "body", "url")
         };
 
         // Execute
-        let resp = post_test_note(app, new_note).await;
+        mirror.create_note(&a, &note(&a)).await.unwrap();
+        mirror.create_note(&b, &note(&b)).await.unwrap();
+        while mirror.stats().pending > 0 {
+            tokio::task::yield_now().await;
+        }
 
         // Assert
-        assert_eq!(resp.status(), StatusCode::CREATED);
-        let note_json = deserialize_note(resp.into_body()).await;
-        assert_eq!(note_json.title, "a");
-        assert_eq!(note_json.body, "b");
+        assert_eq!(mirror.stats().replicated, 2);
+        assert_eq!(
+            *secondary.create_tenants.lock().unwrap(),
+            vec![a.tenant.clone(), b.tenant.clone()]
+        );
     }
 
     #[tokio::test]
-    async fn it_gets_a_note() {
+    async fn it_switches_migration_reads_at_runtime() {
         // Setup
-        let (app, _) = create_test_app();
-        let new_note = NewNote {
-            title: "a".to_string(),
-            body: "b".to_string(),
-        };
-        let resp = post_test_note(app.clone(), new_note).await;
-        let note_json = deserialize_note(resp.into_body()).await;
+        let old = Arc::new(NoteVecDb::new(sync::Mutex::new(Vec::new())));
+        let new = Arc::new(NoteVecDb::new(sync::Mutex::new(Vec::new())));
+        let control = Arc::new(DualWriteControl::new(ReadMode::Old));
+        let dual_write =
+            DualWriteNoteDb::new(old.clone(), new.clone(), control.clone());
+        let state = Arc::new(AppState {
+            notes: Arc::new(Mutex::new(dual_write)),
+            announcements: Arc::new(AnnouncementVecDb::default()),
+            idempotency: Arc::new(NoteMemoryDb::new()),
+            api_keys: Arc::new(NoteMemoryDb::new()),
+            orgs: Arc::new(NoteMemoryDb::new()),
+            shares: Arc::new(NoteMemoryDb::new()),
+            migration: Some(control.clone()),
+            ingest: None,
+            notes_path: "/notes".to_string(),
+            strict_json: false,
+            note_limits: NoteLimits::default(),
+            title_policy: TitlePolicy::default(),
+            require_if_match: false,
+            idempotency_ttl: idempotency::DEFAULT_TTL,
+            default_page_size: DEFAULT_PAGE_SIZE,
+            max_page_size: MAX_PAGE_SIZE,
+            archive_limits: ArchiveLimits::default(),
+        });
+        let app = create_axum_app(state, &AppConfig::default());
+        let resp = post_test_note(app.clone(), NewNote::new("a", "b")).await;
+        let note = deserialize_note(resp.into_body()).await;
+        new.vec.lock().unwrap()[0].title = "drifted".to_string();
 
+        // Execute
         let resp = app
+            .clone()
             .oneshot(
                 Request::builder()
-                    .method("GET")
-                    .uri(format!("/v1/notes/{}", note_json.id))
+                    .method("PUT")
+                    .uri("/v1/admin/migration")
                     .header("Content-Type", "application/json")
-                    .body(Body::empty())
+                    .body(Body::from(r#"{"read_mode": "compare"}"#))
                     .unwrap(),
             )
             .await
             .unwrap();
-
-        // Assert
         assert_eq!(resp.status(), StatusCode::OK);
-        let note_json = deserialize_note(resp.into_body()).await;
-        assert_eq!(note_json.title, "a");
-        assert_eq!(note_json.body, "b");
-    }
-
-    #[tokio::test]
-    async fn it_lists_notes() {
-        // Setup
-        let (app, _) = create_test_app();
-        let note = NewNote::new("note0", "body0");
-        let _ = post_test_note(app.clone(), note).await;
-        let note = NewNote::new("note01", "body1");
-        let _ = post_test_note(app.clone(), note).await;
-
-        // Execute
         let resp = app
             .oneshot(
                 Request::builder()
                     .method("GET")
-                    .uri("/v1/notes")
-                    .header("Content-Type", "application/json")
+                    .uri(format!("/v1/notes/{}", note.id))
                     .body(Body::empty())
                     .unwrap(),
             )
@@ -563,102 +5055,368 @@ mod tests {
             .unwrap();
 
         // Assert
-        assert_eq!(resp.status(), StatusCode::OK);
-        let notes: Vec<Note> = deserialize_notes(resp.into_body()).await;
-        assert_eq!(notes.len(), 2);
+        assert_eq!(old.vec.lock().unwrap().len(), 1);
+        assert_eq!(deserialize_note(resp.into_body()).await.title, "a");
+        let status = control.status();
+        assert_eq!(status.read_mode, ReadMode::Compare);
+        assert_eq!(status.divergences, 1);
     }
 
     #[tokio::test]
-    async fn it_deletes_a_note() {
+    async fn it_reports_shadow_read_mismatches() {
         // Setup
-        let (app, _) = create_test_app();
-        let note = NewNote::new("note0", "body0");
-        let note0 = post_test_note(app.clone(), note).await;
-        let note0 = deserialize_note(note0.into_body()).await;
-        let note = NewNote::new("note01", "body1");
-        let note = post_test_note(app.clone(), note).await;
-        let note = deserialize_note(note.into_body()).await;
+        let ctx = OpContext::background();
+        let primary = Arc::new(NoteVecDb::new(sync::Mutex::new(Vec::new())));
+        let secondary = Arc::new(NoteVecDb::new(sync::Mutex::new(Vec::new())));
+        let same = Note::new("same", "b", "url");
+        let drifted = Note::new("drifted", "b", "url");
+        for note in [&same, &drifted] {
+            primary.create_note(&ctx, note).await.unwrap();
+            secondary.create_note(&ctx, note).await.unwrap();
+        }
+        secondary.vec.lock().unwrap()[1].body = "drifted".to_string();
+        let shadow = ShadowReadNoteDb::new(primary, secondary, 100);
+        let mismatches = metrics::SHADOW_READ_MISMATCHES.get();
 
         // Execute
-        let resp = app
-            .clone()
-            .oneshot(
+        shadow.get_note(&ctx, &same.id).await.unwrap();
+        shadow.get_note(&ctx, &drifted.id).await.unwrap();
+        while shadow.stats().mismatches + shadow.stats().errors == 0 {
+            tokio::task::yield_now().await;
+        }
+
+        // Assert
+        let stats = shadow.stats();
+        assert_eq!(stats.sampled, 2);
+        assert_eq!(stats.mismatches, 1);
+        assert_eq!(stats.errors, 0);
+        // Other tests may report mismatches too.
+        assert!(metrics::gauges().shadow_read_mismatches > mismatches);
+    }
+
+    #[tokio::test]
+    async fn it_hedges_slow_reads_to_the_replica() {
+        // Setup
+        let ctx = OpContext::background();
+        let primary = Arc::new(NoteVecDb::new(sync::Mutex::new(Vec::new())));
+        let replica = Arc::new(NoteVecDb::new(sync::Mutex::new(Vec::new())));
+        let note = Note::new("a", "b", "url");
+        primary.create_note(&ctx, &note).await.unwrap();
+        replica.create_note(&ctx, &note).await.unwrap();
+        let hedged = HedgedNoteDb::new(
+            primary.clone(),
+            replica,
+            std::time::Duration::from_millis(10),
+        );
+        let won = metrics::HEDGED_READS_WON.get();
+
+        // Execute
+        hedged.get_note(&ctx, &note.id).await.unwrap();
+        primary.set_get_delay_ms(1000);
+        let hedged_note = hedged.get_note(&ctx, &note.id).await.unwrap();
+
+        // Assert
+        assert_eq!(hedged_note, Some(note));
+        let stats = hedged.stats();
+        assert_eq!(stats.issued, 1);
+        assert_eq!(stats.won, 1);
+        // Other tests may hedge reads too.
+        assert!(metrics::gauges().hedged_reads_won > won);
+    }
+
+    #[tokio::test]
+    async fn it_reads_strongly_consistent_from_the_primary_only() {
+        // Setup
+        let ctx = OpContext {
+            consistency: Some(Consistency::Strong),
+            ..OpContext::background()
+        };
+        let primary = Arc::new(NoteVecDb::new(sync::Mutex::new(Vec::new())));
+        let replica = Arc::new(NoteVecDb::new(sync::Mutex::new(Vec::new())));
+        let note = Note::new("a", "b", "url");
+        primary.create_note(&ctx, &note).await.unwrap();
+        replica.create_note(&ctx, &note).await.unwrap();
+        primary.set_get_delay_ms(50);
+        let hedged = HedgedNoteDb::new(
+            primary,
+            replica,
+            std::time::Duration::from_millis(10),
+        );
+        let (app, _) = create_test_app();
+        let list = |consistency: &'static str| {
+            app.clone().oneshot(
                 Request::builder()
-                    .method("DELETE")
-                    .uri(format!("/v1/notes/{}", note.id))
-                    .header("Content-Type", "application/json")
+                    .uri("/v1/notes")
+                    .header(consistency::CONSISTENCY_HEADER, consistency)
                     .body(Body::empty())
                     .unwrap(),
             )
-            .await
-            .unwrap();
+        };
+
+        // Execute
+        let read = hedged.get_note(&ctx, &note.id).await.unwrap();
+        let eventual = list("eventual").await.unwrap();
+        let invalid = list("linearizable").await.unwrap();
 
         // Assert
-        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
-        let resp = app
-            .oneshot(
+        assert_eq!(read, Some(note));
+        assert_eq!(hedged.stats().issued, 0);
+        assert_eq!(
+            eventual.headers()[consistency::CONSISTENCY_HEADER],
+            "eventual"
+        );
+        assert_eq!(invalid.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn it_defaults_to_eventual_consistency_with_a_negative_cache() {
+        // Setup
+        let (uncached, _) = create_test_app();
+        let (cached, _) = create_test_app_with_config(AppConfig {
+            negative_cache_ttl: Some(std::time::Duration::from_secs(1)),
+            ..AppConfig::default()
+        });
+        let list = |app: Router| {
+            app.oneshot(
                 Request::builder()
-                    .method("GET")
                     .uri("/v1/notes")
-                    .header("Content-Type", "application/json")
                     .body(Body::empty())
                     .unwrap(),
             )
-            .await
-            .unwrap();
-        let notes = deserialize_notes(resp.into_body()).await;
-        assert_eq!(notes.len(), 1);
-        assert_eq!(notes[0].id, note0.id);
+        };
+
+        // Execute
+        let uncached = list(uncached).await.unwrap();
+        let cached = list(cached).await.unwrap();
+
+        // Assert
+        let header = consistency::CONSISTENCY_HEADER;
+        assert_eq!(uncached.headers()[header], "strong");
+        assert_eq!(cached.headers()[header], "eventual");
     }
 
     #[tokio::test]
-    async fn it_patches_a_note() {
+    async fn it_writes_full_batches_at_once() {
         // Setup
-        let (app, _) = create_test_app();
-        let note = NewNote::new("note0", "body0");
-        let note = post_test_note(app.clone(), note).await;
-        let note = deserialize_note(note.into_body()).await;
+        let ctx = OpContext::background();
+        let inner = Arc::new(NoteVecDb::new(sync::Mutex::new(Vec::new())));
+        let batching = BatchingNoteDb::new(
+            inner.clone(),
+            BatchConfig {
+                max_batch: 3,
+                max_delay: std::time::Duration::from_secs(60),
+                ..BatchConfig::default()
+            },
+        );
+        let notes: Vec<Note> =
+            (0..3).map(|i| Note::new(&i.to_string(), "b", "")).collect();
 
         // Execute
-        let resp = patch_test_note(
-            app.clone(),
-            &note.id,
-            PatchNote {
-                title: Some("newtitle".to_string()),
-                body: Some("newbody".to_string()),
+        let results = futures::future::join_all(
+            notes.iter().map(|note| batching.create_note(&ctx, note)),
+        )
+        .await;
+
+        // Assert
+        assert!(results.iter().all(|res| res.is_ok()));
+        assert_eq!(inner.create_batches.load(Ordering::SeqCst), 1);
+        assert_eq!(*inner.vec.lock().unwrap(), notes);
+    }
+
+    #[test]
+    fn it_parses_ingest_acknowledgements() {
+        // Execute
+        let durable = "durable".parse::<Acknowledge>();
+        let queued = "queued".parse::<Acknowledge>();
+        let invalid = "Queued".parse::<Acknowledge>();
+
+        // Assert
+        assert_eq!(durable, Ok(Acknowledge::Durable));
+        assert_eq!(queued, Ok(Acknowledge::Queued));
+        assert!(invalid.is_err());
+    }
+
+    #[tokio::test]
+    async fn it_writes_batches_to_the_tenant_of_each_note() {
+        // Setup
+        let inner = Arc::new(NoteVecDb::new(sync::Mutex::new(Vec::new())));
+        let batching = BatchingNoteDb::new(
+            inner.clone(),
+            BatchConfig {
+                max_batch: 4,
+                max_delay: std::time::Duration::from_secs(60),
+                ..BatchConfig::default()
             },
+        );
+        let creates: Vec<(OpContext, Note)> = ["a", "b", "a", "b"]
+            .into_iter()
+            .enumerate()
+            .map(|(i, tenant)| {
+                let ctx = OpContext::for_tenant(Some(tenant.to_string()));
+                let note = Note {
+                    tenant: ctx.tenant.clone(),
+                    ..Note::new(&i.to_string(), "b", "")
+                };
+                (ctx, note)
+            })
+            .collect();
+
+        // Execute
+        let results = futures::future::join_all(
+            creates
+                .iter()
+                .map(|(ctx, note)| batching.create_note(ctx, note)),
         )
         .await;
 
-        // assert
-        assert_eq!(resp.status(), StatusCode::OK);
-        let resp = app
-            .oneshot(
-                Request::builder()
-                    .method("GET")
-                    .uri(format!("/v1/notes/{}", note.id))
-                    .header("Content-Type", "application/json")
-                    .body(Body::empty())
-                    .unwrap(),
-            )
-            .await
-            .unwrap();
-        let patched_noted = deserialize_note(resp.into_body()).await;
-        assert_eq!(patched_noted.id, note.id);
-        assert_eq!(patched_noted.title, "newtitle");
-        assert_eq!(patched_noted.body, "newbody");
+        // Assert
+        assert!(results.iter().all(|res| res.is_ok()));
+        assert_eq!(inner.create_batches.load(Ordering::SeqCst), 2);
+        assert_eq!(
+            *inner.create_tenants.lock().unwrap(),
+            vec![Some("a".to_string()), Some("b".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn it_reports_failed_batch_writes_to_durable_creates() {
+        // Setup
+        let ctx = OpContext::background();
+        let inner = Arc::new(NoteVecDb::new(sync::Mutex::new(Vec::new())));
+        inner.set_fail_create(true);
+        let batching = BatchingNoteDb::new(
+            inner.clone(),
+            BatchConfig {
+                max_delay: std::time::Duration::from_millis(1),
+                ..BatchConfig::default()
+            },
+        );
+        let flushes = metrics::BATCH_FLUSHES.get();
+        let failed = metrics::BATCH_FAILED_FLUSHES.get();
+
+        // Execute
+        let res = batching.create_note(&ctx, &Note::new("a", "b", "")).await;
+
+        // Assert
+        assert!(res.is_err());
+        assert!(metrics::gauges().batch_flushes > flushes);
+        assert!(metrics::gauges().batch_failed_flushes > failed);
+    }
+
+    #[tokio::test]
+    async fn it_creates_notes_through_queued_ingest() {
+        // Setup
+        let inner = Arc::new(NoteVecDb::new(sync::Mutex::new(Vec::new())));
+        let ingest = Arc::new(BatchingNoteDb::new(
+            inner.clone(),
+            BatchConfig {
+                max_delay: std::time::Duration::from_millis(1),
+                acknowledge: Acknowledge::Queued,
+                ..BatchConfig::default()
+            },
+        ));
+        let state = Arc::new(AppState {
+            notes: Arc::new(Mutex::new(inner.clone())),
+            announcements: Arc::new(AnnouncementVecDb::default()),
+            idempotency: Arc::new(NoteMemoryDb::new()),
+            api_keys: Arc::new(NoteMemoryDb::new()),
+            orgs: Arc::new(NoteMemoryDb::new()),
+            shares: Arc::new(NoteMemoryDb::new()),
+            migration: None,
+            ingest: Some(ingest),
+            notes_path: "/notes".to_string(),
+            strict_json: false,
+            note_limits: NoteLimits::default(),
+            title_policy: TitlePolicy::default(),
+            require_if_match: false,
+            idempotency_ttl: idempotency::DEFAULT_TTL,
+            default_page_size: DEFAULT_PAGE_SIZE,
+            max_page_size: MAX_PAGE_SIZE,
+            archive_limits: ArchiveLimits::default(),
+        });
+        let app = create_axum_app(state, &AppConfig::default());
+
+        // Execute
+        let resp = post_test_note(app, NewNote::new("a", "b")).await;
+
+        // Assert
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        let note = deserialize_note(resp.into_body()).await;
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        assert_eq!(*inner.vec.lock().unwrap(), vec![note]);
     }
 
     fn create_test_app() -> (axum::Router, Arc<Mutex<NoteVecDb>>) {
+        create_test_app_with_config(AppConfig::default())
+    }
+
+    fn create_test_app_with_config(
+        app_config: AppConfig,
+    ) -> (axum::Router, Arc<Mutex<NoteVecDb>>) {
         let notes = Vec::<Note>::new();
-        let notes_path = "/notes";
+        let notes_path = "/v1/notes";
         let notes =
             Arc::new(Mutex::new(NoteVecDb::new(sync::Mutex::new(notes))));
         let state = Arc::new(AppState {
             notes: notes.clone(),
+            announcements: Arc::new(AnnouncementVecDb::default()),
+            idempotency: Arc::new(NoteMemoryDb::new()),
+            api_keys: Arc::new(NoteMemoryDb::new()),
+            orgs: Arc::new(NoteMemoryDb::new()),
+            shares: Arc::new(NoteMemoryDb::new()),
+            migration: None,
+            ingest: None,
             notes_path: notes_path.to_string(),
+            strict_json: app_config.strict_json,
+            note_limits: app_config.note_limits,
+            title_policy: app_config.title_policy,
+            require_if_match: app_config.require_if_match,
+            idempotency_ttl: app_config.idempotency_ttl,
+            default_page_size: app_config.default_page_size,
+            max_page_size: app_config.max_page_size,
+            archive_limits: app_config.archive_limits,
         });
-        (create_axum_app(state.clone(), "v1"), notes)
+        (create_axum_app(state.clone(), &app_config), notes)
+    }
+
+    const TEST_ISSUER: &str = "https://idp.test";
+
+    /// Accepts HS256 tokens of `TEST_ISSUER` signed with "secret".
+    fn test_auth_config() -> AuthConfig {
+        let keys = serde_json::from_value(serde_json::json!({ "keys": [{
+            "kty": "oct", "kid": "k1", "alg": "HS256", "k": "c2VjcmV0",
+        }]}))
+        .unwrap();
+        AuthConfig {
+            issuer: TEST_ISSUER.to_string(),
+            audience: None,
+            keys: auth::KeySource::Static(keys),
+        }
+    }
+
+    /// An `Authorization` header for a token of `issuer` about `subject`.
+    fn bearer(subject: &str, issuer: &str) -> String {
+        bearer_with_roles(subject, issuer, &[])
+    }
+
+    fn bearer_with_roles(
+        subject: &str,
+        issuer: &str,
+        roles: &[&str],
+    ) -> String {
+        let header = jsonwebtoken::Header {
+            kid: Some("k1".to_string()),
+            ..jsonwebtoken::Header::default()
+        };
+        let claims = serde_json::json!({
+            "sub": subject,
+            "iss": issuer,
+            "exp": chrono::Utc::now().timestamp() + 60,
+            "roles": roles,
+        });
+        let key = jsonwebtoken::EncodingKey::from_secret(b"secret");
+        let token = jsonwebtoken::encode(&header, &claims, &key).unwrap();
+        format!("Bearer {}", token)
     }
 
     async fn deserialize_note(body: axum::body::Body) -> Note {
@@ -688,6 +5446,23 @@ mod tests {
             .unwrap()
     }
 
+    async fn post_raw_test_note(
+        app: axum::routing::Router,
+        body: &'static str,
+    ) -> Response<Body> {
+        app.clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/v1/notes")
+                    .header("Content-Type", "application/json")
+                    .body(Body::from(body))
+                    .unwrap(),
+            )
+            .await
+            .unwrap()
+    }
+
     async fn patch_test_note(
         app: axum::routing::Router,
         id: &str,
@@ -723,6 +5498,28 @@ mod tests {
             .unwrap()
     }
 
+    async fn import_test_notes(
+        app: axum::routing::Router,
+        on_error: &str,
+        body: &'static str,
+    ) -> Response<Body> {
+        app.oneshot(
+            Request::builder()
+                .method("POST")
+                .uri(format!("/v1/notes/import?on_error={}", on_error))
+                .header("Content-Type", "application/json")
+                .body(Body::from(body))
+                .unwrap(),
+        )
+        .await
+        .unwrap()
+    }
+
+    async fn deserialize_report(body: axum::body::Body) -> ImportReport {
+        let body = body.collect().await.unwrap().to_bytes();
+        serde_json::from_slice(&body).unwrap()
+    }
+
     async fn list_test_notes(app: axum::routing::Router) -> Response<Body> {
         app.clone()
             .oneshot(
@@ -0,0 +1,384 @@
+//! Generates traffic against a running instance for capacity planning.
+//! Requests are sent at a fixed rate, whether or not earlier ones were
+//! answered, in the proportions of a mix of operations, and the latencies
+//! are reported per operation. Only built with the `loadgen` feature.
+
+use std::{
+    collections::BTreeMap,
+    fmt,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use serde::Deserialize;
+use serde_json::json;
+use tokio::task::JoinSet;
+
+use crate::notes::Note;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// Notes created before the run, so that the first reads find some.
+const SEED_NOTES: usize = 20;
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Operation {
+    Create,
+    Get,
+    Update,
+    Delete,
+    List,
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Operation::Create => "create",
+            Operation::Get => "get",
+            Operation::Update => "update",
+            Operation::Delete => "delete",
+            Operation::List => "list",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Relative weights of the operations, e.g.
+/// `{"create": 2, "get": 6, "update": 1, "delete": 1}`. Operations left
+/// out aren't sent.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(transparent)]
+pub struct Mix(BTreeMap<Operation, u32>);
+
+impl Default for Mix {
+    /// Mostly reads, with as many creates as deletes.
+    fn default() -> Self {
+        Mix(BTreeMap::from([
+            (Operation::Create, 2),
+            (Operation::Get, 10),
+            (Operation::Update, 3),
+            (Operation::Delete, 2),
+            (Operation::List, 3),
+        ]))
+    }
+}
+
+impl Mix {
+    fn schedule(&self) -> Schedule {
+        let weights: Vec<(Operation, i64)> = self
+            .0
+            .iter()
+            .filter(|(_, weight)| **weight > 0)
+            .map(|(operation, weight)| (*operation, i64::from(*weight)))
+            .collect();
+        Schedule {
+            current: vec![0; weights.len()],
+            total: weights.iter().map(|(_, weight)| weight).sum(),
+            weights,
+        }
+    }
+}
+
+/// Smooth weighted round robin: every window of `total` picks follows the
+/// weights exactly, with the operations interleaved instead of in runs.
+struct Schedule {
+    weights: Vec<(Operation, i64)>,
+    current: Vec<i64>,
+    total: i64,
+}
+
+impl Schedule {
+    fn next(&mut self) -> Operation {
+        for (current, (_, weight)) in self.current.iter_mut().zip(&self.weights)
+        {
+            *current += weight;
+        }
+        let (index, _) = self
+            .current
+            .iter()
+            .enumerate()
+            .max_by_key(|(index, current)| (**current, -(*index as i64)))
+            .expect("mix has an operation");
+        self.current[index] -= self.total;
+        self.weights[index].0
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LoadConfig {
+    /// API root of the instance, e.g. `http://localhost:3000/v1`.
+    pub base_url: String,
+    pub rps: u32,
+    pub duration: Duration,
+    pub mix: Mix,
+    /// Bearer token sent with every request.
+    pub token: Option<String>,
+}
+
+/// Latencies of one operation.
+#[derive(Debug, Clone, Default)]
+pub struct OperationReport {
+    pub requests: usize,
+    /// Requests that failed or weren't answered with success.
+    pub errors: usize,
+    pub p50: Duration,
+    pub p90: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+}
+
+impl OperationReport {
+    fn of(mut latencies: Vec<Duration>, errors: usize) -> OperationReport {
+        latencies.sort();
+        // Nearest rank, so every percentile is a measured latency.
+        let percentile = |p: usize| match latencies.len() {
+            0 => Duration::ZERO,
+            len => latencies[(len * p).div_ceil(100).max(1) - 1],
+        };
+        OperationReport {
+            requests: latencies.len(),
+            errors,
+            p50: percentile(50),
+            p90: percentile(90),
+            p99: percentile(99),
+            max: latencies.last().copied().unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Report {
+    /// How long requests were sent for.
+    pub elapsed: Duration,
+    pub operations: BTreeMap<Operation, OperationReport>,
+}
+
+impl Report {
+    pub fn requests(&self) -> usize {
+        self.operations.values().map(|report| report.requests).sum()
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let rps = self.requests() as f64 / self.elapsed.as_secs_f64();
+        writeln!(
+            f,
+            "{} requests in {:.1}s ({:.1} rps)",
+            self.requests(),
+            self.elapsed.as_secs_f64(),
+            rps
+        )?;
+        writeln!(
+            f,
+            "{:<8} {:>8} {:>7} {:>9} {:>9} {:>9} {:>9}",
+            "op", "requests", "errors", "p50", "p90", "p99", "max"
+        )?;
+        for (operation, report) in &self.operations {
+            writeln!(
+                f,
+                "{:<8} {:>8} {:>7} {:>9} {:>9} {:>9} {:>9}",
+                operation,
+                report.requests,
+                report.errors,
+                format!("{:.1?}", report.p50),
+                format!("{:.1?}", report.p90),
+                format!("{:.1?}", report.p99),
+                format!("{:.1?}", report.max),
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Sends the mix of `config` for its duration and reports the latencies
+/// once all requests are answered.
+pub async fn run(
+    config: &LoadConfig,
+) -> Result<Report, Box<dyn std::error::Error>> {
+    if config.rps == 0 {
+        return Err("rps must be positive".into());
+    }
+    let mut schedule = config.mix.schedule();
+    if schedule.weights.is_empty() {
+        return Err("the mix has no operation with a weight".into());
+    }
+    let client = Arc::new(Client::new(&config.base_url, config.token.clone())?);
+    let ids = Arc::new(Mutex::new(Vec::new()));
+    for n in 0..SEED_NOTES {
+        let id = client.create(n).await?;
+        ids.lock().unwrap().push(id);
+    }
+
+    let started = Instant::now();
+    let mut ticks = tokio::time::interval(Duration::from_secs(1) / config.rps);
+    let mut requests = JoinSet::new();
+    let mut n = SEED_NOTES;
+    while started.elapsed() < config.duration {
+        ticks.tick().await;
+        let operation = schedule.next();
+        let client = client.clone();
+        let ids = ids.clone();
+        requests.spawn(async move {
+            let sent = Instant::now();
+            let ok = client.send(operation, n, &ids).await.is_ok();
+            (operation, sent.elapsed(), ok)
+        });
+        n += 1;
+    }
+    let elapsed = started.elapsed();
+    let mut samples: BTreeMap<Operation, (Vec<Duration>, usize)> =
+        BTreeMap::new();
+    while let Some(result) = requests.join_next().await {
+        let (operation, latency, ok) = result?;
+        let (latencies, errors) = samples.entry(operation).or_default();
+        latencies.push(latency);
+        if !ok {
+            *errors += 1;
+        }
+    }
+    Ok(Report {
+        elapsed,
+        operations: samples
+            .into_iter()
+            .map(|(operation, (latencies, errors))| {
+                (operation, OperationReport::of(latencies, errors))
+            })
+            .collect(),
+    })
+}
+
+/// Sends requests to the API at `base_url`, authenticated with `token`.
+pub(crate) struct Client {
+    http: reqwest::Client,
+    base_url: String,
+    token: Option<String>,
+}
+
+impl Client {
+    pub(crate) fn new(
+        base_url: &str,
+        token: Option<String>,
+    ) -> Result<Client, reqwest::Error> {
+        Ok(Client {
+            http: reqwest::Client::builder()
+                .timeout(REQUEST_TIMEOUT)
+                .build()?,
+            base_url: base_url.trim_end_matches('/').to_string(),
+            token,
+        })
+    }
+
+    pub(crate) fn request(
+        &self,
+        method: reqwest::Method,
+        path: &str,
+    ) -> reqwest::RequestBuilder {
+        let request = self
+            .http
+            .request(method, format!("{}{}", self.base_url, path));
+        match &self.token {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        }
+    }
+
+    async fn create(&self, n: usize) -> Result<String, reqwest::Error> {
+        let note: Note = self
+            .request(reqwest::Method::POST, "/notes")
+            .json(&json!({
+                "title": format!("Load test note {}", n),
+                "body": body(n),
+                "tags": ["loadgen", format!("t{}", n % 10)],
+            }))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(note.id)
+    }
+
+    async fn add(
+        &self,
+        n: usize,
+        ids: &Mutex<Vec<String>>,
+    ) -> Result<(), reqwest::Error> {
+        let id = self.create(n).await?;
+        ids.lock().unwrap().push(id);
+        Ok(())
+    }
+
+    /// Sends the `n`th request of the run. Reads and updates go to one of
+    /// the notes created so far, deletes take one out of the pool.
+    async fn send(
+        &self,
+        operation: Operation,
+        n: usize,
+        ids: &Mutex<Vec<String>>,
+    ) -> Result<(), reqwest::Error> {
+        let pick = |remove: bool| {
+            let mut ids = ids.lock().unwrap();
+            match ids.len() {
+                0 => None,
+                len if remove => Some(ids.swap_remove(n % len)),
+                len => Some(ids[n % len].clone()),
+            }
+        };
+        let response = match operation {
+            Operation::Create => return self.add(n, ids).await,
+            Operation::List => {
+                let path = format!("/notes?tag=t{}&limit=20", n % 10);
+                self.request(reqwest::Method::GET, &path).send().await?
+            }
+            Operation::Get | Operation::Update | Operation::Delete => {
+                // Too many deletes empty the pool, then create instead.
+                let Some(id) = pick(operation == Operation::Delete) else {
+                    return self.add(n, ids).await;
+                };
+                let path = format!("/notes/{}", id);
+                match operation {
+                    Operation::Get => self.request(reqwest::Method::GET, &path),
+                    Operation::Update => self
+                        .request(reqwest::Method::PATCH, &path)
+                        .json(&json!({ "body": body(n) })),
+                    _ => self.request(reqwest::Method::DELETE, &path),
+                }
+                .send()
+                .await?
+            }
+        };
+        response.error_for_status().map(|_| ())
+    }
+}
+
+/// Note bodies of a few hundred bytes to a few kilobytes.
+pub(crate) fn body(n: usize) -> String {
+    "Lorem ipsum dolor sit amet, consectetur adipiscing elit. "
+        .repeat(4 + n % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_interleaves_operations_in_proportion_to_the_mix() {
+        // Setup
+        let mix = Mix(BTreeMap::from([
+            (Operation::Create, 1),
+            (Operation::Get, 3),
+            (Operation::List, 0),
+        ]));
+
+        // Execute
+        let mut schedule = mix.schedule();
+        let picks: Vec<Operation> = (0..8).map(|_| schedule.next()).collect();
+
+        // Assert
+        use Operation::*;
+        assert_eq!(picks, vec![Get, Create, Get, Get, Get, Create, Get, Get]);
+    }
+}
@@ -0,0 +1,321 @@
+//! Setup of tracing and the output formats of the log. Besides human
+//! readable text, the log can be written as JSON lines for log aggregators,
+//! one object per event with the fields of its spans, like the request id,
+//! next to its own.
+
+use std::{fmt, str::FromStr};
+
+use chrono::{SecondsFormat, Utc};
+use serde_json::{Map, Value};
+use tracing::{
+    field::{Field, Visit},
+    Event, Subscriber,
+};
+use tracing_subscriber::{
+    filter::{EnvFilter, Targets},
+    fmt::{
+        format::{JsonFields, Writer},
+        FmtContext, FormatEvent, FormattedFields,
+    },
+    layer::SubscriberExt,
+    registry::LookupSpan,
+    util::SubscriberInitExt,
+    Layer,
+};
+
+use crate::startup::StartupError;
+use crate::AppConfig;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    #[default]
+    Text,
+    /// One JSON object per line, see `FlatJson`.
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err("expected text or json".to_string()),
+        }
+    }
+}
+
+/// What `init` set up that needs to be shut down on exit.
+#[must_use = "spans still buffered for export are lost unless shut down"]
+pub struct Telemetry {
+    #[cfg(feature = "otel")]
+    tracer_provider: Option<opentelemetry_sdk::trace::SdkTracerProvider>,
+}
+
+impl Telemetry {
+    /// Sends the spans still buffered for export.
+    pub async fn shutdown(self) {
+        #[cfg(feature = "otel")]
+        if let Some(provider) = self.tracer_provider {
+            // Blocks until the export is done.
+            let shutdown =
+                tokio::task::spawn_blocking(move || provider.shutdown()).await;
+            if let Ok(Err(err)) = shutdown {
+                tracing::warn!("unable to export remaining spans: {}", err);
+            }
+        }
+    }
+}
+
+/// Installs the subscriber of the server as the global default: the log on
+/// stdout in the format of `app_config`, and tokio-console and trace
+/// export if enabled. Fails if a subscriber is installed already.
+pub fn init(app_config: &AppConfig) -> Result<Telemetry, StartupError> {
+    // The filter only applies to the log, the console layer needs the
+    // runtime's trace events regardless.
+    let filter = EnvFilter::from(format!(
+        "RUST_LOG={},{}=debug,tower_http=debug,axum::rejection=trace",
+        std::env::var("RUST_LOG").unwrap_or("info".to_string()),
+        env!("CARGO_CRATE_NAME")
+    ));
+    #[cfg(feature = "console")]
+    let console = app_config
+        .tokio_console
+        .then(console_subscriber::spawn::<tracing_subscriber::Registry>);
+    #[cfg(not(feature = "console"))]
+    let console = None::<tracing_subscriber::layer::Identity>;
+    #[cfg(feature = "otel")]
+    let (otel, tracer_provider) = match &app_config.otlp_endpoint {
+        Some(endpoint) => {
+            let (layer, provider) = otlp_layer(endpoint).map_err(|err| {
+                StartupError::config(format!(
+                    "invalid OTLP endpoint {:?}: {}",
+                    endpoint, err
+                ))
+            })?;
+            (Some(layer), Some(provider))
+        }
+        None => (None, None),
+    };
+    #[cfg(not(feature = "otel"))]
+    let otel = None::<tracing_subscriber::layer::Identity>;
+    // Only the spans of requests and database calls are exported.
+    let exported = Targets::new()
+        .with_target(env!("CARGO_CRATE_NAME"), tracing::Level::INFO);
+    tracing_subscriber::registry()
+        .with(console)
+        .with(fmt_layer(app_config.log_format).with_filter(filter))
+        .with(otel.with_filter(exported))
+        .try_init()
+        .map_err(|err| {
+            StartupError::config(format!(
+                "unable to set up tracing: {}. Applications with a \
+                 subscriber of their own turn off init_tracing",
+                err
+            ))
+        })?;
+    if app_config.tokio_console && cfg!(not(feature = "console")) {
+        tracing::warn!("tokio console needs a build with the console feature");
+    }
+    if app_config.otlp_endpoint.is_some() && cfg!(not(feature = "otel")) {
+        tracing::warn!("trace export needs a build with the otel feature");
+    }
+    Ok(Telemetry {
+        #[cfg(feature = "otel")]
+        tracer_provider,
+    })
+}
+
+/// The layer writing the log to stdout in `format`.
+pub fn fmt_layer<S>(format: LogFormat) -> Box<dyn Layer<S> + Send + Sync>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    let layer = tracing_subscriber::fmt::layer();
+    match format {
+        LogFormat::Text => layer.boxed(),
+        LogFormat::Json => layer
+            .fmt_fields(JsonFields::new())
+            .event_format(FlatJson)
+            .boxed(),
+    }
+}
+
+/// The layer exporting spans over OTLP/HTTP to `endpoint`, e.g.
+/// `http://localhost:4318/v1/traces`, and the provider to shut down on
+/// exit, which sends the spans still buffered.
+#[cfg(feature = "otel")]
+pub fn otlp_layer<S>(
+    endpoint: &str,
+) -> Result<
+    (impl Layer<S>, opentelemetry_sdk::trace::SdkTracerProvider),
+    opentelemetry_otlp::ExporterBuildError,
+>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    use opentelemetry::trace::TracerProvider;
+    use opentelemetry_otlp::WithExportConfig;
+
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_http()
+        .with_endpoint(endpoint)
+        .build()?;
+    let resource = opentelemetry_sdk::Resource::builder()
+        .with_service_name(env!("CARGO_PKG_NAME"))
+        .build();
+    let provider = opentelemetry_sdk::trace::SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(resource)
+        .build();
+    let tracer = provider.tracer(env!("CARGO_CRATE_NAME"));
+    Ok((tracing_opentelemetry::layer().with_tracer(tracer), provider))
+}
+
+/// Formats events as JSON objects with timestamp, level, target and the
+/// name of the innermost span, followed by the fields of the spans from
+/// the outermost in, followed by the fields of the event. Later fields
+/// replace earlier ones of the same name.
+pub struct FlatJson;
+
+impl<S> FormatEvent<S, JsonFields> for FlatJson
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, JsonFields>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        let meta = event.metadata();
+        let mut fields = Map::new();
+        fields.insert(
+            "timestamp".to_string(),
+            Utc::now()
+                .to_rfc3339_opts(SecondsFormat::Micros, true)
+                .into(),
+        );
+        fields.insert("level".to_string(), meta.level().as_str().into());
+        fields.insert("target".to_string(), meta.target().into());
+        if let Some(scope) = ctx.event_scope() {
+            for span in scope.from_root() {
+                fields.insert("span".to_string(), span.name().into());
+                let extensions = span.extensions();
+                let Some(formatted) =
+                    extensions.get::<FormattedFields<JsonFields>>()
+                else {
+                    continue;
+                };
+                // Spans without fields have nothing formatted.
+                if let Ok(Value::Object(span_fields)) =
+                    serde_json::from_str(&formatted.fields)
+                {
+                    fields.extend(span_fields);
+                }
+            }
+        }
+        event.record(&mut FieldVisitor(&mut fields));
+        writeln!(writer, "{}", Value::Object(fields))
+    }
+}
+
+struct FieldVisitor<'a>(&'a mut Map<String, Value>);
+
+impl FieldVisitor<'_> {
+    fn insert(&mut self, field: &Field, value: impl Into<Value>) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+}
+
+impl Visit for FieldVisitor<'_> {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.insert(field, value);
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.insert(field, value);
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.insert(field, value);
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.insert(field, value);
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.insert(field, value);
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.insert(field, format!("{:?}", value));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use super::*;
+
+    #[test]
+    fn it_flattens_span_fields_into_json_lines() {
+        // Setup
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let writer = log.clone();
+        let layer = tracing_subscriber::fmt::layer()
+            .fmt_fields(JsonFields::new())
+            .event_format(FlatJson)
+            .with_writer(move || LogWriter(writer.clone()));
+        let subscriber = tracing_subscriber::registry().with(layer);
+
+        // Execute
+        tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!("request", request_id = "r1");
+            let _entered = span.enter();
+            tracing::info!(note = 7, "created note");
+        });
+
+        // Assert
+        let log = log.lock().unwrap();
+        let line: Value = serde_json::from_slice(&log).unwrap();
+        assert_eq!(line["level"], "INFO");
+        assert_eq!(line["span"], "request");
+        assert_eq!(line["request_id"], "r1");
+        assert_eq!(line["note"], 7);
+        assert_eq!(line["message"], "created note");
+    }
+
+    #[test]
+    fn it_refuses_to_replace_an_installed_subscriber() {
+        // Setup
+        let installed = tracing::subscriber::set_global_default(
+            tracing::subscriber::NoSubscriber::default(),
+        );
+
+        // Execute
+        let telemetry = init(&AppConfig::default());
+
+        // Assert
+        assert!(installed.is_ok());
+        assert!(telemetry.is_err());
+    }
+
+    struct LogWriter(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for LogWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+}
@@ -1,15 +1,351 @@
-use notes::{create_app, AppConfig};
+use std::{fmt::Display, str::FromStr, time::Duration};
+
+use notes::{
+    auth::{AuthConfig, KeySource},
+    body_limit, catch_up_mirror, create_app, healthcheck, idempotency,
+    import::ArchiveLimits,
+    persistency::batching::BatchConfig,
+    security::SecurityHeaders,
+    startup::StartupError,
+    timeouts::RouteTimeouts,
+    validation::NoteLimits,
+    AppConfig,
+};
+#[cfg(feature = "loadgen")]
+use notes::{
+    loadgen::{LoadConfig, Mix},
+    soak::SoakConfig,
+};
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let host = std::env::var("NOTES_HOST").unwrap_or("0.0.0.0".to_string());
     let port = std::env::var("NOTES_PORT").unwrap_or("3000".to_string());
-    let db_uri = std::env::var("NOTES_DB_ADDRESS").unwrap_or("uri".to_string());
-    create_app(AppConfig {
-        host_port: format!("{}:{}", host, port).to_string(),
-        api_version: "v1".to_string(),
+
+    if std::env::args().nth(1).as_deref() == Some("healthcheck") {
+        let args: Vec<String> = std::env::args().skip(2).collect();
+        let url = match args.iter().position(|arg| arg == "--url") {
+            Some(index) => match args.get(index + 1) {
+                Some(url) => url.clone(),
+                None => return Err("--url needs a value".into()),
+            },
+            None => format!("http://localhost:{}/v1/health", port),
+        };
+        if let Err(err) = healthcheck(&url).await {
+            eprintln!("unhealthy: {}", err);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    if std::env::args().nth(1).as_deref() == Some("loadgen") {
+        let args: Vec<String> = std::env::args().skip(2).collect();
+        return loadgen(&args, &port).await;
+    }
+
+    if std::env::args().nth(1).as_deref() == Some("soak") {
+        let args: Vec<String> = std::env::args().skip(2).collect();
+        return soak(&args, &port).await;
+    }
+
+    let db_uri = db_uri()?;
+    let mirror_db_uri = std::env::var("NOTES_DB_MIRROR_ADDRESS").ok();
+
+    if std::env::args().nth(1).as_deref() == Some("mirror-catch-up") {
+        let Some(mirror_db_uri) = mirror_db_uri else {
+            return Err("NOTES_DB_MIRROR_ADDRESS is not set".into());
+        };
+        // With a database per tenant, each tenant is caught up on its own.
+        let args: Vec<String> = std::env::args().skip(2).collect();
+        let tenant = match args.iter().position(|arg| arg == "--tenant") {
+            Some(index) => match args.get(index + 1) {
+                Some(tenant) => Some(tenant.clone()),
+                None => return Err("--tenant needs a value".into()),
+            },
+            None => None,
+        };
+        let database_per_tenant =
+            env_var("NOTES_MONGO_DATABASE_PER_TENANT")?.unwrap_or(false);
+        let report = catch_up_mirror(
+            &db_uri,
+            &mirror_db_uri,
+            database_per_tenant,
+            tenant,
+        )
+        .await?;
+        println!(
+            "copied {} notes, removed {} notes",
+            report.copied, report.removed
+        );
+        return Ok(());
+    }
+
+    let started = match app_config(host, port, db_uri, mirror_db_uri) {
+        Ok(app_config) => create_app(app_config).await,
+        Err(err) => Err(err),
+    };
+    if let Err(err) = started {
+        eprintln!("{}", err.diagnostic());
+        std::process::exit(err.kind.exit_code());
+    }
+    Ok(())
+}
+
+/// `NOTES_DB_BACKEND=memory` runs without a database, otherwise the
+/// backend follows from the scheme of `NOTES_DB_ADDRESS`.
+fn db_uri() -> Result<String, StartupError> {
+    match std::env::var("NOTES_DB_BACKEND").as_deref() {
+        Ok("memory") => Ok("memory://".to_string()),
+        Ok(backend) => Err(StartupError::config(format!(
+            "invalid NOTES_DB_BACKEND {:?}: expected memory or unset",
+            backend
+        ))),
+        Err(_) => Ok(std::env::var("NOTES_DB_ADDRESS")
+            .unwrap_or_else(|_| AppConfig::default().db_uri)),
+    }
+}
+
+/// Runs `notes loadgen [--url URL] [--rps N] [--duration SECS]
+/// [--mix FILE] [--token TOKEN]` and prints the report.
+#[cfg(feature = "loadgen")]
+async fn loadgen(
+    args: &[String],
+    port: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let flag = |name: &str| flag(args, name);
+    let mix = match flag("--mix")? {
+        Some(path) => serde_json::from_str(&std::fs::read_to_string(path)?)?,
+        None => Mix::default(),
+    };
+    let config = LoadConfig {
+        base_url: flag("--url")?
+            .unwrap_or(format!("http://localhost:{}/v1", port)),
+        rps: flag("--rps")?
+            .map(|rps| rps.parse())
+            .transpose()?
+            .unwrap_or(100),
+        duration: Duration::from_secs(
+            flag("--duration")?
+                .map(|secs| secs.parse())
+                .transpose()?
+                .unwrap_or(30),
+        ),
+        mix,
+        token: flag("--token")?,
+    };
+    let report = notes::loadgen::run(&config).await?;
+    print!("{}", report);
+    Ok(())
+}
+
+#[cfg(not(feature = "loadgen"))]
+async fn loadgen(
+    _args: &[String],
+    _port: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    Err("loadgen needs a build with the loadgen feature".into())
+}
+
+/// Runs `notes soak [--url URL] [--duration SECS] [--seed N]
+/// [--token TOKEN]`, prints the report and exits with 1 if the instance
+/// broke an invariant. Without a seed, one is picked and printed, to repeat
+/// the run.
+#[cfg(feature = "loadgen")]
+async fn soak(
+    args: &[String],
+    port: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let flag = |name: &str| flag(args, name);
+    let seed = match flag("--seed")? {
+        Some(seed) => seed.parse()?,
+        None => std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)?
+            .as_nanos() as u64,
+    };
+    let config = SoakConfig {
+        base_url: flag("--url")?
+            .unwrap_or(format!("http://localhost:{}/v1", port)),
+        duration: Duration::from_secs(
+            flag("--duration")?
+                .map(|secs| secs.parse())
+                .transpose()?
+                .unwrap_or(600),
+        ),
+        seed,
+        token: flag("--token")?,
+    };
+    let report = notes::soak::run(&config).await?;
+    print!("{}", report);
+    if !report.passed() {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "loadgen"))]
+async fn soak(
+    _args: &[String],
+    _port: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    Err("soak needs a build with the loadgen feature".into())
+}
+
+/// The value following `name` in `args`, if given.
+#[cfg(feature = "loadgen")]
+fn flag(args: &[String], name: &str) -> Result<Option<String>, String> {
+    match args.iter().position(|arg| arg == name) {
+        Some(index) => match args.get(index + 1) {
+            Some(value) => Ok(Some(value.clone())),
+            None => Err(format!("{} needs a value", name)),
+        },
+        None => Ok(None),
+    }
+}
+
+/// Reads an optional setting, rejecting values that don't parse instead of
+/// silently falling back to the default.
+fn env_var<T>(name: &str) -> Result<Option<T>, StartupError>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    match std::env::var(name) {
+        Ok(value) => value.parse().map(Some).map_err(|err| {
+            StartupError::config(format!(
+                "invalid {} {:?}: {}",
+                name, value, err
+            ))
+        }),
+        Err(_) => Ok(None),
+    }
+}
+
+fn env_millis(name: &str) -> Result<Option<Duration>, StartupError> {
+    Ok(env_var(name)?.map(Duration::from_millis))
+}
+
+fn app_config(
+    host: String,
+    port: String,
+    db_uri: String,
+    mirror_db_uri: Option<String>,
+) -> Result<AppConfig, StartupError> {
+    let host_port = format!("{}:{}", host, port).parse().map_err(|err| {
+        StartupError::config(format!(
+            "invalid NOTES_HOST {:?} or NOTES_PORT {:?}: {}",
+            host, port, err
+        ))
+    })?;
+    let api_version = env_var("NOTES_API_VERSION")?.unwrap_or_default();
+    let public_url = env_var("NOTES_PUBLIC_URL")?;
+    let migration_db_uri = std::env::var("NOTES_DB_MIGRATION_ADDRESS").ok();
+    let shadow_read_percent = env_var("NOTES_SHADOW_READ_PERCENT")?;
+    let hedge_delay = env_millis("NOTES_HEDGE_DELAY_MS")?;
+    let negative_cache_ttl = env_millis("NOTES_NEGATIVE_CACHE_TTL_MS")?;
+    let hsts_max_age = env_var("NOTES_HSTS_MAX_AGE")?;
+    let strict_json = env_var("NOTES_STRICT_JSON")?.unwrap_or(false);
+    let dedupe_window =
+        env_var("NOTES_DEDUPE_WINDOW_SECS")?.map(Duration::from_secs);
+    let ingest_batching = match env_var("NOTES_INGEST_BATCH_SIZE")? {
+        Some(max_batch) => {
+            let defaults = BatchConfig::default();
+            Some(BatchConfig {
+                max_batch,
+                max_delay: env_millis("NOTES_INGEST_BATCH_DELAY_MS")?
+                    .unwrap_or(defaults.max_delay),
+                queue_capacity: env_var("NOTES_INGEST_QUEUE_CAPACITY")?
+                    .unwrap_or(defaults.queue_capacity),
+                acknowledge: env_var("NOTES_INGEST_ACK")?
+                    .unwrap_or(defaults.acknowledge),
+            })
+        }
+        None => None,
+    };
+    // A timeout of 0 disables the timeout for the route class.
+    let route_timeout = |name: &str, default: Option<Duration>| {
+        Ok(match env_millis(name)? {
+            Some(Duration::ZERO) => None,
+            Some(timeout) => Some(timeout),
+            None => default,
+        })
+    };
+    let defaults = RouteTimeouts::default();
+    let route_timeouts = RouteTimeouts {
+        crud: route_timeout("NOTES_CRUD_TIMEOUT_MS", defaults.crud)?,
+        bulk: route_timeout("NOTES_BULK_TIMEOUT_MS", defaults.bulk)?,
+    };
+    // Authentication is enabled by naming the issuer and its key set.
+    let auth = match (
+        std::env::var("NOTES_JWT_ISSUER").ok(),
+        env_var("NOTES_JWKS_URL")?,
+    ) {
+        (Some(issuer), Some(jwks_url)) => Some(AuthConfig {
+            issuer,
+            audience: std::env::var("NOTES_JWT_AUDIENCE").ok(),
+            keys: KeySource::Url(jwks_url),
+        }),
+        (None, None) => None,
+        _ => {
+            return Err(StartupError::config(
+                "NOTES_JWT_ISSUER and NOTES_JWKS_URL must be set together",
+            ))
+        }
+    };
+    let limit_defaults = NoteLimits::default();
+    let archive_defaults = ArchiveLimits::default();
+    let note_limits = NoteLimits {
+        max_title_chars: env_var("NOTES_MAX_TITLE_CHARS")?
+            .unwrap_or(limit_defaults.max_title_chars),
+        max_body_bytes: env_var("NOTES_MAX_BODY_BYTES")?
+            .unwrap_or(limit_defaults.max_body_bytes),
+        max_tags: env_var("NOTES_MAX_TAGS")?.unwrap_or(limit_defaults.max_tags),
+        max_tag_chars: env_var("NOTES_MAX_TAG_CHARS")?
+            .unwrap_or(limit_defaults.max_tag_chars),
+    };
+    Ok(AppConfig {
+        host_port,
+        api_version,
+        public_url,
         db_uri,
+        mirror_db_uri,
+        shadow_read_percent,
+        hedge_delay,
+        negative_cache_ttl,
+        migration_db_uri,
+        security_headers: SecurityHeaders {
+            hsts_max_age,
+            ..SecurityHeaders::default()
+        },
+        strict_json,
+        dedupe_window,
+        ingest_batching,
+        route_timeouts,
+        note_limits,
+        max_request_bytes: env_var("NOTES_MAX_REQUEST_BYTES")?
+            .unwrap_or(body_limit::DEFAULT_MAX_REQUEST_BYTES),
+        title_policy: env_var("NOTES_DUPLICATE_TITLES")?.unwrap_or_default(),
+        require_if_match: env_var("NOTES_REQUIRE_IF_MATCH")?.unwrap_or(false),
+        idempotency_ttl: env_var("NOTES_IDEMPOTENCY_TTL_SECS")?
+            .map(Duration::from_secs)
+            .unwrap_or(idempotency::DEFAULT_TTL),
+        default_page_size: env_var("NOTES_DEFAULT_PAGE_SIZE")?
+            .unwrap_or_else(|| AppConfig::default().default_page_size),
+        max_page_size: env_var("NOTES_MAX_PAGE_SIZE")?
+            .unwrap_or_else(|| AppConfig::default().max_page_size),
+        archive_limits: ArchiveLimits {
+            max_entry_bytes: env_var("NOTES_MAX_ARCHIVE_ENTRY_BYTES")?
+                .unwrap_or(archive_defaults.max_entry_bytes),
+            max_total_bytes: env_var("NOTES_MAX_ARCHIVE_BYTES")?
+                .unwrap_or(archive_defaults.max_total_bytes),
+        },
+        auth,
+        multi_tenant: env_var("NOTES_MULTI_TENANT")?.unwrap_or(false),
+        database_per_tenant: env_var("NOTES_MONGO_DATABASE_PER_TENANT")?
+            .unwrap_or(false),
+        tokio_console: env_var("NOTES_TOKIO_CONSOLE")?.unwrap_or(false),
+        // Unprefixed, like the RUST_LOG filter it goes with.
+        log_format: env_var("LOG_FORMAT")?.unwrap_or_default(),
+        otlp_endpoint: env_var("NOTES_OTLP_ENDPOINT")?,
+        init_tracing: true,
     })
-    .await?;
-    Ok(())
 }
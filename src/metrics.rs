@@ -0,0 +1,169 @@
+//! Process-wide gauges used to tune termination grace periods: how many
+//! requests are in flight and how many database cursors are open. Counters
+//! of requests that were given up on show whether deadlines are too tight.
+//! The mirror gauges show how far the secondary backend lags behind, and
+//! shadow read mismatches how far it has drifted apart. The hedged read
+//! counters show how often the replica saves a slow read. The batch
+//! gauges show how many queued creates wait for the batch writer and how
+//! often its bulk writes fail.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use axum::{extract::Request, middleware::Next, response::Response};
+use serde::Serialize;
+
+pub static IN_FLIGHT_REQUESTS: Gauge = Gauge::new();
+pub static OPEN_DB_CURSORS: Gauge = Gauge::new();
+/// Requests cut off by their route timeout (408/504 style).
+pub static TIMED_OUT_REQUESTS: Counter = Counter::new();
+/// Requests dropped because the client went away (499 style).
+pub static CLIENT_CLOSED_REQUESTS: Counter = Counter::new();
+/// Database operations abandoned because their deadline passed.
+pub static CANCELLED_DB_OPERATIONS: Counter = Counter::new();
+/// Writes accepted by the primary but not yet applied to the mirror.
+pub static MIRROR_PENDING_WRITES: Gauge = Gauge::new();
+pub static MIRROR_REPLICATED_WRITES: Counter = Counter::new();
+pub static MIRROR_FAILED_WRITES: Counter = Counter::new();
+/// Time between the primary write and its replication, for the most
+/// recently replicated write.
+pub static MIRROR_LAG_MS: Gauge = Gauge::new();
+/// Reads checked against the secondary backend.
+pub static SHADOW_READS_SAMPLED: Counter = Counter::new();
+/// Checked reads the secondary answered differently.
+pub static SHADOW_READ_MISMATCHES: Counter = Counter::new();
+/// Checked reads the secondary failed to answer.
+pub static SHADOW_READ_ERRORS: Counter = Counter::new();
+/// Reads sent to the replica as well because the primary was slow.
+pub static HEDGED_READS_ISSUED: Counter = Counter::new();
+/// Hedged reads the replica answered before the primary.
+pub static HEDGED_READS_WON: Counter = Counter::new();
+/// Creates queued for the batch writer but not yet written.
+pub static BATCH_PENDING_WRITES: Gauge = Gauge::new();
+/// Bulk writes of the batch writer, one per tenant of a batch.
+pub static BATCH_FLUSHES: Counter = Counter::new();
+/// Bulk writes that failed, failing every create in them.
+pub static BATCH_FAILED_FLUSHES: Counter = Counter::new();
+
+pub struct Gauge(AtomicU64);
+
+impl Gauge {
+    pub const fn new() -> Self {
+        Gauge(AtomicU64::new(0))
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    pub fn set(&self, value: u64) {
+        self.0.store(value, Ordering::Relaxed);
+    }
+
+    /// Increments the gauge until the returned guard is dropped.
+    pub fn track(&'static self) -> GaugeGuard {
+        self.0.fetch_add(1, Ordering::Relaxed);
+        GaugeGuard(self)
+    }
+}
+
+impl Default for Gauge {
+    fn default() -> Self {
+        Gauge::new()
+    }
+}
+
+pub struct GaugeGuard(&'static Gauge);
+
+impl Drop for GaugeGuard {
+    fn drop(&mut self) {
+        self.0 .0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Monotonic count of events since process start.
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    pub const fn new() -> Self {
+        Counter(AtomicU64::new(0))
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    pub fn increment(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+impl Default for Counter {
+    fn default() -> Self {
+        Counter::new()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Gauges {
+    pub in_flight_requests: u64,
+    pub open_db_cursors: u64,
+    pub timed_out_requests: u64,
+    pub client_closed_requests: u64,
+    pub cancelled_db_operations: u64,
+    pub mirror_pending_writes: u64,
+    pub mirror_replicated_writes: u64,
+    pub mirror_failed_writes: u64,
+    pub mirror_lag_ms: u64,
+    pub shadow_reads_sampled: u64,
+    pub shadow_read_mismatches: u64,
+    pub shadow_read_errors: u64,
+    pub hedged_reads_issued: u64,
+    pub hedged_reads_won: u64,
+    pub batch_pending_writes: u64,
+    pub batch_flushes: u64,
+    pub batch_failed_flushes: u64,
+}
+
+pub fn gauges() -> Gauges {
+    Gauges {
+        in_flight_requests: IN_FLIGHT_REQUESTS.get(),
+        open_db_cursors: OPEN_DB_CURSORS.get(),
+        timed_out_requests: TIMED_OUT_REQUESTS.get(),
+        client_closed_requests: CLIENT_CLOSED_REQUESTS.get(),
+        cancelled_db_operations: CANCELLED_DB_OPERATIONS.get(),
+        mirror_pending_writes: MIRROR_PENDING_WRITES.get(),
+        mirror_replicated_writes: MIRROR_REPLICATED_WRITES.get(),
+        mirror_failed_writes: MIRROR_FAILED_WRITES.get(),
+        mirror_lag_ms: MIRROR_LAG_MS.get(),
+        shadow_reads_sampled: SHADOW_READS_SAMPLED.get(),
+        shadow_read_mismatches: SHADOW_READ_MISMATCHES.get(),
+        shadow_read_errors: SHADOW_READ_ERRORS.get(),
+        hedged_reads_issued: HEDGED_READS_ISSUED.get(),
+        hedged_reads_won: HEDGED_READS_WON.get(),
+        batch_pending_writes: BATCH_PENDING_WRITES.get(),
+        batch_flushes: BATCH_FLUSHES.get(),
+        batch_failed_flushes: BATCH_FAILED_FLUSHES.get(),
+    }
+}
+
+/// Counts the request in `CLIENT_CLOSED_REQUESTS` if dropped before it
+/// produced a response.
+struct Unanswered(bool);
+
+impl Drop for Unanswered {
+    fn drop(&mut self) {
+        if self.0 {
+            CLIENT_CLOSED_REQUESTS.increment();
+        }
+    }
+}
+
+/// Middleware counting requests in `IN_FLIGHT_REQUESTS`, and those the
+/// client abandoned in `CLIENT_CLOSED_REQUESTS`.
+pub async fn track_in_flight(request: Request, next: Next) -> Response {
+    let _in_flight = IN_FLIGHT_REQUESTS.track();
+    let mut unanswered = Unanswered(true);
+    let response = next.run(request).await;
+    unanswered.0 = false;
+    response
+}
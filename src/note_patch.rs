@@ -0,0 +1,194 @@
+//! PATCH bodies for notes: the merge-style `PatchNote` sent as JSON, or a
+//! JSON Patch (RFC 6902) against the note document sent as
+//! `application/json-patch+json`.
+
+use std::sync::Arc;
+
+use axum::{
+    body::Bytes,
+    extract::{FromRequest, Request},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use json_patch::{Patch, PatchError, PatchErrorKind};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::extract::ValidJson;
+use crate::notes::{self, Note, NoteDb, PatchNote};
+use crate::problem::Problem;
+use crate::AppState;
+
+pub const JSON_PATCH_CONTENT_TYPE: &str = "application/json-patch+json";
+
+/// Fields of the note document a JSON Patch may change.
+const EDITABLE_FIELDS: [&str; 3] = ["title", "body", "tags"];
+
+/// The body of a PATCH, picked by its content type.
+#[derive(Debug)]
+pub enum NotePatch {
+    Merge(PatchNote),
+    Json(Patch),
+}
+
+impl<D: NoteDb + ?Sized> FromRequest<Arc<AppState<D>>> for NotePatch {
+    type Rejection = Response;
+
+    async fn from_request(
+        req: Request,
+        state: &Arc<AppState<D>>,
+    ) -> Result<Self, Self::Rejection> {
+        let is_json_patch = req
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| {
+                value.split(';').next().map(str::trim)
+                    == Some(JSON_PATCH_CONTENT_TYPE)
+            });
+        if !is_json_patch {
+            let ValidJson(patch) = ValidJson::from_request(req, state).await?;
+            return Ok(NotePatch::Merge(patch));
+        }
+        let body = Bytes::from_request(req, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+        match serde_json::from_slice(&body) {
+            Ok(patch) => Ok(NotePatch::Json(patch)),
+            Err(err) => {
+                tracing::debug!("unable to deserialize json patch: {}", err);
+                Err((
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    Json(json!({ "error": err.to_string() })),
+                )
+                    .into_response())
+            }
+        }
+    }
+}
+
+/// A JSON Patch that can't be applied to a note.
+#[derive(Debug)]
+pub enum JsonPatchError {
+    /// An operation failed, e.g. a `test` or a path that doesn't exist.
+    Failed(PatchError),
+    /// The patch changes fields other than title, body and tags.
+    ReadOnly,
+    /// The patched document is no longer a note.
+    Invalid(serde_json::Error),
+}
+
+impl IntoResponse for JsonPatchError {
+    fn into_response(self) -> Response {
+        let (status, detail) = match self {
+            JsonPatchError::Failed(err)
+                if matches!(err.kind, PatchErrorKind::TestFailed) =>
+            {
+                (StatusCode::CONFLICT, err.to_string())
+            }
+            JsonPatchError::Failed(err) => {
+                (StatusCode::UNPROCESSABLE_ENTITY, err.to_string())
+            }
+            JsonPatchError::ReadOnly => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                format!("only {} can be patched", EDITABLE_FIELDS.join(", ")),
+            ),
+            JsonPatchError::Invalid(err) => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                format!("patched note is invalid: {}", err),
+            ),
+        };
+        Problem::new(status, detail).into_response()
+    }
+}
+
+#[derive(Deserialize)]
+struct EditableFields {
+    title: String,
+    body: String,
+    tags: Vec<String>,
+}
+
+/// Applies a JSON Patch to the current state of `note` and returns the
+/// equivalent `PatchNote`, based on the version of `note`.
+pub fn to_patch_note(
+    patch: &Patch,
+    note: &Note,
+) -> Result<PatchNote, JsonPatchError> {
+    let before = serde_json::to_value(note).map_err(JsonPatchError::Invalid)?;
+    let mut after = before.clone();
+    json_patch::patch(&mut after, patch).map_err(JsonPatchError::Failed)?;
+    if read_only(&before) != read_only(&after) {
+        return Err(JsonPatchError::ReadOnly);
+    }
+    let fields: EditableFields =
+        serde_json::from_value(after).map_err(JsonPatchError::Invalid)?;
+    Ok(PatchNote {
+        title: (fields.title != note.title).then_some(fields.title),
+        body: (fields.body != note.body).then_some(fields.body),
+        tags: (fields.tags != note.tags).then_some(fields.tags),
+        updated_at: notes::now(),
+        version: Some(note.version),
+        permissions: None,
+    })
+}
+
+fn read_only(doc: &Value) -> Value {
+    let mut doc = doc.clone();
+    if let Some(fields) = doc.as_object_mut() {
+        for field in EDITABLE_FIELDS {
+            fields.remove(field);
+        }
+    }
+    doc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_turns_json_patches_into_note_patches() {
+        // Setup
+        let mut note = Note::new("a", "b", "/notes/1");
+        note.tags = vec!["home".to_string()];
+        let patch =
+            |ops: Value| -> Patch { serde_json::from_value(ops).unwrap() };
+
+        // Execute
+        let changed = to_patch_note(
+            &patch(json!([
+                { "op": "test", "path": "/title", "value": "a" },
+                { "op": "replace", "path": "/body", "value": "c" },
+                { "op": "add", "path": "/tags/-", "value": "work" },
+            ])),
+            &note,
+        );
+        let stale = to_patch_note(
+            &patch(json!([{ "op": "test", "path": "/title", "value": "x" }])),
+            &note,
+        );
+        let read_only = to_patch_note(
+            &patch(json!([{ "op": "replace", "path": "/id", "value": "2" }])),
+            &note,
+        );
+        let removed = to_patch_note(
+            &patch(json!([{ "op": "remove", "path": "/title" }])),
+            &note,
+        );
+
+        // Assert
+        let changed = changed.unwrap();
+        assert_eq!(changed.title, None);
+        assert_eq!(changed.body.as_deref(), Some("c"));
+        assert_eq!(changed.tags, Some(vec!["home".into(), "work".into()]));
+        assert_eq!(changed.version, Some(note.version));
+        assert_eq!(
+            stale.unwrap_err().into_response().status(),
+            StatusCode::CONFLICT
+        );
+        assert!(matches!(read_only, Err(JsonPatchError::ReadOnly)));
+        assert!(matches!(removed, Err(JsonPatchError::Invalid(_))));
+    }
+}
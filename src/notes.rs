@@ -1,63 +1,775 @@
+use std::{
+    collections::{BTreeSet, HashSet},
+    fmt,
+    sync::Arc,
+};
+
 use async_trait::async_trait;
+use chrono::{DateTime, SubsecRound, Utc};
 use nanoid::nanoid;
 use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+use crate::context::OpContext;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
 pub struct Note {
     pub id: String,
     pub title: String,
     pub body: String,
     pub url: String,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Set by the server. Notes stored before timestamps existed read as
+    /// the Unix epoch.
+    #[serde(default, with = "timestamp")]
+    pub created_at: DateTime<Utc>,
+    #[serde(default, with = "timestamp")]
+    pub updated_at: DateTime<Utc>,
+    /// Incremented by every update. Notes stored before versions existed
+    /// read as version 0.
+    #[serde(default)]
+    pub version: u64,
+    /// Subject of the user who created the note. Notes created without
+    /// authentication, or before owners existed, have none.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+    /// Tenant the note was created in, if the deployment has tenants.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+    /// Organization whose members share the note, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub org: Option<String>,
+    /// Users other than the owner the note is shared with.
+    #[serde(default, skip_serializing_if = "Permissions::is_empty")]
+    pub permissions: Permissions,
+}
+
+/// The current time at the precision notes are stored with. MongoDB keeps
+/// milliseconds, finer times would read back different from what the API
+/// returned for the write.
+pub fn now() -> DateTime<Utc> {
+    Utc::now().trunc_subsecs(3)
 }
 
 impl Note {
     pub fn new(title: &str, body: &str, url: &str) -> Note {
         let id = nanoid!();
+        let now = now();
         Note {
             id: id.clone(),
             title: title.to_string(),
             body: body.to_string(),
             url: url.to_string(),
+            tags: Vec::new(),
+            created_at: now,
+            updated_at: now,
+            version: 1,
+            owner: None,
+            tenant: None,
+            org: None,
+            permissions: Permissions::default(),
         }
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct NewNote {
     pub title: String,
     pub body: String,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Shares the note with the members of the organization. The creator
+    /// must be a member.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub org: Option<String>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+impl NewNote {
+    /// Creates the note with a fresh id, located below `notes_path`.
+    pub fn into_note(self, notes_path: &str) -> Note {
+        let id = nanoid!();
+        let now = now();
+        Note {
+            url: format!("{}/{}", notes_path, id),
+            id,
+            title: self.title,
+            body: self.body,
+            tags: self.tags,
+            created_at: now,
+            updated_at: now,
+            version: 1,
+            owner: None,
+            tenant: None,
+            org: self.org,
+            permissions: Permissions::default(),
+        }
+    }
+}
+
+/// Access to a single note granted to users by their subject. Writers can
+/// read the note as well.
+#[derive(
+    Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema,
+)]
+pub struct Permissions {
+    #[serde(default)]
+    pub read: Vec<String>,
+    #[serde(default)]
+    pub write: Vec<String>,
+}
+
+impl Permissions {
+    pub fn is_empty(&self) -> bool {
+        self.read.is_empty() && self.write.is_empty()
+    }
+
+    pub fn can_read(&self, subject: &str) -> bool {
+        self.can_write(subject) || self.read.iter().any(|s| s == subject)
+    }
+
+    pub fn can_write(&self, subject: &str) -> bool {
+        self.write.iter().any(|s| s == subject)
+    }
+
+    /// Grants `access` to `subject`, replacing any access it had.
+    pub fn grant(&mut self, subject: &str, access: Access) {
+        self.revoke(subject);
+        match access {
+            Access::Read => self.read.push(subject.to_string()),
+            Access::Write => self.write.push(subject.to_string()),
+        }
+    }
+
+    /// Revokes all access of `subject`, false if it had none.
+    pub fn revoke(&mut self, subject: &str) -> bool {
+        let before = self.read.len() + self.write.len();
+        self.read.retain(|s| s != subject);
+        self.write.retain(|s| s != subject);
+        before != self.read.len() + self.write.len()
+    }
+}
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Access {
+    Read,
+    Write,
+}
+
+/// Grants a user access to a note.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct NewPermission {
+    /// Subject of the user.
+    pub subject: String,
+    pub access: Access,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct PatchNote {
     pub title: Option<String>,
     pub body: Option<String>,
+    /// Replaces all tags of the note.
+    pub tags: Option<Vec<String>>,
+    /// Taken when the patch is received, so that every backend a patch is
+    /// applied to stores the same time.
+    #[serde(skip, default = "now")]
+    pub updated_at: DateTime<Utc>,
+    /// Version of the note the patch is based on. The patch is refused
+    /// with a conflict if the note has changed since.
+    #[serde(default)]
+    pub version: Option<u64>,
+    /// Replaces the permissions of the note. Set by the permission
+    /// endpoints, not by clients patching the note.
+    #[serde(skip)]
+    pub permissions: Option<Permissions>,
+}
+
+impl PatchNote {
+    /// Fails with a conflict if the note isn't at the expected version.
+    pub fn check_version(&self, note: &Note) -> Result<(), NoteDbError> {
+        match self.version {
+            Some(version) if version != note.version => {
+                Err(NoteDbError::Conflict(format!(
+                    "note {} is at version {}, not {}",
+                    note.id, note.version, version
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Applies the patch if the note is at the expected version.
+    pub fn apply_to(&self, note: &mut Note) -> Result<(), NoteDbError> {
+        self.check_version(note)?;
+        if let Some(title) = &self.title {
+            note.title = title.clone();
+        }
+        if let Some(body) = &self.body {
+            note.body = body.clone();
+        }
+        if let Some(tags) = &self.tags {
+            note.tags = tags.clone();
+        }
+        if let Some(permissions) = &self.permissions {
+            note.permissions = permissions.clone();
+        }
+        note.updated_at = self.updated_at;
+        note.version += 1;
+        Ok(())
+    }
+}
+
+/// Filters for listing notes. All set conditions must hold.
+#[derive(
+    Debug, Clone, Default, PartialEq, Serialize, Deserialize, IntoParams,
+)]
+#[into_params(parameter_in = Query)]
+pub struct NoteFilter {
+    /// Case-insensitive substring of the title.
+    pub title_contains: Option<String>,
+    /// Tag the note must have, matched exactly.
+    pub tag: Option<String>,
+    /// Only notes created after this time.
+    pub created_after: Option<DateTime<Utc>>,
+    /// Only notes last updated before this time.
+    pub updated_before: Option<DateTime<Utc>>,
+}
+
+impl NoteFilter {
+    pub fn matches(&self, note: &Note) -> bool {
+        if let Some(title) = &self.title_contains {
+            if !note.title.to_lowercase().contains(&title.to_lowercase()) {
+                return false;
+            }
+        }
+        if let Some(tag) = &self.tag {
+            if !note.tags.contains(tag) {
+                return false;
+            }
+        }
+        if self
+            .created_after
+            .is_some_and(|after| note.created_at <= after)
+        {
+            return false;
+        }
+        if self
+            .updated_before
+            .is_some_and(|before| note.updated_at >= before)
+        {
+            return false;
+        }
+        true
+    }
+}
+
+/// A window into a listing. Without a limit all remaining notes are
+/// returned.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct Page {
+    pub limit: Option<u64>,
+    pub offset: Option<u64>,
+}
+
+impl Page {
+    /// Cuts the page out of a complete listing, for backends that can't
+    /// page natively.
+    pub fn slice<T>(&self, items: Vec<T>) -> Vec<T> {
+        let offset = self.offset.unwrap_or(0) as usize;
+        let limit = self.limit.map_or(usize::MAX, |limit| limit as usize);
+        items.into_iter().skip(offset).take(limit).collect()
+    }
+
+    /// Limit and offset for backends that take them as `i64`. Larger values
+    /// are capped, no listing holds that many notes.
+    pub fn as_i64(&self) -> (Option<i64>, i64) {
+        let cap = |value: u64| i64::try_from(value).unwrap_or(i64::MAX);
+        (self.limit.map(cap), cap(self.offset.unwrap_or(0)))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NotePage {
+    pub notes: Vec<Note>,
+    /// Number of notes matching the filter, regardless of the page.
+    pub total: u64,
+}
+
+impl NotePage {
+    /// Compares two pages regardless of the order of their notes.
+    pub fn same_notes(&self, other: &NotePage) -> bool {
+        self.total == other.total
+            && self.notes.len() == other.notes.len()
+            && self.notes.iter().all(|note| other.notes.contains(note))
+    }
+}
+
+/// State of a note before one of its patches. Revisions of a note are
+/// numbered from 1 in the order they were recorded.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct NoteRevision {
+    pub note_id: String,
+    pub rev: u64,
+    pub title: String,
+    pub body: String,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// When the note got this state.
+    #[serde(with = "timestamp")]
+    pub updated_at: DateTime<Utc>,
+}
+
+impl NoteRevision {
+    pub fn new(note: &Note, rev: u64) -> NoteRevision {
+        NoteRevision {
+            note_id: note.id.clone(),
+            rev,
+            title: note.title.clone(),
+            body: note.body.clone(),
+            tags: note.tags.clone(),
+            updated_at: note.updated_at,
+        }
+    }
+}
+
+/// Timestamps of stored notes. MongoDB gets them as BSON dates, so that
+/// they sort and compare as dates, JSON as RFC 3339 strings. Reads accept
+/// both, notes stored as strings before stay readable.
+mod timestamp {
+    use chrono::{DateTime, Utc};
+    use mongodb::bson::{
+        serde_helpers::chrono_datetime_as_bson_datetime, Bson,
+    };
+    use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        value: &DateTime<Utc>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            value.serialize(serializer)
+        } else {
+            chrono_datetime_as_bson_datetime::serialize(value, serializer)
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<DateTime<Utc>, D::Error> {
+        match Bson::deserialize(deserializer)? {
+            Bson::DateTime(value) => Ok(value.to_chrono()),
+            Bson::String(value) => DateTime::parse_from_rfc3339(&value)
+                .map(|value| value.with_timezone(&Utc))
+                .map_err(D::Error::custom),
+            other => Err(D::Error::custom(format!(
+                "expected a timestamp, got {}",
+                other
+            ))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct NoteSuggestion {
+    pub id: String,
+    pub title: String,
+}
+
+/// Share of the trigrams of a query that a title needs to be suggested for
+/// it without starting with it.
+pub const SUGGESTION_SIMILARITY: f64 = 0.5;
+
+/// The trigrams of `text`, sorted: every word, lowercased and padded with
+/// two spaces in front and one behind, cut into windows of three
+/// characters. Words are runs of alphanumeric characters.
+pub fn trigrams(text: &str) -> Vec<String> {
+    let mut trigrams = BTreeSet::new();
+    for word in text
+        .to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+    {
+        let padded: Vec<char> = format!("  {} ", word).chars().collect();
+        for window in padded.windows(3) {
+            trigrams.insert(window.iter().collect());
+        }
+    }
+    trigrams.into_iter().collect()
+}
+
+/// Share of the sorted trigrams `query` found in `title`.
+fn similarity(query: &[String], title: &str) -> f64 {
+    if query.is_empty() {
+        return 0.0;
+    }
+    let title = trigrams(title);
+    let shared = query
+        .iter()
+        .filter(|trigram| title.binary_search(trigram).is_ok())
+        .count();
+    shared as f64 / query.len() as f64
+}
+
+/// Orders title suggestions for `query`: titles starting with it, sorted by
+/// title, then titles sharing at least `SUGGESTION_SIMILARITY` of its
+/// trigrams, most similar first. Other candidates and repeated ids are
+/// dropped, and at most `limit` suggestions kept.
+pub fn rank_suggestions(
+    query: &str,
+    candidates: impl IntoIterator<Item = NoteSuggestion>,
+    limit: usize,
+) -> Vec<NoteSuggestion> {
+    let prefix = query.to_lowercase();
+    let query = trigrams(query);
+    let mut ids = HashSet::new();
+    let mut ranked = Vec::new();
+    for suggestion in candidates {
+        if !ids.insert(suggestion.id.clone()) {
+            continue;
+        }
+        let title = suggestion.title.to_lowercase();
+        let fuzzy = !title.starts_with(&prefix);
+        let score = if fuzzy {
+            similarity(&query, &title)
+        } else {
+            0.0
+        };
+        if fuzzy && score < SUGGESTION_SIMILARITY {
+            continue;
+        }
+        ranked.push((fuzzy, score, title, suggestion));
+    }
+    ranked.sort_by(|a, b| {
+        a.0.cmp(&b.0)
+            .then(b.1.total_cmp(&a.1))
+            .then_with(|| a.2.cmp(&b.2))
+    });
+    ranked
+        .into_iter()
+        .take(limit)
+        .map(|(.., suggestion)| suggestion)
+        .collect()
+}
+
+/// Ids of notes to fetch in one request.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct NoteLookup {
+    pub ids: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct NoteLookupResult {
+    /// The notes found, in the order their ids were asked for.
+    pub notes: Vec<Note>,
+    /// Ids without a note.
+    pub missing: Vec<String>,
+}
+
+/// Errors of `NoteDb` operations, classified so that callers can react
+/// to them.
+#[derive(Debug)]
+pub enum NoteDbError {
+    NotFound,
+    /// The write clashed with the current state, e.g. a duplicate id or a
+    /// concurrent modification.
+    Conflict(String),
+    /// The backend could not be reached.
+    Connection(Box<dyn std::error::Error + Send + Sync>),
+    /// The caller's deadline passed before the operation completed.
+    DeadlineExceeded,
+    /// The note doesn't fit into a single record of the backend.
+    TooLarge {
+        limit: usize,
+    },
+    /// The backend can't do this, e.g. keep revisions.
+    Unsupported(&'static str),
+    Serialization(Box<dyn std::error::Error + Send + Sync>),
+    Other(Box<dyn std::error::Error + Send + Sync>),
+}
+
+impl fmt::Display for NoteDbError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NoteDbError::NotFound => write!(f, "not found"),
+            NoteDbError::Conflict(reason) => write!(f, "conflict: {}", reason),
+            NoteDbError::Connection(err) => write!(f, "connection: {}", err),
+            NoteDbError::DeadlineExceeded => write!(f, "deadline exceeded"),
+            NoteDbError::TooLarge { limit } => {
+                write!(f, "note is larger than {} bytes", limit)
+            }
+            NoteDbError::Unsupported(what) => {
+                write!(f, "{} not supported by this backend", what)
+            }
+            NoteDbError::Serialization(err) => {
+                write!(f, "serialization: {}", err)
+            }
+            NoteDbError::Other(err) => err.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for NoteDbError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            NoteDbError::Connection(err)
+            | NoteDbError::Serialization(err)
+            | NoteDbError::Other(err) => Some(err.as_ref()),
+            NoteDbError::NotFound
+            | NoteDbError::Conflict(_)
+            | NoteDbError::DeadlineExceeded
+            | NoteDbError::TooLarge { .. }
+            | NoteDbError::Unsupported(_) => None,
+        }
+    }
+}
+
+impl From<String> for NoteDbError {
+    fn from(message: String) -> Self {
+        NoteDbError::Other(message.into())
+    }
+}
+
+impl From<&str> for NoteDbError {
+    fn from(message: &str) -> Self {
+        NoteDbError::Other(message.into())
+    }
+}
+
+impl From<serde_json::Error> for NoteDbError {
+    fn from(err: serde_json::Error) -> Self {
+        NoteDbError::Serialization(err.into())
+    }
 }
 
 #[async_trait]
 pub trait NoteDb: Send + Sync {
     async fn create_note(
         &self,
+        ctx: &OpContext,
+        note: &Note,
+    ) -> Result<(), NoteDbError>;
+
+    /// Creates several notes at once. Backends that can insert in bulk
+    /// should override this.
+    async fn create_notes(
+        &self,
+        ctx: &OpContext,
+        notes: &[Note],
+    ) -> Result<(), NoteDbError> {
+        for note in notes {
+            self.create_note(ctx, note).await?;
+        }
+        Ok(())
+    }
+
+    async fn get_note(
+        &self,
+        ctx: &OpContext,
+        id: &str,
+    ) -> Result<Option<Note>, NoteDbError>;
+
+    /// Returns the notes with the given ids that exist, in no particular
+    /// order. Backends that can look up several ids in one query should
+    /// override this.
+    async fn get_notes(
+        &self,
+        ctx: &OpContext,
+        ids: &[String],
+    ) -> Result<Vec<Note>, NoteDbError> {
+        let mut notes = Vec::new();
+        for id in ids {
+            notes.extend(self.get_note(ctx, id).await?);
+        }
+        Ok(notes)
+    }
+
+    async fn update_note(
+        &self,
+        ctx: &OpContext,
+        id: &str,
+        note: &PatchNote,
+    ) -> Result<(), NoteDbError>;
+
+    async fn delete_note(
+        &self,
+        ctx: &OpContext,
+        id: &str,
+    ) -> Result<bool, NoteDbError>;
+
+    async fn list_notes(
+        &self,
+        ctx: &OpContext,
+        filter: &NoteFilter,
+        page: &Page,
+    ) -> Result<NotePage, NoteDbError>;
+
+    /// Returns up to `limit` notes whose title starts with `prefix`,
+    /// compared case-insensitively, followed by notes with similar titles,
+    /// in the order of `rank_suggestions`.
+    async fn suggest_titles(
+        &self,
+        ctx: &OpContext,
+        prefix: &str,
+        limit: usize,
+    ) -> Result<Vec<NoteSuggestion>, NoteDbError>;
+
+    /// Returns the notes titled `title`, compared case-insensitively.
+    /// Backends with an index on titles should override this.
+    async fn find_by_title(
+        &self,
+        ctx: &OpContext,
+        title: &str,
+    ) -> Result<Vec<NoteSuggestion>, NoteDbError> {
+        let filter = NoteFilter {
+            title_contains: Some(title.to_string()),
+            ..NoteFilter::default()
+        };
+        let page = self.list_notes(ctx, &filter, &Page::default()).await?;
+        let title = title.to_lowercase();
+        Ok(page
+            .notes
+            .into_iter()
+            .filter(|note| note.title.to_lowercase() == title)
+            .map(|note| NoteSuggestion {
+                id: note.id,
+                title: note.title,
+            })
+            .collect())
+    }
+
+    /// Stores the current state of `note` as its next revision and returns
+    /// the number of the revision.
+    async fn record_revision(
+        &self,
+        ctx: &OpContext,
+        note: &Note,
+    ) -> Result<u64, NoteDbError>;
+
+    /// Returns the revisions of a note, oldest first. Revisions are not
+    /// scoped to owners, callers check access to the note first.
+    async fn list_revisions(
+        &self,
+        ctx: &OpContext,
+        note_id: &str,
+    ) -> Result<Vec<NoteRevision>, NoteDbError>;
+
+    async fn get_revision(
+        &self,
+        ctx: &OpContext,
+        note_id: &str,
+        rev: u64,
+    ) -> Result<Option<NoteRevision>, NoteDbError> {
+        Ok(self
+            .list_revisions(ctx, note_id)
+            .await?
+            .into_iter()
+            .find(|revision| revision.rev == rev))
+    }
+}
+
+#[async_trait]
+impl<T: NoteDb + ?Sized> NoteDb for Arc<T> {
+    async fn create_note(
+        &self,
+        ctx: &OpContext,
         note: &Note,
-    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>>;
+    ) -> Result<(), NoteDbError> {
+        (**self).create_note(ctx, note).await
+    }
+
+    async fn create_notes(
+        &self,
+        ctx: &OpContext,
+        notes: &[Note],
+    ) -> Result<(), NoteDbError> {
+        (**self).create_notes(ctx, notes).await
+    }
 
     async fn get_note(
         &self,
+        ctx: &OpContext,
         id: &str,
-    ) -> Result<Option<Note>, Box<dyn std::error::Error + Send + Sync>>;
+    ) -> Result<Option<Note>, NoteDbError> {
+        (**self).get_note(ctx, id).await
+    }
+
+    async fn get_notes(
+        &self,
+        ctx: &OpContext,
+        ids: &[String],
+    ) -> Result<Vec<Note>, NoteDbError> {
+        (**self).get_notes(ctx, ids).await
+    }
 
     async fn update_note(
         &self,
+        ctx: &OpContext,
         id: &str,
         note: &PatchNote,
-    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>>;
+    ) -> Result<(), NoteDbError> {
+        (**self).update_note(ctx, id, note).await
+    }
 
     async fn delete_note(
         &self,
+        ctx: &OpContext,
         id: &str,
-    ) -> Result<bool, Box<dyn std::error::Error + Send + Sync>>;
+    ) -> Result<bool, NoteDbError> {
+        (**self).delete_note(ctx, id).await
+    }
 
     async fn list_notes(
         &self,
-    ) -> Result<Vec<Note>, Box<dyn std::error::Error + Send + Sync>>;
+        ctx: &OpContext,
+        filter: &NoteFilter,
+        page: &Page,
+    ) -> Result<NotePage, NoteDbError> {
+        (**self).list_notes(ctx, filter, page).await
+    }
+
+    async fn suggest_titles(
+        &self,
+        ctx: &OpContext,
+        prefix: &str,
+        limit: usize,
+    ) -> Result<Vec<NoteSuggestion>, NoteDbError> {
+        (**self).suggest_titles(ctx, prefix, limit).await
+    }
+
+    async fn find_by_title(
+        &self,
+        ctx: &OpContext,
+        title: &str,
+    ) -> Result<Vec<NoteSuggestion>, NoteDbError> {
+        (**self).find_by_title(ctx, title).await
+    }
+
+    async fn record_revision(
+        &self,
+        ctx: &OpContext,
+        note: &Note,
+    ) -> Result<u64, NoteDbError> {
+        (**self).record_revision(ctx, note).await
+    }
+
+    async fn list_revisions(
+        &self,
+        ctx: &OpContext,
+        note_id: &str,
+    ) -> Result<Vec<NoteRevision>, NoteDbError> {
+        (**self).list_revisions(ctx, note_id).await
+    }
+
+    async fn get_revision(
+        &self,
+        ctx: &OpContext,
+        note_id: &str,
+        rev: u64,
+    ) -> Result<Option<NoteRevision>, NoteDbError> {
+        (**self).get_revision(ctx, note_id, rev).await
+    }
 }
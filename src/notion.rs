@@ -0,0 +1,272 @@
+//! Import of Notion's "Markdown & CSV" export zip.
+//!
+//! Every Markdown page becomes a note. Notes have no notebooks, so the
+//! folder a page was in is kept as a tag instead. Links between pages are
+//! rewritten to the URLs of the imported notes. Everything else, like
+//! database CSVs, images and links to pages missing from the export, is
+//! reported as unmapped.
+
+use std::{collections::HashMap, io::Cursor};
+
+use crate::import::{ArchiveLimits, ArchiveReader};
+use crate::notes::{NewNote, Note};
+use percent_encoding::percent_decode_str;
+
+/// Length of the hex id Notion appends to page and folder names.
+const NOTION_ID_LEN: usize = 32;
+
+struct Page {
+    path: String,
+    dir: String,
+    title: String,
+    body: String,
+    notebook: Option<String>,
+}
+
+pub struct NotionExport {
+    pages: Vec<Page>,
+    unmapped: Vec<String>,
+}
+
+impl NotionExport {
+    pub fn read(
+        data: &[u8],
+        limits: ArchiveLimits,
+    ) -> Result<NotionExport, zip::result::ZipError> {
+        let mut archive = zip::ZipArchive::new(Cursor::new(data))?;
+        let mut reader = ArchiveReader::new(limits);
+        let mut export = NotionExport {
+            pages: Vec::new(),
+            unmapped: Vec::new(),
+        };
+        for index in 0..archive.len() {
+            let mut file = archive.by_index(index)?;
+            if file.is_dir() {
+                continue;
+            }
+            let path = file.name().to_string();
+            if !path.ends_with(".md") {
+                export.unmapped.push(path);
+                continue;
+            }
+            match reader.read_text(&mut file)? {
+                Ok(text) => export.pages.push(Page::new(path, text)),
+                Err(reason) => {
+                    export.unmapped.push(format!("{} ({})", path, reason))
+                }
+            }
+        }
+        Ok(export)
+    }
+
+    /// Creates the notes, with links between pages pointing to the notes.
+    pub fn into_notes(self, notes_path: &str) -> (Vec<Note>, Vec<String>) {
+        let NotionExport {
+            pages,
+            mut unmapped,
+        } = self;
+        let mut notes: Vec<Note> = pages
+            .iter()
+            .map(|page| {
+                NewNote {
+                    title: page.title.clone(),
+                    body: String::new(),
+                    tags: page.notebook.iter().cloned().collect(),
+                    org: None,
+                }
+                .into_note(notes_path)
+            })
+            .collect();
+        let urls: HashMap<&str, &str> = pages
+            .iter()
+            .zip(&notes)
+            .map(|(page, note)| (page.path.as_str(), note.url.as_str()))
+            .collect();
+        let bodies: Vec<String> = pages
+            .iter()
+            .map(|page| rewrite_links(page, &urls, &mut unmapped))
+            .collect();
+        for (note, body) in notes.iter_mut().zip(bodies) {
+            note.body = body;
+        }
+        (notes, unmapped)
+    }
+}
+
+impl Page {
+    fn new(path: String, text: String) -> Page {
+        let (dir, file) = path.rsplit_once('/').unwrap_or(("", &path));
+        let notebook = dir
+            .split('/')
+            .filter(|folder| !folder.is_empty())
+            .map(strip_notion_id)
+            .collect::<Vec<_>>()
+            .join("/");
+        // Notion starts every page with its title as heading.
+        let (title, body) = match text.strip_prefix("# ") {
+            Some(rest) => {
+                let (title, body) = rest.split_once('\n').unwrap_or((rest, ""));
+                (title.trim().to_string(), body.trim_start().to_string())
+            }
+            None => (
+                strip_notion_id(file.trim_end_matches(".md")).to_string(),
+                text,
+            ),
+        };
+        Page {
+            dir: dir.to_string(),
+            title,
+            body,
+            notebook: (!notebook.is_empty()).then_some(notebook),
+            path,
+        }
+    }
+}
+
+fn strip_notion_id(name: &str) -> &str {
+    match name.rsplit_once(' ') {
+        Some((title, id))
+            if id.len() == NOTION_ID_LEN
+                && id.chars().all(|c| c.is_ascii_hexdigit()) =>
+        {
+            title
+        }
+        _ => name,
+    }
+}
+
+/// Rewrites Markdown links to other pages of the export.
+fn rewrite_links(
+    page: &Page,
+    urls: &HashMap<&str, &str>,
+    unmapped: &mut Vec<String>,
+) -> String {
+    let mut body = String::with_capacity(page.body.len());
+    let mut rest = page.body.as_str();
+    while let Some(start) = rest.find("](") {
+        let (before, after) = rest.split_at(start + 2);
+        body.push_str(before);
+        let Some(end) = after.find(')') else {
+            rest = after;
+            break;
+        };
+        let target = &after[..end];
+        rest = &after[end..];
+        let decoded = percent_decode_str(target).decode_utf8_lossy();
+        if target.contains("://") || !decoded.ends_with(".md") {
+            body.push_str(target);
+            continue;
+        }
+        let resolved = resolve(&page.dir, &decoded);
+        match urls.get(resolved.as_str()) {
+            Some(url) => body.push_str(url),
+            None => {
+                unmapped.push(format!("{}: link to {}", page.path, resolved));
+                body.push_str(target);
+            }
+        }
+    }
+    body.push_str(rest);
+    body
+}
+
+/// Resolves a relative path against the directory of a page.
+fn resolve(dir: &str, relative: &str) -> String {
+    let mut parts: Vec<&str> =
+        dir.split('/').filter(|part| !part.is_empty()).collect();
+    for part in relative.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            part => parts.push(part),
+        }
+    }
+    parts.join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    const PROJECTS: &str = "Projects 0123456789abcdef0123456789abcdef";
+
+    fn export_zip(files: &[(&str, &str)]) -> Vec<u8> {
+        let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, content) in files {
+            zip.start_file(*name, zip::write::SimpleFileOptions::default())
+                .unwrap();
+            zip.write_all(content.as_bytes()).unwrap();
+        }
+        zip.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn it_converts_pages_and_rewrites_links() {
+        // Setup
+        let data = export_zip(&[
+            (
+                &format!("{}.md", PROJECTS),
+                "# Projects\n\nSee [Roadmap](Projects%200123456789abcdef0123456789abcdef/Roadmap%20fedcba9876543210fedcba9876543210.md) and [Gone](Gone.md).",
+            ),
+            (
+                &format!("{}/Roadmap fedcba9876543210fedcba9876543210.md", PROJECTS),
+                "# Roadmap\n\nQ1",
+            ),
+            ("Tasks.csv", "Name\nA\n"),
+        ]);
+
+        // Execute
+        let (notes, unmapped) =
+            NotionExport::read(&data, ArchiveLimits::default())
+                .unwrap()
+                .into_notes("/notes");
+
+        // Assert
+        assert_eq!(notes.len(), 2);
+        assert_eq!(notes[0].title, "Projects");
+        assert!(notes[0].tags.is_empty());
+        assert_eq!(notes[1].title, "Roadmap");
+        assert_eq!(notes[1].body, "Q1");
+        assert_eq!(notes[1].tags, vec!["Projects"]);
+        assert_eq!(
+            notes[0].body,
+            format!("See [Roadmap]({}) and [Gone](Gone.md).", notes[1].url)
+        );
+        assert_eq!(
+            unmapped,
+            vec![
+                "Tasks.csv".to_string(),
+                format!("{}.md: link to Gone.md", PROJECTS),
+            ]
+        );
+    }
+
+    #[test]
+    fn it_limits_the_decompressed_size() {
+        // Setup
+        let limits = ArchiveLimits {
+            max_entry_bytes: 100,
+            max_total_bytes: 150,
+        };
+        let large = "a".repeat(101);
+        let fitting = "b".repeat(100);
+        let data = export_zip(&[("Large.md", &large), ("Small.md", "# Small")]);
+        let too_much = export_zip(&[("A.md", &fitting), ("B.md", &fitting)]);
+
+        // Execute
+        let (notes, unmapped) = NotionExport::read(&data, limits)
+            .unwrap()
+            .into_notes("/notes");
+        let refused = NotionExport::read(&too_much, limits);
+
+        // Assert
+        assert_eq!(notes.len(), 1);
+        assert_eq!(notes[0].title, "Small");
+        assert_eq!(unmapped, vec!["Large.md (too large)".to_string()]);
+        assert!(refused.is_err());
+    }
+}
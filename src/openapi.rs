@@ -0,0 +1,98 @@
+//! OpenAPI document of the public API and the Swagger UI serving it.
+
+use axum::{
+    http::{header, HeaderValue},
+    response::Response,
+    Router,
+};
+use utoipa::{
+    openapi::{self, server::Server},
+    OpenApi,
+};
+use utoipa_swagger_ui::SwaggerUi;
+
+/// Swagger UI loads its scripts and styles from the same origin and sets
+/// inline styles, which the API's default policy forbids.
+const DOCS_CONTENT_SECURITY_POLICY: &str = "default-src 'self'; \
+    img-src 'self' data:; style-src 'self' 'unsafe-inline'; \
+    frame-ancestors 'none'";
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Notes API"),
+    paths(
+        crate::get_health,
+        crate::capabilities::get_capabilities,
+        crate::post_note,
+        crate::list_notes,
+        crate::suggest_notes,
+        crate::lookup_notes,
+        crate::get_note,
+        crate::patch_note,
+        crate::delete_note,
+        crate::post_note_share,
+        crate::delete_note_share,
+        crate::post_note_permission,
+        crate::delete_note_permission,
+        crate::get_shared_note,
+        crate::list_note_versions,
+        crate::get_note_version,
+        crate::restore_note_version,
+        crate::import_notes,
+        crate::import_csv,
+        crate::export_csv,
+        crate::import_notion,
+        crate::import_html,
+        crate::import_jex,
+        crate::export_jex,
+        crate::post_announcement,
+        crate::list_announcements,
+        crate::delete_announcement,
+        crate::post_api_key,
+        crate::list_api_keys,
+        crate::delete_api_key,
+        crate::post_org,
+        crate::list_orgs,
+        crate::get_org,
+        crate::put_org_member,
+        crate::delete_org_member,
+    )
+)]
+struct ApiDoc;
+
+#[cfg(feature = "pdf")]
+#[derive(OpenApi)]
+#[openapi(paths(crate::get_note_pdf))]
+struct PdfApiDoc;
+
+/// The OpenAPI document, with paths relative to the versioned API root.
+pub fn api_doc(api_version: &str) -> openapi::OpenApi {
+    #[allow(unused_mut)]
+    let mut doc = ApiDoc::openapi();
+    #[cfg(feature = "pdf")]
+    doc.merge(PdfApiDoc::openapi());
+    doc.servers = Some(vec![Server::new(format!("/{}", api_version))]);
+    doc
+}
+
+/// Serves the document at `/{api_version}/openapi.json` and Swagger UI at
+/// `/docs`.
+pub fn routes<S>(api_version: &str) -> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    let docs = SwaggerUi::new("/docs").url(
+        format!("/{}/openapi.json", api_version),
+        api_doc(api_version),
+    );
+    Router::from(docs)
+        .layer(axum::middleware::map_response(docs_content_security_policy))
+}
+
+async fn docs_content_security_policy(mut response: Response) -> Response {
+    response.headers_mut().insert(
+        header::CONTENT_SECURITY_POLICY,
+        HeaderValue::from_static(DOCS_CONTENT_SECURITY_POLICY),
+    );
+    response
+}
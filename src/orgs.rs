@@ -0,0 +1,72 @@
+//! Organizations share notes among their members. A note created in an
+//! organization stays owned by its creator, but every member can read and
+//! change it.
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use nanoid::nanoid;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::notes::NoteDbError;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct Organization {
+    pub id: String,
+    pub name: String,
+    /// Subjects of the users belonging to the organization.
+    pub members: Vec<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Organization {
+    /// A new organization whose only member is its creator.
+    pub fn new(name: &str, creator: &str, now: DateTime<Utc>) -> Organization {
+        Organization {
+            id: nanoid!(),
+            name: name.to_string(),
+            members: vec![creator.to_string()],
+            created_at: now,
+        }
+    }
+
+    pub fn has_member(&self, subject: &str) -> bool {
+        self.members.iter().any(|member| member == subject)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct NewOrganization {
+    pub name: String,
+}
+
+#[async_trait]
+pub trait OrgDb: Send + Sync {
+    async fn create_org(&self, org: &Organization) -> Result<(), NoteDbError>;
+
+    async fn get_org(
+        &self,
+        id: &str,
+    ) -> Result<Option<Organization>, NoteDbError>;
+
+    /// The organizations `member` belongs to.
+    async fn list_orgs(
+        &self,
+        member: &str,
+    ) -> Result<Vec<Organization>, NoteDbError>;
+
+    /// Adds `member` unless already a member. False if the organization
+    /// doesn't exist.
+    async fn add_member(
+        &self,
+        id: &str,
+        member: &str,
+    ) -> Result<bool, NoteDbError>;
+
+    /// False if the organization doesn't exist.
+    async fn remove_member(
+        &self,
+        id: &str,
+        member: &str,
+    ) -> Result<bool, NoteDbError>;
+}
@@ -0,0 +1,143 @@
+//! Printable PDF rendering of a note. The Markdown body is flattened to
+//! wrapped text lines and laid out on A4 pages, each headed with the note
+//! title and its last modification date.
+
+use printpdf::{BuiltinFont, IndirectFontRef, Mm, PdfDocument};
+use pulldown_cmark::{Event, Parser, Tag, TagEnd};
+
+use crate::notes::Note;
+
+const PAGE_WIDTH: Mm = Mm(210.0);
+const PAGE_HEIGHT: Mm = Mm(297.0);
+const MARGIN: f32 = 20.0;
+const HEADER_SIZE: f32 = 9.0;
+const BODY_SIZE: f32 = 11.0;
+const LINE_HEIGHT: f32 = 5.5;
+/// Characters per line that fit the text width at the body font size.
+const WRAP_WIDTH: usize = 85;
+
+pub fn render_note(note: &Note) -> Result<Vec<u8>, printpdf::Error> {
+    let (doc, page, layer) =
+        PdfDocument::new(&note.title, PAGE_WIDTH, PAGE_HEIGHT, "body");
+    let font = doc.add_builtin_font(BuiltinFont::Helvetica)?;
+    let bold = doc.add_builtin_font(BuiltinFont::HelveticaBold)?;
+    let header = format!(
+        "{} - {}",
+        printable(&note.title),
+        note.updated_at.format("%Y-%m-%d")
+    );
+
+    let top = PAGE_HEIGHT.0 - MARGIN;
+    let bottom = MARGIN;
+    let mut layer = doc.get_page(page).get_layer(layer);
+    write_header(&layer, &header, &bold);
+    let mut y = top - 2.0 * LINE_HEIGHT;
+    for line in markdown_lines(&note.body) {
+        if y < bottom {
+            let (page, index) = doc.add_page(PAGE_WIDTH, PAGE_HEIGHT, "body");
+            layer = doc.get_page(page).get_layer(index);
+            write_header(&layer, &header, &bold);
+            y = top - 2.0 * LINE_HEIGHT;
+        }
+        if !line.is_empty() {
+            layer.use_text(line, BODY_SIZE, Mm(MARGIN), Mm(y), &font);
+        }
+        y -= LINE_HEIGHT;
+    }
+    doc.save_to_bytes()
+}
+
+fn write_header(
+    layer: &printpdf::PdfLayerReference,
+    header: &str,
+    font: &IndirectFontRef,
+) {
+    layer.use_text(
+        header,
+        HEADER_SIZE,
+        Mm(MARGIN),
+        Mm(PAGE_HEIGHT.0 - MARGIN),
+        font,
+    );
+}
+
+/// Flattens Markdown into wrapped plain text lines, with blank lines
+/// between blocks and bullets for list items.
+fn markdown_lines(markdown: &str) -> Vec<String> {
+    let mut paragraphs = Vec::new();
+    let mut current = String::new();
+    for event in Parser::new(markdown) {
+        match event {
+            Event::Start(Tag::Item) => current.push_str("• "),
+            Event::Text(text) | Event::Code(text) => current.push_str(&text),
+            Event::SoftBreak => current.push(' '),
+            Event::HardBreak => current.push('\n'),
+            Event::End(
+                TagEnd::Paragraph
+                | TagEnd::Heading(_)
+                | TagEnd::Item
+                | TagEnd::CodeBlock,
+            ) if !current.is_empty() => {
+                paragraphs.push(std::mem::take(&mut current));
+            }
+            _ => {}
+        }
+    }
+    if !current.is_empty() {
+        paragraphs.push(current);
+    }
+
+    let mut lines = Vec::new();
+    for paragraph in paragraphs {
+        for line in paragraph.trim_end().lines() {
+            wrap(&printable(line), &mut lines);
+        }
+        lines.push(String::new());
+    }
+    lines
+}
+
+fn wrap(line: &str, lines: &mut Vec<String>) {
+    let mut current = String::new();
+    for word in line.split(' ') {
+        let len = current.chars().count();
+        if len > 0 && len + 1 + word.chars().count() > WRAP_WIDTH {
+            lines.push(std::mem::take(&mut current));
+        }
+        if !current.is_empty() {
+            current.push(' ');
+        }
+        current.push_str(word);
+    }
+    lines.push(current);
+}
+
+/// The builtin fonts only cover Latin-1, so anything beyond it is
+/// replaced rather than rendered as garbage.
+fn printable(text: &str) -> String {
+    text.chars()
+        .map(|c| match c {
+            '•' | '\u{20}'..='\u{7e}' | '\u{a0}'..='\u{ff}' => c,
+            '\t' => ' ',
+            _ => '?',
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_renders_a_note_as_pdf() {
+        // Setup
+        let body = "# Heading\n\nSome *text*.\n\n- one\n- two\n".repeat(40);
+        let note = Note::new("Title", &body, "/notes/1");
+
+        // Execute
+        let pdf = render_note(&note).unwrap();
+
+        // Assert
+        assert!(pdf.starts_with(b"%PDF"));
+    }
+}
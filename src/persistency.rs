@@ -1,12 +1,62 @@
+use std::{collections::HashSet, sync::Mutex};
+
 use async_trait::async_trait;
-use mongodb::{bson::doc, options::ClientOptions, Client, Database};
+use chrono::{DateTime, Utc};
+use mongodb::{
+    action::Action,
+    bson::{doc, Bson, Document, Regex},
+    options::{ClientOptions, IndexOptions, ReadPreference, SelectionCriteria},
+    Client, Database, IndexModel,
+};
 
-use crate::notes::{Note, NoteDb, PatchNote};
+use crate::announcements::{Announcement, AnnouncementDb};
+use crate::api_keys::{ApiKey, ApiKeyDb};
+use crate::consistency::Consistency;
+use crate::context::OpContext;
+use crate::idempotency::{IdempotencyDb, IdempotencyRecord};
+use crate::metrics::OPEN_DB_CURSORS;
+use crate::notes::{
+    rank_suggestions, trigrams, Note, NoteDb, NoteDbError, NoteFilter,
+    NotePage, NoteRevision, NoteSuggestion, Page, PatchNote,
+    SUGGESTION_SIMILARITY,
+};
+use crate::orgs::{OrgDb, Organization};
+use crate::shares::{Share, ShareDb};
 
 use futures::stream::TryStreamExt;
 
+pub mod batching;
+#[cfg(feature = "couchdb")]
+pub mod couchdb;
+pub mod dual_write;
+pub mod hedged;
+pub mod memory;
+pub mod mirror;
+pub mod negative_cache;
+#[cfg(feature = "postgres")]
+pub mod postgres;
+#[cfg(feature = "s3")]
+pub mod s3;
+pub mod shadow_read;
+pub mod traced;
+
 const NOTES_DB: &str = "notes";
+// Largest BSON document the server accepts.
+const MAX_DOCUMENT_BYTES: usize = 16 * 1024 * 1024;
 const NOTES_COLLECTION: &str = "notes";
+const ANNOUNCEMENTS_COLLECTION: &str = "announcements";
+const VERSIONS_COLLECTION: &str = "note_versions";
+const IDEMPOTENCY_COLLECTION: &str = "idempotency_keys";
+const API_KEYS_COLLECTION: &str = "api_keys";
+const ORGS_COLLECTION: &str = "orgs";
+const SHARES_COLLECTION: &str = "shares";
+
+// Lowercased copy of the title, kept next to it on every write so that
+// title suggestions can run as an indexed prefix scan.
+const TITLE_KEY_FIELD: &str = "title_key";
+// Trigrams of the title, kept next to it on every write so that title
+// suggestions can find similar titles through a multikey index.
+const TITLE_TRIGRAMS_FIELD: &str = "title_trigrams";
 
 pub async fn create_mongo_client(
     uri: &str,
@@ -18,6 +68,21 @@ pub async fn create_mongo_client(
 
 pub struct NoteMongoDb {
     db: Database,
+    database_per_tenant: bool,
+    // Tenant databases whose indexes exist.
+    indexed_tenants: Mutex<HashSet<String>>,
+}
+
+/// Strong reads go to the primary, eventual reads prefer a secondary.
+/// Without a level the read preference of the connection string applies.
+fn read_preference(ctx: &OpContext) -> Option<SelectionCriteria> {
+    let preference = match ctx.consistency? {
+        Consistency::Strong => ReadPreference::Primary,
+        Consistency::Eventual => {
+            ReadPreference::SecondaryPreferred { options: None }
+        }
+    };
+    Some(SelectionCriteria::ReadPreference(preference))
 }
 
 impl NoteMongoDb {
@@ -26,66 +91,920 @@ impl NoteMongoDb {
     }
 
     pub fn new(db: Database) -> NoteMongoDb {
-        NoteMongoDb { db }
+        NoteMongoDb {
+            db,
+            database_per_tenant: false,
+            indexed_tenants: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// Keeps the notes of each tenant in a database of their own, named
+    /// after the base database and the tenant, e.g. `notes_billing`.
+    pub fn with_database_per_tenant(mut self) -> NoteMongoDb {
+        self.database_per_tenant = true;
+        self
+    }
+
+    /// The database holding the notes in the scope of `ctx`. Indexes of
+    /// tenant databases are created on first use.
+    async fn notes_db(&self, ctx: &OpContext) -> Result<Database, NoteDbError> {
+        let tenant = match &ctx.tenant {
+            Some(tenant) if self.database_per_tenant => tenant,
+            _ => return Ok(self.db.clone()),
+        };
+        let db = self.db.client().database(&format!(
+            "{}_{}",
+            self.db.name(),
+            tenant
+        ));
+        if !self.indexed_tenants.lock().unwrap().contains(tenant) {
+            create_note_indexes(&db).await?;
+            self.indexed_tenants.lock().unwrap().insert(tenant.clone());
+        }
+        Ok(db)
+    }
+
+    /// Checks that the database server answers.
+    pub async fn ping(&self) -> Result<(), mongodb::error::Error> {
+        self.db.run_command(doc! { "ping": 1 }).await?;
+        Ok(())
+    }
+
+    pub async fn create_indexes(&self) -> Result<(), mongodb::error::Error> {
+        create_note_indexes(&self.db).await?;
+        // The server removes expired keys, with a delay of up to a minute.
+        let keys = self.db.collection::<Document>(IDEMPOTENCY_COLLECTION);
+        let index = IndexModel::builder()
+            .keys(doc! { "expires_at": 1 })
+            .options(
+                IndexOptions::builder()
+                    .expire_after(std::time::Duration::ZERO)
+                    .build(),
+            )
+            .build();
+        keys.create_index(index).await?;
+        let api_keys = self.db.collection::<Document>(API_KEYS_COLLECTION);
+        let index = IndexModel::builder()
+            .keys(doc! { "key_hash": 1 })
+            .options(IndexOptions::builder().unique(true).build())
+            .build();
+        api_keys.create_index(index).await?;
+        let index = IndexModel::builder().keys(doc! { "subject": 1 }).build();
+        api_keys.create_index(index).await?;
+        let orgs = self.db.collection::<Document>(ORGS_COLLECTION);
+        let index = IndexModel::builder().keys(doc! { "members": 1 }).build();
+        orgs.create_index(index).await?;
+        let shares = self.db.collection::<Document>(SHARES_COLLECTION);
+        let index = IndexModel::builder()
+            .keys(doc! { "token_hash": 1 })
+            .options(IndexOptions::builder().unique(true).build())
+            .build();
+        shares.create_index(index).await?;
+        Ok(())
+    }
+}
+
+impl From<mongodb::error::Error> for NoteDbError {
+    fn from(err: mongodb::error::Error) -> Self {
+        use mongodb::error::{ErrorKind, WriteFailure};
+        const DUPLICATE_KEY: i32 = 11000;
+        const MAX_TIME_MS_EXPIRED: i32 = 50;
+        // Raised by updates whose result outgrows the document limit.
+        const BSON_OBJECT_TOO_LARGE: i32 = 10334;
+        const DOCUMENT_TOO_LARGE: i32 = 17419;
+        match err.kind.as_ref() {
+            ErrorKind::Command(command)
+                if command.code == MAX_TIME_MS_EXPIRED =>
+            {
+                NoteDbError::DeadlineExceeded
+            }
+            ErrorKind::Command(command)
+                if [BSON_OBJECT_TOO_LARGE, DOCUMENT_TOO_LARGE]
+                    .contains(&command.code) =>
+            {
+                NoteDbError::TooLarge {
+                    limit: MAX_DOCUMENT_BYTES,
+                }
+            }
+            ErrorKind::Write(WriteFailure::WriteError(write))
+                if [BSON_OBJECT_TOO_LARGE, DOCUMENT_TOO_LARGE]
+                    .contains(&write.code) =>
+            {
+                NoteDbError::TooLarge {
+                    limit: MAX_DOCUMENT_BYTES,
+                }
+            }
+            ErrorKind::Io(_)
+            | ErrorKind::ServerSelection { .. }
+            | ErrorKind::ConnectionPoolCleared { .. }
+            | ErrorKind::DnsResolve { .. } => {
+                NoteDbError::Connection(err.into())
+            }
+            ErrorKind::Write(WriteFailure::WriteError(write))
+                if write.code == DUPLICATE_KEY =>
+            {
+                NoteDbError::Conflict(write.message.clone())
+            }
+            ErrorKind::InsertMany(insert)
+                if insert
+                    .write_errors
+                    .iter()
+                    .flatten()
+                    .any(|write| write.code == DUPLICATE_KEY) =>
+            {
+                NoteDbError::Conflict(err.to_string())
+            }
+            ErrorKind::BsonSerialization(_)
+            | ErrorKind::BsonDeserialization(_) => {
+                NoteDbError::Serialization(err.into())
+            }
+            _ => NoteDbError::Other(err.into()),
+        }
+    }
+}
+
+impl From<mongodb::bson::ser::Error> for NoteDbError {
+    fn from(err: mongodb::bson::ser::Error) -> Self {
+        NoteDbError::Serialization(err.into())
+    }
+}
+
+impl From<mongodb::bson::de::Error> for NoteDbError {
+    fn from(err: mongodb::bson::de::Error) -> Self {
+        NoteDbError::Serialization(err.into())
+    }
+}
+
+fn title_key(title: &str) -> String {
+    title.to_lowercase()
+}
+
+/// Restricts a query on notes to those of the owner or the organizations
+/// of the operation, and to its tenant.
+fn scoped(ctx: &OpContext, mut filter: Document) -> Document {
+    if let Some(owner) = ctx.owner() {
+        filter.insert(
+            "$or",
+            vec![
+                doc! { "owner": owner },
+                doc! { "org": { "$in": &ctx.orgs } },
+                doc! { "permissions.read": owner },
+                doc! { "permissions.write": owner },
+            ],
+        );
+    }
+    if let Some(tenant) = &ctx.tenant {
+        filter.insert("tenant", tenant);
+    }
+    filter
+}
+
+fn filter_document(filter: &NoteFilter) -> Document {
+    let mut document = Document::new();
+    if let Some(title) = &filter.title_contains {
+        document.insert(
+            "title",
+            Regex {
+                pattern: escape_regex(title),
+                options: "i".to_string(),
+            },
+        );
+    }
+    if let Some(tag) = &filter.tag {
+        document.insert("tags", tag);
+    }
+    if let Some(after) = filter.created_after {
+        document.insert(
+            "created_at",
+            doc! { "$gt": mongodb::bson::DateTime::from_chrono(after) },
+        );
+    }
+    if let Some(before) = filter.updated_before {
+        document.insert(
+            "updated_at",
+            doc! { "$lt": mongodb::bson::DateTime::from_chrono(before) },
+        );
     }
+    document
 }
 
+fn escape_regex(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if "\\^$.|?*+()[]{}".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+fn note_document(note: &Note) -> Result<Document, NoteDbError> {
+    // The raw serializer isn't human readable, so that timestamps are
+    // stored as BSON dates.
+    let mut document = mongodb::bson::to_raw_document_buf(note)?
+        .to_document()
+        .map_err(|err| NoteDbError::Serialization(err.into()))?;
+    document.insert(TITLE_KEY_FIELD, title_key(&note.title));
+    document.insert(TITLE_TRIGRAMS_FIELD, trigrams(&note.title));
+    check_size(&document)?;
+    Ok(document)
+}
+
+/// Rejects documents the server would refuse, before sending them. The
+/// server answers those with an error that doesn't tell the limit.
+fn check_size(document: &Document) -> Result<(), NoteDbError> {
+    if mongodb::bson::to_vec(document)?.len() > MAX_DOCUMENT_BYTES {
+        return Err(NoteDbError::TooLarge {
+            limit: MAX_DOCUMENT_BYTES,
+        });
+    }
+    Ok(())
+}
+
+// The remaining budget of the caller is passed to reads as `maxTimeMS`, so
+// that the server stops working on them once nobody waits for the result.
+async fn create_note_indexes(
+    db: &Database,
+) -> Result<(), mongodb::error::Error> {
+    let coll = db.collection::<Document>(NOTES_COLLECTION);
+    let index = IndexModel::builder()
+        .keys(doc! { TITLE_KEY_FIELD: 1 })
+        .build();
+    coll.create_index(index).await?;
+    let index = IndexModel::builder()
+        .keys(doc! { TITLE_TRIGRAMS_FIELD: 1 })
+        .build();
+    coll.create_index(index).await?;
+    backfill_title_keys(&coll).await?;
+    let index = IndexModel::builder().keys(doc! { "tags": 1 }).build();
+    coll.create_index(index).await?;
+    let index = IndexModel::builder().keys(doc! { "owner": 1 }).build();
+    coll.create_index(index).await?;
+    let index = IndexModel::builder().keys(doc! { "tenant": 1 }).build();
+    coll.create_index(index).await?;
+    let index = IndexModel::builder().keys(doc! { "org": 1 }).build();
+    coll.create_index(index).await?;
+    for field in ["permissions.read", "permissions.write"] {
+        let index = IndexModel::builder().keys(doc! { field: 1 }).build();
+        coll.create_index(index).await?;
+    }
+    let versions = db.collection::<Document>(VERSIONS_COLLECTION);
+    let index = IndexModel::builder()
+        .keys(doc! { "note_id": 1, "rev": 1 })
+        .options(IndexOptions::builder().unique(true).build())
+        .build();
+    versions.create_index(index).await?;
+    Ok(())
+}
+
+/// Adds the title key and trigrams to notes stored before they existed,
+/// which title suggestions wouldn't find otherwise. Computed here rather
+/// than in an update pipeline, the server lowercases ASCII letters only.
+async fn backfill_title_keys(
+    coll: &mongodb::Collection<Document>,
+) -> Result<(), mongodb::error::Error> {
+    let missing = doc! {
+        "$or": [
+            { TITLE_KEY_FIELD: null },
+            { TITLE_TRIGRAMS_FIELD: null },
+        ]
+    };
+    let mut cursor = coll
+        .find(missing)
+        .projection(doc! { "_id": 1, "title": 1 })
+        .await?;
+    while let Some(note) = cursor.try_next().await? {
+        let (Some(id), Ok(title)) = (note.get("_id"), note.get_str("title"))
+        else {
+            continue;
+        };
+        coll.update_one(
+            doc! { "_id": id.clone() },
+            doc! {
+                "$set": {
+                    TITLE_KEY_FIELD: title_key(title),
+                    TITLE_TRIGRAMS_FIELD: trigrams(title),
+                }
+            },
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+// Writes can't be limited server-side and are only abandoned client-side.
 #[async_trait]
 impl NoteDb for NoteMongoDb {
     async fn create_note(
         &self,
+        ctx: &OpContext,
         note: &Note,
-    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        let coll = self.db.collection::<Note>(NOTES_COLLECTION);
-        coll.insert_one(note).await?;
-        Ok(())
+    ) -> Result<(), NoteDbError> {
+        ctx.within_deadline(async {
+            let coll = self
+                .notes_db(ctx)
+                .await?
+                .collection::<Document>(NOTES_COLLECTION);
+            coll.insert_one(note_document(note)?).await?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn create_notes(
+        &self,
+        ctx: &OpContext,
+        notes: &[Note],
+    ) -> Result<(), NoteDbError> {
+        if notes.is_empty() {
+            return Ok(());
+        }
+        ctx.within_deadline(async {
+            let coll = self
+                .notes_db(ctx)
+                .await?
+                .collection::<Document>(NOTES_COLLECTION);
+            let documents = notes
+                .iter()
+                .map(note_document)
+                .collect::<Result<Vec<_>, _>>()?;
+            coll.insert_many(documents).await?;
+            Ok(())
+        })
+        .await
     }
 
     async fn get_note(
         &self,
+        ctx: &OpContext,
         id: &str,
-    ) -> Result<Option<Note>, Box<dyn std::error::Error + Send + Sync>> {
-        let coll = self.db.collection::<Note>(NOTES_COLLECTION);
-        let option = coll.find_one(doc! { "id": id }).await?;
-        Ok(option)
+    ) -> Result<Option<Note>, NoteDbError> {
+        ctx.within_deadline(async {
+            let coll = self
+                .notes_db(ctx)
+                .await?
+                .collection::<Note>(NOTES_COLLECTION);
+            let option = coll
+                .find_one(scoped(ctx, doc! { "id": id }))
+                .optional(ctx.remaining(), |find, max| find.max_time(max))
+                .optional(read_preference(ctx), |find, criteria| {
+                    find.selection_criteria(criteria)
+                })
+                .await?;
+            Ok(option)
+        })
+        .await
+    }
+
+    async fn get_notes(
+        &self,
+        ctx: &OpContext,
+        ids: &[String],
+    ) -> Result<Vec<Note>, NoteDbError> {
+        ctx.within_deadline(async {
+            let coll = self
+                .notes_db(ctx)
+                .await?
+                .collection::<Note>(NOTES_COLLECTION);
+            let mut cursor = coll
+                .find(scoped(ctx, doc! { "id": { "$in": ids } }))
+                .optional(ctx.remaining(), |find, max| find.max_time(max))
+                .optional(read_preference(ctx), |find, criteria| {
+                    find.selection_criteria(criteria)
+                })
+                .await?;
+            let _cursor = OPEN_DB_CURSORS.track();
+            let mut notes = Vec::new();
+            while let Some(note) = cursor.try_next().await? {
+                notes.push(note);
+            }
+            Ok(notes)
+        })
+        .await
     }
 
     async fn update_note(
         &self,
+        ctx: &OpContext,
         id: &str,
         note: &PatchNote,
-    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        let coll = self.db.collection::<Note>(NOTES_COLLECTION);
-        let filter = doc! { "id": id };
-        let update = doc! {
-            "$set": {
-                "title": &note.title,
-                "body": &note.body
-            }
-        };
-        coll.update_one(filter, update).await?;
+    ) -> Result<(), NoteDbError> {
+        let coll = self
+            .notes_db(ctx)
+            .await?
+            .collection::<Note>(NOTES_COLLECTION);
+        let mut filter = scoped(ctx, doc! { "id": id });
+        if let Some(version) = note.version {
+            // Notes stored before versions existed have no version field.
+            match version {
+                0 => filter.insert("version", Bson::Null),
+                _ => filter.insert("version", version as i64),
+            };
+        }
+        let mut set = Document::new();
+        if let Some(title) = &note.title {
+            set.insert("title", title);
+            set.insert(TITLE_KEY_FIELD, title_key(title));
+            set.insert(TITLE_TRIGRAMS_FIELD, trigrams(title));
+        }
+        if let Some(body) = &note.body {
+            set.insert("body", body);
+        }
+        if let Some(tags) = &note.tags {
+            set.insert("tags", tags);
+        }
+        if let Some(permissions) = &note.permissions {
+            set.insert("permissions", mongodb::bson::to_bson(permissions)?);
+        }
+        set.insert(
+            "updated_at",
+            mongodb::bson::DateTime::from_chrono(note.updated_at),
+        );
+        // Catches oversized fields early, the server still rejects updates
+        // that make the whole note too large.
+        let update = doc! { "$set": set, "$inc": { "version": 1_i64 } };
+        check_size(&update)?;
+        let res = ctx
+            .within_deadline(async {
+                Ok(coll.update_one(filter, update).await?)
+            })
+            .await?;
+        if res.matched_count == 0 {
+            return match self.get_note(ctx, id).await? {
+                Some(current) => {
+                    note.check_version(&current)?;
+                    Err(NoteDbError::Conflict(format!(
+                        "note {} changed concurrently",
+                        id
+                    )))
+                }
+                None => Err(NoteDbError::NotFound),
+            };
+        }
         Ok(())
     }
 
     async fn delete_note(
         &self,
+        ctx: &OpContext,
         id: &str,
-    ) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
-        let coll = self.db.collection::<Note>(NOTES_COLLECTION);
-        let filter = doc! { "id": id };
-        let res = coll.delete_one(filter).await?;
+    ) -> Result<bool, NoteDbError> {
+        let coll = self
+            .notes_db(ctx)
+            .await?
+            .collection::<Note>(NOTES_COLLECTION);
+        let filter = scoped(ctx, doc! { "id": id });
+        let res = ctx
+            .within_deadline(async { Ok(coll.delete_one(filter).await?) })
+            .await?;
         Ok(res.deleted_count > 0)
     }
 
     async fn list_notes(
         &self,
-    ) -> Result<Vec<Note>, Box<dyn std::error::Error + Send + Sync>> {
-        let coll = self.db.collection::<Note>(NOTES_COLLECTION);
+        ctx: &OpContext,
+        filter: &NoteFilter,
+        page: &Page,
+    ) -> Result<NotePage, NoteDbError> {
+        ctx.within_deadline(async {
+            let coll = self
+                .notes_db(ctx)
+                .await?
+                .collection::<Note>(NOTES_COLLECTION);
+            let filter = scoped(ctx, filter_document(filter));
+            let total = coll
+                .count_documents(filter.clone())
+                .optional(ctx.remaining(), |count, max| count.max_time(max))
+                .optional(read_preference(ctx), |count, criteria| {
+                    count.selection_criteria(criteria)
+                })
+                .await?;
+            let (limit, offset) = page.as_i64();
+            // The server reads a limit of 0 as no limit.
+            if limit == Some(0) {
+                return Ok(NotePage {
+                    notes: Vec::new(),
+                    total,
+                });
+            }
+            // Sort by insertion so that pages don't overlap.
+            let mut find = coll
+                .find(filter)
+                .sort(doc! { "_id": 1 })
+                .skip(offset as u64)
+                .optional(ctx.remaining(), |find, max| find.max_time(max))
+                .optional(read_preference(ctx), |find, criteria| {
+                    find.selection_criteria(criteria)
+                });
+            if let Some(limit) = limit {
+                find = find.limit(limit);
+            }
+            let mut cursor = find.await?;
+            let _cursor = OPEN_DB_CURSORS.track();
+            let mut notes = Vec::new();
+            while let Some(note) = cursor.try_next().await? {
+                notes.push(note);
+            }
+            Ok(NotePage { notes, total })
+        })
+        .await
+    }
+
+    async fn suggest_titles(
+        &self,
+        ctx: &OpContext,
+        prefix: &str,
+        limit: usize,
+    ) -> Result<Vec<NoteSuggestion>, NoteDbError> {
+        ctx.within_deadline(async {
+            let coll = self
+                .notes_db(ctx)
+                .await?
+                .collection::<NoteSuggestion>(NOTES_COLLECTION);
+            let filter = scoped(
+                ctx,
+                doc! {
+                    TITLE_KEY_FIELD: Regex {
+                        pattern: format!(
+                            "^{}",
+                            escape_regex(&title_key(prefix))
+                        ),
+                        options: String::new(),
+                    }
+                },
+            );
+            let mut cursor = coll
+                .find(filter)
+                .projection(doc! { "id": 1, "title": 1 })
+                .sort(doc! { TITLE_KEY_FIELD: 1 })
+                .limit(limit as i64)
+                .optional(ctx.remaining(), |find, max| find.max_time(max))
+                .await?;
+            let _cursor = OPEN_DB_CURSORS.track();
+            let mut suggestions = Vec::new();
+            while let Some(suggestion) = cursor.try_next().await? {
+                suggestions.push(suggestion);
+            }
+            let query = trigrams(prefix);
+            if suggestions.len() >= limit || query.is_empty() {
+                return Ok(suggestions);
+            }
+            // Counts the shared trigrams of notes with any of them, the
+            // most similar titles first. Prefix matches may come again.
+            let shared = (query.len() as f64 * SUGGESTION_SIMILARITY).ceil();
+            let pipeline = vec![
+                doc! {
+                    "$match": scoped(
+                        ctx,
+                        doc! { TITLE_TRIGRAMS_FIELD: { "$in": &query } },
+                    )
+                },
+                doc! {
+                    "$project": {
+                        "_id": 0,
+                        "id": 1,
+                        "title": 1,
+                        TITLE_KEY_FIELD: 1,
+                        "shared": {
+                            "$size": {
+                                "$setIntersection": [
+                                    format!("${}", TITLE_TRIGRAMS_FIELD),
+                                    &query,
+                                ]
+                            }
+                        },
+                    }
+                },
+                doc! { "$match": { "shared": { "$gte": shared as i64 } } },
+                doc! { "$sort": { "shared": -1, TITLE_KEY_FIELD: 1 } },
+                doc! { "$limit": limit as i64 },
+            ];
+            let mut cursor = coll
+                .aggregate(pipeline)
+                .optional(ctx.remaining(), |aggregate, max| {
+                    aggregate.max_time(max)
+                })
+                .await?;
+            while let Some(similar) = cursor.try_next().await? {
+                suggestions.push(mongodb::bson::from_document(similar)?);
+            }
+            Ok(rank_suggestions(prefix, suggestions, limit))
+        })
+        .await
+    }
+
+    /// Looks the title up in the `title_key` index.
+    async fn find_by_title(
+        &self,
+        ctx: &OpContext,
+        title: &str,
+    ) -> Result<Vec<NoteSuggestion>, NoteDbError> {
+        ctx.within_deadline(async {
+            let coll = self
+                .notes_db(ctx)
+                .await?
+                .collection::<NoteSuggestion>(NOTES_COLLECTION);
+            let mut cursor = coll
+                .find(scoped(ctx, doc! { TITLE_KEY_FIELD: title_key(title) }))
+                .projection(doc! { "id": 1, "title": 1 })
+                .optional(ctx.remaining(), |find, max| find.max_time(max))
+                .await?;
+            let _cursor = OPEN_DB_CURSORS.track();
+            let mut notes = Vec::new();
+            while let Some(note) = cursor.try_next().await? {
+                notes.push(note);
+            }
+            Ok(notes)
+        })
+        .await
+    }
+
+    /// Numbers revisions by counting the existing ones. The unique index
+    /// on `note_id` and `rev` turns concurrent recordings into conflicts.
+    async fn record_revision(
+        &self,
+        ctx: &OpContext,
+        note: &Note,
+    ) -> Result<u64, NoteDbError> {
+        ctx.within_deadline(async {
+            let coll = self
+                .notes_db(ctx)
+                .await?
+                .collection::<NoteRevision>(VERSIONS_COLLECTION);
+            let rev = coll
+                .count_documents(doc! { "note_id": &note.id })
+                .optional(ctx.remaining(), |count, max| count.max_time(max))
+                .await?
+                + 1;
+            coll.insert_one(NoteRevision::new(note, rev)).await?;
+            Ok(rev)
+        })
+        .await
+    }
+
+    async fn list_revisions(
+        &self,
+        ctx: &OpContext,
+        note_id: &str,
+    ) -> Result<Vec<NoteRevision>, NoteDbError> {
+        ctx.within_deadline(async {
+            let coll = self
+                .notes_db(ctx)
+                .await?
+                .collection::<NoteRevision>(VERSIONS_COLLECTION);
+            let mut cursor = coll
+                .find(doc! { "note_id": note_id })
+                .sort(doc! { "rev": 1 })
+                .optional(ctx.remaining(), |find, max| find.max_time(max))
+                .await?;
+            let _cursor = OPEN_DB_CURSORS.track();
+            let mut revisions = Vec::new();
+            while let Some(revision) = cursor.try_next().await? {
+                revisions.push(revision);
+            }
+            Ok(revisions)
+        })
+        .await
+    }
+
+    async fn get_revision(
+        &self,
+        ctx: &OpContext,
+        note_id: &str,
+        rev: u64,
+    ) -> Result<Option<NoteRevision>, NoteDbError> {
+        ctx.within_deadline(async {
+            let coll = self
+                .notes_db(ctx)
+                .await?
+                .collection::<NoteRevision>(VERSIONS_COLLECTION);
+            let revision = coll
+                .find_one(doc! { "note_id": note_id, "rev": rev as i64 })
+                .optional(ctx.remaining(), |find, max| find.max_time(max))
+                .await?;
+            Ok(revision)
+        })
+        .await
+    }
+}
+
+fn bson_datetime(datetime: DateTime<Utc>) -> mongodb::bson::DateTime {
+    mongodb::bson::DateTime::from_millis(datetime.timestamp_millis())
+}
+
+#[async_trait]
+impl IdempotencyDb for NoteMongoDb {
+    async fn record_key(
+        &self,
+        record: &IdempotencyRecord,
+    ) -> Result<(), NoteDbError> {
+        let coll = self.db.collection::<Document>(IDEMPOTENCY_COLLECTION);
+        coll.replace_one(
+            doc! { "_id": &record.key },
+            doc! {
+                "_id": &record.key,
+                "note_id": &record.note_id,
+                "expires_at": bson_datetime(record.expires_at),
+            },
+        )
+        .upsert(true)
+        .await?;
+        Ok(())
+    }
+
+    /// Filters on the expiry as well, since the server removes expired
+    /// keys only periodically.
+    async fn find_key(
+        &self,
+        key: &str,
+        now: DateTime<Utc>,
+    ) -> Result<Option<String>, NoteDbError> {
+        let coll = self.db.collection::<Document>(IDEMPOTENCY_COLLECTION);
+        let record = coll
+            .find_one(doc! {
+                "_id": key,
+                "expires_at": { "$gt": bson_datetime(now) },
+            })
+            .await?;
+        Ok(record.and_then(|record| {
+            record.get_str("note_id").ok().map(str::to_string)
+        }))
+    }
+}
+
+#[async_trait]
+impl ApiKeyDb for NoteMongoDb {
+    async fn create_api_key(
+        &self,
+        api_key: &ApiKey,
+    ) -> Result<(), NoteDbError> {
+        let coll = self.db.collection::<ApiKey>(API_KEYS_COLLECTION);
+        coll.insert_one(api_key).await?;
+        Ok(())
+    }
+
+    async fn find_api_key(
+        &self,
+        key_hash: &str,
+    ) -> Result<Option<ApiKey>, NoteDbError> {
+        let coll = self.db.collection::<ApiKey>(API_KEYS_COLLECTION);
+        Ok(coll.find_one(doc! { "key_hash": key_hash }).await?)
+    }
+
+    async fn list_api_keys(&self) -> Result<Vec<ApiKey>, NoteDbError> {
+        let coll = self.db.collection::<ApiKey>(API_KEYS_COLLECTION);
+        let mut cursor = coll.find(doc! {}).await?;
+        let _cursor = OPEN_DB_CURSORS.track();
+        let mut api_keys = Vec::new();
+        while let Some(api_key) = cursor.try_next().await? {
+            api_keys.push(api_key);
+        }
+        Ok(api_keys)
+    }
+
+    async fn list_api_keys_of(
+        &self,
+        subject: &str,
+    ) -> Result<Vec<ApiKey>, NoteDbError> {
+        let coll = self.db.collection::<ApiKey>(API_KEYS_COLLECTION);
+        let mut cursor = coll.find(doc! { "subject": subject }).await?;
+        let _cursor = OPEN_DB_CURSORS.track();
+        let mut api_keys = Vec::new();
+        while let Some(api_key) = cursor.try_next().await? {
+            api_keys.push(api_key);
+        }
+        Ok(api_keys)
+    }
+
+    async fn delete_api_key(&self, id: &str) -> Result<bool, NoteDbError> {
+        let coll = self.db.collection::<ApiKey>(API_KEYS_COLLECTION);
+        let res = coll.delete_one(doc! { "id": id }).await?;
+        Ok(res.deleted_count > 0)
+    }
+}
+
+#[async_trait]
+impl OrgDb for NoteMongoDb {
+    async fn create_org(&self, org: &Organization) -> Result<(), NoteDbError> {
+        let coll = self.db.collection::<Organization>(ORGS_COLLECTION);
+        coll.insert_one(org).await?;
+        Ok(())
+    }
+
+    async fn get_org(
+        &self,
+        id: &str,
+    ) -> Result<Option<Organization>, NoteDbError> {
+        let coll = self.db.collection::<Organization>(ORGS_COLLECTION);
+        Ok(coll.find_one(doc! { "id": id }).await?)
+    }
+
+    async fn list_orgs(
+        &self,
+        member: &str,
+    ) -> Result<Vec<Organization>, NoteDbError> {
+        let coll = self.db.collection::<Organization>(ORGS_COLLECTION);
+        let mut cursor = coll.find(doc! { "members": member }).await?;
+        let _cursor = OPEN_DB_CURSORS.track();
+        let mut orgs = Vec::new();
+        while let Some(org) = cursor.try_next().await? {
+            orgs.push(org);
+        }
+        Ok(orgs)
+    }
+
+    async fn add_member(
+        &self,
+        id: &str,
+        member: &str,
+    ) -> Result<bool, NoteDbError> {
+        let coll = self.db.collection::<Organization>(ORGS_COLLECTION);
+        let res = coll
+            .update_one(
+                doc! { "id": id },
+                doc! { "$addToSet": { "members": member } },
+            )
+            .await?;
+        Ok(res.matched_count > 0)
+    }
+
+    async fn remove_member(
+        &self,
+        id: &str,
+        member: &str,
+    ) -> Result<bool, NoteDbError> {
+        let coll = self.db.collection::<Organization>(ORGS_COLLECTION);
+        let res = coll
+            .update_one(
+                doc! { "id": id },
+                doc! { "$pull": { "members": member } },
+            )
+            .await?;
+        Ok(res.matched_count > 0)
+    }
+}
+
+#[async_trait]
+impl ShareDb for NoteMongoDb {
+    async fn create_share(&self, share: &Share) -> Result<(), NoteDbError> {
+        let coll = self.db.collection::<Share>(SHARES_COLLECTION);
+        coll.insert_one(share).await?;
+        Ok(())
+    }
+
+    async fn find_share(
+        &self,
+        token_hash: &str,
+    ) -> Result<Option<Share>, NoteDbError> {
+        let coll = self.db.collection::<Share>(SHARES_COLLECTION);
+        Ok(coll.find_one(doc! { "token_hash": token_hash }).await?)
+    }
+
+    async fn delete_share(
+        &self,
+        note_id: &str,
+        id: &str,
+    ) -> Result<bool, NoteDbError> {
+        let coll = self.db.collection::<Share>(SHARES_COLLECTION);
+        let res = coll
+            .delete_one(doc! { "id": id, "note_id": note_id })
+            .await?;
+        Ok(res.deleted_count > 0)
+    }
+}
+
+#[async_trait]
+impl AnnouncementDb for NoteMongoDb {
+    async fn create_announcement(
+        &self,
+        announcement: &Announcement,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let coll = self.db.collection::<Announcement>(ANNOUNCEMENTS_COLLECTION);
+        coll.insert_one(announcement).await?;
+        Ok(())
+    }
+
+    async fn delete_announcement(
+        &self,
+        id: &str,
+    ) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+        let coll = self.db.collection::<Announcement>(ANNOUNCEMENTS_COLLECTION);
+        let res = coll.delete_one(doc! { "id": id }).await?;
+        Ok(res.deleted_count > 0)
+    }
+
+    async fn list_announcements(
+        &self,
+    ) -> Result<Vec<Announcement>, Box<dyn std::error::Error + Send + Sync>>
+    {
+        let coll = self.db.collection::<Announcement>(ANNOUNCEMENTS_COLLECTION);
         let mut cursor = coll.find(doc! {}).await?;
-        let mut notes = Vec::new();
-        while let Some(note) = cursor.try_next().await? {
-            notes.push(note);
+        let _cursor = OPEN_DB_CURSORS.track();
+        let mut announcements = Vec::new();
+        while let Some(announcement) = cursor.try_next().await? {
+            announcements.push(announcement);
         }
-        Ok(notes)
+        Ok(announcements)
     }
 }
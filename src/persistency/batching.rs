@@ -0,0 +1,268 @@
+//! A `NoteDb` combinator for high-throughput ingest: created notes are
+//! buffered and written in bulk once a batch is full or a delay has passed.
+//! All other operations go straight to the wrapped backend.
+
+use std::{collections::BTreeMap, str::FromStr, sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use tokio::sync::{mpsc, oneshot};
+
+use crate::context::OpContext;
+use crate::jobs::Job;
+use crate::metrics::{
+    GaugeGuard, BATCH_FAILED_FLUSHES, BATCH_FLUSHES, BATCH_PENDING_WRITES,
+};
+use crate::notes::{
+    Note, NoteDb, NoteDbError, NoteFilter, NotePage, NoteRevision,
+    NoteSuggestion, Page, PatchNote,
+};
+
+/// When `create_note` returns.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Acknowledge {
+    /// After the batch containing the note has been written.
+    Durable,
+    /// As soon as the note is queued. Faster, but queued notes are not
+    /// readable until written, are lost if the process dies, and write
+    /// failures are only logged.
+    Queued,
+}
+
+impl FromStr for Acknowledge {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "durable" => Ok(Acknowledge::Durable),
+            "queued" => Ok(Acknowledge::Queued),
+            _ => Err("expected durable or queued".to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BatchConfig {
+    pub max_batch: usize,
+    pub max_delay: Duration,
+    /// Creates beyond this many queued notes wait for room, which pushes
+    /// back on clients instead of growing memory.
+    pub queue_capacity: usize,
+    pub acknowledge: Acknowledge,
+}
+
+impl Default for BatchConfig {
+    fn default() -> Self {
+        BatchConfig {
+            max_batch: 100,
+            max_delay: Duration::from_millis(20),
+            queue_capacity: 1000,
+            acknowledge: Acknowledge::Durable,
+        }
+    }
+}
+
+type Ack = oneshot::Sender<Result<(), String>>;
+// A queued note with the acknowledgement it waits for, the request and
+// tenant it was created by and its share of `BATCH_PENDING_WRITES`.
+type Queued = (
+    Note,
+    Option<Ack>,
+    Option<String>,
+    Option<String>,
+    GaugeGuard,
+);
+
+pub struct BatchingNoteDb<D: NoteDb + ?Sized> {
+    inner: Arc<D>,
+    queue: mpsc::Sender<Queued>,
+    acknowledge: Acknowledge,
+}
+
+impl<D: NoteDb + ?Sized + 'static> BatchingNoteDb<D> {
+    /// Creates the batcher and spawns its flush task, so this must be
+    /// called from within a tokio runtime.
+    pub fn new(inner: Arc<D>, config: BatchConfig) -> Self {
+        let (queue, mut queued) =
+            mpsc::channel::<Queued>(config.queue_capacity.max(1));
+
+        let worker_inner = inner.clone();
+        let max_batch = config.max_batch.max(1);
+        tokio::spawn(async move {
+            while let Some(first) = queued.recv().await {
+                let mut batch = vec![first];
+                let deadline = tokio::time::sleep(config.max_delay);
+                tokio::pin!(deadline);
+                while batch.len() < max_batch {
+                    tokio::select! {
+                        next = queued.recv() => match next {
+                            Some(next) => batch.push(next),
+                            None => break,
+                        },
+                        _ = &mut deadline => break,
+                    }
+                }
+                Job::new("batch_write")
+                    .run(flush(worker_inner.as_ref(), batch))
+                    .await;
+            }
+        });
+
+        BatchingNoteDb {
+            inner,
+            queue,
+            acknowledge: config.acknowledge,
+        }
+    }
+}
+
+/// Writes `batch` with one bulk create per tenant, so that every note lands
+/// in the database of its tenant.
+async fn flush<D: NoteDb + ?Sized>(inner: &D, batch: Vec<Queued>) {
+    let mut tenants: BTreeMap<Option<String>, (Vec<Note>, Vec<Ack>)> =
+        BTreeMap::new();
+    let mut request_ids = Vec::new();
+    // The notes count as pending until the whole batch is written.
+    let mut pending = Vec::new();
+    for (note, ack, request_id, tenant, guard) in batch {
+        pending.push(guard);
+        let (notes, acks) = tenants.entry(tenant).or_default();
+        notes.push(note);
+        acks.extend(ack);
+        request_ids.extend(request_id);
+    }
+    // A batch serves many requests, so they are logged instead of linked.
+    tracing::debug!(
+        "flush batch for {} tenants for requests {:?}",
+        tenants.len(),
+        request_ids
+    );
+    for (tenant, (notes, acks)) in tenants {
+        let res = inner
+            .create_notes(&OpContext::for_tenant(tenant), &notes)
+            .await
+            .map_err(|err| {
+                BATCH_FAILED_FLUSHES.increment();
+                tracing::error!(
+                    "unable to write batch of {} notes: {}",
+                    notes.len(),
+                    err
+                );
+                err.to_string()
+            });
+        BATCH_FLUSHES.increment();
+        for ack in acks {
+            // The creator may have given up waiting.
+            let _ = ack.send(res.clone());
+        }
+    }
+}
+
+#[async_trait]
+impl<D: NoteDb + ?Sized + 'static> NoteDb for BatchingNoteDb<D> {
+    async fn create_note(
+        &self,
+        ctx: &OpContext,
+        note: &Note,
+    ) -> Result<(), NoteDbError> {
+        let (ack, acked) = match self.acknowledge {
+            Acknowledge::Durable => {
+                let (ack, acked) = oneshot::channel();
+                (Some(ack), Some(acked))
+            }
+            Acknowledge::Queued => (None, None),
+        };
+        let queued = (
+            note.clone(),
+            ack,
+            ctx.request_id.clone(),
+            ctx.tenant.clone(),
+            BATCH_PENDING_WRITES.track(),
+        );
+        if self.queue.send(queued).await.is_err() {
+            return Err("batch writer is gone".into());
+        }
+        let Some(acked) = acked else {
+            return Ok(());
+        };
+        match acked.await {
+            Ok(res) => res.map_err(Into::into),
+            Err(_) => Err("batch writer is gone".into()),
+        }
+    }
+
+    async fn get_note(
+        &self,
+        ctx: &OpContext,
+        id: &str,
+    ) -> Result<Option<Note>, NoteDbError> {
+        self.inner.get_note(ctx, id).await
+    }
+
+    async fn get_notes(
+        &self,
+        ctx: &OpContext,
+        ids: &[String],
+    ) -> Result<Vec<Note>, NoteDbError> {
+        self.inner.get_notes(ctx, ids).await
+    }
+
+    async fn update_note(
+        &self,
+        ctx: &OpContext,
+        id: &str,
+        note: &PatchNote,
+    ) -> Result<(), NoteDbError> {
+        self.inner.update_note(ctx, id, note).await
+    }
+
+    async fn delete_note(
+        &self,
+        ctx: &OpContext,
+        id: &str,
+    ) -> Result<bool, NoteDbError> {
+        self.inner.delete_note(ctx, id).await
+    }
+
+    async fn list_notes(
+        &self,
+        ctx: &OpContext,
+        filter: &NoteFilter,
+        page: &Page,
+    ) -> Result<NotePage, NoteDbError> {
+        self.inner.list_notes(ctx, filter, page).await
+    }
+
+    async fn suggest_titles(
+        &self,
+        ctx: &OpContext,
+        prefix: &str,
+        limit: usize,
+    ) -> Result<Vec<NoteSuggestion>, NoteDbError> {
+        self.inner.suggest_titles(ctx, prefix, limit).await
+    }
+
+    async fn record_revision(
+        &self,
+        ctx: &OpContext,
+        note: &Note,
+    ) -> Result<u64, NoteDbError> {
+        self.inner.record_revision(ctx, note).await
+    }
+
+    async fn list_revisions(
+        &self,
+        ctx: &OpContext,
+        note_id: &str,
+    ) -> Result<Vec<NoteRevision>, NoteDbError> {
+        self.inner.list_revisions(ctx, note_id).await
+    }
+
+    async fn get_revision(
+        &self,
+        ctx: &OpContext,
+        note_id: &str,
+        rev: u64,
+    ) -> Result<Option<NoteRevision>, NoteDbError> {
+        self.inner.get_revision(ctx, note_id, rev).await
+    }
+}
@@ -0,0 +1,720 @@
+//! Notes stored as CouchDB documents, using CouchDB's `_rev` for
+//! optimistic concurrency and Mango queries for filtering.
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use reqwest::{Method, StatusCode, Url};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use super::escape_regex;
+use crate::announcements::{Announcement, AnnouncementDb};
+use crate::api_keys::{ApiKey, ApiKeyDb};
+use crate::context::OpContext;
+use crate::idempotency::{IdempotencyDb, IdempotencyRecord};
+use crate::notes::{
+    rank_suggestions, Note, NoteDb, NoteDbError, NoteFilter, NotePage,
+    NoteRevision, NoteSuggestion, Page, PatchNote,
+};
+use crate::orgs::{OrgDb, Organization};
+use crate::shares::{Share, ShareDb};
+
+const NOTE_TYPE: &str = "note";
+const ANNOUNCEMENT_TYPE: &str = "announcement";
+const IDEMPOTENCY_KEY_TYPE: &str = "idempotency_key";
+const API_KEY_TYPE: &str = "api_key";
+const ORG_TYPE: &str = "org";
+const SHARE_TYPE: &str = "share";
+
+// Revision conflicts are retried this often before giving up.
+const CONFLICT_RETRIES: usize = 10;
+const FIND_PAGE_SIZE: usize = 1000;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CouchDoc<T> {
+    #[serde(rename = "_id")]
+    id: String,
+    #[serde(rename = "_rev", default, skip_serializing_if = "Option::is_none")]
+    rev: Option<String>,
+    #[serde(rename = "type")]
+    doc_type: String,
+    #[serde(flatten)]
+    value: T,
+}
+
+#[derive(Debug, Deserialize)]
+struct FindResponse<T> {
+    docs: Vec<T>,
+    bookmark: Option<String>,
+}
+
+impl From<reqwest::Error> for NoteDbError {
+    fn from(err: reqwest::Error) -> Self {
+        if err.is_connect() || err.is_timeout() {
+            NoteDbError::Connection(err.into())
+        } else if err.is_decode() {
+            NoteDbError::Serialization(err.into())
+        } else {
+            NoteDbError::Other(err.into())
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct NoteCouchDb {
+    client: reqwest::Client,
+    db_url: Url,
+}
+
+impl NoteCouchDb {
+    pub fn new(db_url: Url) -> NoteCouchDb {
+        NoteCouchDb {
+            client: reqwest::Client::new(),
+            db_url,
+        }
+    }
+
+    /// Connects to `couchdb://[user:password@]host:port/<database>`, or
+    /// `couchdbs://` for HTTPS.
+    pub fn from_uri(
+        uri: &str,
+    ) -> Result<NoteCouchDb, Box<dyn std::error::Error + Send + Sync>> {
+        let url = if let Some(rest) = uri.strip_prefix("couchdb://") {
+            format!("http://{}", rest)
+        } else if let Some(rest) = uri.strip_prefix("couchdbs://") {
+            format!("https://{}", rest)
+        } else {
+            return Err(format!("not a couchdb uri: {}", uri).into());
+        };
+        Ok(NoteCouchDb::new(Url::parse(&url)?))
+    }
+
+    /// Creates the database and the Mango index used for listing if they
+    /// don't exist yet.
+    pub async fn ensure_database(
+        &self,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let resp = self.client.put(self.db_url.clone()).send().await?;
+        if !resp.status().is_success()
+            && resp.status() != StatusCode::PRECONDITION_FAILED
+        {
+            return Err(format!(
+                "unable to create database: {}",
+                resp.status()
+            )
+            .into());
+        }
+        let index = json!({
+            "index": { "fields": ["type", "title"] },
+            "name": "type-title",
+        });
+        self.send(Method::POST, self.url(&["_index"]), Some(&index))
+            .await?;
+        Ok(())
+    }
+
+    fn url(&self, segments: &[&str]) -> Url {
+        let mut url = self.db_url.clone();
+        if let Ok(mut path) = url.path_segments_mut() {
+            path.pop_if_empty().extend(segments);
+        }
+        url
+    }
+
+    async fn send(
+        &self,
+        method: Method,
+        url: Url,
+        body: Option<&Value>,
+    ) -> Result<reqwest::Response, NoteDbError> {
+        let mut request = self.client.request(method, url);
+        if let Some(body) = body {
+            request = request
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .body(serde_json::to_vec(body)?);
+        }
+        let resp = request.send().await?;
+        match resp.status() {
+            status if status.is_success() => Ok(resp),
+            StatusCode::NOT_FOUND | StatusCode::CONFLICT => Ok(resp),
+            status => {
+                let url = resp.url().clone();
+                let text = resp.text().await.unwrap_or_default();
+                Err(format!(
+                    "couchdb responded {} for {}: {}",
+                    status, url, text
+                )
+                .into())
+            }
+        }
+    }
+
+    async fn get_doc<T: DeserializeOwned>(
+        &self,
+        id: &str,
+        doc_type: &str,
+    ) -> Result<Option<CouchDoc<T>>, NoteDbError> {
+        let resp = self.send(Method::GET, self.url(&[id]), None).await?;
+        if resp.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let doc: Value = serde_json::from_slice(&resp.bytes().await?)?;
+        if doc["type"] != doc_type {
+            return Ok(None);
+        }
+        Ok(Some(serde_json::from_value(doc)?))
+    }
+
+    /// Writes a document, returning false on a revision conflict.
+    async fn put_doc<T: Serialize>(
+        &self,
+        doc: &CouchDoc<T>,
+    ) -> Result<bool, NoteDbError> {
+        let body = serde_json::to_value(doc)?;
+        let resp = self
+            .send(Method::PUT, self.url(&[&doc.id]), Some(&body))
+            .await?;
+        Ok(resp.status() != StatusCode::CONFLICT)
+    }
+
+    /// Deletes a document of the given type, returning false if there is
+    /// none.
+    async fn delete_doc(
+        &self,
+        id: &str,
+        doc_type: &str,
+    ) -> Result<bool, NoteDbError> {
+        for _ in 0..CONFLICT_RETRIES {
+            let Some(doc) = self.get_doc::<Value>(id, doc_type).await? else {
+                return Ok(false);
+            };
+            let mut url = self.url(&[id]);
+            url.query_pairs_mut()
+                .append_pair("rev", doc.rev.as_deref().unwrap_or_default());
+            let resp = self.send(Method::DELETE, url, None).await?;
+            match resp.status() {
+                StatusCode::CONFLICT => continue,
+                StatusCode::NOT_FOUND => return Ok(false),
+                _ => return Ok(true),
+            }
+        }
+        Err(NoteDbError::Conflict(format!(
+            "too many conflicts deleting {}",
+            id
+        )))
+    }
+
+    async fn find<T: DeserializeOwned>(
+        &self,
+        selector: Value,
+        page: &Page,
+    ) -> Result<Vec<T>, NoteDbError> {
+        self.find_fields(selector, None, page).await
+    }
+
+    async fn count(&self, selector: Value) -> Result<u64, NoteDbError> {
+        let ids: Vec<Value> = self
+            .find_fields(selector, Some(&["_id"]), &Page::default())
+            .await?;
+        Ok(ids.len() as u64)
+    }
+
+    /// Runs a Mango query, following bookmarks until the page is full.
+    async fn find_fields<T: DeserializeOwned>(
+        &self,
+        selector: Value,
+        fields: Option<&[&str]>,
+        page: &Page,
+    ) -> Result<Vec<T>, NoteDbError> {
+        let mut found = Vec::new();
+        let mut remaining = page.limit.map_or(usize::MAX, |l| l as usize);
+        let mut query = json!({
+            "selector": selector,
+            "skip": page.as_i64().1,
+        });
+        if let Some(fields) = fields {
+            query["fields"] = json!(fields);
+        }
+        while remaining > 0 {
+            let batch = remaining.min(FIND_PAGE_SIZE);
+            query["limit"] = json!(batch);
+            let resp = self
+                .send(Method::POST, self.url(&["_find"]), Some(&query))
+                .await?;
+            let result: FindResponse<T> =
+                serde_json::from_slice(&resp.bytes().await?)?;
+            let result_len = result.docs.len();
+            found.extend(result.docs);
+            if result_len < batch {
+                break;
+            }
+            remaining -= result_len;
+            // The bookmark already accounts for the skipped documents.
+            query["skip"] = json!(0);
+            query["bookmark"] = json!(result.bookmark);
+        }
+        Ok(found)
+    }
+}
+
+/// Restricts a selector on notes to those of the owner, shared with them
+/// or with the organizations of the operation, and to its tenant.
+fn scoped(ctx: &OpContext, mut selector: Value) -> Value {
+    if let Some(owner) = ctx.owner() {
+        selector["$or"] = json!([
+            { "owner": owner },
+            { "org": { "$in": ctx.orgs } },
+            { "permissions.read": { "$elemMatch": { "$eq": owner } } },
+            { "permissions.write": { "$elemMatch": { "$eq": owner } } },
+        ]);
+    }
+    if let Some(tenant) = &ctx.tenant {
+        selector["tenant"] = json!(tenant);
+    }
+    selector
+}
+
+fn note_selector(filter: &NoteFilter) -> Value {
+    let mut selector = json!({ "type": NOTE_TYPE });
+    if let Some(title) = &filter.title_contains {
+        selector["title"] =
+            json!({ "$regex": format!("(?i){}", escape_regex(title)) });
+    }
+    if let Some(tag) = &filter.tag {
+        selector["tags"] = json!({ "$elemMatch": { "$eq": tag } });
+    }
+    // Timestamps are stored as RFC 3339 strings, which only sort like the
+    // times they stand for down to the second. The selector narrows to the
+    // seconds in range and `list_notes` checks the rest.
+    if let Some(after) = filter.created_after {
+        selector["created_at"] = json!({ "$gte": whole_seconds(after) });
+    }
+    if let Some(before) = filter.updated_before {
+        let next = before + chrono::Duration::seconds(1);
+        selector["updated_at"] = json!({ "$lt": whole_seconds(next) });
+    }
+    selector
+}
+
+fn whole_seconds(time: DateTime<Utc>) -> String {
+    time.format("%Y-%m-%dT%H:%M:%S").to_string()
+}
+
+#[async_trait]
+impl NoteDb for NoteCouchDb {
+    async fn create_note(
+        &self,
+        _ctx: &OpContext,
+        note: &Note,
+    ) -> Result<(), NoteDbError> {
+        let doc = CouchDoc {
+            id: note.id.clone(),
+            rev: None,
+            doc_type: NOTE_TYPE.to_string(),
+            value: note,
+        };
+        if !self.put_doc(&doc).await? {
+            return Err(format!("note {} already exists", note.id).into());
+        }
+        Ok(())
+    }
+
+    async fn get_note(
+        &self,
+        ctx: &OpContext,
+        id: &str,
+    ) -> Result<Option<Note>, NoteDbError> {
+        let doc = self.get_doc::<Note>(id, NOTE_TYPE).await?;
+        Ok(doc.map(|doc| doc.value).filter(|note| ctx.can_access(note)))
+    }
+
+    async fn update_note(
+        &self,
+        ctx: &OpContext,
+        id: &str,
+        note: &PatchNote,
+    ) -> Result<(), NoteDbError> {
+        for _ in 0..CONFLICT_RETRIES {
+            let Some(mut doc) = self
+                .get_doc::<Note>(id, NOTE_TYPE)
+                .await?
+                .filter(|doc| ctx.can_access(&doc.value))
+            else {
+                return Err(NoteDbError::NotFound);
+            };
+            note.apply_to(&mut doc.value)?;
+            if self.put_doc(&doc).await? {
+                return Ok(());
+            }
+            tracing::debug!("retry update of note {} after conflict", id);
+        }
+        Err(NoteDbError::Conflict(format!(
+            "too many conflicts updating note {}",
+            id
+        )))
+    }
+
+    async fn delete_note(
+        &self,
+        ctx: &OpContext,
+        id: &str,
+    ) -> Result<bool, NoteDbError> {
+        let scoped = ctx.owner().is_some() || ctx.tenant.is_some();
+        if scoped && self.get_note(ctx, id).await?.is_none() {
+            return Ok(false);
+        }
+        self.delete_doc(id, NOTE_TYPE).await
+    }
+
+    async fn list_notes(
+        &self,
+        ctx: &OpContext,
+        filter: &NoteFilter,
+        page: &Page,
+    ) -> Result<NotePage, NoteDbError> {
+        let selector = scoped(ctx, note_selector(filter));
+        if filter.created_after.is_some() || filter.updated_before.is_some() {
+            let docs: Vec<CouchDoc<Note>> =
+                self.find(selector, &Page::default()).await?;
+            let notes: Vec<Note> = docs
+                .into_iter()
+                .map(|doc| doc.value)
+                .filter(|note| filter.matches(note))
+                .collect();
+            return Ok(NotePage {
+                total: notes.len() as u64,
+                notes: page.slice(notes),
+            });
+        }
+        let total = self.count(selector.clone()).await?;
+        let docs: Vec<CouchDoc<Note>> = self.find(selector, page).await?;
+        Ok(NotePage {
+            notes: docs.into_iter().map(|doc| doc.value).collect(),
+            total,
+        })
+    }
+
+    /// Mango selectors can't compare trigrams, so all titles in scope are
+    /// ranked here.
+    async fn suggest_titles(
+        &self,
+        ctx: &OpContext,
+        prefix: &str,
+        limit: usize,
+    ) -> Result<Vec<NoteSuggestion>, NoteDbError> {
+        let selector = scoped(ctx, json!({ "type": NOTE_TYPE }));
+        let docs: Vec<CouchDoc<NoteSuggestion>> =
+            self.find(selector, &Page::default()).await?;
+        Ok(rank_suggestions(
+            prefix,
+            docs.into_iter().map(|doc| doc.value),
+            limit,
+        ))
+    }
+
+    async fn record_revision(
+        &self,
+        _ctx: &OpContext,
+        _note: &Note,
+    ) -> Result<u64, NoteDbError> {
+        Err(NoteDbError::Unsupported("note revisions"))
+    }
+
+    async fn list_revisions(
+        &self,
+        _ctx: &OpContext,
+        _note_id: &str,
+    ) -> Result<Vec<NoteRevision>, NoteDbError> {
+        Err(NoteDbError::Unsupported("note revisions"))
+    }
+}
+
+// Keys are client supplied, so they get a prefix to stay clear of note
+// ids and of CouchDB's reserved `_` ids.
+fn idempotency_doc_id(key: &str) -> String {
+    format!("idempotency:{}", key)
+}
+
+/// Expired documents are replaced when the key is reused, nothing removes
+/// them otherwise.
+#[async_trait]
+impl IdempotencyDb for NoteCouchDb {
+    async fn record_key(
+        &self,
+        record: &IdempotencyRecord,
+    ) -> Result<(), NoteDbError> {
+        let id = idempotency_doc_id(&record.key);
+        for _ in 0..CONFLICT_RETRIES {
+            let current =
+                self.get_doc::<Value>(&id, IDEMPOTENCY_KEY_TYPE).await?;
+            let doc = CouchDoc {
+                id: id.clone(),
+                rev: current.and_then(|doc| doc.rev),
+                doc_type: IDEMPOTENCY_KEY_TYPE.to_string(),
+                value: record,
+            };
+            if self.put_doc(&doc).await? {
+                return Ok(());
+            }
+        }
+        Err(NoteDbError::Conflict(format!(
+            "too many conflicts recording idempotency key {}",
+            record.key
+        )))
+    }
+
+    async fn find_key(
+        &self,
+        key: &str,
+        now: DateTime<Utc>,
+    ) -> Result<Option<String>, NoteDbError> {
+        let doc = self
+            .get_doc::<IdempotencyRecord>(
+                &idempotency_doc_id(key),
+                IDEMPOTENCY_KEY_TYPE,
+            )
+            .await?;
+        Ok(doc
+            .filter(|doc| now < doc.value.expires_at)
+            .map(|doc| doc.value.note_id))
+    }
+}
+
+// Keeps key ids, which are generated like note ids, apart from those.
+fn api_key_doc_id(id: &str) -> String {
+    format!("api_key:{}", id)
+}
+
+fn org_doc_id(id: &str) -> String {
+    format!("org:{}", id)
+}
+
+fn share_doc_id(id: &str) -> String {
+    format!("share:{}", id)
+}
+
+#[async_trait]
+impl ApiKeyDb for NoteCouchDb {
+    async fn create_api_key(
+        &self,
+        api_key: &ApiKey,
+    ) -> Result<(), NoteDbError> {
+        let doc = CouchDoc {
+            id: api_key_doc_id(&api_key.id),
+            rev: None,
+            doc_type: API_KEY_TYPE.to_string(),
+            value: api_key,
+        };
+        if !self.put_doc(&doc).await? {
+            return Err(NoteDbError::Conflict(format!(
+                "api key {} already exists",
+                api_key.id
+            )));
+        }
+        Ok(())
+    }
+
+    async fn find_api_key(
+        &self,
+        key_hash: &str,
+    ) -> Result<Option<ApiKey>, NoteDbError> {
+        let selector = json!({ "type": API_KEY_TYPE, "key_hash": key_hash });
+        let docs: Vec<CouchDoc<ApiKey>> =
+            self.find(selector, &Page::default()).await?;
+        Ok(docs.into_iter().next().map(|doc| doc.value))
+    }
+
+    async fn list_api_keys(&self) -> Result<Vec<ApiKey>, NoteDbError> {
+        let docs: Vec<CouchDoc<ApiKey>> = self
+            .find(json!({ "type": API_KEY_TYPE }), &Page::default())
+            .await?;
+        Ok(docs.into_iter().map(|doc| doc.value).collect())
+    }
+
+    async fn list_api_keys_of(
+        &self,
+        subject: &str,
+    ) -> Result<Vec<ApiKey>, NoteDbError> {
+        let selector = json!({ "type": API_KEY_TYPE, "subject": subject });
+        let docs: Vec<CouchDoc<ApiKey>> =
+            self.find(selector, &Page::default()).await?;
+        Ok(docs.into_iter().map(|doc| doc.value).collect())
+    }
+
+    async fn delete_api_key(&self, id: &str) -> Result<bool, NoteDbError> {
+        self.delete_doc(&api_key_doc_id(id), API_KEY_TYPE).await
+    }
+}
+
+impl NoteCouchDb {
+    /// Applies `update` to the members of an organization, returning false
+    /// if there is none.
+    async fn update_members<F>(
+        &self,
+        id: &str,
+        update: F,
+    ) -> Result<bool, NoteDbError>
+    where
+        F: Fn(&mut Vec<String>) + Send + Sync,
+    {
+        for _ in 0..CONFLICT_RETRIES {
+            let Some(mut doc) = self
+                .get_doc::<Organization>(&org_doc_id(id), ORG_TYPE)
+                .await?
+            else {
+                return Ok(false);
+            };
+            update(&mut doc.value.members);
+            if self.put_doc(&doc).await? {
+                return Ok(true);
+            }
+        }
+        Err(NoteDbError::Conflict(format!(
+            "too many conflicts updating organization {}",
+            id
+        )))
+    }
+}
+
+#[async_trait]
+impl OrgDb for NoteCouchDb {
+    async fn create_org(&self, org: &Organization) -> Result<(), NoteDbError> {
+        let doc = CouchDoc {
+            id: org_doc_id(&org.id),
+            rev: None,
+            doc_type: ORG_TYPE.to_string(),
+            value: org,
+        };
+        if !self.put_doc(&doc).await? {
+            return Err(NoteDbError::Conflict(format!(
+                "organization {} already exists",
+                org.id
+            )));
+        }
+        Ok(())
+    }
+
+    async fn get_org(
+        &self,
+        id: &str,
+    ) -> Result<Option<Organization>, NoteDbError> {
+        let doc = self.get_doc(&org_doc_id(id), ORG_TYPE).await?;
+        Ok(doc.map(|doc| doc.value))
+    }
+
+    async fn list_orgs(
+        &self,
+        member: &str,
+    ) -> Result<Vec<Organization>, NoteDbError> {
+        let selector = json!({
+            "type": ORG_TYPE,
+            "members": { "$elemMatch": { "$eq": member } },
+        });
+        let docs: Vec<CouchDoc<Organization>> =
+            self.find(selector, &Page::default()).await?;
+        Ok(docs.into_iter().map(|doc| doc.value).collect())
+    }
+
+    async fn add_member(
+        &self,
+        id: &str,
+        member: &str,
+    ) -> Result<bool, NoteDbError> {
+        self.update_members(id, |members| {
+            if !members.iter().any(|m| m == member) {
+                members.push(member.to_string());
+            }
+        })
+        .await
+    }
+
+    async fn remove_member(
+        &self,
+        id: &str,
+        member: &str,
+    ) -> Result<bool, NoteDbError> {
+        self.update_members(id, |members| members.retain(|m| m != member))
+            .await
+    }
+}
+
+#[async_trait]
+impl ShareDb for NoteCouchDb {
+    async fn create_share(&self, share: &Share) -> Result<(), NoteDbError> {
+        let doc = CouchDoc {
+            id: share_doc_id(&share.id),
+            rev: None,
+            doc_type: SHARE_TYPE.to_string(),
+            value: share,
+        };
+        if !self.put_doc(&doc).await? {
+            return Err(NoteDbError::Conflict(format!(
+                "share {} already exists",
+                share.id
+            )));
+        }
+        Ok(())
+    }
+
+    async fn find_share(
+        &self,
+        token_hash: &str,
+    ) -> Result<Option<Share>, NoteDbError> {
+        let selector = json!({ "type": SHARE_TYPE, "token_hash": token_hash });
+        let docs: Vec<CouchDoc<Share>> =
+            self.find(selector, &Page::default()).await?;
+        Ok(docs.into_iter().next().map(|doc| doc.value))
+    }
+
+    async fn delete_share(
+        &self,
+        note_id: &str,
+        id: &str,
+    ) -> Result<bool, NoteDbError> {
+        let doc = self.get_doc::<Share>(&share_doc_id(id), SHARE_TYPE).await?;
+        if doc.is_none_or(|doc| doc.value.note_id != note_id) {
+            return Ok(false);
+        }
+        self.delete_doc(&share_doc_id(id), SHARE_TYPE).await
+    }
+}
+
+#[async_trait]
+impl AnnouncementDb for NoteCouchDb {
+    async fn create_announcement(
+        &self,
+        announcement: &Announcement,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let doc = CouchDoc {
+            id: announcement.id.clone(),
+            rev: None,
+            doc_type: ANNOUNCEMENT_TYPE.to_string(),
+            value: announcement,
+        };
+        if !self.put_doc(&doc).await? {
+            return Err(format!(
+                "announcement {} already exists",
+                announcement.id
+            )
+            .into());
+        }
+        Ok(())
+    }
+
+    async fn delete_announcement(
+        &self,
+        id: &str,
+    ) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+        Ok(self.delete_doc(id, ANNOUNCEMENT_TYPE).await?)
+    }
+
+    async fn list_announcements(
+        &self,
+    ) -> Result<Vec<Announcement>, Box<dyn std::error::Error + Send + Sync>>
+    {
+        let docs: Vec<CouchDoc<Announcement>> = self
+            .find(json!({ "type": ANNOUNCEMENT_TYPE }), &Page::default())
+            .await?;
+        Ok(docs.into_iter().map(|doc| doc.value).collect())
+    }
+}
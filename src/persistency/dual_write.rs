@@ -0,0 +1,291 @@
+//! A `NoteDb` combinator for moving live traffic from one backend to
+//! another. Writes go to both backends while reads are served according to
+//! a mode that can be switched at runtime.
+
+use std::sync::{
+    atomic::{AtomicU64, AtomicU8, Ordering},
+    Arc,
+};
+
+use async_trait::async_trait;
+use futures::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+
+use crate::context::OpContext;
+use crate::notes::{
+    Note, NoteDb, NoteDbError, NoteFilter, NotePage, NoteRevision,
+    NoteSuggestion, Page, PatchNote,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReadMode {
+    /// Read from the old backend only.
+    Old,
+    /// Read from both backends, serve the old result and log divergence.
+    Compare,
+    /// Read from the new backend only, the cutover is done.
+    New,
+}
+
+impl ReadMode {
+    fn from_u8(value: u8) -> ReadMode {
+        match value {
+            0 => ReadMode::Old,
+            1 => ReadMode::Compare,
+            _ => ReadMode::New,
+        }
+    }
+
+    fn as_u8(self) -> u8 {
+        match self {
+            ReadMode::Old => 0,
+            ReadMode::Compare => 1,
+            ReadMode::New => 2,
+        }
+    }
+}
+
+/// Runtime switch shared between the `DualWriteNoteDb` and the admin API.
+pub struct DualWriteControl {
+    read_mode: AtomicU8,
+    divergences: AtomicU64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DualWriteStatus {
+    pub read_mode: ReadMode,
+    /// Reads in compare mode where the backends disagreed.
+    #[serde(default)]
+    pub divergences: u64,
+}
+
+impl DualWriteControl {
+    pub fn new(read_mode: ReadMode) -> Self {
+        DualWriteControl {
+            read_mode: AtomicU8::new(read_mode.as_u8()),
+            divergences: AtomicU64::new(0),
+        }
+    }
+
+    pub fn read_mode(&self) -> ReadMode {
+        ReadMode::from_u8(self.read_mode.load(Ordering::SeqCst))
+    }
+
+    pub fn set_read_mode(&self, read_mode: ReadMode) {
+        tracing::info!("switch migration read mode to {:?}", read_mode);
+        self.read_mode.store(read_mode.as_u8(), Ordering::SeqCst);
+    }
+
+    pub fn status(&self) -> DualWriteStatus {
+        DualWriteStatus {
+            read_mode: self.read_mode(),
+            divergences: self.divergences.load(Ordering::SeqCst),
+        }
+    }
+
+    fn record_divergence(&self, what: &str) {
+        self.divergences.fetch_add(1, Ordering::SeqCst);
+        tracing::warn!("migration backends diverge on {}", what);
+    }
+}
+
+pub struct DualWriteNoteDb<O: NoteDb + ?Sized, N: NoteDb + ?Sized> {
+    old: Arc<O>,
+    new: Arc<N>,
+    control: Arc<DualWriteControl>,
+}
+
+impl<O: NoteDb + ?Sized, N: NoteDb + ?Sized> DualWriteNoteDb<O, N> {
+    pub fn new(
+        old: Arc<O>,
+        new: Arc<N>,
+        control: Arc<DualWriteControl>,
+    ) -> Self {
+        DualWriteNoteDb { old, new, control }
+    }
+
+    /// Runs a write against both backends. The backend that is currently
+    /// read from is authoritative: its result is returned and its failure
+    /// fails the write, while a failure of the other one is only logged.
+    async fn write<T: Send>(
+        &self,
+        what: &str,
+        old_write: BoxFuture<'_, Result<T, NoteDbError>>,
+        new_write: BoxFuture<'_, Result<T, NoteDbError>>,
+    ) -> Result<T, NoteDbError> {
+        let (primary, secondary) = match self.control.read_mode() {
+            ReadMode::Old | ReadMode::Compare => (old_write, new_write),
+            ReadMode::New => (new_write, old_write),
+        };
+        let res = primary.await?;
+        if let Err(err) = secondary.await {
+            tracing::error!("unable to dual-write {}: {}", what, err);
+        }
+        Ok(res)
+    }
+}
+
+#[async_trait]
+impl<O, N> NoteDb for DualWriteNoteDb<O, N>
+where
+    O: NoteDb + ?Sized,
+    N: NoteDb + ?Sized,
+{
+    async fn create_note(
+        &self,
+        ctx: &OpContext,
+        note: &Note,
+    ) -> Result<(), NoteDbError> {
+        self.write(
+            "create",
+            self.old.create_note(ctx, note),
+            self.new.create_note(ctx, note),
+        )
+        .await
+    }
+
+    async fn get_note(
+        &self,
+        ctx: &OpContext,
+        id: &str,
+    ) -> Result<Option<Note>, NoteDbError> {
+        match self.control.read_mode() {
+            ReadMode::Old => self.old.get_note(ctx, id).await,
+            ReadMode::New => self.new.get_note(ctx, id).await,
+            ReadMode::Compare => {
+                let (old, new) = tokio::join!(
+                    self.old.get_note(ctx, id),
+                    self.new.get_note(ctx, id)
+                );
+                match &new {
+                    Ok(new) if old.as_ref().is_ok_and(|old| old == new) => {}
+                    _ => {
+                        self.control.record_divergence(&format!("note {}", id))
+                    }
+                }
+                old
+            }
+        }
+    }
+
+    async fn get_notes(
+        &self,
+        ctx: &OpContext,
+        ids: &[String],
+    ) -> Result<Vec<Note>, NoteDbError> {
+        match self.control.read_mode() {
+            ReadMode::Old | ReadMode::Compare => {
+                self.old.get_notes(ctx, ids).await
+            }
+            ReadMode::New => self.new.get_notes(ctx, ids).await,
+        }
+    }
+
+    async fn update_note(
+        &self,
+        ctx: &OpContext,
+        id: &str,
+        note: &PatchNote,
+    ) -> Result<(), NoteDbError> {
+        self.write(
+            "update",
+            self.old.update_note(ctx, id, note),
+            self.new.update_note(ctx, id, note),
+        )
+        .await
+    }
+
+    async fn delete_note(
+        &self,
+        ctx: &OpContext,
+        id: &str,
+    ) -> Result<bool, NoteDbError> {
+        self.write(
+            "delete",
+            self.old.delete_note(ctx, id),
+            self.new.delete_note(ctx, id),
+        )
+        .await
+    }
+
+    async fn list_notes(
+        &self,
+        ctx: &OpContext,
+        filter: &NoteFilter,
+        page: &Page,
+    ) -> Result<NotePage, NoteDbError> {
+        match self.control.read_mode() {
+            ReadMode::Old => self.old.list_notes(ctx, filter, page).await,
+            ReadMode::New => self.new.list_notes(ctx, filter, page).await,
+            ReadMode::Compare => {
+                let (old, new) = tokio::join!(
+                    self.old.list_notes(ctx, filter, page),
+                    self.new.list_notes(ctx, filter, page)
+                );
+                let same = match (&old, &new) {
+                    (Ok(old), Ok(new)) => old.same_notes(new),
+                    _ => false,
+                };
+                if !same {
+                    self.control.record_divergence("list");
+                }
+                old
+            }
+        }
+    }
+
+    async fn suggest_titles(
+        &self,
+        ctx: &OpContext,
+        prefix: &str,
+        limit: usize,
+    ) -> Result<Vec<NoteSuggestion>, NoteDbError> {
+        match self.control.read_mode() {
+            ReadMode::Old | ReadMode::Compare => {
+                self.old.suggest_titles(ctx, prefix, limit).await
+            }
+            ReadMode::New => self.new.suggest_titles(ctx, prefix, limit).await,
+        }
+    }
+
+    async fn record_revision(
+        &self,
+        ctx: &OpContext,
+        note: &Note,
+    ) -> Result<u64, NoteDbError> {
+        self.write(
+            "record revision",
+            self.old.record_revision(ctx, note),
+            self.new.record_revision(ctx, note),
+        )
+        .await
+    }
+
+    async fn list_revisions(
+        &self,
+        ctx: &OpContext,
+        note_id: &str,
+    ) -> Result<Vec<NoteRevision>, NoteDbError> {
+        match self.control.read_mode() {
+            ReadMode::Old | ReadMode::Compare => {
+                self.old.list_revisions(ctx, note_id).await
+            }
+            ReadMode::New => self.new.list_revisions(ctx, note_id).await,
+        }
+    }
+
+    async fn get_revision(
+        &self,
+        ctx: &OpContext,
+        note_id: &str,
+        rev: u64,
+    ) -> Result<Option<NoteRevision>, NoteDbError> {
+        match self.control.read_mode() {
+            ReadMode::Old | ReadMode::Compare => {
+                self.old.get_revision(ctx, note_id, rev).await
+            }
+            ReadMode::New => self.new.get_revision(ctx, note_id, rev).await,
+        }
+    }
+}
@@ -0,0 +1,181 @@
+//! A `NoteDb` combinator that hedges `get_note`: if the primary hasn't
+//! answered after a delay, the same read is sent to a replica and whichever
+//! answers first wins. This trims tail latency at the cost of extra reads.
+//! Reads asking for strong consistency only go to the primary.
+
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use serde::Serialize;
+
+use crate::consistency::Consistency;
+use crate::context::OpContext;
+use crate::metrics::{HEDGED_READS_ISSUED, HEDGED_READS_WON};
+use crate::notes::{
+    Note, NoteDb, NoteDbError, NoteFilter, NotePage, NoteRevision,
+    NoteSuggestion, Page, PatchNote,
+};
+
+#[derive(Default)]
+struct HedgeCounters {
+    issued: AtomicU64,
+    won: AtomicU64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct HedgeStats {
+    /// Hedged reads sent because the primary was slower than the delay.
+    pub issued: u64,
+    /// Hedged reads that answered before the primary.
+    pub won: u64,
+}
+
+pub struct HedgedNoteDb<P: NoteDb + ?Sized, R: NoteDb + ?Sized> {
+    primary: Arc<P>,
+    replica: Arc<R>,
+    delay: Duration,
+    counters: HedgeCounters,
+}
+
+impl<P: NoteDb + ?Sized, R: NoteDb + ?Sized> HedgedNoteDb<P, R> {
+    pub fn new(primary: Arc<P>, replica: Arc<R>, delay: Duration) -> Self {
+        HedgedNoteDb {
+            primary,
+            replica,
+            delay,
+            counters: HedgeCounters::default(),
+        }
+    }
+
+    pub fn stats(&self) -> HedgeStats {
+        HedgeStats {
+            issued: self.counters.issued.load(Ordering::SeqCst),
+            won: self.counters.won.load(Ordering::SeqCst),
+        }
+    }
+}
+
+#[async_trait]
+impl<P, R> NoteDb for HedgedNoteDb<P, R>
+where
+    P: NoteDb + ?Sized,
+    R: NoteDb + ?Sized,
+{
+    async fn create_note(
+        &self,
+        ctx: &OpContext,
+        note: &Note,
+    ) -> Result<(), NoteDbError> {
+        self.primary.create_note(ctx, note).await
+    }
+
+    async fn get_note(
+        &self,
+        ctx: &OpContext,
+        id: &str,
+    ) -> Result<Option<Note>, NoteDbError> {
+        if ctx.consistency == Some(Consistency::Strong) {
+            return self.primary.get_note(ctx, id).await;
+        }
+        let primary = self.primary.get_note(ctx, id);
+        tokio::pin!(primary);
+        tokio::select! {
+            res = &mut primary => return res,
+            _ = tokio::time::sleep(self.delay) => {}
+        }
+
+        self.counters.issued.fetch_add(1, Ordering::SeqCst);
+        HEDGED_READS_ISSUED.increment();
+        tracing::debug!("hedge read of note {}", id);
+        tokio::select! {
+            res = &mut primary => res,
+            res = self.replica.get_note(ctx, id) => match res {
+                Ok(note) => {
+                    self.counters.won.fetch_add(1, Ordering::SeqCst);
+                    HEDGED_READS_WON.increment();
+                    Ok(note)
+                }
+                // A failed hedge must not fail a read the primary may
+                // still answer.
+                Err(err) => {
+                    tracing::debug!("hedged read failed: {}", err);
+                    primary.await
+                }
+            },
+        }
+    }
+
+    async fn get_notes(
+        &self,
+        ctx: &OpContext,
+        ids: &[String],
+    ) -> Result<Vec<Note>, NoteDbError> {
+        self.primary.get_notes(ctx, ids).await
+    }
+
+    async fn update_note(
+        &self,
+        ctx: &OpContext,
+        id: &str,
+        note: &PatchNote,
+    ) -> Result<(), NoteDbError> {
+        self.primary.update_note(ctx, id, note).await
+    }
+
+    async fn delete_note(
+        &self,
+        ctx: &OpContext,
+        id: &str,
+    ) -> Result<bool, NoteDbError> {
+        self.primary.delete_note(ctx, id).await
+    }
+
+    async fn list_notes(
+        &self,
+        ctx: &OpContext,
+        filter: &NoteFilter,
+        page: &Page,
+    ) -> Result<NotePage, NoteDbError> {
+        self.primary.list_notes(ctx, filter, page).await
+    }
+
+    async fn suggest_titles(
+        &self,
+        ctx: &OpContext,
+        prefix: &str,
+        limit: usize,
+    ) -> Result<Vec<NoteSuggestion>, NoteDbError> {
+        self.primary.suggest_titles(ctx, prefix, limit).await
+    }
+
+    async fn record_revision(
+        &self,
+        ctx: &OpContext,
+        note: &Note,
+    ) -> Result<u64, NoteDbError> {
+        self.primary.record_revision(ctx, note).await
+    }
+
+    async fn list_revisions(
+        &self,
+        ctx: &OpContext,
+        note_id: &str,
+    ) -> Result<Vec<NoteRevision>, NoteDbError> {
+        self.primary.list_revisions(ctx, note_id).await
+    }
+
+    async fn get_revision(
+        &self,
+        ctx: &OpContext,
+        note_id: &str,
+        rev: u64,
+    ) -> Result<Option<NoteRevision>, NoteDbError> {
+        self.primary.get_revision(ctx, note_id, rev).await
+    }
+}
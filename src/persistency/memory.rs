@@ -0,0 +1,435 @@
+//! Notes kept in process memory, for dev containers and tests that should
+//! run without external services. Everything is lost on restart.
+
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+
+use crate::announcements::{Announcement, AnnouncementDb};
+use crate::api_keys::{ApiKey, ApiKeyDb};
+use crate::context::OpContext;
+use crate::idempotency::{IdempotencyDb, IdempotencyRecord};
+use crate::notes::{
+    rank_suggestions, Note, NoteDb, NoteDbError, NoteFilter, NotePage,
+    NoteRevision, NoteSuggestion, Page, PatchNote,
+};
+use crate::orgs::{OrgDb, Organization};
+use crate::shares::{Share, ShareDb};
+
+/// Notes, announcements, API keys, organizations and shares in insertion
+/// order, and idempotency keys.
+#[derive(Default)]
+pub struct NoteMemoryDb {
+    notes: RwLock<Vec<Note>>,
+    announcements: RwLock<Vec<Announcement>>,
+    revisions: RwLock<Vec<NoteRevision>>,
+    idempotency_keys: RwLock<HashMap<String, IdempotencyRecord>>,
+    api_keys: RwLock<Vec<ApiKey>>,
+    orgs: RwLock<Vec<Organization>>,
+    shares: RwLock<Vec<Share>>,
+}
+
+impl NoteMemoryDb {
+    pub fn new() -> NoteMemoryDb {
+        NoteMemoryDb::default()
+    }
+
+    /// Starts out with the given notes, e.g. fixtures of a test.
+    pub fn with_notes(notes: Vec<Note>) -> NoteMemoryDb {
+        NoteMemoryDb {
+            notes: RwLock::new(notes),
+            ..NoteMemoryDb::default()
+        }
+    }
+}
+
+fn conflict(id: &str) -> NoteDbError {
+    NoteDbError::Conflict(format!("note {} already exists", id))
+}
+
+#[async_trait]
+impl NoteDb for NoteMemoryDb {
+    async fn create_note(
+        &self,
+        _ctx: &OpContext,
+        note: &Note,
+    ) -> Result<(), NoteDbError> {
+        let mut notes = self.notes.write().unwrap();
+        if notes.iter().any(|n| n.id == note.id) {
+            return Err(conflict(&note.id));
+        }
+        notes.push(note.clone());
+        Ok(())
+    }
+
+    /// Creates all notes or none of them.
+    async fn create_notes(
+        &self,
+        _ctx: &OpContext,
+        new_notes: &[Note],
+    ) -> Result<(), NoteDbError> {
+        let mut notes = self.notes.write().unwrap();
+        for (index, note) in new_notes.iter().enumerate() {
+            if notes.iter().any(|n| n.id == note.id)
+                || new_notes[..index].iter().any(|n| n.id == note.id)
+            {
+                return Err(conflict(&note.id));
+            }
+        }
+        notes.extend_from_slice(new_notes);
+        Ok(())
+    }
+
+    async fn get_note(
+        &self,
+        ctx: &OpContext,
+        id: &str,
+    ) -> Result<Option<Note>, NoteDbError> {
+        let notes = self.notes.read().unwrap();
+        Ok(notes
+            .iter()
+            .find(|note| note.id == id && ctx.can_access(note))
+            .cloned())
+    }
+
+    async fn get_notes(
+        &self,
+        ctx: &OpContext,
+        ids: &[String],
+    ) -> Result<Vec<Note>, NoteDbError> {
+        let notes = self.notes.read().unwrap();
+        Ok(notes
+            .iter()
+            .filter(|note| ids.contains(&note.id) && ctx.can_access(note))
+            .cloned()
+            .collect())
+    }
+
+    async fn update_note(
+        &self,
+        ctx: &OpContext,
+        id: &str,
+        patch: &PatchNote,
+    ) -> Result<(), NoteDbError> {
+        let mut notes = self.notes.write().unwrap();
+        let Some(note) = notes
+            .iter_mut()
+            .find(|note| note.id == id && ctx.can_access(note))
+        else {
+            return Err(NoteDbError::NotFound);
+        };
+        patch.apply_to(note)?;
+        Ok(())
+    }
+
+    async fn delete_note(
+        &self,
+        ctx: &OpContext,
+        id: &str,
+    ) -> Result<bool, NoteDbError> {
+        let mut notes = self.notes.write().unwrap();
+        let len = notes.len();
+        notes.retain(|note| note.id != id || !ctx.can_access(note));
+        Ok(notes.len() < len)
+    }
+
+    async fn list_notes(
+        &self,
+        ctx: &OpContext,
+        filter: &NoteFilter,
+        page: &Page,
+    ) -> Result<NotePage, NoteDbError> {
+        let notes = self.notes.read().unwrap();
+        let matching: Vec<Note> = notes
+            .iter()
+            .filter(|note| ctx.can_access(note) && filter.matches(note))
+            .cloned()
+            .collect();
+        let total = matching.len() as u64;
+        Ok(NotePage {
+            notes: page.slice(matching),
+            total,
+        })
+    }
+
+    async fn suggest_titles(
+        &self,
+        ctx: &OpContext,
+        prefix: &str,
+        limit: usize,
+    ) -> Result<Vec<NoteSuggestion>, NoteDbError> {
+        let notes = self.notes.read().unwrap();
+        let candidates =
+            notes
+                .iter()
+                .filter(|note| ctx.can_access(note))
+                .map(|note| NoteSuggestion {
+                    id: note.id.clone(),
+                    title: note.title.clone(),
+                });
+        Ok(rank_suggestions(prefix, candidates, limit))
+    }
+
+    async fn record_revision(
+        &self,
+        _ctx: &OpContext,
+        note: &Note,
+    ) -> Result<u64, NoteDbError> {
+        let mut revisions = self.revisions.write().unwrap();
+        let rev = revisions.iter().filter(|r| r.note_id == note.id).count()
+            as u64
+            + 1;
+        revisions.push(NoteRevision::new(note, rev));
+        Ok(rev)
+    }
+
+    async fn list_revisions(
+        &self,
+        _ctx: &OpContext,
+        note_id: &str,
+    ) -> Result<Vec<NoteRevision>, NoteDbError> {
+        let revisions = self.revisions.read().unwrap();
+        Ok(revisions
+            .iter()
+            .filter(|revision| revision.note_id == note_id)
+            .cloned()
+            .collect())
+    }
+}
+
+#[async_trait]
+impl AnnouncementDb for NoteMemoryDb {
+    async fn create_announcement(
+        &self,
+        announcement: &Announcement,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.announcements
+            .write()
+            .unwrap()
+            .push(announcement.clone());
+        Ok(())
+    }
+
+    async fn delete_announcement(
+        &self,
+        id: &str,
+    ) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+        let mut announcements = self.announcements.write().unwrap();
+        let len = announcements.len();
+        announcements.retain(|announcement| announcement.id != id);
+        Ok(announcements.len() < len)
+    }
+
+    async fn list_announcements(
+        &self,
+    ) -> Result<Vec<Announcement>, Box<dyn std::error::Error + Send + Sync>>
+    {
+        Ok(self.announcements.read().unwrap().clone())
+    }
+}
+
+#[async_trait]
+impl IdempotencyDb for NoteMemoryDb {
+    async fn record_key(
+        &self,
+        record: &IdempotencyRecord,
+    ) -> Result<(), NoteDbError> {
+        self.idempotency_keys
+            .write()
+            .unwrap()
+            .insert(record.key.clone(), record.clone());
+        Ok(())
+    }
+
+    async fn find_key(
+        &self,
+        key: &str,
+        now: DateTime<Utc>,
+    ) -> Result<Option<String>, NoteDbError> {
+        let keys = self.idempotency_keys.read().unwrap();
+        Ok(keys
+            .get(key)
+            .filter(|record| now < record.expires_at)
+            .map(|record| record.note_id.clone()))
+    }
+}
+
+#[async_trait]
+impl ApiKeyDb for NoteMemoryDb {
+    async fn create_api_key(
+        &self,
+        api_key: &ApiKey,
+    ) -> Result<(), NoteDbError> {
+        self.api_keys.write().unwrap().push(api_key.clone());
+        Ok(())
+    }
+
+    async fn find_api_key(
+        &self,
+        key_hash: &str,
+    ) -> Result<Option<ApiKey>, NoteDbError> {
+        let api_keys = self.api_keys.read().unwrap();
+        Ok(api_keys
+            .iter()
+            .find(|api_key| api_key.key_hash == key_hash)
+            .cloned())
+    }
+
+    async fn list_api_keys(&self) -> Result<Vec<ApiKey>, NoteDbError> {
+        Ok(self.api_keys.read().unwrap().clone())
+    }
+
+    async fn list_api_keys_of(
+        &self,
+        subject: &str,
+    ) -> Result<Vec<ApiKey>, NoteDbError> {
+        let api_keys = self.api_keys.read().unwrap();
+        Ok(api_keys
+            .iter()
+            .filter(|api_key| api_key.subject == subject)
+            .cloned()
+            .collect())
+    }
+
+    async fn delete_api_key(&self, id: &str) -> Result<bool, NoteDbError> {
+        let mut api_keys = self.api_keys.write().unwrap();
+        let len = api_keys.len();
+        api_keys.retain(|api_key| api_key.id != id);
+        Ok(api_keys.len() < len)
+    }
+}
+
+#[async_trait]
+impl OrgDb for NoteMemoryDb {
+    async fn create_org(&self, org: &Organization) -> Result<(), NoteDbError> {
+        self.orgs.write().unwrap().push(org.clone());
+        Ok(())
+    }
+
+    async fn get_org(
+        &self,
+        id: &str,
+    ) -> Result<Option<Organization>, NoteDbError> {
+        let orgs = self.orgs.read().unwrap();
+        Ok(orgs.iter().find(|org| org.id == id).cloned())
+    }
+
+    async fn list_orgs(
+        &self,
+        member: &str,
+    ) -> Result<Vec<Organization>, NoteDbError> {
+        let orgs = self.orgs.read().unwrap();
+        Ok(orgs
+            .iter()
+            .filter(|org| org.has_member(member))
+            .cloned()
+            .collect())
+    }
+
+    async fn add_member(
+        &self,
+        id: &str,
+        member: &str,
+    ) -> Result<bool, NoteDbError> {
+        let mut orgs = self.orgs.write().unwrap();
+        let Some(org) = orgs.iter_mut().find(|org| org.id == id) else {
+            return Ok(false);
+        };
+        if !org.has_member(member) {
+            org.members.push(member.to_string());
+        }
+        Ok(true)
+    }
+
+    async fn remove_member(
+        &self,
+        id: &str,
+        member: &str,
+    ) -> Result<bool, NoteDbError> {
+        let mut orgs = self.orgs.write().unwrap();
+        let Some(org) = orgs.iter_mut().find(|org| org.id == id) else {
+            return Ok(false);
+        };
+        org.members.retain(|m| m != member);
+        Ok(true)
+    }
+}
+
+#[async_trait]
+impl ShareDb for NoteMemoryDb {
+    async fn create_share(&self, share: &Share) -> Result<(), NoteDbError> {
+        self.shares.write().unwrap().push(share.clone());
+        Ok(())
+    }
+
+    async fn find_share(
+        &self,
+        token_hash: &str,
+    ) -> Result<Option<Share>, NoteDbError> {
+        let shares = self.shares.read().unwrap();
+        Ok(shares
+            .iter()
+            .find(|share| share.token_hash == token_hash)
+            .cloned())
+    }
+
+    async fn delete_share(
+        &self,
+        note_id: &str,
+        id: &str,
+    ) -> Result<bool, NoteDbError> {
+        let mut shares = self.shares.write().unwrap();
+        let len = shares.len();
+        shares.retain(|share| share.id != id || share.note_id != note_id);
+        Ok(shares.len() < len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn it_keeps_notes_in_memory() {
+        // Setup
+        let ctx = OpContext::background();
+        let db = NoteMemoryDb::new();
+        let mut note = Note::new("Groceries", "milk", "/notes/1");
+        note.tags = vec!["home".to_string()];
+        let other = Note::new("Work", "report", "/notes/2");
+        db.create_notes(&ctx, &[note.clone(), other.clone()])
+            .await
+            .unwrap();
+
+        // Execute
+        let batch = [Note::new("a", "b", "c"), other];
+        let duplicate = db.create_notes(&ctx, &batch).await;
+        let filter = NoteFilter {
+            tag: Some("home".to_string()),
+            ..NoteFilter::default()
+        };
+        let listed = db
+            .list_notes(&ctx, &filter, &Page::default())
+            .await
+            .unwrap();
+        let suggested = db.suggest_titles(&ctx, "gro", 10).await.unwrap();
+        let deleted = db.delete_note(&ctx, &note.id).await.unwrap();
+
+        // Assert
+        assert!(matches!(duplicate, Err(NoteDbError::Conflict(_))));
+        assert_eq!(listed.total, 1);
+        assert_eq!(listed.notes, vec![note.clone()]);
+        assert_eq!(suggested.len(), 1);
+        assert!(deleted);
+        // The batch with a duplicate created nothing
+        assert_eq!(
+            db.list_notes(&ctx, &NoteFilter::default(), &Page::default())
+                .await
+                .unwrap()
+                .total,
+            1
+        );
+    }
+}
@@ -0,0 +1,347 @@
+//! A `NoteDb` combinator that serves everything from a primary backend and
+//! replicates writes to a secondary backend in the background, e.g. a Mongo
+//! primary with an object storage mirror.
+
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
+
+use async_trait::async_trait;
+use serde::Serialize;
+use tokio::sync::mpsc;
+
+use crate::context::OpContext;
+use crate::jobs::Job;
+use crate::metrics::{
+    GaugeGuard, MIRROR_FAILED_WRITES, MIRROR_LAG_MS, MIRROR_PENDING_WRITES,
+    MIRROR_REPLICATED_WRITES,
+};
+use crate::notes::{
+    Note, NoteDb, NoteDbError, NoteFilter, NotePage, NoteRevision,
+    NoteSuggestion, Page, PatchNote,
+};
+
+enum Replication {
+    Upsert(String),
+    Delete(String),
+}
+
+// A replication with the time, request and tenant it was queued by, pending
+// until dropped.
+type Queued = (
+    Replication,
+    Instant,
+    Option<String>,
+    Option<String>,
+    GaugeGuard,
+);
+
+#[derive(Default)]
+struct MirrorCounters {
+    pending: AtomicU64,
+    replicated: AtomicU64,
+    failed: AtomicU64,
+    last_lag_ms: AtomicU64,
+}
+
+/// Point-in-time view of the replication state.
+#[derive(Debug, Clone, Serialize)]
+pub struct MirrorStats {
+    /// Writes accepted by the primary but not yet applied to the secondary.
+    pub pending: u64,
+    pub replicated: u64,
+    pub failed: u64,
+    /// Time between the primary write and its replication, for the most
+    /// recently replicated write.
+    pub last_lag_ms: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CatchUpReport {
+    pub copied: usize,
+    pub removed: usize,
+}
+
+pub struct MirroredNoteDb<P: NoteDb + ?Sized, S: NoteDb + ?Sized> {
+    primary: Arc<P>,
+    secondary: Arc<S>,
+    queue: mpsc::UnboundedSender<Queued>,
+    counters: Arc<MirrorCounters>,
+}
+
+impl<P, S> MirroredNoteDb<P, S>
+where
+    P: NoteDb + ?Sized + 'static,
+    S: NoteDb + ?Sized + 'static,
+{
+    /// Creates the mirror and spawns its replication task, so this must be
+    /// called from within a tokio runtime.
+    pub fn new(primary: Arc<P>, secondary: Arc<S>) -> Self {
+        let (queue, mut replications) = mpsc::unbounded_channel::<Queued>();
+        let counters = Arc::new(MirrorCounters::default());
+
+        let worker_primary = primary.clone();
+        let worker_secondary = secondary.clone();
+        let worker_counters = counters.clone();
+        tokio::spawn(async move {
+            while let Some((
+                replication,
+                enqueued_at,
+                request_id,
+                tenant,
+                pending,
+            )) = replications.recv().await
+            {
+                let res = Job::new("mirror_write")
+                    .caused_by(request_id.as_deref())
+                    .run(replicate(
+                        &OpContext::for_tenant(tenant),
+                        worker_primary.as_ref(),
+                        worker_secondary.as_ref(),
+                        &replication,
+                    ))
+                    .await;
+                let lag = Instant::now().duration_since(enqueued_at);
+                drop(pending);
+                worker_counters.pending.fetch_sub(1, Ordering::SeqCst);
+                worker_counters
+                    .last_lag_ms
+                    .store(lag.as_millis() as u64, Ordering::SeqCst);
+                MIRROR_LAG_MS.set(lag.as_millis() as u64);
+                match res {
+                    Ok(()) => {
+                        worker_counters
+                            .replicated
+                            .fetch_add(1, Ordering::SeqCst);
+                        MIRROR_REPLICATED_WRITES.increment();
+                        tracing::debug!("replicated write after {:?}", lag);
+                    }
+                    Err(err) => {
+                        worker_counters.failed.fetch_add(1, Ordering::SeqCst);
+                        MIRROR_FAILED_WRITES.increment();
+                        tracing::error!("unable to replicate write: {}", err);
+                    }
+                }
+            }
+        });
+
+        MirroredNoteDb {
+            primary,
+            secondary,
+            queue,
+            counters,
+        }
+    }
+
+    pub fn stats(&self) -> MirrorStats {
+        MirrorStats {
+            pending: self.counters.pending.load(Ordering::SeqCst),
+            replicated: self.counters.replicated.load(Ordering::SeqCst),
+            failed: self.counters.failed.load(Ordering::SeqCst),
+            last_lag_ms: self.counters.last_lag_ms.load(Ordering::SeqCst),
+        }
+    }
+
+    /// Makes the secondary match the primary, e.g. after failed
+    /// replications or when the mirror is added to an existing deployment.
+    /// Catches up the notes in the scope of `ctx`, so with a database per
+    /// tenant once for every tenant.
+    pub async fn catch_up(
+        &self,
+        ctx: &OpContext,
+    ) -> Result<CatchUpReport, NoteDbError> {
+        catch_up(ctx, self.primary.as_ref(), self.secondary.as_ref()).await
+    }
+
+    fn enqueue(&self, ctx: &OpContext, replication: Replication) {
+        self.counters.pending.fetch_add(1, Ordering::SeqCst);
+        let queued = (
+            replication,
+            Instant::now(),
+            ctx.request_id.clone(),
+            ctx.tenant.clone(),
+            MIRROR_PENDING_WRITES.track(),
+        );
+        if self.queue.send(queued).is_err() {
+            self.counters.pending.fetch_sub(1, Ordering::SeqCst);
+            self.counters.failed.fetch_add(1, Ordering::SeqCst);
+            MIRROR_FAILED_WRITES.increment();
+            tracing::error!("replication task is gone");
+        }
+    }
+}
+
+/// Copies every note of `primary` into `secondary` and removes notes the
+/// primary doesn't have.
+pub async fn catch_up<P, S>(
+    ctx: &OpContext,
+    primary: &P,
+    secondary: &S,
+) -> Result<CatchUpReport, NoteDbError>
+where
+    P: NoteDb + ?Sized,
+    S: NoteDb + ?Sized,
+{
+    let mut report = CatchUpReport::default();
+    let notes = primary
+        .list_notes(ctx, &NoteFilter::default(), &Page::default())
+        .await?
+        .notes;
+    for note in &notes {
+        copy_note(ctx, secondary, note).await?;
+        report.copied += 1;
+    }
+    let mirrored = secondary
+        .list_notes(ctx, &NoteFilter::default(), &Page::default())
+        .await?
+        .notes;
+    for stale in mirrored {
+        if !notes.iter().any(|note| note.id == stale.id) {
+            secondary.delete_note(ctx, &stale.id).await?;
+            report.removed += 1;
+        }
+    }
+    tracing::info!(
+        "mirror caught up, copied {} and removed {} notes",
+        report.copied,
+        report.removed
+    );
+    Ok(report)
+}
+
+async fn replicate<P, S>(
+    ctx: &OpContext,
+    primary: &P,
+    secondary: &S,
+    replication: &Replication,
+) -> Result<(), NoteDbError>
+where
+    P: NoteDb + ?Sized,
+    S: NoteDb + ?Sized,
+{
+    match replication {
+        Replication::Upsert(id) => match primary.get_note(ctx, id).await? {
+            Some(note) => copy_note(ctx, secondary, &note).await,
+            // Deleted again before we got to it, the delete follows.
+            None => Ok(()),
+        },
+        Replication::Delete(id) => {
+            secondary.delete_note(ctx, id).await?;
+            Ok(())
+        }
+    }
+}
+
+// The trait has no upsert, so the copy replaces whatever the secondary has.
+async fn copy_note<S: NoteDb + ?Sized>(
+    ctx: &OpContext,
+    secondary: &S,
+    note: &Note,
+) -> Result<(), NoteDbError> {
+    secondary.delete_note(ctx, &note.id).await?;
+    secondary.create_note(ctx, note).await
+}
+
+#[async_trait]
+impl<P, S> NoteDb for MirroredNoteDb<P, S>
+where
+    P: NoteDb + ?Sized + 'static,
+    S: NoteDb + ?Sized + 'static,
+{
+    async fn create_note(
+        &self,
+        ctx: &OpContext,
+        note: &Note,
+    ) -> Result<(), NoteDbError> {
+        self.primary.create_note(ctx, note).await?;
+        self.enqueue(ctx, Replication::Upsert(note.id.clone()));
+        Ok(())
+    }
+
+    async fn get_note(
+        &self,
+        ctx: &OpContext,
+        id: &str,
+    ) -> Result<Option<Note>, NoteDbError> {
+        self.primary.get_note(ctx, id).await
+    }
+
+    async fn get_notes(
+        &self,
+        ctx: &OpContext,
+        ids: &[String],
+    ) -> Result<Vec<Note>, NoteDbError> {
+        self.primary.get_notes(ctx, ids).await
+    }
+
+    async fn update_note(
+        &self,
+        ctx: &OpContext,
+        id: &str,
+        note: &PatchNote,
+    ) -> Result<(), NoteDbError> {
+        self.primary.update_note(ctx, id, note).await?;
+        self.enqueue(ctx, Replication::Upsert(id.to_string()));
+        Ok(())
+    }
+
+    async fn delete_note(
+        &self,
+        ctx: &OpContext,
+        id: &str,
+    ) -> Result<bool, NoteDbError> {
+        let deleted = self.primary.delete_note(ctx, id).await?;
+        if deleted {
+            self.enqueue(ctx, Replication::Delete(id.to_string()));
+        }
+        Ok(deleted)
+    }
+
+    async fn list_notes(
+        &self,
+        ctx: &OpContext,
+        filter: &NoteFilter,
+        page: &Page,
+    ) -> Result<NotePage, NoteDbError> {
+        self.primary.list_notes(ctx, filter, page).await
+    }
+
+    async fn suggest_titles(
+        &self,
+        ctx: &OpContext,
+        prefix: &str,
+        limit: usize,
+    ) -> Result<Vec<NoteSuggestion>, NoteDbError> {
+        self.primary.suggest_titles(ctx, prefix, limit).await
+    }
+
+    /// Revisions are kept by the primary only.
+    async fn record_revision(
+        &self,
+        ctx: &OpContext,
+        note: &Note,
+    ) -> Result<u64, NoteDbError> {
+        self.primary.record_revision(ctx, note).await
+    }
+
+    async fn list_revisions(
+        &self,
+        ctx: &OpContext,
+        note_id: &str,
+    ) -> Result<Vec<NoteRevision>, NoteDbError> {
+        self.primary.list_revisions(ctx, note_id).await
+    }
+
+    async fn get_revision(
+        &self,
+        ctx: &OpContext,
+        note_id: &str,
+        rev: u64,
+    ) -> Result<Option<NoteRevision>, NoteDbError> {
+        self.primary.get_revision(ctx, note_id, rev).await
+    }
+}
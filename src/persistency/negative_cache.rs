@@ -0,0 +1,306 @@
+//! A `NoteDb` combinator that remembers for a short while which ids
+//! `get_note` found nothing for, so that clients polling deleted or
+//! made-up ids don't reach the database on every request.
+//!
+//! Creating or updating a note drops the entries of its id, since updates
+//! like permission grants make the note visible to more callers. Entries
+//! are only dropped in this process, so with several instances a note
+//! created or shared elsewhere may read as missing until the entry expires,
+//! as may notes of an organization someone just joined. Reads asking for
+//! strong consistency skip the cache.
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use tokio::time::Instant;
+
+use crate::consistency::Consistency;
+use crate::context::OpContext;
+use crate::notes::{
+    Note, NoteDb, NoteDbError, NoteFilter, NotePage, NoteRevision,
+    NoteSuggestion, Page, PatchNote,
+};
+
+// Bounds memory when clients probe many distinct ids. Misses beyond this
+// aren't cached until entries expire.
+const MAX_ENTRIES: usize = 10_000;
+
+// A note may exist for one owner or tenant and be missing for everyone
+// else.
+type Key = (Option<String>, Option<String>, String);
+
+pub struct NegativeCacheNoteDb<D: NoteDb + ?Sized> {
+    inner: Arc<D>,
+    ttl: Duration,
+    misses: Mutex<HashMap<Key, Instant>>,
+    hits: AtomicU64,
+}
+
+impl<D: NoteDb + ?Sized> NegativeCacheNoteDb<D> {
+    pub fn new(inner: Arc<D>, ttl: Duration) -> Self {
+        NegativeCacheNoteDb {
+            inner,
+            ttl,
+            misses: Mutex::new(HashMap::new()),
+            hits: AtomicU64::new(0),
+        }
+    }
+
+    /// Reads answered from the cache.
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::SeqCst)
+    }
+
+    fn key(ctx: &OpContext, id: &str) -> Key {
+        (
+            ctx.owner().map(str::to_string),
+            ctx.tenant.clone(),
+            id.to_string(),
+        )
+    }
+
+    fn is_cached_miss(&self, key: &Key) -> bool {
+        let mut misses = self.misses.lock().unwrap();
+        match misses.get(key) {
+            Some(expires_at) if Instant::now() < *expires_at => true,
+            Some(_) => {
+                misses.remove(key);
+                false
+            }
+            None => false,
+        }
+    }
+
+    fn remember_miss(&self, key: Key) {
+        let mut misses = self.misses.lock().unwrap();
+        if misses.len() >= MAX_ENTRIES {
+            let now = Instant::now();
+            misses.retain(|_, expires_at| now < *expires_at);
+            if misses.len() >= MAX_ENTRIES {
+                return;
+            }
+        }
+        misses.insert(key, Instant::now() + self.ttl);
+    }
+
+    fn forget(&self, ids: &[&str]) {
+        self.misses
+            .lock()
+            .unwrap()
+            .retain(|(_, _, id), _| !ids.contains(&id.as_str()));
+    }
+}
+
+#[async_trait]
+impl<D: NoteDb + ?Sized> NoteDb for NegativeCacheNoteDb<D> {
+    async fn create_note(
+        &self,
+        ctx: &OpContext,
+        note: &Note,
+    ) -> Result<(), NoteDbError> {
+        let res = self.inner.create_note(ctx, note).await;
+        self.forget(&[&note.id]);
+        res
+    }
+
+    async fn create_notes(
+        &self,
+        ctx: &OpContext,
+        notes: &[Note],
+    ) -> Result<(), NoteDbError> {
+        let res = self.inner.create_notes(ctx, notes).await;
+        let ids: Vec<&str> =
+            notes.iter().map(|note| note.id.as_str()).collect();
+        self.forget(&ids);
+        res
+    }
+
+    async fn get_note(
+        &self,
+        ctx: &OpContext,
+        id: &str,
+    ) -> Result<Option<Note>, NoteDbError> {
+        if ctx.consistency == Some(Consistency::Strong) {
+            return self.inner.get_note(ctx, id).await;
+        }
+        let key = Self::key(ctx, id);
+        if self.is_cached_miss(&key) {
+            self.hits.fetch_add(1, Ordering::SeqCst);
+            return Ok(None);
+        }
+        let note = self.inner.get_note(ctx, id).await?;
+        if note.is_none() {
+            self.remember_miss(key);
+        }
+        Ok(note)
+    }
+
+    async fn get_notes(
+        &self,
+        ctx: &OpContext,
+        ids: &[String],
+    ) -> Result<Vec<Note>, NoteDbError> {
+        self.inner.get_notes(ctx, ids).await
+    }
+
+    async fn update_note(
+        &self,
+        ctx: &OpContext,
+        id: &str,
+        note: &PatchNote,
+    ) -> Result<(), NoteDbError> {
+        let res = self.inner.update_note(ctx, id, note).await;
+        self.forget(&[id]);
+        res
+    }
+
+    async fn delete_note(
+        &self,
+        ctx: &OpContext,
+        id: &str,
+    ) -> Result<bool, NoteDbError> {
+        self.inner.delete_note(ctx, id).await
+    }
+
+    async fn list_notes(
+        &self,
+        ctx: &OpContext,
+        filter: &NoteFilter,
+        page: &Page,
+    ) -> Result<NotePage, NoteDbError> {
+        self.inner.list_notes(ctx, filter, page).await
+    }
+
+    async fn suggest_titles(
+        &self,
+        ctx: &OpContext,
+        prefix: &str,
+        limit: usize,
+    ) -> Result<Vec<NoteSuggestion>, NoteDbError> {
+        self.inner.suggest_titles(ctx, prefix, limit).await
+    }
+
+    async fn find_by_title(
+        &self,
+        ctx: &OpContext,
+        title: &str,
+    ) -> Result<Vec<NoteSuggestion>, NoteDbError> {
+        self.inner.find_by_title(ctx, title).await
+    }
+
+    async fn record_revision(
+        &self,
+        ctx: &OpContext,
+        note: &Note,
+    ) -> Result<u64, NoteDbError> {
+        self.inner.record_revision(ctx, note).await
+    }
+
+    async fn list_revisions(
+        &self,
+        ctx: &OpContext,
+        note_id: &str,
+    ) -> Result<Vec<NoteRevision>, NoteDbError> {
+        self.inner.list_revisions(ctx, note_id).await
+    }
+
+    async fn get_revision(
+        &self,
+        ctx: &OpContext,
+        note_id: &str,
+        rev: u64,
+    ) -> Result<Option<NoteRevision>, NoteDbError> {
+        self.inner.get_revision(ctx, note_id, rev).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notes::{Access, Permissions};
+    use crate::persistency::memory::NoteMemoryDb;
+
+    #[tokio::test]
+    async fn it_caches_missing_notes_until_created() {
+        // Setup
+        let ctx = OpContext::background();
+        let strong = OpContext {
+            consistency: Some(Consistency::Strong),
+            ..OpContext::background()
+        };
+        let inner = Arc::new(NoteMemoryDb::new());
+        let db =
+            NegativeCacheNoteDb::new(inner.clone(), Duration::from_secs(60));
+        let note = Note::new("a", "b", "/notes/1");
+
+        // Execute
+        let first = db.get_note(&ctx, &note.id).await.unwrap();
+        inner.create_note(&ctx, &note).await.unwrap();
+        let cached = db.get_note(&ctx, &note.id).await.unwrap();
+        let bypassed = db.get_note(&strong, &note.id).await.unwrap();
+        let hits = db.hits();
+        db.create_note(&ctx, &Note::new("c", "d", "/notes/2"))
+            .await
+            .unwrap();
+        db.delete_note(&ctx, &note.id).await.unwrap();
+        db.get_note(&ctx, &note.id).await.unwrap();
+        db.create_note(&ctx, &note).await.unwrap();
+        let recreated = db.get_note(&ctx, &note.id).await.unwrap();
+
+        // Assert
+        assert_eq!(first, None);
+        // Created behind the cache's back, so the miss is still cached
+        assert_eq!(cached, None);
+        assert_eq!(bypassed, Some(note.clone()));
+        assert_eq!(hits, 1);
+        assert_eq!(recreated, Some(note));
+    }
+
+    #[tokio::test]
+    async fn it_drops_cached_misses_when_access_is_granted() {
+        // Setup
+        let alice = OpContext {
+            principal: Some("alice".to_string()),
+            ..OpContext::background()
+        };
+        let bob = OpContext {
+            principal: Some("bob".to_string()),
+            ..OpContext::background()
+        };
+        let db = NegativeCacheNoteDb::new(
+            Arc::new(NoteMemoryDb::new()),
+            Duration::from_secs(60),
+        );
+        let note = Note {
+            owner: Some("alice".to_string()),
+            ..Note::new("a", "b", "/notes/1")
+        };
+        db.create_note(&alice, &note).await.unwrap();
+        let mut permissions = Permissions::default();
+        permissions.grant("bob", Access::Read);
+        let grant = PatchNote {
+            title: None,
+            body: None,
+            tags: None,
+            updated_at: chrono::Utc::now(),
+            version: None,
+            permissions: Some(permissions),
+        };
+
+        // Execute
+        let before = db.get_note(&bob, &note.id).await.unwrap();
+        db.update_note(&alice, &note.id, &grant).await.unwrap();
+        let after = db.get_note(&bob, &note.id).await.unwrap();
+
+        // Assert
+        assert_eq!(before, None);
+        assert_eq!(after.map(|note| note.id), Some(note.id));
+    }
+}
@@ -0,0 +1,688 @@
+//! Notes stored as one JSON object per note in S3-compatible object
+//! storage, for deployments without a database.
+//!
+//! Next to the notes lives an index object listing the id, title, owner,
+//! tenant and organization of every note, which serves listing and title
+//! suggestions. All read-modify-write cycles use conditional PUTs so
+//! concurrent writers never lose an update.
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use futures::stream::{self, StreamExt, TryStreamExt};
+use object_store::{
+    aws::AmazonS3Builder, path::Path, prefix::PrefixStore, ObjectStore,
+    ObjectStoreExt, PutMode, PutPayload, UpdateVersion,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::announcements::{Announcement, AnnouncementDb};
+use crate::api_keys::{ApiKey, ApiKeyDb};
+use crate::context::OpContext;
+use crate::idempotency::{IdempotencyDb, IdempotencyRecord};
+use crate::notes::{
+    rank_suggestions, Note, NoteDb, NoteDbError, NoteFilter, NotePage,
+    NoteRevision, NoteSuggestion, Page, PatchNote,
+};
+use crate::orgs::{OrgDb, Organization};
+use crate::shares::{Share, ShareDb};
+
+const NOTES_DIR: &str = "notes";
+const INDEX_OBJECT: &str = "index.json";
+const ANNOUNCEMENTS_OBJECT: &str = "announcements.json";
+const IDEMPOTENCY_DIR: &str = "idempotency";
+const API_KEYS_OBJECT: &str = "api_keys.json";
+const ORGS_OBJECT: &str = "orgs.json";
+const SHARES_OBJECT: &str = "shares.json";
+
+// Conditional writes are retried this often before giving up, which only
+// happens under heavy contention on the same object.
+const CONDITIONAL_WRITE_RETRIES: usize = 10;
+const LIST_CONCURRENCY: usize = 16;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct IndexEntry {
+    id: String,
+    title: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    owner: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tenant: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    org: Option<String>,
+    /// Subjects the note is shared with, for reading or writing.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    readers: Vec<String>,
+}
+
+fn readers(note: &Note) -> Vec<String> {
+    let permissions = &note.permissions;
+    permissions
+        .read
+        .iter()
+        .chain(&permissions.write)
+        .cloned()
+        .collect()
+}
+
+impl From<object_store::Error> for NoteDbError {
+    fn from(err: object_store::Error) -> Self {
+        match err {
+            object_store::Error::NotFound { .. } => NoteDbError::NotFound,
+            object_store::Error::AlreadyExists { .. }
+            | object_store::Error::Precondition { .. } => {
+                NoteDbError::Conflict(err.to_string())
+            }
+            _ => NoteDbError::Other(err.into()),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct NoteS3Db {
+    store: Arc<dyn ObjectStore>,
+}
+
+impl NoteS3Db {
+    pub fn new(store: Arc<dyn ObjectStore>) -> NoteS3Db {
+        NoteS3Db { store }
+    }
+
+    /// Connects to `s3://<bucket>[/<prefix>]`. Credentials, region and
+    /// endpoint are read from the usual `AWS_*` environment variables.
+    pub fn from_uri(
+        uri: &str,
+    ) -> Result<NoteS3Db, Box<dyn std::error::Error + Send + Sync>> {
+        let Some(location) = uri.strip_prefix("s3://") else {
+            return Err(format!("not an s3 uri: {}", uri).into());
+        };
+        let (bucket, prefix) =
+            location.split_once('/').unwrap_or((location, ""));
+        let store = AmazonS3Builder::from_env()
+            .with_bucket_name(bucket)
+            .build()?;
+        let prefix = prefix.trim_matches('/');
+        if prefix.is_empty() {
+            return Ok(NoteS3Db::new(Arc::new(store)));
+        }
+        Ok(NoteS3Db::new(Arc::new(PrefixStore::new(store, prefix))))
+    }
+
+    /// Checks that the bucket can be reached with the configured
+    /// credentials.
+    pub async fn ping(&self) -> Result<(), object_store::Error> {
+        match self.store.head(&Path::from(INDEX_OBJECT)).await {
+            Ok(_) | Err(object_store::Error::NotFound { .. }) => Ok(()),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn note_path(id: &str) -> Path {
+        Path::from_iter([NOTES_DIR, &format!("{}.json", id)])
+    }
+
+    async fn read_json<T: DeserializeOwned>(
+        &self,
+        path: &Path,
+    ) -> Result<Option<(T, UpdateVersion)>, NoteDbError> {
+        let result = match self.store.get(path).await {
+            Ok(result) => result,
+            Err(object_store::Error::NotFound { .. }) => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        let version = UpdateVersion {
+            e_tag: result.meta.e_tag.clone(),
+            version: result.meta.version.clone(),
+        };
+        let value = serde_json::from_slice(&result.bytes().await?)?;
+        Ok(Some((value, version)))
+    }
+
+    async fn write_json<T: Serialize>(
+        &self,
+        path: &Path,
+        value: &T,
+        mode: PutMode,
+    ) -> Result<(), object_store::Error> {
+        let payload =
+            PutPayload::from(serde_json::to_vec(value).map_err(|err| {
+                object_store::Error::Generic {
+                    store: "notes",
+                    source: err.into(),
+                }
+            })?);
+        self.store.put_opts(path, payload, mode.into()).await?;
+        Ok(())
+    }
+
+    /// Applies `update` to the JSON object at `path` (starting from the
+    /// default value if it does not exist yet) and writes it back only if
+    /// nobody else changed it in the meantime.
+    async fn update_json<T, F>(
+        &self,
+        path: &Path,
+        update: F,
+    ) -> Result<(), NoteDbError>
+    where
+        T: Default + Serialize + DeserializeOwned,
+        F: Fn(&mut T),
+    {
+        for _ in 0..CONDITIONAL_WRITE_RETRIES {
+            let (mut value, mode) = match self.read_json::<T>(path).await? {
+                Some((value, version)) => (value, PutMode::Update(version)),
+                None => (T::default(), PutMode::Create),
+            };
+            update(&mut value);
+            match self.write_json(path, &value, mode).await {
+                Ok(()) => return Ok(()),
+                Err(object_store::Error::Precondition { .. })
+                | Err(object_store::Error::AlreadyExists { .. }) => {
+                    tracing::debug!("retry conditional write of {}", path);
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+        Err(NoteDbError::Conflict(format!(
+            "too much contention writing {}",
+            path
+        )))
+    }
+
+    /// The index entries of the notes in the scope of `ctx`.
+    async fn read_index(
+        &self,
+        ctx: &OpContext,
+    ) -> Result<Vec<IndexEntry>, NoteDbError> {
+        let index: Option<(Vec<IndexEntry>, _)> =
+            self.read_json(&Path::from(INDEX_OBJECT)).await?;
+        let mut index = index.map(|(index, _)| index).unwrap_or_default();
+        if let Some(owner) = ctx.owner() {
+            index.retain(|entry| {
+                entry.owner.as_deref() == Some(owner)
+                    || entry.org.as_ref().is_some_and(|o| ctx.orgs.contains(o))
+                    || entry.readers.iter().any(|reader| reader == owner)
+            });
+        }
+        if let Some(tenant) = &ctx.tenant {
+            index.retain(|entry| entry.tenant.as_ref() == Some(tenant));
+        }
+        Ok(index)
+    }
+
+    /// Fetches the notes of the index entries, skipping notes deleted in
+    /// the meantime.
+    async fn fetch_notes(
+        &self,
+        ctx: &OpContext,
+        entries: Vec<IndexEntry>,
+    ) -> Result<Vec<Note>, NoteDbError> {
+        let notes: Vec<Option<Note>> = stream::iter(entries)
+            .map(|entry| async move { self.get_note(ctx, &entry.id).await })
+            .buffered(LIST_CONCURRENCY)
+            .try_collect()
+            .await?;
+        Ok(notes.into_iter().flatten().collect())
+    }
+
+    async fn read_orgs(&self) -> Result<Vec<Organization>, NoteDbError> {
+        let all = self.read_json(&Path::from(ORGS_OBJECT)).await?;
+        Ok(all.map(|(all, _)| all).unwrap_or_default())
+    }
+
+    async fn update_index<F>(&self, update: F) -> Result<(), NoteDbError>
+    where
+        F: Fn(&mut Vec<IndexEntry>),
+    {
+        self.update_json(&Path::from(INDEX_OBJECT), update).await
+    }
+}
+
+#[async_trait]
+impl NoteDb for NoteS3Db {
+    async fn create_note(
+        &self,
+        _ctx: &OpContext,
+        note: &Note,
+    ) -> Result<(), NoteDbError> {
+        self.write_json(&Self::note_path(&note.id), note, PutMode::Create)
+            .await?;
+        self.update_index(|index| {
+            index.push(IndexEntry {
+                id: note.id.clone(),
+                title: note.title.clone(),
+                owner: note.owner.clone(),
+                tenant: note.tenant.clone(),
+                org: note.org.clone(),
+                readers: readers(note),
+            })
+        })
+        .await
+    }
+
+    async fn get_note(
+        &self,
+        ctx: &OpContext,
+        id: &str,
+    ) -> Result<Option<Note>, NoteDbError> {
+        let note = self.read_json(&Self::note_path(id)).await?;
+        Ok(note
+            .map(|(note, _)| note)
+            .filter(|note| ctx.can_access(note)))
+    }
+
+    async fn update_note(
+        &self,
+        ctx: &OpContext,
+        id: &str,
+        note: &PatchNote,
+    ) -> Result<(), NoteDbError> {
+        let path = Self::note_path(id);
+        for _ in 0..CONDITIONAL_WRITE_RETRIES {
+            let Some((mut current, version)) = self
+                .read_json::<Note>(&path)
+                .await?
+                .filter(|(current, _)| ctx.can_access(current))
+            else {
+                return Err(NoteDbError::NotFound);
+            };
+            note.apply_to(&mut current)?;
+            match self
+                .write_json(&path, &current, PutMode::Update(version))
+                .await
+            {
+                Ok(()) => {
+                    if note.title.is_none() && note.permissions.is_none() {
+                        return Ok(());
+                    }
+                    return self
+                        .update_index(|index| {
+                            for entry in index.iter_mut().filter(|e| e.id == id)
+                            {
+                                entry.title = current.title.clone();
+                                entry.readers = readers(&current);
+                            }
+                        })
+                        .await;
+                }
+                Err(object_store::Error::Precondition { .. }) => {
+                    tracing::debug!("retry update of note {}", id);
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+        Err(NoteDbError::Conflict(format!(
+            "too much contention updating note {}",
+            id
+        )))
+    }
+
+    async fn delete_note(
+        &self,
+        ctx: &OpContext,
+        id: &str,
+    ) -> Result<bool, NoteDbError> {
+        if self.get_note(ctx, id).await?.is_none() {
+            return Ok(false);
+        }
+        self.store.delete(&Self::note_path(id)).await?;
+        self.update_index(|index| index.retain(|e| e.id != id))
+            .await?;
+        Ok(true)
+    }
+
+    async fn list_notes(
+        &self,
+        ctx: &OpContext,
+        filter: &NoteFilter,
+        page: &Page,
+    ) -> Result<NotePage, NoteDbError> {
+        let index = self.read_index(ctx).await?;
+        // Without a filter the index alone decides what is on the page, so
+        // only the notes on it need to be fetched.
+        if *filter == NoteFilter::default() {
+            let total = index.len() as u64;
+            let notes = self.fetch_notes(ctx, page.slice(index)).await?;
+            return Ok(NotePage { notes, total });
+        }
+        let notes: Vec<Note> = self
+            .fetch_notes(ctx, index)
+            .await?
+            .into_iter()
+            .filter(|note| filter.matches(note))
+            .collect();
+        Ok(NotePage {
+            total: notes.len() as u64,
+            notes: page.slice(notes),
+        })
+    }
+
+    async fn suggest_titles(
+        &self,
+        ctx: &OpContext,
+        prefix: &str,
+        limit: usize,
+    ) -> Result<Vec<NoteSuggestion>, NoteDbError> {
+        let candidates =
+            self.read_index(ctx)
+                .await?
+                .into_iter()
+                .map(|e| NoteSuggestion {
+                    id: e.id,
+                    title: e.title,
+                });
+        Ok(rank_suggestions(prefix, candidates, limit))
+    }
+
+    async fn record_revision(
+        &self,
+        _ctx: &OpContext,
+        _note: &Note,
+    ) -> Result<u64, NoteDbError> {
+        Err(NoteDbError::Unsupported("note revisions"))
+    }
+
+    async fn list_revisions(
+        &self,
+        _ctx: &OpContext,
+        _note_id: &str,
+    ) -> Result<Vec<NoteRevision>, NoteDbError> {
+        Err(NoteDbError::Unsupported("note revisions"))
+    }
+}
+
+#[async_trait]
+impl AnnouncementDb for NoteS3Db {
+    async fn create_announcement(
+        &self,
+        announcement: &Announcement,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.update_json(
+            &Path::from(ANNOUNCEMENTS_OBJECT),
+            |all: &mut Vec<Announcement>| all.push(announcement.clone()),
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn delete_announcement(
+        &self,
+        id: &str,
+    ) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+        let exists =
+            self.list_announcements().await?.iter().any(|a| a.id == id);
+        if !exists {
+            return Ok(false);
+        }
+        self.update_json(
+            &Path::from(ANNOUNCEMENTS_OBJECT),
+            |all: &mut Vec<Announcement>| all.retain(|a| a.id != id),
+        )
+        .await?;
+        Ok(true)
+    }
+
+    async fn list_announcements(
+        &self,
+    ) -> Result<Vec<Announcement>, Box<dyn std::error::Error + Send + Sync>>
+    {
+        let all = self.read_json(&Path::from(ANNOUNCEMENTS_OBJECT)).await?;
+        Ok(all.map(|(all, _)| all).unwrap_or_default())
+    }
+}
+
+/// One object per key. Expired objects stay behind until a bucket
+/// lifecycle rule on the `idempotency/` prefix removes them.
+#[async_trait]
+impl IdempotencyDb for NoteS3Db {
+    async fn record_key(
+        &self,
+        record: &IdempotencyRecord,
+    ) -> Result<(), NoteDbError> {
+        let path =
+            Path::from_iter([IDEMPOTENCY_DIR, &format!("{}.json", record.key)]);
+        self.write_json(&path, record, PutMode::Overwrite).await?;
+        Ok(())
+    }
+
+    async fn find_key(
+        &self,
+        key: &str,
+        now: DateTime<Utc>,
+    ) -> Result<Option<String>, NoteDbError> {
+        let path = Path::from_iter([IDEMPOTENCY_DIR, &format!("{}.json", key)]);
+        let record = self.read_json::<IdempotencyRecord>(&path).await?;
+        Ok(record
+            .filter(|(record, _)| now < record.expires_at)
+            .map(|(record, _)| record.note_id))
+    }
+}
+
+/// All keys in one object, there are few of them.
+#[async_trait]
+impl ApiKeyDb for NoteS3Db {
+    async fn create_api_key(
+        &self,
+        api_key: &ApiKey,
+    ) -> Result<(), NoteDbError> {
+        self.update_json(
+            &Path::from(API_KEYS_OBJECT),
+            |all: &mut Vec<ApiKey>| all.push(api_key.clone()),
+        )
+        .await
+    }
+
+    async fn find_api_key(
+        &self,
+        key_hash: &str,
+    ) -> Result<Option<ApiKey>, NoteDbError> {
+        Ok(self
+            .list_api_keys()
+            .await?
+            .into_iter()
+            .find(|api_key| api_key.key_hash == key_hash))
+    }
+
+    async fn list_api_keys(&self) -> Result<Vec<ApiKey>, NoteDbError> {
+        let all = self.read_json(&Path::from(API_KEYS_OBJECT)).await?;
+        Ok(all.map(|(all, _)| all).unwrap_or_default())
+    }
+
+    async fn delete_api_key(&self, id: &str) -> Result<bool, NoteDbError> {
+        if !self.list_api_keys().await?.iter().any(|k| k.id == id) {
+            return Ok(false);
+        }
+        self.update_json(
+            &Path::from(API_KEYS_OBJECT),
+            |all: &mut Vec<ApiKey>| all.retain(|k| k.id != id),
+        )
+        .await?;
+        Ok(true)
+    }
+}
+
+#[async_trait]
+impl OrgDb for NoteS3Db {
+    async fn create_org(&self, org: &Organization) -> Result<(), NoteDbError> {
+        self.update_json(
+            &Path::from(ORGS_OBJECT),
+            |all: &mut Vec<Organization>| all.push(org.clone()),
+        )
+        .await
+    }
+
+    async fn get_org(
+        &self,
+        id: &str,
+    ) -> Result<Option<Organization>, NoteDbError> {
+        Ok(self.read_orgs().await?.into_iter().find(|org| org.id == id))
+    }
+
+    async fn list_orgs(
+        &self,
+        member: &str,
+    ) -> Result<Vec<Organization>, NoteDbError> {
+        let mut orgs = self.read_orgs().await?;
+        orgs.retain(|org| org.has_member(member));
+        Ok(orgs)
+    }
+
+    async fn add_member(
+        &self,
+        id: &str,
+        member: &str,
+    ) -> Result<bool, NoteDbError> {
+        if self.get_org(id).await?.is_none() {
+            return Ok(false);
+        }
+        self.update_json(
+            &Path::from(ORGS_OBJECT),
+            |all: &mut Vec<Organization>| {
+                for org in all.iter_mut().filter(|org| org.id == id) {
+                    if !org.has_member(member) {
+                        org.members.push(member.to_string());
+                    }
+                }
+            },
+        )
+        .await?;
+        Ok(true)
+    }
+
+    async fn remove_member(
+        &self,
+        id: &str,
+        member: &str,
+    ) -> Result<bool, NoteDbError> {
+        if self.get_org(id).await?.is_none() {
+            return Ok(false);
+        }
+        self.update_json(
+            &Path::from(ORGS_OBJECT),
+            |all: &mut Vec<Organization>| {
+                for org in all.iter_mut().filter(|org| org.id == id) {
+                    org.members.retain(|m| m != member);
+                }
+            },
+        )
+        .await?;
+        Ok(true)
+    }
+}
+
+#[async_trait]
+impl ShareDb for NoteS3Db {
+    async fn create_share(&self, share: &Share) -> Result<(), NoteDbError> {
+        self.update_json(&Path::from(SHARES_OBJECT), |all: &mut Vec<Share>| {
+            all.push(share.clone())
+        })
+        .await
+    }
+
+    async fn find_share(
+        &self,
+        token_hash: &str,
+    ) -> Result<Option<Share>, NoteDbError> {
+        let all: Option<(Vec<Share>, _)> =
+            self.read_json(&Path::from(SHARES_OBJECT)).await?;
+        Ok(all
+            .map(|(all, _)| all)
+            .unwrap_or_default()
+            .into_iter()
+            .find(|share| share.token_hash == token_hash))
+    }
+
+    async fn delete_share(
+        &self,
+        note_id: &str,
+        id: &str,
+    ) -> Result<bool, NoteDbError> {
+        let matches =
+            |share: &Share| share.id == id && share.note_id == note_id;
+        let all: Option<(Vec<Share>, _)> =
+            self.read_json(&Path::from(SHARES_OBJECT)).await?;
+        if !all.is_some_and(|(all, _)| all.iter().any(matches)) {
+            return Ok(false);
+        }
+        self.update_json(&Path::from(SHARES_OBJECT), |all: &mut Vec<Share>| {
+            all.retain(|share| !matches(share))
+        })
+        .await?;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use object_store::memory::InMemory;
+
+    fn create_test_db() -> NoteS3Db {
+        NoteS3Db::new(Arc::new(InMemory::new()))
+    }
+
+    #[tokio::test]
+    async fn it_stores_notes_as_objects() {
+        let ctx = OpContext::background();
+        let db = create_test_db();
+        let note = Note::new("note", "body", "url");
+
+        db.create_note(&ctx, &note).await.unwrap();
+        db.update_note(
+            &ctx,
+            &note.id,
+            &PatchNote {
+                title: Some("newtitle".to_string()),
+                body: None,
+                tags: None,
+                updated_at: chrono::Utc::now(),
+                version: None,
+                permissions: None,
+            },
+        )
+        .await
+        .unwrap();
+
+        let stored = db.get_note(&ctx, &note.id).await.unwrap().unwrap();
+        assert_eq!(stored.title, "newtitle");
+        assert_eq!(stored.body, "body");
+        let suggestions = db.suggest_titles(&ctx, "new", 10).await.unwrap();
+        assert_eq!(suggestions.len(), 1);
+        assert!(db.delete_note(&ctx, &note.id).await.unwrap());
+        assert!(!db.delete_note(&ctx, &note.id).await.unwrap());
+        assert!(db.get_note(&ctx, &note.id).await.unwrap().is_none());
+        let listed = db
+            .list_notes(&ctx, &NoteFilter::default(), &Page::default())
+            .await
+            .unwrap();
+        assert_eq!(listed.total, 0);
+    }
+
+    #[tokio::test]
+    async fn it_keeps_the_index_consistent_under_concurrent_creates() {
+        let ctx = OpContext::background();
+        let db = create_test_db();
+
+        let creates = (0..20).map(|i| {
+            let db = db.clone();
+            let ctx = ctx.clone();
+            tokio::spawn(async move {
+                let note = Note::new(&format!("note{}", i), "body", "url");
+                db.create_note(&ctx, &note).await.unwrap();
+            })
+        });
+        futures::future::join_all(creates).await;
+
+        let page = Page {
+            limit: Some(5),
+            offset: Some(10),
+        };
+        let listed = db
+            .list_notes(&ctx, &NoteFilter::default(), &page)
+            .await
+            .unwrap();
+        assert_eq!(listed.total, 20);
+        assert_eq!(listed.notes.len(), 5);
+    }
+}
@@ -0,0 +1,251 @@
+//! A `NoteDb` combinator that checks a sample of reads against a secondary
+//! backend, e.g. a mirror, and reports when the two have drifted apart.
+//! The shadow read runs in the background and never affects the response.
+
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+use async_trait::async_trait;
+use serde::Serialize;
+
+use crate::context::OpContext;
+use crate::jobs::Job;
+use crate::metrics::{
+    SHADOW_READS_SAMPLED, SHADOW_READ_ERRORS, SHADOW_READ_MISMATCHES,
+};
+use crate::notes::{
+    Note, NoteDb, NoteDbError, NoteFilter, NotePage, NoteRevision,
+    NoteSuggestion, Page, PatchNote,
+};
+
+#[derive(Default)]
+struct ShadowCounters {
+    reads: AtomicU64,
+    sampled: AtomicU64,
+    mismatches: AtomicU64,
+    errors: AtomicU64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ShadowReadStats {
+    pub sampled: u64,
+    pub mismatches: u64,
+    pub errors: u64,
+}
+
+pub struct ShadowReadNoteDb<P: NoteDb + ?Sized, S: NoteDb + ?Sized> {
+    primary: Arc<P>,
+    secondary: Arc<S>,
+    sample_percent: u64,
+    counters: Arc<ShadowCounters>,
+}
+
+impl<P, S> ShadowReadNoteDb<P, S>
+where
+    P: NoteDb + ?Sized,
+    S: NoteDb + ?Sized + 'static,
+{
+    /// Checks `sample_percent` (capped at 100) of all reads.
+    pub fn new(
+        primary: Arc<P>,
+        secondary: Arc<S>,
+        sample_percent: u64,
+    ) -> Self {
+        ShadowReadNoteDb {
+            primary,
+            secondary,
+            sample_percent: sample_percent.min(100),
+            counters: Arc::new(ShadowCounters::default()),
+        }
+    }
+
+    pub fn stats(&self) -> ShadowReadStats {
+        ShadowReadStats {
+            sampled: self.counters.sampled.load(Ordering::SeqCst),
+            mismatches: self.counters.mismatches.load(Ordering::SeqCst),
+            errors: self.counters.errors.load(Ordering::SeqCst),
+        }
+    }
+
+    // Samples exactly `sample_percent` out of every 100 reads.
+    fn sample(&self) -> bool {
+        let n = self.counters.reads.fetch_add(1, Ordering::SeqCst);
+        (n + 1) * self.sample_percent / 100 != n * self.sample_percent / 100
+    }
+
+    fn check_note(&self, ctx: &OpContext, id: &str, expected: &Option<Note>) {
+        let ctx = detached(ctx);
+        let secondary = self.secondary.clone();
+        let counters = self.counters.clone();
+        let id = id.to_string();
+        let expected = expected.clone();
+        counters.sampled.fetch_add(1, Ordering::SeqCst);
+        SHADOW_READS_SAMPLED.increment();
+        let job = Job::new("shadow_read").caused_by(ctx.request_id.as_deref());
+        tokio::spawn(job.run(async move {
+            match secondary.get_note(&ctx, &id).await {
+                Ok(actual) if actual == expected => {}
+                Ok(_) => {
+                    counters.mismatches.fetch_add(1, Ordering::SeqCst);
+                    SHADOW_READ_MISMATCHES.increment();
+                    tracing::warn!("shadow read mismatch for note {}", id);
+                }
+                Err(err) => {
+                    counters.errors.fetch_add(1, Ordering::SeqCst);
+                    SHADOW_READ_ERRORS.increment();
+                    tracing::error!(
+                        "shadow read of note {} failed: {}",
+                        id,
+                        err
+                    );
+                }
+            }
+        }));
+    }
+
+    fn check_list(
+        &self,
+        ctx: &OpContext,
+        filter: &NoteFilter,
+        page: &Page,
+        expected: &NotePage,
+    ) {
+        let secondary = self.secondary.clone();
+        let counters = self.counters.clone();
+        let filter = filter.clone();
+        let ctx = detached(ctx);
+        let page = *page;
+        let expected = expected.clone();
+        counters.sampled.fetch_add(1, Ordering::SeqCst);
+        SHADOW_READS_SAMPLED.increment();
+        let job = Job::new("shadow_read").caused_by(ctx.request_id.as_deref());
+        tokio::spawn(job.run(async move {
+            match secondary.list_notes(&ctx, &filter, &page).await {
+                Ok(actual) if actual.same_notes(&expected) => {}
+                Ok(_) => {
+                    counters.mismatches.fetch_add(1, Ordering::SeqCst);
+                    SHADOW_READ_MISMATCHES.increment();
+                    tracing::warn!(
+                        "shadow read mismatch for list {:?}",
+                        filter
+                    );
+                }
+                Err(err) => {
+                    counters.errors.fetch_add(1, Ordering::SeqCst);
+                    SHADOW_READ_ERRORS.increment();
+                    tracing::error!("shadow read of list failed: {}", err);
+                }
+            }
+        }));
+    }
+}
+
+#[async_trait]
+impl<P, S> NoteDb for ShadowReadNoteDb<P, S>
+where
+    P: NoteDb + ?Sized,
+    S: NoteDb + ?Sized + 'static,
+{
+    async fn create_note(
+        &self,
+        ctx: &OpContext,
+        note: &Note,
+    ) -> Result<(), NoteDbError> {
+        self.primary.create_note(ctx, note).await
+    }
+
+    async fn get_note(
+        &self,
+        ctx: &OpContext,
+        id: &str,
+    ) -> Result<Option<Note>, NoteDbError> {
+        let note = self.primary.get_note(ctx, id).await?;
+        if self.sample() {
+            self.check_note(ctx, id, &note);
+        }
+        Ok(note)
+    }
+
+    async fn get_notes(
+        &self,
+        ctx: &OpContext,
+        ids: &[String],
+    ) -> Result<Vec<Note>, NoteDbError> {
+        self.primary.get_notes(ctx, ids).await
+    }
+
+    async fn update_note(
+        &self,
+        ctx: &OpContext,
+        id: &str,
+        note: &PatchNote,
+    ) -> Result<(), NoteDbError> {
+        self.primary.update_note(ctx, id, note).await
+    }
+
+    async fn delete_note(
+        &self,
+        ctx: &OpContext,
+        id: &str,
+    ) -> Result<bool, NoteDbError> {
+        self.primary.delete_note(ctx, id).await
+    }
+
+    async fn list_notes(
+        &self,
+        ctx: &OpContext,
+        filter: &NoteFilter,
+        page: &Page,
+    ) -> Result<NotePage, NoteDbError> {
+        let notes = self.primary.list_notes(ctx, filter, page).await?;
+        if self.sample() {
+            self.check_list(ctx, filter, page, &notes);
+        }
+        Ok(notes)
+    }
+
+    async fn suggest_titles(
+        &self,
+        ctx: &OpContext,
+        prefix: &str,
+        limit: usize,
+    ) -> Result<Vec<NoteSuggestion>, NoteDbError> {
+        self.primary.suggest_titles(ctx, prefix, limit).await
+    }
+
+    async fn record_revision(
+        &self,
+        ctx: &OpContext,
+        note: &Note,
+    ) -> Result<u64, NoteDbError> {
+        self.primary.record_revision(ctx, note).await
+    }
+
+    async fn list_revisions(
+        &self,
+        ctx: &OpContext,
+        note_id: &str,
+    ) -> Result<Vec<NoteRevision>, NoteDbError> {
+        self.primary.list_revisions(ctx, note_id).await
+    }
+
+    async fn get_revision(
+        &self,
+        ctx: &OpContext,
+        note_id: &str,
+        rev: u64,
+    ) -> Result<Option<NoteRevision>, NoteDbError> {
+        self.primary.get_revision(ctx, note_id, rev).await
+    }
+}
+
+/// Shadow reads run after the response was sent, so the request deadline
+/// no longer applies to them.
+fn detached(ctx: &OpContext) -> OpContext {
+    OpContext {
+        deadline: None,
+        ..ctx.clone()
+    }
+}
@@ -0,0 +1,233 @@
+//! A `NoteDb` combinator that runs every call in a span of its own, so that
+//! traces of requests break down how long the database took.
+
+use std::{future::Future, sync::Arc};
+
+use async_trait::async_trait;
+use tracing::{field, Instrument};
+
+use crate::context::OpContext;
+use crate::notes::{
+    Note, NoteDb, NoteDbError, NoteFilter, NotePage, NoteRevision,
+    NoteSuggestion, Page, PatchNote,
+};
+
+pub struct TracedNoteDb<D: NoteDb + ?Sized> {
+    inner: Arc<D>,
+}
+
+impl<D: NoteDb + ?Sized> TracedNoteDb<D> {
+    pub fn new(inner: Arc<D>) -> Self {
+        TracedNoteDb { inner }
+    }
+}
+
+/// Awaits `call` in a span named after `operation`, marked as failed if
+/// the call fails.
+async fn traced<T>(
+    operation: &'static str,
+    call: impl Future<Output = Result<T, NoteDbError>>,
+) -> Result<T, NoteDbError> {
+    let span = tracing::info_span!(
+        "note_db",
+        otel.name = operation,
+        db.operation = operation,
+        otel.status_code = field::Empty,
+    );
+    let result = call.instrument(span.clone()).await;
+    if result.is_err() {
+        span.record("otel.status_code", "error");
+    }
+    result
+}
+
+#[async_trait]
+impl<D: NoteDb + ?Sized> NoteDb for TracedNoteDb<D> {
+    async fn create_note(
+        &self,
+        ctx: &OpContext,
+        note: &Note,
+    ) -> Result<(), NoteDbError> {
+        traced("create_note", self.inner.create_note(ctx, note)).await
+    }
+
+    async fn create_notes(
+        &self,
+        ctx: &OpContext,
+        notes: &[Note],
+    ) -> Result<(), NoteDbError> {
+        traced("create_notes", self.inner.create_notes(ctx, notes)).await
+    }
+
+    async fn get_note(
+        &self,
+        ctx: &OpContext,
+        id: &str,
+    ) -> Result<Option<Note>, NoteDbError> {
+        traced("get_note", self.inner.get_note(ctx, id)).await
+    }
+
+    async fn get_notes(
+        &self,
+        ctx: &OpContext,
+        ids: &[String],
+    ) -> Result<Vec<Note>, NoteDbError> {
+        traced("get_notes", self.inner.get_notes(ctx, ids)).await
+    }
+
+    async fn update_note(
+        &self,
+        ctx: &OpContext,
+        id: &str,
+        note: &PatchNote,
+    ) -> Result<(), NoteDbError> {
+        traced("update_note", self.inner.update_note(ctx, id, note)).await
+    }
+
+    async fn delete_note(
+        &self,
+        ctx: &OpContext,
+        id: &str,
+    ) -> Result<bool, NoteDbError> {
+        traced("delete_note", self.inner.delete_note(ctx, id)).await
+    }
+
+    async fn list_notes(
+        &self,
+        ctx: &OpContext,
+        filter: &NoteFilter,
+        page: &Page,
+    ) -> Result<NotePage, NoteDbError> {
+        traced("list_notes", self.inner.list_notes(ctx, filter, page)).await
+    }
+
+    async fn suggest_titles(
+        &self,
+        ctx: &OpContext,
+        prefix: &str,
+        limit: usize,
+    ) -> Result<Vec<NoteSuggestion>, NoteDbError> {
+        let call = self.inner.suggest_titles(ctx, prefix, limit);
+        traced("suggest_titles", call).await
+    }
+
+    async fn find_by_title(
+        &self,
+        ctx: &OpContext,
+        title: &str,
+    ) -> Result<Vec<NoteSuggestion>, NoteDbError> {
+        traced("find_by_title", self.inner.find_by_title(ctx, title)).await
+    }
+
+    async fn record_revision(
+        &self,
+        ctx: &OpContext,
+        note: &Note,
+    ) -> Result<u64, NoteDbError> {
+        traced("record_revision", self.inner.record_revision(ctx, note)).await
+    }
+
+    async fn list_revisions(
+        &self,
+        ctx: &OpContext,
+        note_id: &str,
+    ) -> Result<Vec<NoteRevision>, NoteDbError> {
+        let call = self.inner.list_revisions(ctx, note_id);
+        traced("list_revisions", call).await
+    }
+
+    async fn get_revision(
+        &self,
+        ctx: &OpContext,
+        note_id: &str,
+        rev: u64,
+    ) -> Result<Option<NoteRevision>, NoteDbError> {
+        let call = self.inner.get_revision(ctx, note_id, rev);
+        traced("get_revision", call).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use chrono::Utc;
+    use tracing::{
+        field::{Field, Visit},
+        span::{Attributes, Id, Record},
+        Subscriber,
+    };
+    use tracing_subscriber::{
+        layer::{Context, SubscriberExt},
+        Layer,
+    };
+
+    use super::*;
+    use crate::persistency::memory::NoteMemoryDb;
+
+    /// Records the string fields of all spans, in the order they are set.
+    #[derive(Clone, Default)]
+    struct SpanFields(Arc<Mutex<Vec<(String, String)>>>);
+
+    impl Visit for SpanFields {
+        fn record_str(&mut self, field: &Field, value: &str) {
+            let mut fields = self.0.lock().unwrap();
+            fields.push((field.name().to_string(), value.to_string()));
+        }
+
+        fn record_debug(&mut self, _: &Field, _: &dyn std::fmt::Debug) {}
+    }
+
+    impl<S: Subscriber> Layer<S> for SpanFields {
+        fn on_new_span(&self, attrs: &Attributes<'_>, _: &Id, _: Context<S>) {
+            attrs.record(&mut self.clone());
+        }
+
+        fn on_record(&self, _: &Id, values: &Record<'_>, _: Context<S>) {
+            values.record(&mut self.clone());
+        }
+    }
+
+    #[tokio::test]
+    async fn it_traces_every_call() {
+        // Setup
+        let ctx = OpContext::background();
+        let fields = SpanFields::default();
+        let subscriber = tracing_subscriber::registry().with(fields.clone());
+        let _default = tracing::subscriber::set_default(subscriber);
+        let db = TracedNoteDb::new(Arc::new(NoteMemoryDb::new()));
+        let note = Note::new("a", "b", "/notes/1");
+        let patch = PatchNote {
+            title: Some("c".to_string()),
+            body: None,
+            tags: None,
+            updated_at: Utc::now(),
+            version: None,
+            permissions: None,
+        };
+
+        // Execute
+        db.create_note(&ctx, &note).await.unwrap();
+        db.update_note(&ctx, "unknown", &patch).await.unwrap_err();
+
+        // Assert
+        let operations: Vec<(String, String)> = fields
+            .0
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(name, _)| name != "otel.name")
+            .cloned()
+            .collect();
+        let field =
+            |name: &str, value: &str| (name.to_string(), value.to_string());
+        assert_eq!(
+            operations,
+            vec![
+                field("db.operation", "create_note"),
+                field("db.operation", "update_note"),
+                field("otel.status_code", "error"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,137 @@
+//! Correlation ids for requests. Every request gets an `X-Request-Id`,
+//! taken from the client if it sent a usable one. The id is part of the
+//! request's span, is echoed in the response and is added to the body of
+//! error responses, so that a client's report can be matched with the
+//! server's logs.
+
+use axum::{
+    body::Body,
+    extract::{MatchedPath, Request},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    middleware::{self, Next},
+    response::Response,
+    Router,
+};
+use http_body_util::BodyExt;
+use nanoid::nanoid;
+use serde_json::Value;
+use tracing::Span;
+
+use crate::context::REQUEST_ID_HEADER;
+use crate::problem::Problem;
+
+const PROBLEM_CONTENT_TYPE: &str = "application/problem+json";
+// Longer ids are replaced, they would only bloat the logs.
+const MAX_CLIENT_ID_LEN: usize = 128;
+
+/// Assigns request ids to all requests of `router`. Apply it outside of
+/// the trace layer, whose spans pick up the id.
+pub fn apply(router: Router) -> Router {
+    router.layer(middleware::from_fn(assign_request_id))
+}
+
+/// The span of a request, named like the default of the trace layer and
+/// additionally carrying the request id. Exported traces name it after the
+/// method and the route.
+pub fn make_span(request: &Request) -> Span {
+    let route = request.extensions().get::<MatchedPath>();
+    let name = match route {
+        Some(route) => format!("{} {}", request.method(), route.as_str()),
+        None => request.method().to_string(),
+    };
+    tracing::info_span!(
+        "request",
+        otel.name = name,
+        otel.kind = "server",
+        method = %request.method(),
+        uri = %request.uri(),
+        version = ?request.version(),
+        request_id = request_id(request.headers()).unwrap_or_default(),
+    )
+}
+
+fn request_id(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| {
+            !id.is_empty()
+                && id.len() <= MAX_CLIENT_ID_LEN
+                && id.chars().all(|c| c.is_ascii_graphic())
+        })
+}
+
+async fn assign_request_id(mut request: Request, next: Next) -> Response {
+    let id = match request_id(request.headers()) {
+        Some(id) => id.to_string(),
+        None => nanoid!(),
+    };
+    // Ids are ASCII, either checked above or generated.
+    let value = HeaderValue::from_str(&id).expect("request id is ASCII");
+    request
+        .headers_mut()
+        .insert(REQUEST_ID_HEADER, value.clone());
+    let response = next.run(request).await;
+    let mut response = match response.status() {
+        status if status.is_client_error() || status.is_server_error() => {
+            with_request_id(response, &id).await
+        }
+        _ => response,
+    };
+    response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    response
+}
+
+/// Adds the request id to the body of an error response. JSON objects get
+/// a `request_id` member, empty and plain text bodies are replaced by a
+/// problem carrying it. Other bodies are left as they are.
+async fn with_request_id(response: Response, id: &str) -> Response {
+    let (mut parts, body) = response.into_parts();
+    let bytes = match body.collect().await {
+        Ok(collected) => collected.to_bytes(),
+        Err(err) => {
+            tracing::error!("unable to read error response body: {}", err);
+            return Response::from_parts(parts, Body::empty());
+        }
+    };
+    let content_type = parts
+        .headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    let is_json = content_type.starts_with("application/json")
+        || content_type.starts_with(PROBLEM_CONTENT_TYPE);
+    let is_text = bytes.is_empty() || content_type.starts_with("text/plain");
+    let body = match serde_json::from_slice(&bytes) {
+        Ok(Value::Object(mut fields)) if is_json => {
+            fields.insert("request_id".to_string(), id.into());
+            serde_json::to_vec(&fields)
+        }
+        _ if is_text => {
+            let detail = match bytes.is_empty() {
+                true => parts.status.canonical_reason().unwrap_or_default(),
+                false => std::str::from_utf8(&bytes).unwrap_or_default(),
+            };
+            let problem = Problem::new(parts.status, detail)
+                .with_extension("request_id", id);
+            parts.headers.insert(
+                header::CONTENT_TYPE,
+                HeaderValue::from_static(PROBLEM_CONTENT_TYPE),
+            );
+            serde_json::to_vec(&problem)
+        }
+        _ => return Response::from_parts(parts, Body::from(bytes)),
+    };
+    match body {
+        Ok(body) => {
+            parts.headers.remove(header::CONTENT_LENGTH);
+            Response::from_parts(parts, Body::from(body))
+        }
+        Err(err) => {
+            tracing::error!("unable to add request id to response: {}", err);
+            let mut response = Response::new(Body::empty());
+            *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+            response
+        }
+    }
+}
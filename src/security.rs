@@ -0,0 +1,76 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderValue},
+    middleware::{self, Next},
+    response::Response,
+    Router,
+};
+
+/// Standard security headers added to every response of a route group.
+/// Headers already set by a handler are left untouched.
+#[derive(Debug, Clone)]
+pub struct SecurityHeaders {
+    pub content_security_policy: Option<HeaderValue>,
+    pub referrer_policy: Option<HeaderValue>,
+    /// `max-age` for `Strict-Transport-Security`. Only set this when the
+    /// service is reached over TLS, browsers pin the policy for the host.
+    pub hsts_max_age: Option<u64>,
+}
+
+impl Default for SecurityHeaders {
+    fn default() -> Self {
+        // The API only serves JSON, so nothing may be loaded or framed.
+        SecurityHeaders {
+            content_security_policy: Some(HeaderValue::from_static(
+                "default-src 'none'; frame-ancestors 'none'",
+            )),
+            referrer_policy: Some(HeaderValue::from_static("no-referrer")),
+            hsts_max_age: None,
+        }
+    }
+}
+
+impl SecurityHeaders {
+    pub fn apply<S>(&self, router: Router<S>) -> Router<S>
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        router.layer(middleware::from_fn_with_state(
+            Arc::new(self.clone()),
+            set_security_headers,
+        ))
+    }
+}
+
+async fn set_security_headers(
+    State(config): State<Arc<SecurityHeaders>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let mut response = next.run(request).await;
+    let headers = response.headers_mut();
+    headers
+        .entry(header::X_CONTENT_TYPE_OPTIONS)
+        .or_insert(HeaderValue::from_static("nosniff"));
+    if let Some(csp) = &config.content_security_policy {
+        headers
+            .entry(header::CONTENT_SECURITY_POLICY)
+            .or_insert(csp.clone());
+    }
+    if let Some(referrer_policy) = &config.referrer_policy {
+        headers
+            .entry(header::REFERRER_POLICY)
+            .or_insert(referrer_policy.clone());
+    }
+    if let Some(max_age) = config.hsts_max_age {
+        let hsts = format!("max-age={}; includeSubDomains", max_age);
+        if let Ok(hsts) = HeaderValue::try_from(hsts) {
+            headers
+                .entry(header::STRICT_TRANSPORT_SECURITY)
+                .or_insert(hsts);
+        }
+    }
+    response
+}
@@ -0,0 +1,95 @@
+//! Links that give anyone holding them read access to a single note,
+//! without authentication. Like API keys, only a hash of each token is
+//! stored and the token itself is shown once on creation. Deleting the
+//! share revokes the link.
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use nanoid::nanoid;
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::api_keys::hash_key;
+use crate::notes::NoteDbError;
+
+const TOKEN_PREFIX: &str = "ns_";
+const TOKEN_RANDOM_CHARS: usize = 40;
+
+/// A stored share of a note.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Share {
+    pub id: String,
+    pub note_id: String,
+    /// Tenant of the note, which the shared view reads from.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+    /// Hex encoded SHA-256 of the token.
+    pub token_hash: String,
+    pub created_at: DateTime<Utc>,
+    /// The link stops working at this time, if set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+impl Share {
+    /// Generates a share of a note and returns its record along with the
+    /// token.
+    pub fn generate(
+        note_id: &str,
+        tenant: Option<String>,
+        now: DateTime<Utc>,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> (Share, String) {
+        let token = format!("{}{}", TOKEN_PREFIX, nanoid!(TOKEN_RANDOM_CHARS));
+        let share = Share {
+            id: nanoid!(),
+            note_id: note_id.to_string(),
+            tenant,
+            token_hash: hash_key(&token),
+            created_at: now,
+            expires_at,
+        };
+        (share, token)
+    }
+
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ShareQuery {
+    /// Seconds until the link stops working. Links without one work until
+    /// revoked.
+    pub expires_in_secs: Option<u64>,
+}
+
+/// A newly created share. The token can't be retrieved again.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct CreatedShare {
+    pub id: String,
+    pub note_id: String,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub token: String,
+    /// Path serving the shared note, relative to the API root.
+    pub url: String,
+}
+
+#[async_trait]
+pub trait ShareDb: Send + Sync {
+    async fn create_share(&self, share: &Share) -> Result<(), NoteDbError>;
+
+    /// The share with the given token hash, if it exists.
+    async fn find_share(
+        &self,
+        token_hash: &str,
+    ) -> Result<Option<Share>, NoteDbError>;
+
+    /// Deletes a share of the given note, false if there is none.
+    async fn delete_share(
+        &self,
+        note_id: &str,
+        id: &str,
+    ) -> Result<bool, NoteDbError>;
+}
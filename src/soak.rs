@@ -0,0 +1,339 @@
+//! Soak test of a running instance, to run before releases: random
+//! operations for as long as asked, each checked against what the instance
+//! must keep true. Notes are readable right after they are created and show
+//! their last update, deleted notes are gone and listings count what was
+//! created minus what was deleted.
+//!
+//! The notes of a run carry a tag of their own, so that other traffic
+//! doesn't disturb the checks, and are deleted at the end. Reads ask for
+//! strong consistency. Only built with the `loadgen` feature.
+
+use std::{
+    collections::BTreeMap,
+    fmt,
+    time::{Duration, Instant},
+};
+
+use nanoid::nanoid;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use reqwest::{header, Method, StatusCode};
+use serde_json::json;
+
+use crate::consistency::CONSISTENCY_HEADER;
+use crate::loadgen::{self, Client};
+use crate::notes::Note;
+use crate::TOTAL_COUNT_HEADER;
+
+#[derive(Debug, Clone)]
+pub struct SoakConfig {
+    /// API root of the instance, e.g. `http://localhost:3000/v1`.
+    pub base_url: String,
+    pub duration: Duration,
+    /// Runs with the same seed send the same operations.
+    pub seed: u64,
+    /// Bearer token sent with every request.
+    pub token: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SoakReport {
+    pub seed: u64,
+    pub operations: u64,
+    /// What the instance did wrong, in the order it was noticed.
+    pub violations: Vec<String>,
+}
+
+impl SoakReport {
+    pub fn passed(&self) -> bool {
+        self.violations.is_empty()
+    }
+}
+
+impl fmt::Display for SoakReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} operations with seed {}, {} violations",
+            self.operations,
+            self.seed,
+            self.violations.len()
+        )?;
+        for violation in &self.violations {
+            writeln!(f, "  {}", violation)?;
+        }
+        Ok(())
+    }
+}
+
+/// Runs the soak test of `config`. Requests that get no answer at all end
+/// the run with an error.
+pub async fn run(
+    config: &SoakConfig,
+) -> Result<SoakReport, Box<dyn std::error::Error>> {
+    let mut soak = Soak {
+        client: Client::new(&config.base_url, config.token.clone())?,
+        rng: StdRng::seed_from_u64(config.seed),
+        tag: format!("soak-{}", nanoid!(10, &nanoid::alphabet::SAFE)),
+        notes: BTreeMap::new(),
+        operations: 0,
+        violations: Vec::new(),
+    };
+    let started = Instant::now();
+    while started.elapsed() < config.duration {
+        soak.step().await?;
+    }
+    soak.clean_up().await?;
+    Ok(SoakReport {
+        seed: config.seed,
+        operations: soak.operations,
+        violations: soak.violations,
+    })
+}
+
+/// A note of the run as the instance should return it.
+struct Expected {
+    title: String,
+    body: String,
+    version: u64,
+}
+
+struct Soak {
+    client: Client,
+    rng: StdRng,
+    tag: String,
+    notes: BTreeMap<String, Expected>,
+    operations: u64,
+    violations: Vec<String>,
+}
+
+impl Soak {
+    fn request(&self, method: Method, path: &str) -> reqwest::RequestBuilder {
+        self.client
+            .request(method, path)
+            .header(CONSISTENCY_HEADER, "strong")
+    }
+
+    fn violation(&mut self, violation: String) {
+        self.violations.push(violation);
+    }
+
+    async fn step(&mut self) -> Result<(), reqwest::Error> {
+        self.operations += 1;
+        let roll = self.rng.gen_range(0..100);
+        let id = match self.notes.len() {
+            0 => None,
+            len => {
+                let index = self.rng.gen_range(0..len);
+                self.notes.keys().nth(index).cloned()
+            }
+        };
+        match (roll, id) {
+            (0..25, _) | (_, None) => self.create().await,
+            (25..50, Some(id)) => {
+                self.check_note(&id, "on read").await.map(|_| ())
+            }
+            (50..70, Some(id)) => self.update(&id).await,
+            (70..90, Some(id)) => self.delete(&id).await,
+            (_, Some(_)) => self.check_count().await,
+        }
+    }
+
+    async fn create(&mut self) -> Result<(), reqwest::Error> {
+        let n = self.operations as usize;
+        let title = format!("Soak note {}", n);
+        let body = loadgen::body(n);
+        let response = self
+            .request(Method::POST, "/notes")
+            .json(&json!({ "title": title, "body": body, "tags": [self.tag] }))
+            .send()
+            .await?;
+        if response.status() != StatusCode::CREATED {
+            let status = response.status();
+            self.violation(format!("create answered {}", status));
+            return Ok(());
+        }
+        let note: Note = response.json().await?;
+        let expected = Expected {
+            title,
+            body,
+            version: note.version,
+        };
+        self.notes.insert(note.id.clone(), expected);
+        self.check_note(&note.id, "after create").await?;
+        self.check_count().await
+    }
+
+    async fn update(&mut self, id: &str) -> Result<(), reqwest::Error> {
+        let Some(etag) = self.check_note(id, "before update").await? else {
+            return Ok(());
+        };
+        let body = loadgen::body(self.operations as usize);
+        let response = self
+            .request(Method::PATCH, &format!("/notes/{}", id))
+            .header(header::IF_MATCH, etag)
+            .json(&json!({ "body": body }))
+            .send()
+            .await?;
+        if response.status() != StatusCode::OK {
+            let status = response.status();
+            self.violation(format!(
+                "update of note {} answered {}",
+                id, status
+            ));
+            return Ok(());
+        }
+        let expected = self.notes.get_mut(id).expect("note of the run");
+        expected.body = body;
+        expected.version += 1;
+        self.check_note(id, "after update").await.map(|_| ())
+    }
+
+    async fn delete(&mut self, id: &str) -> Result<(), reqwest::Error> {
+        let path = format!("/notes/{}", id);
+        let status = self.request(Method::DELETE, &path).send().await?.status();
+        if status != StatusCode::NO_CONTENT {
+            self.violation(format!(
+                "delete of note {} answered {}",
+                id, status
+            ));
+            return Ok(());
+        }
+        self.notes.remove(id);
+        let status = self.request(Method::GET, &path).send().await?.status();
+        if status != StatusCode::NOT_FOUND {
+            self.violation(format!(
+                "get of note {} after delete answered {}",
+                id, status
+            ));
+        }
+        self.check_count().await
+    }
+
+    /// Reads the note `id` and compares it with what was written, returning
+    /// its ETag if it could be read.
+    async fn check_note(
+        &mut self,
+        id: &str,
+        when: &str,
+    ) -> Result<Option<String>, reqwest::Error> {
+        let response = self
+            .request(Method::GET, &format!("/notes/{}", id))
+            .send()
+            .await?;
+        if response.status() != StatusCode::OK {
+            let status = response.status();
+            self.violation(format!(
+                "get of note {} {} answered {}",
+                id, when, status
+            ));
+            return Ok(None);
+        }
+        let etag = response
+            .headers()
+            .get(header::ETAG)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+        let note: Note = response.json().await?;
+        let expected = &self.notes[id];
+        if note.title != expected.title
+            || note.body != expected.body
+            || note.version != expected.version
+        {
+            let violation = format!(
+                "note {} {} is version {} titled {:?}, expected version {} \
+                 titled {:?}",
+                id,
+                when,
+                note.version,
+                note.title,
+                expected.version,
+                expected.title
+            );
+            self.violation(violation);
+        }
+        Ok(etag)
+    }
+
+    /// Checks that listing the notes of the run counts all of them.
+    async fn check_count(&mut self) -> Result<(), reqwest::Error> {
+        let path = format!("/notes?tag={}&limit=1", self.tag);
+        let response = self.request(Method::GET, &path).send().await?;
+        let total = response
+            .headers()
+            .get(TOTAL_COUNT_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<usize>().ok());
+        if total != Some(self.notes.len()) {
+            let status = response.status();
+            self.violation(format!(
+                "listing counted {:?} notes with {}, expected {}",
+                total,
+                status,
+                self.notes.len()
+            ));
+        }
+        Ok(())
+    }
+
+    async fn clean_up(&mut self) -> Result<(), reqwest::Error> {
+        let ids: Vec<String> = self.notes.keys().cloned().collect();
+        for id in ids {
+            self.delete(&id).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use tokio::sync::Mutex;
+
+    use super::*;
+    use crate::persistency::memory::NoteMemoryDb;
+    use crate::{create_axum_app, AppConfig, AppState};
+
+    #[tokio::test]
+    async fn it_finds_no_violations_on_the_memory_backend() {
+        // Setup
+        let app_config = AppConfig::default();
+        let state = Arc::new(AppState {
+            notes: Arc::new(Mutex::new(NoteMemoryDb::new())),
+            announcements: Arc::new(NoteMemoryDb::new()),
+            idempotency: Arc::new(NoteMemoryDb::new()),
+            api_keys: Arc::new(NoteMemoryDb::new()),
+            orgs: Arc::new(NoteMemoryDb::new()),
+            shares: Arc::new(NoteMemoryDb::new()),
+            migration: None,
+            ingest: None,
+            notes_path: "/v1/notes".to_string(),
+            strict_json: app_config.strict_json,
+            note_limits: app_config.note_limits,
+            title_policy: app_config.title_policy,
+            require_if_match: app_config.require_if_match,
+            idempotency_ttl: app_config.idempotency_ttl,
+            default_page_size: app_config.default_page_size,
+            max_page_size: app_config.max_page_size,
+            archive_limits: app_config.archive_limits,
+        });
+        let app = create_axum_app(state, &app_config);
+        let listener =
+            tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        let config = SoakConfig {
+            base_url: format!("http://{}/v1", address),
+            duration: Duration::from_millis(300),
+            seed: 7,
+            token: None,
+        };
+
+        // Execute
+        let report = run(&config).await.unwrap();
+
+        // Assert
+        assert!(report.operations > 0);
+        assert_eq!(report.violations, Vec::<String>::new());
+    }
+}
@@ -0,0 +1,107 @@
+//! Classified startup failures, so that orchestration can tell a bad
+//! configuration from an unreachable database by the exit code alone.
+
+use std::fmt;
+
+/// Failure classes with their exit codes, taken from `sysexits.h`:
+///
+/// | kind            | code               | exit code |
+/// |-----------------|--------------------|-----------|
+/// | `Config`        | `E_CONFIG`         | 78        |
+/// | `Database`      | `E_DB_UNREACHABLE` | 69        |
+/// | `DatabaseSetup` | `E_DB_SETUP`       | 76        |
+/// | `Bind`          | `E_BIND`           | 71        |
+/// | `Serve`         | `E_SERVE`          | 70        |
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StartupErrorKind {
+    /// A setting is missing or invalid.
+    Config,
+    /// The database did not answer.
+    Database,
+    /// The database answered but could not be prepared, e.g. indexes.
+    DatabaseSetup,
+    /// The listener could not be bound, e.g. the port is in use.
+    Bind,
+    /// The server failed while serving.
+    Serve,
+}
+
+impl StartupErrorKind {
+    pub fn code(&self) -> &'static str {
+        match self {
+            StartupErrorKind::Config => "E_CONFIG",
+            StartupErrorKind::Database => "E_DB_UNREACHABLE",
+            StartupErrorKind::DatabaseSetup => "E_DB_SETUP",
+            StartupErrorKind::Bind => "E_BIND",
+            StartupErrorKind::Serve => "E_SERVE",
+        }
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            StartupErrorKind::Config => 78,
+            StartupErrorKind::Database => 69,
+            StartupErrorKind::DatabaseSetup => 76,
+            StartupErrorKind::Bind => 71,
+            StartupErrorKind::Serve => 70,
+        }
+    }
+
+    pub fn hint(&self) -> &'static str {
+        match self {
+            StartupErrorKind::Config => {
+                "check the NOTES_* environment variables"
+            }
+            StartupErrorKind::Database => {
+                "check that the database is running and NOTES_DB_ADDRESS \
+                 points to it"
+            }
+            StartupErrorKind::DatabaseSetup => {
+                "check that the database user may create indexes and \
+                 collections"
+            }
+            StartupErrorKind::Bind => {
+                "check that NOTES_HOST and NOTES_PORT are valid and the port \
+                 is not in use"
+            }
+            StartupErrorKind::Serve => "check the logs preceding the failure",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct StartupError {
+    pub kind: StartupErrorKind,
+    pub message: String,
+}
+
+impl StartupError {
+    pub fn new(kind: StartupErrorKind, message: impl fmt::Display) -> Self {
+        StartupError {
+            kind,
+            message: message.to_string(),
+        }
+    }
+
+    pub fn config(message: impl fmt::Display) -> Self {
+        StartupError::new(StartupErrorKind::Config, message)
+    }
+
+    /// One JSON line for log collectors and humans alike.
+    pub fn diagnostic(&self) -> serde_json::Value {
+        serde_json::json!({
+            "error_code": self.kind.code(),
+            "message": self.message,
+            "hint": self.kind.hint(),
+            "exit_code": self.kind.exit_code(),
+        })
+    }
+}
+
+impl fmt::Display for StartupError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.kind.code(), self.message)
+    }
+}
+
+impl std::error::Error for StartupError {}
@@ -0,0 +1,166 @@
+//! Isolated tenants in one deployment. Clients name their tenant with the
+//! `X-Tenant-Id` header or by prefixing the path with `/t/{tenant}`, e.g.
+//! `/t/billing/v1/notes`. Notes belong to the tenant they were created in
+//! and are invisible to every other tenant.
+
+use axum::{
+    extract::Request,
+    http::{uri::PathAndQuery, StatusCode, Uri},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    Router,
+};
+use tower::Layer;
+
+use crate::problem::Problem;
+
+pub const TENANT_HEADER: &str = "x-tenant-id";
+const PATH_PREFIX: &str = "/t/";
+// Tenants may name a database of their own, so they stay within what
+// database names allow on every backend.
+const MAX_TENANT_LEN: usize = 32;
+
+/// The tenant of a request, added to the request extensions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tenant(pub String);
+
+impl Tenant {
+    pub fn parse(value: &str) -> Result<Tenant, InvalidTenant> {
+        let valid = !value.is_empty()
+            && value.len() <= MAX_TENANT_LEN
+            && value.bytes().all(|b| {
+                b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-'
+            });
+        match valid {
+            true => Ok(Tenant(value.to_string())),
+            false => Err(InvalidTenant::Malformed),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum InvalidTenant {
+    Malformed,
+    /// Path and header name different tenants.
+    Ambiguous,
+    Missing,
+}
+
+impl IntoResponse for InvalidTenant {
+    fn into_response(self) -> Response {
+        let detail = match self {
+            InvalidTenant::Malformed => format!(
+                "tenants are 1 to {} lowercase letters, digits and dashes",
+                MAX_TENANT_LEN
+            ),
+            InvalidTenant::Ambiguous => {
+                format!("the tenant of the path differs from {}", TENANT_HEADER)
+            }
+            InvalidTenant::Missing => format!(
+                "a tenant is required, send {} or prefix the path with \
+                 /t/{{tenant}}",
+                TENANT_HEADER
+            ),
+        };
+        Problem::new(StatusCode::BAD_REQUEST, detail).into_response()
+    }
+}
+
+/// Resolves the tenant of every request and strips the tenant prefix from
+/// its path before routing. Wraps the whole app, since a router can't
+/// change the path it routes by.
+pub fn resolve(app: Router) -> Router {
+    Router::new()
+        .fallback_service(middleware::from_fn(resolve_tenant).layer(app))
+}
+
+/// Rejects requests without a tenant.
+pub fn require<S>(router: Router<S>) -> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    router.layer(middleware::from_fn(require_tenant))
+}
+
+async fn resolve_tenant(mut request: Request, next: Next) -> Response {
+    let header = match request.headers().get(TENANT_HEADER) {
+        Some(value) => match value.to_str().map(Tenant::parse) {
+            Ok(Ok(tenant)) => Some(tenant),
+            _ => return InvalidTenant::Malformed.into_response(),
+        },
+        None => None,
+    };
+    let path = match strip_tenant(request.uri()) {
+        Some(Ok((tenant, uri))) => {
+            *request.uri_mut() = uri;
+            Some(tenant)
+        }
+        Some(Err(err)) => return err.into_response(),
+        None => None,
+    };
+    let tenant = match (header, path) {
+        (Some(header), Some(path)) if header != path => {
+            return InvalidTenant::Ambiguous.into_response()
+        }
+        (header, path) => path.or(header),
+    };
+    if let Some(tenant) = tenant {
+        request.extensions_mut().insert(tenant);
+    }
+    next.run(request).await
+}
+
+async fn require_tenant(request: Request, next: Next) -> Response {
+    if request.extensions().get::<Tenant>().is_none() {
+        return InvalidTenant::Missing.into_response();
+    }
+    next.run(request).await
+}
+
+/// The tenant of a `/t/{tenant}/...` path and the path without it.
+fn strip_tenant(uri: &Uri) -> Option<Result<(Tenant, Uri), InvalidTenant>> {
+    let rest = uri.path().strip_prefix(PATH_PREFIX)?;
+    let (tenant, rest) = rest.split_once('/').unwrap_or((rest, ""));
+    let tenant = match Tenant::parse(tenant) {
+        Ok(tenant) => tenant,
+        Err(err) => return Some(Err(err)),
+    };
+    let path_and_query = match uri.query() {
+        Some(query) => format!("/{}?{}", rest, query),
+        None => format!("/{}", rest),
+    };
+    let mut parts = uri.clone().into_parts();
+    parts.path_and_query = Some(
+        PathAndQuery::try_from(path_and_query)
+            .expect("a suffix of a valid path stays valid"),
+    );
+    Some(Ok((
+        tenant,
+        Uri::from_parts(parts).expect("only the path was replaced"),
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_strips_the_tenant_from_the_path() {
+        // Setup
+        let uri: Uri = "/t/billing/v1/notes?limit=2".parse().unwrap();
+        let other: Uri = "/v1/notes".parse().unwrap();
+        let invalid: Uri = "/t/Billing/v1/notes".parse().unwrap();
+
+        // Execute
+        let stripped = strip_tenant(&uri).unwrap().unwrap();
+
+        // Assert
+        assert_eq!(stripped.0, Tenant("billing".to_string()));
+        assert_eq!(stripped.1, "/v1/notes?limit=2");
+        assert!(strip_tenant(&other).is_none());
+        assert!(matches!(
+            strip_tenant(&invalid),
+            Some(Err(InvalidTenant::Malformed))
+        ));
+    }
+}
@@ -0,0 +1,119 @@
+//! Support for testing code built on this crate: proptest generators for
+//! note input and a conformance suite for `NoteDb` implementations. Only
+//! built with the `test-support` feature.
+
+use chrono::{DateTime, Duration, Utc};
+use proptest::{collection::vec, option, prelude::*, sample};
+use serde_json::{json, Value};
+
+use crate::notes::{self, NewNote, NoteFilter, PatchNote};
+
+pub mod conformance;
+
+pub use conformance::notedb_conformance;
+
+/// Titles that pass validation: not blank and at most 40 characters.
+pub fn title() -> impl Strategy<Value = String> {
+    "[a-zA-Z0-9][a-zA-Z0-9 äöüß-]{0,39}"
+}
+
+/// Any printable text, including none at all.
+pub fn body() -> impl Strategy<Value = String> {
+    "\\PC{0,200}"
+}
+
+pub fn tags() -> impl Strategy<Value = Vec<String>> {
+    vec("[a-z]{1,10}", 0..5)
+}
+
+pub fn new_note() -> impl Strategy<Value = NewNote> {
+    (title(), body(), tags()).prop_map(|(title, body, tags)| NewNote {
+        title,
+        body,
+        tags,
+        org: None,
+    })
+}
+
+/// Patches changing any combination of title, body and tags, without an
+/// expected version.
+pub fn patch_note() -> impl Strategy<Value = PatchNote> {
+    (option::of(title()), option::of(body()), option::of(tags())).prop_map(
+        |(title, body, tags)| PatchNote {
+            title,
+            body,
+            tags,
+            updated_at: Utc::now(),
+            version: None,
+            permissions: None,
+        },
+    )
+}
+
+/// Times up to a minute before or after now, at the precision notes are
+/// stored with.
+pub fn timestamp() -> impl Strategy<Value = DateTime<Utc>> {
+    (-60_000i64..60_000)
+        .prop_map(|millis| notes::now() + Duration::milliseconds(millis))
+}
+
+pub fn note_filter() -> impl Strategy<Value = NoteFilter> {
+    (
+        option::of("[a-zA-Z ]{0,5}"),
+        option::of("[a-z]{1,3}"),
+        option::of(timestamp()),
+        option::of(timestamp()),
+    )
+        .prop_map(
+            |(title_contains, tag, created_after, updated_before)| NoteFilter {
+                title_contains,
+                tag,
+                created_after,
+                updated_before,
+            },
+        )
+}
+
+/// JSON Patch documents against a note, valid or not: unknown operations,
+/// missing members, read-only and nonexistent paths and values of the
+/// wrong type.
+pub fn json_patch() -> impl Strategy<Value = Value> {
+    let op = sample::select(vec![
+        "add", "remove", "replace", "move", "copy", "test", "merge",
+    ]);
+    let path = prop_oneof![
+        sample::select(vec![
+            "",
+            "/title",
+            "/body",
+            "/tags",
+            "/tags/0",
+            "/tags/-",
+            "/id",
+            "/version",
+            "/owner",
+            "/tags/x/y",
+        ])
+        .prop_map(str::to_string),
+        "/[a-z~0-9]{0,6}",
+    ];
+    let value = prop_oneof![
+        Just(Value::Null),
+        any::<bool>().prop_map(Value::from),
+        any::<i64>().prop_map(Value::from),
+        body().prop_map(Value::from),
+        tags().prop_map(|tags| json!(tags)),
+    ];
+    let operation = (op, path.clone(), option::of(path), option::of(value))
+        .prop_map(|(op, path, from, value)| {
+            let mut operation = json!({ "op": op, "path": path });
+            if let Some(from) = from {
+                operation["from"] = json!(from);
+            }
+            if let Some(value) = value {
+                operation["value"] = value;
+            }
+            operation
+        });
+    vec(operation, 0..5).prop_map(Value::from)
+}
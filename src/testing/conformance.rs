@@ -0,0 +1,482 @@
+//! Checks that a `NoteDb` implementation keeps the contract the handlers
+//! rely on, so that new backends, including ones outside this crate, can
+//! be verified against the same expectations as the built-in ones.
+
+use std::collections::HashSet;
+
+use chrono::Duration;
+use nanoid::nanoid;
+
+use crate::context::OpContext;
+use crate::notes::{
+    self, Note, NoteDb, NoteDbError, NoteFilter, Page, PatchNote,
+};
+
+/// Runs all checks against `db` and panics on the first violation, naming
+/// the expectation. Every check works on notes tagged with a fresh tag and
+/// titled with a fresh prefix, so `db` doesn't have to be empty and can be
+/// shared with other tests.
+pub async fn notedb_conformance<D: NoteDb + ?Sized>(db: &D) {
+    check_crud(db).await;
+    check_pagination(db).await;
+    check_search(db).await;
+    check_time_filters(db).await;
+    check_concurrent_updates(db).await;
+    check_owner_scope(db).await;
+    check_revisions(db).await;
+}
+
+/// Notes of one check, tagged so that listings can be limited to them.
+struct Fixture {
+    tag: String,
+    prefix: String,
+}
+
+impl Fixture {
+    fn new() -> Fixture {
+        // Ids are URL safe but may contain '-' and '_', which some
+        // backends treat specially in patterns.
+        let run = nanoid!(10, &nanoid::alphabet::SAFE[2..]);
+        Fixture {
+            tag: format!("conformance{}", run.to_lowercase()),
+            prefix: format!("Conformance {} ", run),
+        }
+    }
+
+    fn note(&self, title: &str) -> Note {
+        let mut note = Note::new(
+            &format!("{}{}", self.prefix, title),
+            "body",
+            "/v1/notes",
+        );
+        note.tags = vec![self.tag.clone()];
+        note
+    }
+
+    fn filter(&self) -> NoteFilter {
+        NoteFilter {
+            tag: Some(self.tag.clone()),
+            ..NoteFilter::default()
+        }
+    }
+}
+
+/// Ids of `notes`, sorted. Notes are compared by id since backends may
+/// store timestamps with less precision.
+fn ids(notes: &[Note]) -> Vec<&str> {
+    let mut ids: Vec<&str> =
+        notes.iter().map(|note| note.id.as_str()).collect();
+    ids.sort();
+    ids
+}
+
+fn patch(title: &str) -> PatchNote {
+    PatchNote {
+        title: Some(title.to_string()),
+        body: None,
+        tags: None,
+        updated_at: chrono::Utc::now(),
+        version: None,
+        permissions: None,
+    }
+}
+
+async fn check_crud<D: NoteDb + ?Sized>(db: &D) {
+    let ctx = OpContext::background();
+    let fixture = Fixture::new();
+    let note = fixture.note("crud");
+
+    db.create_note(&ctx, &note).await.expect("create a note");
+    let stored = db
+        .get_note(&ctx, &note.id)
+        .await
+        .expect("get a note")
+        .expect("get returns the created note");
+    assert_eq!(
+        (&stored.title, &stored.body, &stored.tags, stored.version),
+        (&note.title, &note.body, &note.tags, note.version),
+        "get returns the fields of the created note"
+    );
+    let duplicate = db.create_note(&ctx, &note).await;
+    assert!(
+        matches!(duplicate, Err(NoteDbError::Conflict(_))),
+        "creating a note with an existing id conflicts, got {:?}",
+        duplicate
+    );
+    let missing = db.get_note(&ctx, &nanoid!()).await.expect("get missing");
+    assert_eq!(missing, None, "get of an unknown id returns none");
+    let found = db
+        .get_notes(&ctx, &[note.id.clone(), nanoid!()])
+        .await
+        .expect("get several notes");
+    assert_eq!(
+        ids(&found),
+        vec![note.id.as_str()],
+        "get_notes skips unknown ids"
+    );
+
+    let title = format!("{}patched", fixture.prefix);
+    let patch_of_title = patch(&title);
+    let patch = PatchNote {
+        tags: Some(vec![fixture.tag.clone(), "second".to_string()]),
+        ..patch_of_title.clone()
+    };
+    db.update_note(&ctx, &note.id, &patch)
+        .await
+        .expect("update a note");
+    let updated = db
+        .get_note(&ctx, &note.id)
+        .await
+        .expect("get an updated note")
+        .expect("updated note exists");
+    assert_eq!(updated.title, title, "update sets the title");
+    assert_eq!(updated.body, note.body, "update keeps unset fields");
+    assert_eq!(updated.tags, patch.tags.unwrap(), "update replaces tags");
+    assert_eq!(
+        updated.version,
+        note.version + 1,
+        "update bumps the version"
+    );
+    assert_eq!(
+        updated.created_at, stored.created_at,
+        "update keeps created_at"
+    );
+    let missing = db.update_note(&ctx, &nanoid!(), &patch_of_title).await;
+    assert!(
+        matches!(missing, Err(NoteDbError::NotFound)),
+        "updating an unknown id fails with not found, got {:?}",
+        missing
+    );
+
+    assert!(
+        db.delete_note(&ctx, &note.id).await.expect("delete a note"),
+        "delete of an existing note returns true"
+    );
+    assert!(
+        !db.delete_note(&ctx, &note.id).await.expect("delete again"),
+        "delete of a deleted note returns false"
+    );
+    let deleted = db.get_note(&ctx, &note.id).await.expect("get deleted");
+    assert_eq!(deleted, None, "get of a deleted note returns none");
+}
+
+async fn check_pagination<D: NoteDb + ?Sized>(db: &D) {
+    let ctx = OpContext::background();
+    let fixture = Fixture::new();
+    let notes: Vec<Note> = (0..5)
+        .map(|i| fixture.note(&format!("page {}", i)))
+        .collect();
+    db.create_notes(&ctx, &notes).await.expect("create notes");
+
+    let all = db
+        .list_notes(&ctx, &fixture.filter(), &Page::default())
+        .await
+        .expect("list notes");
+    assert_eq!(all.total, 5, "the total counts all matching notes");
+    assert_eq!(all.notes.len(), 5, "no limit lists all matching notes");
+    let mut seen = HashSet::new();
+    for offset in [0, 2, 4] {
+        let page = Page {
+            limit: Some(2),
+            offset: Some(offset),
+        };
+        let listed = db
+            .list_notes(&ctx, &fixture.filter(), &page)
+            .await
+            .expect("list a page");
+        assert_eq!(listed.total, 5, "the total ignores the page");
+        let expected = if offset == 4 { 1 } else { 2 };
+        assert_eq!(listed.notes.len(), expected, "pages hold up to limit");
+        for note in listed.notes {
+            assert!(seen.insert(note.id), "pages don't overlap");
+        }
+    }
+    assert_eq!(seen.len(), 5, "pages cover all notes");
+    let past_end = Page {
+        limit: Some(2),
+        offset: Some(10),
+    };
+    let listed = db
+        .list_notes(&ctx, &fixture.filter(), &past_end)
+        .await
+        .expect("list past the end");
+    assert!(listed.notes.is_empty(), "pages past the end are empty");
+    let empty = Page {
+        limit: Some(0),
+        offset: None,
+    };
+    let listed = db
+        .list_notes(&ctx, &fixture.filter(), &empty)
+        .await
+        .expect("list an empty page");
+    assert!(listed.notes.is_empty(), "a limit of 0 lists no notes");
+    assert_eq!(listed.total, 5, "a limit of 0 still counts the notes");
+    let huge = Page {
+        limit: Some(u64::MAX),
+        offset: Some(u64::MAX),
+    };
+    let listed = db
+        .list_notes(&ctx, &fixture.filter(), &huge)
+        .await
+        .expect("list with a huge limit and offset");
+    assert!(listed.notes.is_empty(), "huge offsets are past the end");
+}
+
+async fn check_search<D: NoteDb + ?Sized>(db: &D) {
+    let ctx = OpContext::background();
+    let fixture = Fixture::new();
+    let apple = fixture.note("Apple pie");
+    let mut banana = fixture.note("Banana bread");
+    banana.tags.push("baking".to_string());
+    db.create_notes(&ctx, &[apple.clone(), banana.clone()])
+        .await
+        .expect("create notes");
+
+    let filter = NoteFilter {
+        title_contains: Some("APPLE".to_string()),
+        ..fixture.filter()
+    };
+    let listed = db
+        .list_notes(&ctx, &filter, &Page::default())
+        .await
+        .expect("list by title");
+    assert_eq!(
+        ids(&listed.notes),
+        vec![apple.id.as_str()],
+        "titles match any case"
+    );
+    let filter = NoteFilter {
+        tag: Some("baking".to_string()),
+        title_contains: Some(fixture.prefix.clone()),
+        ..NoteFilter::default()
+    };
+    let listed = db
+        .list_notes(&ctx, &filter, &Page::default())
+        .await
+        .expect("list by tag");
+    assert_eq!(
+        ids(&listed.notes),
+        vec![banana.id.as_str()],
+        "tags match exactly"
+    );
+    let filter = NoteFilter {
+        title_contains: Some("%_.*".to_string()),
+        ..fixture.filter()
+    };
+    let listed = db
+        .list_notes(&ctx, &filter, &Page::default())
+        .await
+        .expect("list by pattern characters");
+    assert!(listed.notes.is_empty(), "titles are matched literally");
+
+    let prefix = fixture.prefix.to_uppercase();
+    let suggestions = db
+        .suggest_titles(&ctx, &prefix, 10)
+        .await
+        .expect("suggest titles");
+    // Notes of other fixtures share the word "Conformance" and may follow
+    // as similar titles.
+    let titles: Vec<&str> = suggestions
+        .iter()
+        .take(2)
+        .map(|s| s.title.as_str())
+        .collect();
+    assert_eq!(
+        titles,
+        vec![apple.title.as_str(), banana.title.as_str()],
+        "suggestions match the prefix in any case, sorted by title"
+    );
+    let suggestions = db
+        .suggest_titles(&ctx, &prefix, 1)
+        .await
+        .expect("suggest one title");
+    assert_eq!(suggestions.len(), 1, "suggestions respect the limit");
+    let misspelled = format!("{}banan brea", fixture.prefix);
+    let suggestions = db
+        .suggest_titles(&ctx, &misspelled, 10)
+        .await
+        .expect("suggest titles for a misspelled query");
+    assert_eq!(
+        suggestions.first().map(|s| s.id.as_str()),
+        Some(banana.id.as_str()),
+        "misspelled queries suggest the most similar title first"
+    );
+    let found = db
+        .find_by_title(&ctx, &banana.title.to_lowercase())
+        .await
+        .expect("find by title");
+    assert_eq!(found.len(), 1, "titles are found in any case");
+    assert_eq!(found[0].id, banana.id);
+}
+
+async fn check_time_filters<D: NoteDb + ?Sized>(db: &D) {
+    let ctx = OpContext::background();
+    let fixture = Fixture::new();
+    let now = notes::now();
+    let mut old = fixture.note("old");
+    old.created_at = now - Duration::minutes(10);
+    old.updated_at = old.created_at;
+    let mut new = fixture.note("new");
+    new.created_at = now;
+    new.updated_at = now;
+    db.create_notes(&ctx, &[old.clone(), new.clone()])
+        .await
+        .expect("create notes");
+
+    let ctx = &ctx;
+    let list = |filter: NoteFilter| async move {
+        db.list_notes(ctx, &filter, &Page::default())
+            .await
+            .expect("list by time")
+    };
+    let listed = list(NoteFilter {
+        created_after: Some(now - Duration::minutes(5)),
+        ..fixture.filter()
+    })
+    .await;
+    assert_eq!(
+        ids(&listed.notes),
+        vec![new.id.as_str()],
+        "created_after keeps notes created later"
+    );
+    assert_eq!(listed.total, 1, "time filters apply to the total");
+    let listed = list(NoteFilter {
+        updated_before: Some(now - Duration::minutes(5)),
+        ..fixture.filter()
+    })
+    .await;
+    assert_eq!(
+        ids(&listed.notes),
+        vec![old.id.as_str()],
+        "updated_before keeps notes updated earlier"
+    );
+    let listed = list(NoteFilter {
+        created_after: Some(old.created_at),
+        updated_before: Some(now),
+        ..fixture.filter()
+    })
+    .await;
+    assert!(listed.notes.is_empty(), "time filters exclude their bounds");
+    let listed = list(NoteFilter {
+        created_after: Some(old.created_at - Duration::milliseconds(1)),
+        updated_before: Some(now + Duration::milliseconds(1)),
+        ..fixture.filter()
+    })
+    .await;
+    assert_eq!(listed.total, 2, "time filters combine with AND");
+}
+
+async fn check_concurrent_updates<D: NoteDb + ?Sized>(db: &D) {
+    let ctx = OpContext::background();
+    let fixture = Fixture::new();
+    let note = fixture.note("concurrent");
+    db.create_note(&ctx, &note).await.expect("create a note");
+
+    let based_on = |title: &str| PatchNote {
+        version: Some(note.version),
+        ..patch(title)
+    };
+    let (first, second) = (based_on("first"), based_on("second"));
+    let (first, second) = tokio::join!(
+        db.update_note(&ctx, &note.id, &first),
+        db.update_note(&ctx, &note.id, &second),
+    );
+    let conflicts = [&first, &second]
+        .iter()
+        .filter(|result| matches!(result, Err(NoteDbError::Conflict(_))))
+        .count();
+    assert!(
+        first.is_ok() != second.is_ok() && conflicts == 1,
+        "of two updates of the same version exactly one wins, got {:?} and \
+         {:?}",
+        first,
+        second
+    );
+    let updated = db
+        .get_note(&ctx, &note.id)
+        .await
+        .expect("get the note")
+        .expect("note exists");
+    assert_eq!(updated.version, note.version + 1, "only one update applied");
+    let stale = db.update_note(&ctx, &note.id, &based_on("stale")).await;
+    assert!(
+        matches!(stale, Err(NoteDbError::Conflict(_))),
+        "updates of a stale version conflict, got {:?}",
+        stale
+    );
+}
+
+async fn check_owner_scope<D: NoteDb + ?Sized>(db: &D) {
+    let background = OpContext::background();
+    let fixture = Fixture::new();
+    let owner = format!("{}-owner", fixture.tag);
+    let mut note = fixture.note("owned");
+    note.owner = Some(owner.clone());
+    db.create_note(&background, &note)
+        .await
+        .expect("create a note");
+    let as_owner = OpContext {
+        principal: Some(owner),
+        ..OpContext::background()
+    };
+    let as_other = OpContext {
+        principal: Some(format!("{}-other", fixture.tag)),
+        ..OpContext::background()
+    };
+
+    let own = db
+        .get_note(&as_owner, &note.id)
+        .await
+        .expect("get own note");
+    assert!(own.is_some(), "owners get their notes");
+    let foreign = db.get_note(&as_other, &note.id).await.expect("get other");
+    assert_eq!(foreign, None, "other users don't get the note");
+    let listed = db
+        .list_notes(&as_other, &fixture.filter(), &Page::default())
+        .await
+        .expect("list as another user");
+    assert_eq!(listed.total, 0, "other users don't list the note");
+    let update = db.update_note(&as_other, &note.id, &patch("taken")).await;
+    assert!(update.is_err(), "other users can't update the note");
+    let deleted = db
+        .delete_note(&as_other, &note.id)
+        .await
+        .expect("delete as another user");
+    assert!(!deleted, "other users can't delete the note");
+    let background_view = db
+        .get_note(&background, &note.id)
+        .await
+        .expect("get without principal");
+    assert!(background_view.is_some(), "no principal sees all notes");
+}
+
+/// Revisions are optional, backends without history answer with
+/// `NoteDbError::Unsupported`.
+async fn check_revisions<D: NoteDb + ?Sized>(db: &D) {
+    let ctx = OpContext::background();
+    let fixture = Fixture::new();
+    let note = fixture.note("revisions");
+    db.create_note(&ctx, &note).await.expect("create a note");
+
+    let first = match db.record_revision(&ctx, &note).await {
+        Ok(rev) => rev,
+        Err(NoteDbError::Unsupported(_)) => return,
+        Err(err) => panic!("record a revision: {}", err),
+    };
+    let second = db
+        .record_revision(&ctx, &note)
+        .await
+        .expect("record another revision");
+    assert_eq!((first, second), (1, 2), "revisions are numbered from 1");
+    let revisions = db
+        .list_revisions(&ctx, &note.id)
+        .await
+        .expect("list revisions");
+    let revs: Vec<u64> = revisions.iter().map(|r| r.rev).collect();
+    assert_eq!(revs, vec![1, 2], "revisions are listed oldest first");
+    let revision = db
+        .get_revision(&ctx, &note.id, 2)
+        .await
+        .expect("get a revision");
+    assert_eq!(revision.map(|r| r.title), Some(note.title));
+}
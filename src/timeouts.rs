@@ -0,0 +1,90 @@
+//! Request timeouts per class of route, configured in one place.
+
+use std::time::Duration;
+
+use axum::{
+    extract::{Request, State},
+    http::StatusCode,
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::MethodRouter,
+};
+
+use crate::context::Deadline;
+use crate::metrics::TIMED_OUT_REQUESTS;
+use crate::problem::Problem;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RouteClass {
+    /// Reads and writes of single items.
+    Crud,
+    /// Listings and searches that may touch many items.
+    Bulk,
+}
+
+/// Timeouts per route class. `None` disables the timeout for the class.
+#[derive(Debug, Clone)]
+pub struct RouteTimeouts {
+    pub crud: Option<Duration>,
+    pub bulk: Option<Duration>,
+}
+
+impl Default for RouteTimeouts {
+    fn default() -> Self {
+        RouteTimeouts {
+            crud: Some(Duration::from_secs(5)),
+            bulk: Some(Duration::from_secs(30)),
+        }
+    }
+}
+
+impl RouteTimeouts {
+    pub fn get(&self, class: RouteClass) -> Option<Duration> {
+        match class {
+            RouteClass::Crud => self.crud,
+            RouteClass::Bulk => self.bulk,
+        }
+    }
+
+    /// Limits the handlers of `route` to the timeout of `class`. Requests
+    /// running over get a 504 problem response.
+    pub fn on<S>(
+        &self,
+        class: RouteClass,
+        route: MethodRouter<S>,
+    ) -> MethodRouter<S>
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        match self.get(class) {
+            Some(timeout) => route.layer(middleware::from_fn_with_state(
+                timeout,
+                enforce_timeout,
+            )),
+            None => route,
+        }
+    }
+}
+
+async fn enforce_timeout(
+    State(timeout): State<Duration>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let path = request.uri().path().to_string();
+    request
+        .extensions_mut()
+        .insert(Deadline(tokio::time::Instant::now() + timeout));
+    match tokio::time::timeout(timeout, next.run(request)).await {
+        Ok(response) => response,
+        Err(_) => {
+            TIMED_OUT_REQUESTS.increment();
+            tracing::warn!("request to {} timed out after {:?}", path, timeout);
+            Problem::new(
+                StatusCode::GATEWAY_TIMEOUT,
+                format!("request did not complete within {:?}", timeout),
+            )
+            .into_response()
+        }
+    }
+}
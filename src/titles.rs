@@ -0,0 +1,98 @@
+//! Policy for notes sharing a title, for teams that treat titles as
+//! identifiers. Titles are compared case-insensitively.
+
+use std::{fmt, str::FromStr};
+
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+
+use crate::context::OpContext;
+use crate::notes::{NoteDb, NoteDbError};
+use crate::problem::Problem;
+
+/// Response header listing the ids of notes with the same title, sent when
+/// duplicates only warn.
+pub const DUPLICATE_TITLE_HEADER: &str = "x-duplicate-title-of";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TitlePolicy {
+    #[default]
+    Allow,
+    /// Accept the note, listing the duplicates in `DUPLICATE_TITLE_HEADER`.
+    Warn,
+    /// Refuse the note with 409.
+    Reject,
+}
+
+impl FromStr for TitlePolicy {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "allow" => Ok(TitlePolicy::Allow),
+            "warn" => Ok(TitlePolicy::Warn),
+            "reject" => Ok(TitlePolicy::Reject),
+            _ => Err("expected allow, warn or reject".to_string()),
+        }
+    }
+}
+
+impl TitlePolicy {
+    /// Checks `title` against the stored notes other than `own_id`. The
+    /// check runs before the write, so concurrent writes of the same title
+    /// can both pass.
+    pub async fn check<D: NoteDb + ?Sized>(
+        &self,
+        ctx: &OpContext,
+        db: &D,
+        title: &str,
+        own_id: Option<&str>,
+    ) -> Result<Duplicates, NoteDbError> {
+        if *self == TitlePolicy::Allow {
+            return Ok(Duplicates::default());
+        }
+        let ids: Vec<String> = db
+            .find_by_title(ctx, title)
+            .await?
+            .into_iter()
+            .map(|note| note.id)
+            .filter(|id| Some(id.as_str()) != own_id)
+            .collect();
+        Ok(Duplicates {
+            rejected: *self == TitlePolicy::Reject && !ids.is_empty(),
+            ids,
+        })
+    }
+}
+
+/// Outcome of a `TitlePolicy` check.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Duplicates {
+    pub ids: Vec<String>,
+    pub rejected: bool,
+}
+
+impl Duplicates {
+    /// The warning header, if there are duplicates.
+    pub fn header(&self) -> Option<(&'static str, String)> {
+        (!self.ids.is_empty())
+            .then(|| (DUPLICATE_TITLE_HEADER, self.ids.join(",")))
+    }
+}
+
+impl fmt::Display for Duplicates {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "title is already used by {}", self.ids.join(", "))
+    }
+}
+
+/// A rejected duplicate, answered with 409 and the clashing ids.
+impl IntoResponse for Duplicates {
+    fn into_response(self) -> Response {
+        Problem::new(StatusCode::CONFLICT, self.to_string())
+            .with_extension("duplicates", self.ids)
+            .into_response()
+    }
+}
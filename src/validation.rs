@@ -0,0 +1,169 @@
+//! Validation of note input beyond what deserialization checks.
+
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::notes::{NewNote, Note, NoteLookup, PatchNote};
+
+/// Size limits for note fields. Titles are counted in characters, bodies
+/// in bytes since that is what ends up in the database.
+#[derive(Debug, Clone, Copy)]
+pub struct NoteLimits {
+    pub max_title_chars: usize,
+    pub max_body_bytes: usize,
+    pub max_tags: usize,
+    pub max_tag_chars: usize,
+}
+
+impl Default for NoteLimits {
+    fn default() -> Self {
+        NoteLimits {
+            max_title_chars: 200,
+            max_body_bytes: 1024 * 1024,
+            max_tags: 100,
+            max_tag_chars: 100,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct FieldError {
+    pub field: &'static str,
+    pub message: String,
+}
+
+/// Rejected input, answered with 422 and the failing fields.
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct ValidationErrors {
+    pub errors: Vec<FieldError>,
+}
+
+impl std::fmt::Display for ValidationErrors {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let fields: Vec<String> = self
+            .errors
+            .iter()
+            .map(|err| format!("{} {}", err.field, err.message))
+            .collect();
+        write!(f, "{}", fields.join(", "))
+    }
+}
+
+impl IntoResponse for ValidationErrors {
+    fn into_response(self) -> Response {
+        (StatusCode::UNPROCESSABLE_ENTITY, Json(self)).into_response()
+    }
+}
+
+/// Input checked against the configured `NoteLimits`.
+pub trait Validate {
+    fn validate(&self, limits: &NoteLimits) -> Result<(), ValidationErrors>;
+}
+
+impl Validate for NewNote {
+    fn validate(&self, limits: &NoteLimits) -> Result<(), ValidationErrors> {
+        limits.check(Some(&self.title), Some(&self.body), Some(&self.tags))
+    }
+}
+
+impl Validate for PatchNote {
+    fn validate(&self, limits: &NoteLimits) -> Result<(), ValidationErrors> {
+        limits.check(
+            self.title.as_deref(),
+            self.body.as_deref(),
+            self.tags.as_deref(),
+        )
+    }
+}
+
+// Keeps a lookup within what a single `$in` query handles well.
+const MAX_LOOKUP_IDS: usize = 1000;
+
+impl Validate for NoteLookup {
+    fn validate(&self, _limits: &NoteLimits) -> Result<(), ValidationErrors> {
+        if self.ids.len() > MAX_LOOKUP_IDS {
+            return Err(ValidationErrors {
+                errors: vec![FieldError {
+                    field: "ids",
+                    message: format!("must be at most {} ids", MAX_LOOKUP_IDS),
+                }],
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Notes that didn't come in as `NewNote`, like archive imports.
+impl Validate for Note {
+    fn validate(&self, limits: &NoteLimits) -> Result<(), ValidationErrors> {
+        limits.check(Some(&self.title), Some(&self.body), Some(&self.tags))
+    }
+}
+
+impl NoteLimits {
+    fn check(
+        &self,
+        title: Option<&str>,
+        body: Option<&str>,
+        tags: Option<&[String]>,
+    ) -> Result<(), ValidationErrors> {
+        let mut errors = Vec::new();
+        if let Some(title) = title {
+            if title.trim().is_empty() {
+                errors.push(FieldError {
+                    field: "title",
+                    message: "must not be empty".to_string(),
+                });
+            } else if title.chars().count() > self.max_title_chars {
+                errors.push(FieldError {
+                    field: "title",
+                    message: format!(
+                        "must be at most {} characters",
+                        self.max_title_chars
+                    ),
+                });
+            }
+        }
+        if let Some(body) = body {
+            if body.len() > self.max_body_bytes {
+                errors.push(FieldError {
+                    field: "body",
+                    message: format!(
+                        "must be at most {} bytes",
+                        self.max_body_bytes
+                    ),
+                });
+            }
+        }
+        if let Some(tags) = tags {
+            if tags.len() > self.max_tags {
+                errors.push(FieldError {
+                    field: "tags",
+                    message: format!("must be at most {} tags", self.max_tags),
+                });
+            }
+            if tags
+                .iter()
+                .any(|tag| tag.chars().count() > self.max_tag_chars)
+            {
+                errors.push(FieldError {
+                    field: "tags",
+                    message: format!(
+                        "must each be at most {} characters",
+                        self.max_tag_chars
+                    ),
+                });
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(ValidationErrors { errors })
+        }
+    }
+}
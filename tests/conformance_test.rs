@@ -0,0 +1,17 @@
+use std::sync::Arc;
+
+use notes::{persistency::memory::NoteMemoryDb, testing::notedb_conformance};
+
+#[tokio::test]
+async fn it_conforms_in_memory() {
+    notedb_conformance(&NoteMemoryDb::new()).await;
+}
+
+#[cfg(feature = "s3")]
+#[tokio::test]
+async fn it_conforms_in_s3() {
+    use notes::persistency::s3::NoteS3Db;
+    use object_store::memory::InMemory;
+
+    notedb_conformance(&NoteS3Db::new(Arc::new(InMemory::new()))).await;
+}
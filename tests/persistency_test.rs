@@ -2,7 +2,8 @@ use mongodb::{options::ClientOptions, Client};
 use testcontainers::{clients, GenericImage, RunnableImage};
 
 use notes::{
-    notes::{Note, NoteDb, PatchNote},
+    context::OpContext,
+    notes::{Note, NoteDb, NoteFilter, Page, PatchNote},
     persistency::NoteMongoDb,
 };
 
@@ -29,12 +30,13 @@ async fn test_with_mongodb_container() {
     let client = Client::with_options(options).unwrap();
 
     // Get notes DB
+    let ctx = OpContext::background();
     let db = NoteMongoDb::get_notes_db(client);
     let note_db = NoteMongoDb::new(db);
 
     let create_note = Note::new("note", "body", "url");
-    note_db.create_note(&create_note).await.unwrap();
-    let get_note = note_db.get_note(&create_note.id).await.unwrap();
+    note_db.create_note(&ctx, &create_note).await.unwrap();
+    let get_note = note_db.get_note(&ctx, &create_note.id).await.unwrap();
     match get_note {
         Some(note) => assert_eq!(note.id, create_note.id),
         None => panic!("expected note"),
@@ -42,24 +44,55 @@ async fn test_with_mongodb_container() {
     let patch_note = PatchNote {
         title: Some("newtitle".to_string()),
         body: Some("newbody".to_string()),
+        tags: Some(vec!["work".to_string()]),
+        updated_at: notes::notes::now(),
+        version: None,
+        permissions: None,
     };
+    let rev = note_db.record_revision(&ctx, &create_note).await.unwrap();
+    assert_eq!(rev, 1);
     note_db
-        .update_note(&create_note.id, &patch_note)
+        .update_note(&ctx, &create_note.id, &patch_note)
         .await
         .unwrap();
-    let get_note = note_db.get_note(&create_note.id).await.unwrap();
+    let revisions =
+        note_db.list_revisions(&ctx, &create_note.id).await.unwrap();
+    assert_eq!(revisions.len(), 1);
+    assert_eq!(revisions[0].title, "note");
+    assert!(note_db
+        .get_revision(&ctx, &create_note.id, 1)
+        .await
+        .unwrap()
+        .is_some());
+    let get_note = note_db.get_note(&ctx, &create_note.id).await.unwrap();
     match get_note {
         Some(note) => {
             assert_eq!(note.id, create_note.id);
             assert_eq!(note.title, patch_note.title.unwrap());
             assert_eq!(note.body, patch_note.body.unwrap());
+            assert_eq!(note.tags, patch_note.tags.unwrap());
+            assert_eq!(note.created_at, create_note.created_at);
+            assert_eq!(note.updated_at, patch_note.updated_at);
+            assert_eq!(note.version, create_note.version + 1);
         }
         None => panic!("expected note"),
     }
-    let deleted = note_db.delete_note(&create_note.id).await.unwrap();
+    let filter = NoteFilter {
+        tag: Some("work".to_string()),
+        ..NoteFilter::default()
+    };
+    let listed = note_db
+        .list_notes(&ctx, &filter, &Page::default())
+        .await
+        .unwrap();
+    assert_eq!(listed.total, 1);
+    let deleted = note_db.delete_note(&ctx, &create_note.id).await.unwrap();
     assert!(deleted);
-    let get_note = note_db.get_note(&create_note.id).await.unwrap();
+    let get_note = note_db.get_note(&ctx, &create_note.id).await.unwrap();
     if get_note.is_some() {
         panic!("expected no note");
     };
+
+    #[cfg(feature = "test-support")]
+    notes::testing::notedb_conformance(&note_db).await;
 }
@@ -0,0 +1,122 @@
+use proptest::prelude::*;
+
+use notes::{
+    context::OpContext,
+    note_patch::to_patch_note,
+    notes::{Note, NoteDb, NoteFilter, Page},
+    persistency::memory::NoteMemoryDb,
+    testing,
+};
+
+fn block_on<F: std::future::Future>(future: F) -> F::Output {
+    tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap()
+        .block_on(future)
+}
+
+/// The value of a field after a patch that sets it to `patched`, if at all.
+fn expected<'a, T>(patched: &'a Option<T>, current: &'a T) -> &'a T {
+    patched.as_ref().unwrap_or(current)
+}
+
+proptest! {
+    #[test]
+    fn it_reflects_patches_in_get(
+        new_note in testing::new_note(),
+        patch in testing::patch_note(),
+    ) {
+        // Setup
+        let ctx = OpContext::background();
+        let db = NoteMemoryDb::new();
+        let note = new_note.into_note("/v1/notes");
+
+        // Execute
+        let patched = block_on(async {
+            db.create_note(&ctx, &note).await.unwrap();
+            db.update_note(&ctx, &note.id, &patch).await.unwrap();
+            db.get_note(&ctx, &note.id).await.unwrap().unwrap()
+        });
+
+        // Assert
+        prop_assert_eq!(&patched.title, expected(&patch.title, &note.title));
+        prop_assert_eq!(&patched.body, expected(&patch.body, &note.body));
+        prop_assert_eq!(&patched.tags, expected(&patch.tags, &note.tags));
+        prop_assert_eq!(patched.version, note.version + 1);
+        prop_assert_eq!(patched.created_at, note.created_at);
+    }
+
+    #[test]
+    fn it_lists_the_notes_a_filter_matches(
+        new_notes in proptest::collection::vec(testing::new_note(), 0..10),
+        filter in testing::note_filter(),
+    ) {
+        // Setup
+        let ctx = OpContext::background();
+        let notes: Vec<Note> = new_notes
+            .into_iter()
+            .map(|new_note| new_note.into_note("/v1/notes"))
+            .collect();
+        let db = NoteMemoryDb::with_notes(notes.clone());
+
+        // Execute
+        let page = block_on(db.list_notes(&ctx, &filter, &Page::default()))
+            .unwrap();
+
+        // Assert
+        let matching: Vec<&Note> =
+            notes.iter().filter(|note| filter.matches(note)).collect();
+        prop_assert_eq!(page.total, matching.len() as u64);
+        prop_assert!(page.notes.iter().all(|note| matching.contains(&note)));
+    }
+
+    #[test]
+    fn it_matches_notes_by_part_of_their_title_in_any_case(
+        new_note in testing::new_note(),
+        start in 0usize..40,
+        len in 0usize..40,
+    ) {
+        // Setup
+        let note = new_note.into_note("/v1/notes");
+        let chars: Vec<char> = note.title.chars().collect();
+        let start = start.min(chars.len());
+        let end = (start + len).min(chars.len());
+        let part: String = chars[start..end].iter().collect();
+        let filter = NoteFilter {
+            // Full Unicode case mapping isn't reversible, "ß" becomes "SS".
+            title_contains: Some(part.to_ascii_uppercase()),
+            tag: note.tags.first().cloned(),
+            ..NoteFilter::default()
+        };
+
+        // Execute
+        let matches = filter.matches(&note);
+
+        // Assert
+        prop_assert!(matches);
+    }
+
+    #[test]
+    fn it_never_panics_on_json_patches(
+        new_note in testing::new_note(),
+        ops in testing::json_patch(),
+    ) {
+        // Setup
+        let note = new_note.into_note("/v1/notes");
+
+        // Execute
+        let patch = serde_json::from_value(ops)
+            .ok()
+            .map(|ops| to_patch_note(&ops, &note));
+
+        // Assert
+        if let Some(Ok(patch)) = patch {
+            let mut patched = note.clone();
+            patch.apply_to(&mut patched).unwrap();
+            prop_assert_eq!(&patched.id, &note.id);
+            prop_assert_eq!(&patched.url, &note.url);
+            prop_assert_eq!(&patched.owner, &note.owner);
+            prop_assert_eq!(patched.created_at, note.created_at);
+        }
+    }
+}
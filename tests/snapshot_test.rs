@@ -0,0 +1,364 @@
+//! Snapshots of the JSON of every endpoint, to notice changes to response
+//! shapes. After intended changes, review them with `cargo insta review`.
+
+use std::sync::Arc;
+
+use axum::{
+    body::Body,
+    http::{header, Request, StatusCode},
+    Router,
+};
+use http_body_util::BodyExt;
+use insta::assert_json_snapshot;
+use serde_json::{json, Value};
+use tokio::sync::Mutex;
+use tower::ServiceExt;
+
+use notes::{
+    auth::{AuthConfig, KeySource},
+    create_axum_app,
+    persistency::memory::NoteMemoryDb,
+    AppConfig, AppState,
+};
+
+const ISSUER: &str = "https://idp.test";
+
+/// The API on memory databases, called as an authenticated admin, with
+/// responses captured as their status and JSON body.
+struct Api {
+    app: Router,
+    authorization: String,
+}
+
+impl Api {
+    fn new() -> Api {
+        // Accepts HS256 tokens signed with "secret".
+        let keys = serde_json::from_value(json!({ "keys": [{
+            "kty": "oct", "kid": "k1", "alg": "HS256", "k": "c2VjcmV0",
+        }]}))
+        .unwrap();
+        let app_config = AppConfig {
+            auth: Some(AuthConfig {
+                issuer: ISSUER.to_string(),
+                audience: None,
+                keys: KeySource::Static(keys),
+            }),
+            ..AppConfig::default()
+        };
+        let state = Arc::new(AppState {
+            notes: Arc::new(Mutex::new(NoteMemoryDb::new())),
+            announcements: Arc::new(NoteMemoryDb::new()),
+            idempotency: Arc::new(NoteMemoryDb::new()),
+            api_keys: Arc::new(NoteMemoryDb::new()),
+            orgs: Arc::new(NoteMemoryDb::new()),
+            shares: Arc::new(NoteMemoryDb::new()),
+            migration: None,
+            ingest: None,
+            notes_path: "/v1/notes".to_string(),
+            strict_json: app_config.strict_json,
+            note_limits: app_config.note_limits,
+            title_policy: app_config.title_policy,
+            require_if_match: app_config.require_if_match,
+            idempotency_ttl: app_config.idempotency_ttl,
+            default_page_size: app_config.default_page_size,
+            max_page_size: app_config.max_page_size,
+            archive_limits: app_config.archive_limits,
+        });
+        Api {
+            app: create_axum_app(state, &app_config),
+            authorization: bearer("alice"),
+        }
+    }
+
+    async fn send(
+        &self,
+        method: &str,
+        uri: &str,
+        body: Option<Value>,
+    ) -> Value {
+        let body = match body {
+            Some(body) => Body::from(body.to_string()),
+            None => Body::empty(),
+        };
+        self.send_raw(method, uri, "application/json", body).await
+    }
+
+    async fn send_raw(
+        &self,
+        method: &str,
+        uri: &str,
+        content_type: &str,
+        body: Body,
+    ) -> Value {
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(header::AUTHORIZATION, &self.authorization)
+            .header(header::CONTENT_TYPE, content_type)
+            .body(body)
+            .unwrap();
+        let response = self.app.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let bytes = response.into_body().collect().await.unwrap().to_bytes();
+        let body = match bytes.is_empty() {
+            true => Value::Null,
+            false => serde_json::from_slice(&bytes).unwrap(),
+        };
+        json!({ "status": status.as_u16(), "body": body })
+    }
+
+    /// Creates a note and returns its id.
+    async fn create_note(&self, title: &str, tags: &[&str]) -> String {
+        let note = json!({ "title": title, "body": "Body", "tags": tags });
+        let created = self.send("POST", "/v1/notes", Some(note)).await;
+        assert_eq!(created["status"], StatusCode::CREATED.as_u16());
+        created["body"]["id"].as_str().unwrap().to_string()
+    }
+}
+
+fn bearer(subject: &str) -> String {
+    let header = jsonwebtoken::Header {
+        kid: Some("k1".to_string()),
+        ..jsonwebtoken::Header::default()
+    };
+    let claims = json!({
+        "sub": subject,
+        "iss": ISSUER,
+        "exp": chrono::Utc::now().timestamp() + 60,
+        "roles": ["admin"],
+    });
+    let key = jsonwebtoken::EncodingKey::from_secret(b"secret");
+    let token = jsonwebtoken::encode(&header, &claims, &key).unwrap();
+    format!("Bearer {}", token)
+}
+
+/// Snapshots `response` with generated ids, secrets and timestamps
+/// replaced, so that only the shape and the stable values are compared.
+macro_rules! assert_response_snapshot {
+    ($name:expr, $response:expr) => {
+        assert_json_snapshot!($name, $response, {
+            ".**.id" => "[id]",
+            ".**.note_id" => "[id]",
+            ".**.url" => "[url]",
+            ".**.token" => "[token]",
+            ".**.key" => "[key]",
+            ".**.request_id" => "[request_id]",
+            ".**.created_at" => "[timestamp]",
+            ".**.updated_at" => "[timestamp]",
+            ".**.starts_at" => "[timestamp]",
+        })
+    };
+}
+
+#[tokio::test]
+async fn it_keeps_the_shape_of_note_responses() {
+    // Setup
+    let api = Api::new();
+    let id = api.create_note("Groceries", &["home"]).await;
+    let note_uri = format!("/v1/notes/{}", id);
+
+    // Execute
+    let created = api
+        .send(
+            "POST",
+            "/v1/notes",
+            Some(json!({ "title": "Todo", "body": "Call Bob" })),
+        )
+        .await;
+    let got = api.send("GET", &note_uri, None).await;
+    let patched = api
+        .send("PATCH", &note_uri, Some(json!({ "title": "Shopping" })))
+        .await;
+    let listed = api.send("GET", "/v1/notes?tag=home", None).await;
+    let suggested = api.send("GET", "/v1/notes/suggest?q=Sho", None).await;
+    let looked_up = api
+        .send(
+            "POST",
+            "/v1/notes/lookup",
+            Some(json!({ "ids": [id, "unknown"] })),
+        )
+        .await;
+    let deleted = api.send("DELETE", &note_uri, None).await;
+
+    // Assert
+    assert_response_snapshot!("create_note", created);
+    assert_response_snapshot!("get_note", got);
+    assert_response_snapshot!("patch_note", patched);
+    assert_response_snapshot!("list_notes", listed);
+    assert_response_snapshot!("suggest_notes", suggested);
+    assert_response_snapshot!("lookup_notes", looked_up);
+    assert_response_snapshot!("delete_note", deleted);
+}
+
+#[tokio::test]
+async fn it_keeps_the_shape_of_note_version_responses() {
+    // Setup
+    let api = Api::new();
+    let id = api.create_note("Groceries", &[]).await;
+    let note_uri = format!("/v1/notes/{}", id);
+    api.send("PATCH", &note_uri, Some(json!({ "title": "Shopping" })))
+        .await;
+
+    // Execute
+    let listed = api
+        .send("GET", &format!("{}/versions", note_uri), None)
+        .await;
+    let got = api
+        .send("GET", &format!("{}/versions/1", note_uri), None)
+        .await;
+    let restored = api
+        .send("POST", &format!("{}/versions/1/restore", note_uri), None)
+        .await;
+
+    // Assert
+    assert_response_snapshot!("list_note_versions", listed);
+    assert_response_snapshot!("get_note_version", got);
+    assert_response_snapshot!("restore_note_version", restored);
+}
+
+#[tokio::test]
+async fn it_keeps_the_shape_of_sharing_responses() {
+    // Setup
+    let api = Api::new();
+    let id = api.create_note("Groceries", &[]).await;
+    let note_uri = format!("/v1/notes/{}", id);
+
+    // Execute
+    let shared = api.send("POST", &format!("{}/share", note_uri), None).await;
+    let token = shared["body"]["token"].as_str().unwrap();
+    let got = api
+        .send("GET", &format!("/v1/shared/{}", token), None)
+        .await;
+    let granted = api
+        .send(
+            "POST",
+            &format!("{}/permissions", note_uri),
+            Some(json!({ "subject": "bob", "access": "write" })),
+        )
+        .await;
+    let revoked = api
+        .send("DELETE", &format!("{}/permissions/bob", note_uri), None)
+        .await;
+
+    // Assert
+    assert_response_snapshot!("post_note_share", shared);
+    assert_response_snapshot!("get_shared_note", got);
+    assert_response_snapshot!("post_note_permission", granted);
+    assert_response_snapshot!("delete_note_permission", revoked);
+}
+
+#[tokio::test]
+async fn it_keeps_the_shape_of_import_responses() {
+    // Setup
+    let api = Api::new();
+    let notes = json!([
+        { "title": "Groceries", "body": "Milk" },
+        { "title": "" },
+    ]);
+    let csv = "title,body,tags\nGroceries,Milk,home\n";
+
+    // Execute
+    let imported = api
+        .send("POST", "/v1/notes/import?on_error=continue", Some(notes))
+        .await;
+    let imported_csv = api
+        .send_raw("POST", "/v1/import.csv", "text/csv", Body::from(csv))
+        .await;
+
+    // Assert
+    assert_response_snapshot!("import_notes", imported);
+    assert_response_snapshot!("import_csv", imported_csv);
+}
+
+#[tokio::test]
+async fn it_keeps_the_shape_of_admin_responses() {
+    // Setup
+    let api = Api::new();
+    let announcement = json!({
+        "title": "Maintenance",
+        "body": "Saturday",
+        "severity": "warning",
+    });
+
+    // Execute
+    let announced = api
+        .send("POST", "/v1/announcements", Some(announcement))
+        .await;
+    let announcements = api.send("GET", "/v1/announcements", None).await;
+    let api_key = api
+        .send("POST", "/v1/admin/api-keys", Some(json!({ "name": "ci" })))
+        .await;
+    let api_keys = api.send("GET", "/v1/admin/api-keys", None).await;
+    let migration = api.send("GET", "/v1/admin/migration", None).await;
+
+    // Assert
+    assert_response_snapshot!("post_announcement", announced);
+    assert_response_snapshot!("list_announcements", announcements);
+    assert_response_snapshot!("post_api_key", api_key);
+    assert_response_snapshot!("list_api_keys", api_keys);
+    assert_response_snapshot!("get_migration", migration);
+}
+
+#[tokio::test]
+async fn it_keeps_the_shape_of_org_responses() {
+    // Setup
+    let api = Api::new();
+
+    // Execute
+    let created = api
+        .send("POST", "/v1/orgs", Some(json!({ "name": "Acme" })))
+        .await;
+    let org_uri =
+        format!("/v1/orgs/{}", created["body"]["id"].as_str().unwrap());
+    let added = api
+        .send("PUT", &format!("{}/members/bob", org_uri), None)
+        .await;
+    let got = api.send("GET", &org_uri, None).await;
+    let listed = api.send("GET", "/v1/orgs", None).await;
+
+    // Assert
+    assert_response_snapshot!("post_org", created);
+    assert_response_snapshot!("put_org_member", added);
+    assert_response_snapshot!("get_org", got);
+    assert_response_snapshot!("list_orgs", listed);
+}
+
+#[tokio::test]
+async fn it_keeps_the_shape_of_error_responses() {
+    // Setup
+    let api = Api::new();
+
+    // Execute
+    let missing = api.send("GET", "/v1/notes/unknown", None).await;
+    let invalid = api
+        .send(
+            "POST",
+            "/v1/notes",
+            Some(json!({ "title": " ", "body": "" })),
+        )
+        .await;
+    let malformed = api
+        .send_raw("POST", "/v1/notes", "application/json", Body::from("{"))
+        .await;
+
+    // Assert
+    assert_response_snapshot!("not_found", missing);
+    assert_response_snapshot!("invalid_note", invalid);
+    assert_response_snapshot!("malformed_note", malformed);
+}
+
+// Optional features add routes and formats to these documents.
+#[tokio::test]
+#[cfg_attr(feature = "pdf", ignore = "snapshots are of the default build")]
+async fn it_keeps_the_shape_of_service_documents() {
+    // Setup
+    let api = Api::new();
+
+    // Execute
+    let capabilities = api.send("GET", "/v1/capabilities", None).await;
+    let openapi = api.send("GET", "/v1/openapi.json", None).await;
+
+    // Assert
+    assert_response_snapshot!("capabilities", capabilities);
+    assert_json_snapshot!("openapi", openapi);
+}